@@ -0,0 +1,173 @@
+//! The wallpaper, themes and dark or light style environments switch to
+//! when launched, set through gsettings (and swaymsg for the wallpaper on
+//! Sway). The values they replace are kept in the state directory, to be
+//! restored once the environment is stopped.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command as ProcessCommand;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Appearance, ColorScheme};
+use crate::launch::{expand_home, is_command_available};
+use crate::paths;
+
+const INTERFACE: &str = "org.gnome.desktop.interface";
+const BACKGROUND: &str = "org.gnome.desktop.background";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Setting {
+    schema: String,
+    key: String,
+    /// GVariant text, as printed by `gsettings get`.
+    value: String,
+}
+
+/// The environment whose appearance is applied, and the settings from
+/// before the first appearance was.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Applied {
+    env: String,
+    previous: Vec<Setting>,
+}
+
+fn applied_path() -> PathBuf {
+    paths::state_dir().join("appearance.yaml")
+}
+
+fn load_applied() -> Option<Applied> {
+    let contents = fs::read_to_string(applied_path()).ok()?;
+    serde_yaml::from_str(&contents).ok()
+}
+
+fn gsettings(args: &[&str]) -> io::Result<String> {
+    let output = ProcessCommand::new("gsettings").args(args).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Quotes `text` as a GVariant string.
+pub fn gvariant_string(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// The gsettings `appearance` changes, as `(schema, key, value)`.
+fn settings(appearance: &Appearance) -> Vec<(&'static str, &'static str, String)> {
+    let mut settings = Vec::new();
+    if let Some(wallpaper) = &appearance.wallpaper {
+        let uri = format!("file://{}", expand_home(wallpaper).display());
+        settings.push((BACKGROUND, "picture-uri", gvariant_string(&uri)));
+        settings.push((BACKGROUND, "picture-uri-dark", gvariant_string(&uri)));
+    }
+    if let Some(theme) = &appearance.gtk_theme {
+        settings.push((INTERFACE, "gtk-theme", gvariant_string(theme)));
+    }
+    if let Some(theme) = &appearance.icon_theme {
+        settings.push((INTERFACE, "icon-theme", gvariant_string(theme)));
+    }
+    if let Some(scheme) = appearance.color_scheme {
+        let value = match scheme {
+            ColorScheme::Dark => "prefer-dark",
+            // `prefer-light` is only known to recent versions of GNOME
+            ColorScheme::Light => "default",
+        };
+        settings.push((INTERFACE, "color-scheme", gvariant_string(value)));
+    }
+    settings
+}
+
+/// Switches to the appearance of `env`, keeping the settings it replaces
+/// unless an environment already did. The ones the appearance of the
+/// previous environment changed and this one does not are restored.
+pub fn apply(env: &str, appearance: &Appearance) -> io::Result<()> {
+    let mut applied = load_applied().unwrap_or_default();
+    applied.env = env.to_string();
+    let settings = settings(appearance);
+    for setting in &applied.previous {
+        let replaced = settings
+            .iter()
+            .any(|(schema, key, _)| setting.schema == *schema && setting.key == *key);
+        if !replaced {
+            gsettings(&["set", &setting.schema, &setting.key, &setting.value])?;
+        }
+    }
+    if !settings.is_empty() && !is_command_available("gsettings") {
+        warn!(
+            "Cannot switch the appearance of '{}', gsettings not found",
+            env
+        );
+    } else {
+        for (schema, key, value) in settings {
+            // Keys missing from older desktops are skipped
+            let Ok(current) = gsettings(&["get", schema, key]) else {
+                continue;
+            };
+            let known = applied
+                .previous
+                .iter()
+                .any(|s| s.schema == schema && s.key == key);
+            if !known {
+                applied.previous.push(Setting {
+                    schema: schema.to_string(),
+                    key: key.to_string(),
+                    value: current,
+                });
+            }
+            gsettings(&["set", schema, key, &value])?;
+        }
+    }
+    if let (Some(wallpaper), true) = (
+        &appearance.wallpaper,
+        std::env::var_os("SWAYSOCK").is_some(),
+    ) {
+        let wallpaper = expand_home(wallpaper);
+        let status = ProcessCommand::new("swaymsg")
+            .args(["output", "*", "bg"])
+            .arg(&wallpaper)
+            .arg("fill")
+            .output()?
+            .status;
+        if !status.success() {
+            warn!(
+                "Failed to set the Sway wallpaper to {}",
+                wallpaper.display()
+            );
+        }
+    }
+
+    let path = applied_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(
+        &path,
+        serde_yaml::to_string(&applied).map_err(io::Error::other)?,
+    )?;
+    info!("Switched to the appearance of {}", env);
+    Ok(())
+}
+
+/// The environment whose appearance is applied, if any.
+pub fn applied_env() -> Option<String> {
+    load_applied().map(|applied| applied.env)
+}
+
+/// Restores the settings from before the appearance of `env` was applied,
+/// unless the appearance of another environment replaced it.
+pub fn revert(env: &str) -> io::Result<()> {
+    let Some(applied) = load_applied().filter(|applied| applied.env == env) else {
+        return Ok(());
+    };
+    for setting in &applied.previous {
+        gsettings(&["set", &setting.schema, &setting.key, &setting.value])?;
+    }
+    fs::remove_file(applied_path())?;
+    info!("Restored the appearance from before {}", env);
+    Ok(())
+}
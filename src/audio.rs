@@ -0,0 +1,70 @@
+//! Default output and input, and their volumes, set with `pactl` (which
+//! PipeWire serves through pipewire-pulse) when an environment is launched.
+
+use std::io;
+use std::process::Command as ProcessCommand;
+
+use log::{info, warn};
+
+use crate::config::Audio;
+use crate::launch::is_command_available;
+
+fn pactl(args: &[&str]) -> io::Result<String> {
+    let output = ProcessCommand::new("pactl").args(args).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Names of the sinks or sources (`kind`) known to the sound server.
+fn device_names(kind: &str) -> io::Result<Vec<String>> {
+    // `<index>\t<name>\t<driver>\t<sample spec>\t<state>`
+    Ok(pactl(&["list", "short", kind])?
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1).map(str::to_string))
+        .collect())
+}
+
+/// Makes `name` the default sink or source (`kind`), unless it is missing,
+/// e.g. as the headset is not plugged in.
+fn set_default(kind: &str, name: &str) -> io::Result<()> {
+    if !device_names(&format!("{}s", kind))?
+        .iter()
+        .any(|n| n == name)
+    {
+        warn!(
+            "Audio {} '{}' not found, keeping the default one",
+            kind, name
+        );
+        return Ok(());
+    }
+    pactl(&[&format!("set-default-{}", kind), name]).map(|_| ())
+}
+
+/// Switches to the audio devices and volumes of an environment.
+pub fn apply(env: &str, audio: &Audio) -> io::Result<()> {
+    if !is_command_available("pactl") {
+        warn!("Cannot set the audio devices of '{}', pactl not found", env);
+        return Ok(());
+    }
+    if let Some(sink) = &audio.sink {
+        set_default("sink", sink)?;
+    }
+    if let Some(source) = &audio.source {
+        set_default("source", source)?;
+    }
+    if let Some(volume) = audio.volume {
+        pactl(&["set-sink-volume", "@DEFAULT_SINK@", &format!("{}%", volume)])?;
+    }
+    if let Some(volume) = audio.source_volume {
+        pactl(&[
+            "set-source-volume",
+            "@DEFAULT_SOURCE@",
+            &format!("{}%", volume),
+        ])?;
+    }
+    info!("Set the audio devices of {}", env);
+    Ok(())
+}
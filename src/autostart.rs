@@ -0,0 +1,90 @@
+//! `clovis autostart`: XDG autostart entries launching an environment at
+//! every login, which any desktop following the freedesktop autostart
+//! specification runs.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::desktop::clovis_exec;
+use crate::style::{paint, Style};
+
+/// `~/.config/autostart`.
+fn autostart_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("autostart")
+}
+
+fn entry_path(env: &str) -> PathBuf {
+    autostart_dir().join(format!("clovis-{}.desktop", env.replace('/', "-")))
+}
+
+/// Writes the autostart entry of `env`.
+pub fn enable(config: &Config, env: &str) -> io::Result<bool> {
+    let env = config.resolve_env(env);
+    if !config.environments.contains_key(env) {
+        eprintln!("Environment '{}' not found.", env);
+        return Ok(false);
+    }
+    let entry = format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=clovis: {env}\n\
+         Comment=Launches the {env} environment\n\
+         Exec={}\n\
+         Terminal=false\n\
+         X-GNOME-Autostart-enabled=true\n",
+        clovis_exec(&["launch", env]),
+    );
+    let path = entry_path(env);
+    fs::create_dir_all(autostart_dir())?;
+    fs::write(&path, entry)?;
+    println!("'{}' now starts at login ({}).", env, path.display());
+    Ok(true)
+}
+
+/// Removes the autostart entry of `env`.
+pub fn disable(config: &Config, env: &str) -> io::Result<()> {
+    let env = config.resolve_env(env);
+    let path = entry_path(env);
+    if !path.exists() {
+        println!("'{}' does not start at login.", env);
+        return Ok(());
+    }
+    fs::remove_file(&path)?;
+    println!("'{}' no longer starts at login.", env);
+    Ok(())
+}
+
+/// Whether the entry at `path` is in use: desktops skip hidden ones and
+/// GNOME the ones it disabled.
+fn is_enabled(path: &Path) -> bool {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return false;
+    };
+    !contents.lines().any(|line| {
+        matches!(
+            line.trim(),
+            "Hidden=true" | "X-GNOME-Autostart-enabled=false"
+        )
+    })
+}
+
+/// Lists the environments and whether they start at login.
+pub fn show_status(config: &Config) {
+    let mut envs: Vec<&String> = config.environments.keys().collect();
+    envs.sort();
+    for env in envs {
+        let path = entry_path(env);
+        let state = if !path.exists() {
+            paint(Style::Dim, "not at login")
+        } else if is_enabled(&path) {
+            paint(Style::Running, "at login")
+        } else {
+            paint(Style::Dim, "disabled by the desktop")
+        };
+        println!("{} ({})", paint(Style::Env, env), state);
+    }
+}
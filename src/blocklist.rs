@@ -0,0 +1,65 @@
+//! Programs environments block while they run, e.g. games in a focus
+//! environment: `clovis daemon` terminates them with a notification.
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+use log::info;
+
+use crate::notifications;
+use crate::snapshot::process_names;
+
+/// The processes of the user named `name`, by the file name of their
+/// executable, their first argument or their `comm`.
+fn find_processes(name: &str) -> Vec<u32> {
+    let Ok(read_dir) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    // SAFETY: getuid cannot fail
+    let uid = unsafe { libc::getuid() };
+    read_dir
+        .filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+        .filter(|pid: &u32| *pid != std::process::id())
+        .filter(|pid| fs::metadata(format!("/proc/{}", pid)).is_ok_and(|m| m.uid() == uid))
+        .filter(|pid| {
+            let comm = fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default();
+            process_names(*pid)
+                .iter()
+                .chain([comm.trim().to_string()].iter())
+                .any(|process| process.eq_ignore_ascii_case(name))
+        })
+        .collect()
+}
+
+/// Terminates the programs `env` blocks. The processes in `terminated`
+/// were asked to exit before: the ones still running are killed.
+pub fn enforce(env: &str, blocks: &[String], terminated: &mut Vec<u32>) {
+    for name in blocks {
+        let pids = find_processes(name);
+        if pids.is_empty() {
+            continue;
+        }
+        let mut closed = false;
+        for pid in pids {
+            let signal = if terminated.contains(&pid) {
+                libc::SIGKILL
+            } else {
+                closed = true;
+                terminated.push(pid);
+                libc::SIGTERM
+            };
+            // SAFETY: kill has no memory safety requirements
+            unsafe {
+                libc::kill(pid as libc::pid_t, signal);
+            }
+        }
+        if closed {
+            info!("Terminated {}, blocked by {}", name, env);
+            notifications::send(
+                &format!("Closed {}", name),
+                &format!("It is blocked while '{}' runs.", env),
+            );
+        }
+    }
+    terminated.retain(|pid| fs::metadata(format!("/proc/{}", pid)).is_ok());
+}
@@ -0,0 +1,90 @@
+//! Bluetooth devices environments connect when launched, through BlueZ on
+//! the system bus. Devices are named by address or by alias, and must have
+//! been paired already.
+
+use std::io;
+
+use crate::dbus::{Connection, Value};
+
+const BLUEZ: &str = "org.bluez";
+const DEVICE: &str = "org.bluez.Device1";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+/// Names of the children of the object at `path`, e.g. `hci0` for
+/// `/org/bluez`.
+fn child_nodes(bus: &mut Connection, path: &str) -> io::Result<Vec<String>> {
+    let reply = bus.call(
+        BLUEZ,
+        path,
+        "org.freedesktop.DBus.Introspectable",
+        "Introspect",
+        &[],
+    )?;
+    let xml = reply.args.first().cloned().unwrap_or_default();
+    Ok(xml
+        .split("<node name=\"")
+        .skip(1)
+        .filter_map(|node| node.split('"').next())
+        .map(str::to_string)
+        .collect())
+}
+
+fn property(bus: &mut Connection, path: &str, name: &str) -> io::Result<Option<Value>> {
+    let reply = bus.call(
+        BLUEZ,
+        path,
+        PROPERTIES,
+        "Get",
+        &[Value::Str(DEVICE.to_string()), Value::Str(name.to_string())],
+    )?;
+    Ok(reply.variant)
+}
+
+/// Whether `device` is an address such as `AA:BB:CC:DD:EE:FF`.
+fn is_address(device: &str) -> bool {
+    let parts: Vec<&str> = device.split(':').collect();
+    parts.len() == 6
+        && parts
+            .iter()
+            .all(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// The object path of a paired device, named by address or alias.
+fn device_path(bus: &mut Connection, device: &str) -> io::Result<String> {
+    let node = format!("dev_{}", device.to_uppercase().replace(':', "_"));
+    for adapter in child_nodes(bus, "/org/bluez")? {
+        let adapter_path = format!("/org/bluez/{}", adapter);
+        for child in child_nodes(bus, &adapter_path)? {
+            if !child.starts_with("dev_") {
+                continue;
+            }
+            let path = format!("{}/{}", adapter_path, child);
+            let matches = if is_address(device) {
+                child == node
+            } else {
+                matches!(property(bus, &path, "Alias")?, Some(Value::Str(alias)) if alias.eq_ignore_ascii_case(device))
+            };
+            if matches {
+                return Ok(path);
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no paired Bluetooth device '{}'", device),
+    ))
+}
+
+/// Connects `device`, or disconnects it when `connect` is false. Returns
+/// false when it already was.
+pub fn set_connected(device: &str, connect: bool) -> io::Result<bool> {
+    let mut bus = Connection::system()?;
+    let path = device_path(&mut bus, device)?;
+    let connected = property(&mut bus, &path, "Connected")? == Some(Value::Bool(true));
+    if connected == connect {
+        return Ok(false);
+    }
+    let member = if connect { "Connect" } else { "Disconnect" };
+    bus.call(BLUEZ, &path, DEVICE, member, &[])?;
+    Ok(true)
+}
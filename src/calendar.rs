@@ -0,0 +1,474 @@
+//! Upcoming events of an ICS feed or CalDAV calendar, for the `calendar:`
+//! section of `clovis daemon`.
+//!
+//! Times with a `TZID` are taken as local time, all-day events are left
+//! out, and of the recurrence rules only daily and weekly ones are
+//! expanded; CalDAV servers expand recurring events themselves.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::process::{Command as ProcessCommand, Stdio};
+
+use time::{Date, Duration, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset, Weekday};
+
+use crate::config::{CalendarConfig, CalendarRule};
+use crate::launch::expand_home;
+
+/// How long fetching the calendar may take.
+const FETCH_TIMEOUT_SECS: u32 = 30;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub summary: String,
+    pub start: OffsetDateTime,
+}
+
+/// Whether `event` is one `rule` launches an environment for.
+pub fn rule_matches(rule: &CalendarRule, event: &Event) -> bool {
+    event
+        .summary
+        .to_lowercase()
+        .contains(&rule.pattern.to_lowercase())
+}
+
+/// Lists the events starting from `from` until `until`, sorted by start.
+pub fn upcoming(
+    config: &CalendarConfig,
+    from: OffsetDateTime,
+    until: OffsetDateTime,
+) -> io::Result<Vec<Event>> {
+    let text = fetch(config, from, until)?;
+    let mut events: Vec<Event> = if config.caldav {
+        calendar_data(&text)
+            .iter()
+            .flat_map(|ics| parse(ics, from, until))
+            .collect()
+    } else {
+        parse(&text, from, until)
+    };
+    events.sort_by_key(|event| event.start);
+    events.dedup();
+    Ok(events)
+}
+
+fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+fn fetch(
+    config: &CalendarConfig,
+    from: OffsetDateTime,
+    until: OffsetDateTime,
+) -> io::Result<String> {
+    if !is_url(&config.url) {
+        return fs::read_to_string(expand_home(&config.url));
+    }
+    let mut command = ProcessCommand::new("curl");
+    command
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .args(["--max-time", &FETCH_TIMEOUT_SECS.to_string()])
+        // The credentials are read from stdin to keep them out of `ps`
+        .args(["--config", "-"]);
+    if config.caldav {
+        command
+            .args(["--request", "REPORT", "--header", "Depth: 1"])
+            .args(["--header", "Content-Type: application/xml; charset=utf-8"])
+            .args(["--data", &calendar_query(from, until)]);
+    }
+    let mut child = command
+        .arg(&config.url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let (Some(mut stdin), Some(username)) = (child.stdin.take(), &config.username) {
+        let user = format!("{}:{}", username, config.password.as_deref().unwrap_or(""));
+        writeln!(
+            stdin,
+            "user = \"{}\"",
+            user.replace('\\', "\\\\").replace('"', "\\\"")
+        )?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The body of a CalDAV REPORT asking for the events in a time range, with
+/// recurring events expanded by the server.
+fn calendar_query(from: OffsetDateTime, until: OffsetDateTime) -> String {
+    let format = time::macros::format_description!("[year][month][day]T[hour][minute][second]Z");
+    let from = from
+        .to_offset(UtcOffset::UTC)
+        .format(&format)
+        .unwrap_or_default();
+    let until = until
+        .to_offset(UtcOffset::UTC)
+        .format(&format)
+        .unwrap_or_default();
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop>
+    <C:calendar-data><C:expand start="{from}" end="{until}"/></C:calendar-data>
+  </D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="VEVENT">
+        <C:time-range start="{from}" end="{until}"/>
+      </C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>"#
+    )
+}
+
+/// Extracts the calendars of a CalDAV multistatus response.
+fn calendar_data(xml: &str) -> Vec<String> {
+    let mut calendars = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+        // `<C:calendar-data>`, with whatever prefix the server gave to the
+        // CalDAV namespace
+        let name = tag.split_whitespace().next().unwrap_or_default();
+        let name = name.rsplit(':').next().unwrap_or_default();
+        if name == "calendar-data" && !tag.ends_with('/') {
+            let content_end = rest.find("</").unwrap_or(rest.len());
+            calendars.push(unescape_xml(&rest[..content_end]));
+            rest = &rest[content_end..];
+        }
+    }
+    calendars
+}
+
+fn unescape_xml(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find(';') else {
+            break;
+        };
+        let entity = &rest[start + 1..start + end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match c {
+            Some(c) => unescaped.push(c),
+            None => unescaped.push_str(&rest[start..=start + end]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// A date and time of the calendar, in UTC or in local time.
+#[derive(Debug, Clone, Copy)]
+struct CalendarTime {
+    time: PrimitiveDateTime,
+    utc: bool,
+}
+
+impl CalendarTime {
+    /// Parses `20261016T090000Z`, `20261016T090000` or `20261016`.
+    fn parse(value: &str) -> Option<CalendarTime> {
+        let (date, time) = value.split_once('T').unwrap_or((value, "000000"));
+        let (time, utc) = match time.strip_suffix('Z') {
+            Some(time) => (time, true),
+            None => (time, false),
+        };
+        let number = |s: &str, range: std::ops::Range<usize>| s.get(range)?.parse::<u32>().ok();
+        if date.len() != 8 || time.len() != 6 {
+            return None;
+        }
+        let month = Month::try_from(number(date, 4..6)? as u8).ok()?;
+        let date =
+            Date::from_calendar_date(number(date, 0..4)? as i32, month, number(date, 6..8)? as u8)
+                .ok()?;
+        let time = Time::from_hms(
+            number(time, 0..2)? as u8,
+            number(time, 2..4)? as u8,
+            number(time, 4..6)? as u8,
+        )
+        .ok()?;
+        Some(CalendarTime {
+            time: PrimitiveDateTime::new(date, time),
+            utc,
+        })
+    }
+
+    /// The time at the `local` offset.
+    fn to_offset(self, local: UtcOffset) -> OffsetDateTime {
+        if self.utc {
+            self.time.assume_utc().to_offset(local)
+        } else {
+            self.time.assume_offset(local)
+        }
+    }
+}
+
+/// A property line, e.g. `DTSTART;TZID=Europe/Paris:20261016T090000`.
+struct Property<'a> {
+    name: String,
+    params: Vec<(String, &'a str)>,
+    value: &'a str,
+}
+
+impl Property<'_> {
+    fn parse(line: &str) -> Option<Property<'_>> {
+        // The value starts at the first colon outside of a quoted parameter
+        let mut quoted = false;
+        let colon = line.char_indices().find_map(|(i, c)| {
+            match c {
+                '"' => quoted = !quoted,
+                ':' if !quoted => return Some(i),
+                _ => {}
+            }
+            None
+        })?;
+        let mut head = line[..colon].split(';');
+        let name = head.next()?.to_ascii_uppercase();
+        let params = head
+            .filter_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                Some((key.to_ascii_uppercase(), value.trim_matches('"')))
+            })
+            .collect();
+        Some(Property {
+            name,
+            params,
+            value: &line[colon + 1..],
+        })
+    }
+
+    fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| *value)
+    }
+
+    /// Whether the value is a date without a time.
+    fn is_date(&self) -> bool {
+        self.param("VALUE") == Some("DATE") || !self.value.contains('T')
+    }
+}
+
+/// A `VEVENT` component, with the properties clovis cares about.
+#[derive(Default)]
+struct Component {
+    uid: String,
+    summary: String,
+    start: Option<CalendarTime>,
+    all_day: bool,
+    cancelled: bool,
+    rrule: Option<String>,
+    exdates: Vec<PrimitiveDateTime>,
+    /// Start of the occurrence of a recurring event this one replaces.
+    recurrence_id: Option<PrimitiveDateTime>,
+}
+
+/// Joins the lines folded with a leading space or tab.
+fn unfold(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ics.lines() {
+        let line = line.trim_end_matches('\r');
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+fn unescape_text(value: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push(' '),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+fn components(ics: &str) -> Vec<Component> {
+    let mut components = Vec::new();
+    let mut current: Option<Component> = None;
+    // Alarms nested in an event have properties of their own
+    let mut nested = 0;
+    for line in unfold(ics) {
+        let Some(property) = Property::parse(&line) else {
+            continue;
+        };
+        match (property.name.as_str(), property.value) {
+            ("BEGIN", "VEVENT") if current.is_none() => current = Some(Component::default()),
+            ("BEGIN", _) if current.is_some() => nested += 1,
+            ("END", "VEVENT") if nested == 0 => components.extend(current.take()),
+            ("END", _) if current.is_some() => nested -= 1,
+            _ => {}
+        }
+        let Some(component) = current.as_mut().filter(|_| nested == 0) else {
+            continue;
+        };
+        match property.name.as_str() {
+            "UID" => component.uid = property.value.to_string(),
+            "SUMMARY" => component.summary = unescape_text(property.value),
+            "STATUS" => component.cancelled = property.value == "CANCELLED",
+            "RRULE" => component.rrule = Some(property.value.to_string()),
+            "DTSTART" => {
+                component.all_day = property.is_date();
+                component.start = CalendarTime::parse(property.value);
+            }
+            "EXDATE" => component.exdates.extend(
+                property
+                    .value
+                    .split(',')
+                    .filter_map(CalendarTime::parse)
+                    .map(|t| t.time),
+            ),
+            "RECURRENCE-ID" => {
+                component.recurrence_id = CalendarTime::parse(property.value).map(|t| t.time)
+            }
+            _ => {}
+        }
+    }
+    components
+}
+
+fn weekday(code: &str) -> Option<Weekday> {
+    // BYDAY entries may be prefixed with an ordinal, e.g. `1MO`
+    let code = code.trim_start_matches(|c: char| c == '+' || c == '-' || c.is_ascii_digit());
+    Some(match code {
+        "MO" => Weekday::Monday,
+        "TU" => Weekday::Tuesday,
+        "WE" => Weekday::Wednesday,
+        "TH" => Weekday::Thursday,
+        "FR" => Weekday::Friday,
+        "SA" => Weekday::Saturday,
+        "SU" => Weekday::Sunday,
+        _ => return None,
+    })
+}
+
+/// The starts of the occurrences of a recurring event until `until`, or
+/// only the first one for the rules that are not supported.
+fn occurrences(
+    start: PrimitiveDateTime,
+    rrule: &str,
+    until: PrimitiveDateTime,
+) -> Vec<PrimitiveDateTime> {
+    let mut freq = "";
+    let mut interval = 1i64;
+    let mut count = None;
+    let mut rule_until = None;
+    let mut weekdays = Vec::new();
+    for part in rrule.split(';') {
+        match part.split_once('=') {
+            Some(("FREQ", value)) => freq = value,
+            Some(("INTERVAL", value)) => interval = value.parse().unwrap_or(1).max(1),
+            Some(("COUNT", value)) => count = value.parse::<usize>().ok(),
+            Some(("UNTIL", value)) => rule_until = CalendarTime::parse(value).map(|t| t.time),
+            Some(("BYDAY", value)) => weekdays = value.split(',').filter_map(weekday).collect(),
+            _ => {}
+        }
+    }
+    if freq != "DAILY" && freq != "WEEKLY" {
+        return vec![start];
+    }
+    if weekdays.is_empty() && freq == "WEEKLY" {
+        weekdays.push(start.weekday());
+    }
+    let until = rule_until.map_or(until, |rule_until| rule_until.min(until));
+    // Weeks are counted from their Monday, the default start of week
+    let first_monday =
+        start.date() - Duration::days(start.weekday().number_days_from_monday().into());
+
+    let mut starts = Vec::new();
+    let mut day = start;
+    while day <= until && count.is_none_or(|count| starts.len() < count) {
+        let days = (day.date() - start.date()).whole_days();
+        let in_interval = match freq {
+            "DAILY" => days % interval == 0,
+            _ => ((day.date() - first_monday).whole_weeks()) % interval == 0,
+        };
+        if in_interval && (weekdays.is_empty() || weekdays.contains(&day.weekday())) {
+            starts.push(day);
+        }
+        day += Duration::days(1);
+    }
+    starts
+}
+
+/// Lists the events of `ics` starting from `from` until `until`.
+fn parse(ics: &str, from: OffsetDateTime, until: OffsetDateTime) -> Vec<Event> {
+    let local = from.offset();
+    let components = components(ics);
+    // Occurrences of recurring events that were moved or cancelled
+    let replaced: HashSet<(&str, PrimitiveDateTime)> = components
+        .iter()
+        .filter_map(|c| Some((c.uid.as_str(), c.recurrence_id?)))
+        .collect();
+
+    let mut events = Vec::new();
+    for component in &components {
+        let Some(start) = component
+            .start
+            .filter(|_| !component.all_day && !component.cancelled)
+        else {
+            continue;
+        };
+        let local_until = until.to_offset(if start.utc { UtcOffset::UTC } else { local });
+        let local_until = PrimitiveDateTime::new(local_until.date(), local_until.time());
+        let starts = match component
+            .rrule
+            .as_deref()
+            .filter(|_| component.recurrence_id.is_none())
+        {
+            Some(rrule) => occurrences(start.time, rrule, local_until),
+            None => vec![start.time],
+        };
+        for time in starts {
+            if component.exdates.contains(&time)
+                || (component.recurrence_id.is_none()
+                    && component.rrule.is_some()
+                    && replaced.contains(&(component.uid.as_str(), time)))
+            {
+                continue;
+            }
+            let start = CalendarTime { time, ..start }.to_offset(local);
+            if start >= from && start < until {
+                events.push(Event {
+                    summary: component.summary.clone(),
+                    start,
+                });
+            }
+        }
+    }
+    events
+}
@@ -0,0 +1,1060 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::migrate::{document_version, migrate, CONFIG_VERSION};
+use crate::yaml_edit::YamlDocument;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    pub version: u32,
+    pub environments: HashMap<String, Environment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub aliases: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync: Option<SyncConfig>,
+    /// Directories searched for AppImage entries that have no `path`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub appimage_dirs: Vec<String>,
+    /// Shell running `script` entries, `$SHELL` (or `/bin/sh`) when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    /// Terminal emulator command, followed by the program to run in it
+    /// (e.g. `foot -e`). `$TERMINAL -e` or `xterm -e` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terminal: Option<String>,
+    /// Command `clovis config` opens the config file with, e.g. `code
+    /// --wait`. `$VISUAL`, then `$EDITOR` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub editor: Option<String>,
+    /// Start apps in a transient systemd scope of their own, so that they
+    /// can be stopped with all of their children.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub systemd_scope: bool,
+    /// When set, apps only inherit the session variables (display, XDG,
+    /// locale, ...) and the ones listed here from the environment clovis
+    /// runs in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clean_env: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mqtt: Option<MqttConfig>,
+    /// Launches and stops run by `clovis daemon` at set times.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<ScheduledAction>,
+    /// Launches and stops run by `clovis daemon` when hardware is plugged
+    /// or unplugged.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub device_triggers: Vec<DeviceTrigger>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle: Option<IdleConfig>,
+    /// Launches and stops run by `clovis daemon` when joining or leaving a
+    /// network.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub network_rules: Vec<NetworkRule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calendar: Option<CalendarConfig>,
+}
+
+impl Config {
+    /// Maps an alias to the environment it stands for; other names are
+    /// returned unchanged.
+    pub fn resolve_env<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map(String::as_str).unwrap_or(name)
+    }
+
+    /// The environments that cannot run alongside `env`: the ones it lists
+    /// in `conflicts_with` and the ones listing it, sorted.
+    pub fn conflicting_envs(&self, env: &str) -> Vec<&str> {
+        let mut conflicts: Vec<&str> = Vec::new();
+        for (name, environment) in &self.environments {
+            for other in &environment.conflicts_with {
+                let other = self.resolve_env(other);
+                if name == env && other != env {
+                    conflicts.push(other);
+                } else if other == env && name != env {
+                    conflicts.push(name);
+                }
+            }
+        }
+        conflicts.retain(|name| self.environments.contains_key(*name));
+        conflicts.sort_unstable();
+        conflicts.dedup();
+        conflicts
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            version: CONFIG_VERSION,
+            environments: HashMap::new(),
+            default: None,
+            aliases: HashMap::new(),
+            sync: None,
+            appimage_dirs: Vec::new(),
+            shell: None,
+            terminal: None,
+            editor: None,
+            systemd_scope: false,
+            clean_env: None,
+            mqtt: None,
+            schedule: Vec::new(),
+            device_triggers: Vec::new(),
+            idle: None,
+            network_rules: Vec::new(),
+            calendar: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Environment {
+    /// What the environment is for, shown by launchers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Icon name or file shown by launchers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// Color of the environment's name in the terminal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Color>,
+    #[serde(default)]
+    pub apps: Vec<App>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slice: Option<Slice>,
+    /// Environments stopped before this one is launched, and the other way
+    /// around.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicts_with: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<Layout>,
+    /// Connections brought up before the apps are launched, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub network: Vec<NetworkAction>,
+    /// Shares and disks mounted before the apps are launched, in order,
+    /// and unmounted once the environment is stopped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<Mount>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appearance: Option<Appearance>,
+    /// Turn on the do-not-disturb mode of the notification daemon while
+    /// the environment runs.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub do_not_disturb: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio: Option<Audio>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyboard: Option<Keyboard>,
+    /// Paired Bluetooth devices connected before the apps are launched, by
+    /// address or alias.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bluetooth: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub bluetooth_disconnect_on_stop: bool,
+    /// Programs `clovis daemon` terminates while the environment runs, by
+    /// process name, e.g. `steam`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocks: Vec<String>,
+}
+
+/// XKB keyboard layout switched to while an environment runs.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keyboard {
+    /// Layouts, comma-separated, e.g. `us,fr`.
+    pub layout: String,
+    /// Variants of the layouts, comma-separated, e.g. `intl,`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// XKB options, comma-separated, e.g. `ctrl:nocaps`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<String>,
+}
+
+/// Audio devices set up when an environment is launched, by their
+/// PipeWire or PulseAudio names as listed by `pactl list short sinks` (or
+/// `sources`).
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Audio {
+    /// Default output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sink: Option<String>,
+    /// Default input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Volume of the default output, in percent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u32>,
+    /// Volume of the default input, in percent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_volume: Option<u32>,
+    /// Outputs of the apps that do not play to the default one, by app
+    /// name, set as `PULSE_SINK`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub app_sinks: HashMap<String, String>,
+}
+
+/// Look of the desktop while an environment runs, switched to on launch
+/// and reverted once it is stopped.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Appearance {
+    /// Image file set as the wallpaper.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallpaper: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gtk_theme: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon_theme: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_scheme: Option<ColorScheme>,
+    /// Qt style of the apps of the environment, set as `QT_STYLE_OVERRIDE`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qt_style: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorScheme {
+    Dark,
+    Light,
+}
+
+/// A connection an environment needs, checked and brought up before its
+/// apps are launched.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkAction {
+    #[serde(rename = "type")]
+    pub kind: NetworkKind,
+    /// NetworkManager connection of `nmcli` entries, interface of
+    /// `wireguard` ones.
+    pub name: String,
+    /// Bring the connection down once the environment is stopped.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub down_on_stop: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkKind {
+    /// A NetworkManager connection, e.g. a VPN, handled with `nmcli`.
+    Nmcli,
+    /// A WireGuard interface handled with `wg-quick`, through `sudo` when
+    /// clovis does not run as root.
+    Wireguard,
+}
+
+/// A share or disk an environment needs, mounted before its apps are
+/// launched.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mount {
+    #[serde(rename = "type")]
+    pub kind: MountKind,
+    /// GVfs location of `gio` entries, e.g. `smb://nas/share`, block device
+    /// of `udisks` ones, e.g. `/dev/disk/by-label/Data`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Mount point of `command` entries, checked before mounting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Shell command mounting `command` entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount: Option<String>,
+    /// Shell command unmounting `command` entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unmount: Option<String>,
+    /// Leave it mounted once the environment is stopped.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub keep_mounted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MountKind {
+    /// A network share mounted through GVfs with `gio mount`.
+    Gio,
+    /// A block device mounted with `udisksctl`.
+    Udisks,
+    /// Mounted and unmounted with shell commands, e.g. `sshfs`.
+    Command,
+}
+
+impl MountKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MountKind::Gio => "gio",
+            MountKind::Udisks => "udisks",
+            MountKind::Command => "command",
+        }
+    }
+}
+
+/// Monitor layout an environment needs: exactly `outputs` enabled, set up
+/// with the `apply` shell command when they are not.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    pub outputs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub apply: Option<String>,
+}
+
+/// systemd user slice the apps of an environment are started in, with the
+/// resource limits applied to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Slice {
+    pub name: String,
+    /// `MemoryMax=` of the slice, e.g. `8G`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_max: Option<String>,
+    /// `CPUQuota=` of the slice, e.g. `200%`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_quota: Option<String>,
+}
+
+impl Slice {
+    pub fn unit(&self) -> String {
+        if self.name.ends_with(".slice") {
+            self.name.clone()
+        } else {
+            format!("{}.slice", self.name)
+        }
+    }
+}
+
+impl Environment {
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.apps.iter().position(|app| app.name == name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct App {
+    pub name: String,
+    #[serde(rename = "type", default, skip_serializing_if = "AppKind::is_auto")]
+    pub kind: AppKind,
+    /// File the entry points at: the AppImage of `appimage` entries, the
+    /// compose file of `compose` ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Flake output run by `nix` entries, `nixpkgs#<name>` when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flake: Option<String>,
+    /// Shell snippet run by `script` entries, or remote command of `ssh` ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Multiplexer session name, the app name when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Layout file the multiplexer session is created from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    /// Run the app in the terminal emulator, as desktop entries with
+    /// `Terminal=true` are.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub terminal: bool,
+    /// Browser profile opened by `browser` entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Pages opened in a new window by `browser` entries.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub urls: Vec<String>,
+    /// Services started by `compose` entries, all of them when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<String>,
+    /// Distrobox container `distrobox` entries run in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// Host `ssh` entries run their command on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// MAC address woken up with a Wake-on-LAN packet before connecting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wake_on_lan: Option<String>,
+    /// Libvirt connection URI of `vm` entries, virsh's default when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    /// Open the console of a `vm` entry with virt-viewer once it started.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub viewer: bool,
+    /// Scheduling priority, from -20 (favorable) to 19.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nice: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ionice_class: Option<IoniceClass>,
+    /// Adjustment of the OOM killer score, from -1000 (never killed) to 1000.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oom_score_adj: Option<i32>,
+    /// CPUs the app may run on, all of them when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cpu_affinity: Vec<usize>,
+    /// GPU the app renders on, the system default when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<Gpu>,
+    /// Run the app with `gamemoderun`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub gamemode: bool,
+    /// Run the app in a `gamescope` session started with these arguments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gamescope: Option<Vec<String>>,
+    /// Run the app confined by firejail or bwrap. Cannot be combined with
+    /// `privileged`, as pkexec does not work in a sandbox.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<Sandbox>,
+    /// Run the app as root through pkexec.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub privileged: bool,
+    /// Wait for a window of the app to open before launching the next one.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub wait_for_window: bool,
+    /// Wayland app id or X11 `WM_CLASS` of the app's windows, guessed from
+    /// the desktop file or the name when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_class: Option<String>,
+    /// Workspace the app's window is moved to once launched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+    /// Position and size the app's window is given once launched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geometry: Option<Geometry>,
+    /// Shell snippet stopping the app, instead of the default for its kind.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_command: Option<String>,
+    /// Seconds `clovis stop` waits for the app to exit before moving on to
+    /// the next one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_timeout: Option<u64>,
+    /// Signal the processes of the app are sent to stop it, `SIGTERM` when
+    /// unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_signal: Option<StopSignal>,
+    /// Kill the app when it is still running after `stop_timeout`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub kill_on_timeout: bool,
+    /// Whether the app is launched again while it is running, regardless of
+    /// `--force`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instances: Option<Instances>,
+    /// Memory and CPU use `clovis daemon` acts on when the app keeps above
+    /// them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thresholds: Option<Thresholds>,
+}
+
+/// Whether launching an app that is already running starts another
+/// instance of it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Instances {
+    /// Never, even with `--force`.
+    Single,
+    /// Always, even without `--force`.
+    Multiple,
+}
+
+/// Colors environment names can be shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+}
+
+/// Signals apps can be asked to stop with; some terminal apps only exit
+/// cleanly on `SIGINT`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum StopSignal {
+    Sigterm,
+    Sigint,
+    Sighup,
+}
+
+impl StopSignal {
+    /// The name `pkill` and `systemctl kill` take.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StopSignal::Sigterm => "SIGTERM",
+            StopSignal::Sigint => "SIGINT",
+            StopSignal::Sighup => "SIGHUP",
+        }
+    }
+}
+
+/// Limits on what a running app and its children use, checked by `clovis
+/// daemon`. `action` is taken once one of them has been exceeded for
+/// `seconds`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Thresholds {
+    /// Resident memory, in megabytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_mb: Option<u64>,
+    /// Percent of one CPU.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_percent: Option<f64>,
+    #[serde(default = "default_threshold_seconds")]
+    pub seconds: u64,
+    #[serde(default)]
+    pub action: ThresholdAction,
+    /// Shell snippet run by the `hook` action.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hook: Option<String>,
+}
+
+fn default_threshold_seconds() -> u64 {
+    30
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThresholdAction {
+    /// Send a desktop notification.
+    #[default]
+    Notify,
+    /// Stop the app and launch it again.
+    Restart,
+    /// Run the `hook` snippet.
+    Hook,
+}
+
+/// Position and size of a window, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Geometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Confinement the app runs in. Only one of the sandboxes can be used.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sandbox {
+    /// Firejail profile name or file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firejail: Option<String>,
+    /// Arguments passed to bwrap before the command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bwrap: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Gpu {
+    Discrete,
+    Integrated,
+}
+
+/// I/O scheduling class, as set by `ionice -c`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IoniceClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// How an app entry is launched. `auto` goes through the desktop file
+/// named by the entry (or matching it), other kinds name their launcher.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AppKind {
+    #[default]
+    Auto,
+    Snap,
+    Appimage,
+    Nix,
+    Script,
+    TmuxSession,
+    ZellijSession,
+    Browser,
+    Compose,
+    Distrobox,
+    Ssh,
+    Vm,
+}
+
+impl AppKind {
+    fn is_auto(&self) -> bool {
+        *self == AppKind::Auto
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AppKind::Auto => "auto",
+            AppKind::Snap => "snap",
+            AppKind::Appimage => "appimage",
+            AppKind::Nix => "nix",
+            AppKind::Script => "script",
+            AppKind::TmuxSession => "tmux_session",
+            AppKind::ZellijSession => "zellij_session",
+            AppKind::Browser => "browser",
+            AppKind::Compose => "compose",
+            AppKind::Distrobox => "distrobox",
+            AppKind::Ssh => "ssh",
+            AppKind::Vm => "vm",
+        }
+    }
+}
+
+impl App {
+    pub fn new(name: &str) -> App {
+        App {
+            name: name.to_string(),
+            kind: AppKind::Auto,
+            path: None,
+            flake: None,
+            command: None,
+            session: None,
+            layout: None,
+            terminal: false,
+            profile: None,
+            urls: Vec::new(),
+            services: Vec::new(),
+            container: None,
+            host: None,
+            wake_on_lan: None,
+            uri: None,
+            viewer: false,
+            nice: None,
+            ionice_class: None,
+            oom_score_adj: None,
+            cpu_affinity: Vec::new(),
+            gpu: None,
+            gamemode: false,
+            gamescope: None,
+            sandbox: None,
+            privileged: false,
+            wait_for_window: false,
+            window_class: None,
+            workspace: None,
+            geometry: None,
+            stop_command: None,
+            stop_timeout: None,
+            stop_signal: None,
+            kill_on_timeout: false,
+            instances: None,
+            thresholds: None,
+        }
+    }
+
+    /// Whether the entry carries nothing but its name, i.e. it can still be
+    /// written as a plain string in a version 1 config.
+    fn is_plain(&self) -> bool {
+        *self == App::new(&self.name)
+    }
+}
+
+/// Git remote the config directory is synchronized with by `clovis sync`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SyncConfig {
+    pub remote: String,
+    #[serde(default = "default_sync_branch")]
+    pub branch: String,
+}
+
+fn default_sync_branch() -> String {
+    "main".to_string()
+}
+
+/// MQTT broker `clovis daemon` subscribes to, and what its messages trigger.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MqttConfig {
+    /// `host` or `host:port`, port 1883 when not given.
+    pub broker: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub triggers: Vec<MqttTrigger>,
+}
+
+fn default_mqtt_client_id() -> String {
+    "clovis".to_string()
+}
+
+/// Runs `action` on `env` when a message arrives on `topic`, or only when
+/// its payload is `payload` if set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MqttTrigger {
+    /// Topic filter, which may use the `+` and `#` wildcards.
+    pub topic: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+    pub action: TriggerAction,
+    pub env: String,
+}
+
+/// Runs `action` on `env` at the minutes matching the `cron` expression,
+/// in local time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledAction {
+    pub cron: String,
+    pub action: TriggerAction,
+    pub env: String,
+}
+
+/// Runs `action` on `env` when the kernel reports a device event matching
+/// every condition that is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceTrigger {
+    #[serde(default)]
+    pub event: DeviceEvent,
+    /// Kernel subsystem, e.g. `usb`, or `drm` for monitors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subsystem: Option<String>,
+    /// `vendor:product` id of a USB device, as shown by `lsusb`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usb_id: Option<String>,
+    /// Properties the event must have, as shown by `udevadm monitor -k -p`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub properties: HashMap<String, String>,
+    pub action: TriggerAction,
+    pub env: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceEvent {
+    #[default]
+    Add,
+    Remove,
+    Change,
+}
+
+impl DeviceEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeviceEvent::Add => "add",
+            DeviceEvent::Remove => "remove",
+            DeviceEvent::Change => "change",
+        }
+    }
+}
+
+/// Environments `clovis daemon` stops or pauses once the session has been
+/// idle for `minutes`, and brings back when it is in use again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdleConfig {
+    pub minutes: u64,
+    #[serde(default)]
+    pub action: IdleAction,
+    pub environments: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdleAction {
+    /// Stop the apps, and launch them again on resume.
+    #[default]
+    Stop,
+    /// Suspend the apps, and let them carry on on resume.
+    Pause,
+}
+
+/// Runs `action` on `env` once NetworkManager is connected to the Wi-Fi
+/// network and the VPN that are set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssid: Option<String>,
+    /// Name of a VPN or WireGuard connection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vpn: Option<String>,
+    pub action: TriggerAction,
+    pub env: String,
+}
+
+/// Calendar `clovis daemon` reads to launch environments ahead of events.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CalendarConfig {
+    /// `http://` or `https://` URL of an ICS feed or CalDAV calendar, or
+    /// path of an ICS file.
+    pub url: String,
+    /// Query `url` as a CalDAV calendar collection rather than download it
+    /// as an ICS feed.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub caldav: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default = "default_calendar_refresh_minutes")]
+    pub refresh_minutes: u64,
+    #[serde(default)]
+    pub rules: Vec<CalendarRule>,
+}
+
+fn default_calendar_refresh_minutes() -> u64 {
+    15
+}
+
+/// Runs `action` on `env` `minutes_before` the start of the events whose
+/// summary contains `pattern`, ignoring case.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CalendarRule {
+    pub pattern: String,
+    #[serde(default = "default_calendar_minutes_before")]
+    pub minutes_before: u64,
+    #[serde(default = "default_calendar_action")]
+    pub action: TriggerAction,
+    pub env: String,
+}
+
+fn default_calendar_minutes_before() -> u64 {
+    5
+}
+
+fn default_calendar_action() -> TriggerAction {
+    TriggerAction::Launch
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerAction {
+    Launch,
+    Stop,
+    /// Stop the environments that have apps running, then launch.
+    Switch,
+}
+
+impl TriggerAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TriggerAction::Launch => "launch",
+            TriggerAction::Stop => "stop",
+            TriggerAction::Switch => "switch",
+        }
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Parses config file contents, upgrading older formats in memory. Returns
+/// the config along with the format version the file is written in.
+pub fn parse_config(contents: &str) -> io::Result<(Config, u32)> {
+    let mut value: Value = serde_yaml::from_str(contents).map_err(invalid_data)?;
+    let version = migrate(&mut value).map_err(invalid_data)?;
+    let config = serde_yaml::from_value(value).map_err(invalid_data)?;
+    Ok((config, version))
+}
+
+pub fn load_config(path: &Path) -> io::Result<Config> {
+    let contents = fs::read_to_string(path)?;
+    let (config, version) = parse_config(&contents).map_err(|e| {
+        error!("Failed to parse config file: {}", e);
+        e
+    })?;
+    if version < CONFIG_VERSION {
+        warn!(
+            "Config file uses format version {}, run `clovis migrate` to upgrade it to version {}",
+            version, CONFIG_VERSION
+        );
+    }
+    info!("Config loaded successfully");
+    Ok(config)
+}
+
+/// Writes `config` back to `path`. When the file already exists, only the
+/// parts that changed are rewritten so comments and formatting of the
+/// hand-written file survive; if that is not possible the whole file is
+/// regenerated. Files still in an older format are kept in that format.
+pub fn save_config(path: &Path, config: &Config) -> io::Result<()> {
+    let contents = match fs::read_to_string(path) {
+        Ok(original) => match update_in_place(&original, config) {
+            Some(updated) => updated,
+            None if document_version(&original) < CONFIG_VERSION => {
+                error!("Cannot save this change in the old config format");
+                return Err(invalid_data(
+                    "the config file uses an old format, run `clovis migrate` first",
+                ));
+            }
+            None => {
+                warn!("Could not edit config in place, rewriting it without comments");
+                serialize(config)?
+            }
+        },
+        Err(_) => serialize(config)?,
+    };
+    let mut file = File::create(path)?;
+    file.write_all(contents.as_bytes())?;
+    info!("Config saved successfully");
+    Ok(())
+}
+
+pub fn serialize(config: &Config) -> io::Result<String> {
+    serde_yaml::to_string(config).map_err(|e| {
+        error!("Failed to serialize config: {}", e);
+        invalid_data(e)
+    })
+}
+
+/// Applies the differences between the config stored in `original` and
+/// `config` as targeted edits. Returns `None` when an edit is not supported
+/// or the result would not parse back to exactly `config`.
+fn update_in_place(original: &str, config: &Config) -> Option<String> {
+    let (previous, version) = parse_config(original).ok()?;
+    let mut document = YamlDocument::parse(original);
+
+    for (name, env) in &config.environments {
+        let old = previous.environments.get(name);
+        if old == Some(env) {
+            continue;
+        }
+        let updated = if version < CONFIG_VERSION {
+            if !env.apps.iter().all(App::is_plain) {
+                return None;
+            }
+            let names: Vec<String> = env.apps.iter().map(|app| app.name.clone()).collect();
+            document.set_sequence(&["environments", name], &names)
+        } else if old.is_some_and(|old| same_except_apps(old, env)) {
+            document.set_sequence(&["environments", name, "apps"], &env.apps)
+        } else {
+            document.set_value(&["environments", name], env)
+        };
+        if !updated {
+            return None;
+        }
+    }
+    for name in previous.environments.keys() {
+        if !config.environments.contains_key(name) && !document.remove_key(&["environments", name])
+        {
+            return None;
+        }
+    }
+    let top_level = [
+        update_field(&mut document, "default", &previous.default, &config.default),
+        update_field(&mut document, "aliases", &previous.aliases, &config.aliases),
+        update_field(&mut document, "sync", &previous.sync, &config.sync),
+        update_field(
+            &mut document,
+            "appimage_dirs",
+            &previous.appimage_dirs,
+            &config.appimage_dirs,
+        ),
+        update_field(&mut document, "shell", &previous.shell, &config.shell),
+        update_field(
+            &mut document,
+            "terminal",
+            &previous.terminal,
+            &config.terminal,
+        ),
+        update_field(&mut document, "editor", &previous.editor, &config.editor),
+        update_field(
+            &mut document,
+            "systemd_scope",
+            &previous.systemd_scope,
+            &config.systemd_scope,
+        ),
+        update_field(
+            &mut document,
+            "clean_env",
+            &previous.clean_env,
+            &config.clean_env,
+        ),
+        update_field(&mut document, "mqtt", &previous.mqtt, &config.mqtt),
+        update_field(
+            &mut document,
+            "schedule",
+            &previous.schedule,
+            &config.schedule,
+        ),
+        update_field(
+            &mut document,
+            "device_triggers",
+            &previous.device_triggers,
+            &config.device_triggers,
+        ),
+        update_field(&mut document, "idle", &previous.idle, &config.idle),
+        update_field(
+            &mut document,
+            "network_rules",
+            &previous.network_rules,
+            &config.network_rules,
+        ),
+        update_field(
+            &mut document,
+            "calendar",
+            &previous.calendar,
+            &config.calendar,
+        ),
+    ];
+    if top_level.contains(&false) {
+        return None;
+    }
+
+    let updated = document.render();
+    let (reparsed, _) = parse_config(&updated).ok()?;
+    (reparsed == *config).then_some(updated)
+}
+
+/// Rewrites a top-level field when it changed, dropping it from the file
+/// when it went back to its (empty) default.
+fn update_field<T: Serialize + PartialEq + Default>(
+    document: &mut YamlDocument,
+    key: &str,
+    old: &T,
+    new: &T,
+) -> bool {
+    if old == new {
+        true
+    } else if *new == T::default() {
+        document.remove_key(&[key])
+    } else {
+        document.set_value(&[key], new)
+    }
+}
+
+fn same_except_apps(a: &Environment, b: &Environment) -> bool {
+    let mut a = a.clone();
+    a.apps.clone_from(&b.apps);
+    a == *b
+}
+
+/// Converts config file contents to the current format version, keeping
+/// comments outside of the converted blocks. Returns `None` when the
+/// contents are already up to date.
+pub fn migrate_contents(original: &str) -> io::Result<Option<String>> {
+    let (config, version) = parse_config(original)?;
+    if version == CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    let mut document = YamlDocument::parse(original);
+    let mut converted = if document.contains_key(&["version"]) {
+        document.set_value(&["version"], &CONFIG_VERSION)
+    } else {
+        document.insert_first("version", &CONFIG_VERSION)
+    };
+    for (name, env) in &config.environments {
+        converted &= document.set_value(&["environments", name], env);
+    }
+    let migrated = document.render();
+    match parse_config(&migrated) {
+        Ok((reparsed, _)) if converted && reparsed == config => Ok(Some(migrated)),
+        _ => {
+            warn!("Could not migrate config in place, rewriting it without comments");
+            serialize(&config).map(Some)
+        }
+    }
+}
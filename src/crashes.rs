@@ -0,0 +1,259 @@
+//! Apps that exited on their own with an error or were killed. `clovis
+//! daemon` becomes the subreaper of the apps it launches, so they end up
+//! its children and their exit status can be read with `waitpid`; each
+//! crash is notified with the end of the app's log and kept for `clovis
+//! history crashes`.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::history::format_timestamp;
+use crate::launch;
+use crate::launches;
+use crate::notifications;
+use crate::paths;
+
+/// How often the daemon looks for new or exited children.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How many crashes are kept.
+const MAX_CRASHES: usize = 200;
+/// How many lines of the app's log are kept with a crash.
+const LOG_LINES: usize = 10;
+/// How long after clovis kills an app its death is put down to clovis.
+const KILL_GRACE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Crash {
+    pub timestamp: i64,
+    /// The environment the app was last launched in, when it is known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    pub app: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// The signal that killed the app, when it did not exit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    /// The last lines the app wrote.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub log: Vec<String>,
+}
+
+impl Crash {
+    fn describe(&self) -> String {
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => format!("exited with code {}", code),
+            (None, Some(signal)) => format!("was killed by signal {}", signal),
+            (None, None) => "exited".to_string(),
+        }
+    }
+}
+
+pub fn crashes_path() -> PathBuf {
+    paths::state_dir().join("crashes.yaml")
+}
+
+pub fn load_crashes(path: &Path) -> io::Result<Vec<Crash>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    serde_yaml::from_str(&contents).map_err(|e| {
+        error!("Failed to parse crashes: {}", e);
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })
+}
+
+fn record_crash(crash: Crash) -> io::Result<()> {
+    let path = crashes_path();
+    let mut crashes = load_crashes(&path)?;
+    crashes.push(crash);
+    let excess = crashes.len().saturating_sub(MAX_CRASHES);
+    crashes.drain(..excess);
+    let contents = serde_yaml::to_string(&crashes).map_err(|e| {
+        error!("Failed to serialize crashes: {}", e);
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })?;
+    fs::create_dir_all(paths::state_dir())?;
+    fs::write(path, contents)
+}
+
+pub fn show_crashes(limit: usize) -> io::Result<()> {
+    let crashes = load_crashes(&crashes_path())?;
+    if crashes.is_empty() {
+        println!("No crashes recorded yet.");
+        return Ok(());
+    }
+    let start = crashes.len().saturating_sub(limit);
+    for crash in &crashes[start..] {
+        println!(
+            "{}  {:<15}  {} {}",
+            format_timestamp(crash.timestamp),
+            crash.env.as_deref().unwrap_or("-"),
+            crash.app,
+            crash.describe()
+        );
+        for line in &crash.log {
+            println!("    {}", line);
+        }
+    }
+    Ok(())
+}
+
+fn kill_marker(name: &str) -> PathBuf {
+    paths::runtime_dir()
+        .join("killed")
+        .join(launch::file_name(name))
+}
+
+/// Notes that clovis is about to kill the app named `name` because it did
+/// not stop, so that its death is not taken for a crash.
+pub fn note_kill(name: &str) {
+    let marker = kill_marker(name);
+    let noted = paths::create_runtime_dir()
+        .and_then(|_| fs::create_dir_all(paths::runtime_dir().join("killed")))
+        .and_then(|()| fs::write(&marker, ""));
+    if let Err(e) = noted {
+        warn!("Failed to note that {} is being killed: {}", name, e);
+    }
+}
+
+/// Whether clovis killed the app named `name` lately. Every process of the
+/// app is killed, so the note is kept until it is out of date.
+fn was_killed(name: &str) -> bool {
+    fs::metadata(kill_marker(name))
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified.elapsed().is_ok_and(|elapsed| elapsed < KILL_GRACE))
+}
+
+/// The last `LOG_LINES` lines of the log of the app named `name`.
+fn log_tail(name: &str) -> Vec<String> {
+    let log = fs::read_to_string(launch::log_path(name)).unwrap_or_default();
+    let lines: Vec<&str> = log.lines().collect();
+    lines[lines.len().saturating_sub(LOG_LINES)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// The processes whose parent is the daemon.
+fn children() -> Vec<u32> {
+    let own = std::process::id().to_string();
+    fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| {
+            let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+            // The parent follows the state, after the command name
+            let fields = stat.rsplit_once(')').map_or("", |(_, fields)| fields);
+            fields.split_whitespace().nth(1) == Some(own.as_str())
+        })
+        .collect()
+}
+
+/// Follows the apps the daemon launched until they exit.
+pub struct Watcher {
+    /// The app of each child, by pid.
+    apps: HashMap<u32, String>,
+}
+
+impl Watcher {
+    /// Makes the current process adopt the processes orphaned below it,
+    /// the apps it launches among them.
+    pub fn new() -> Watcher {
+        #[cfg(target_os = "linux")]
+        // SAFETY: prctl with PR_SET_CHILD_SUBREAPER only sets a flag
+        if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1) } != 0 {
+            warn!(
+                "Crashes will not be detected, cannot adopt the launched apps: {}",
+                io::Error::last_os_error()
+            );
+        }
+        #[cfg(not(target_os = "linux"))]
+        warn!("Crashes will not be detected, adopting the launched apps needs Linux");
+        Watcher {
+            apps: HashMap::new(),
+        }
+    }
+
+    /// Notes the apps of the children adopted since the last call, and
+    /// reaps the children that exited, reporting the apps that crashed.
+    pub fn check(&mut self) {
+        for pid in children() {
+            if let Entry::Vacant(entry) = self.apps.entry(pid) {
+                if let Some(app) = launch::app_of(pid) {
+                    entry.insert(app);
+                }
+            }
+        }
+        loop {
+            let mut status = 0;
+            // SAFETY: status is a valid pointer. The daemon runs no other
+            // child it waits for while this runs.
+            let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+            if pid <= 0 {
+                break;
+            }
+            let Some(app) = self.apps.remove(&(pid as u32)) else {
+                continue;
+            };
+            let (exit_code, signal) = if libc::WIFEXITED(status) {
+                (Some(libc::WEXITSTATUS(status)), None)
+            } else {
+                (None, Some(libc::WTERMSIG(status)))
+            };
+            // Exiting cleanly or on request is not a crash, nor being killed
+            // by clovis for not stopping, and neither is one process of an
+            // app exiting while others go on
+            let requested = matches!(signal, Some(libc::SIGTERM | libc::SIGINT | libc::SIGHUP))
+                || (signal == Some(libc::SIGKILL) && was_killed(&app));
+            if exit_code == Some(0) || requested || self.apps.values().any(|a| *a == app) {
+                info!("{} (pid {}) exited", app, pid);
+                continue;
+            }
+            report(Crash {
+                timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+                env: launches::launched_apps()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|(_, name)| *name == app)
+                    .map(|(env, _)| env),
+                log: log_tail(&app),
+                app,
+                exit_code,
+                signal,
+            });
+        }
+    }
+}
+
+fn report(crash: Crash) {
+    error!("{} {}", crash.app, crash.describe());
+    let mut body = match &crash.env {
+        Some(env) => format!("It was launched in '{}'.", env),
+        None => String::new(),
+    };
+    for line in crash.log.iter().skip(crash.log.len().saturating_sub(3)) {
+        body.push('\n');
+        body.push_str(line);
+    }
+    notifications::send(
+        &format!("{} {}", crash.app, crash.describe()),
+        body.trim_start(),
+    );
+    if let Err(e) = record_crash(crash) {
+        error!("Failed to record the crash: {}", e);
+    }
+}
@@ -0,0 +1,1188 @@
+//! `clovis daemon`: serves the commands of other clovis invocations over a
+//! Unix socket, one at a time, so they never race over the state files.
+//!
+//! A client sends its working directory, environment and arguments, as
+//! fields ended by NUL bytes, and closes its end for writing. The daemon
+//! runs the command in a forked child that has the directory and
+//! environment of the client and whose stdout and stderr are the
+//! connection, then sends a NUL byte and the exit code. The socket is in
+//! the runtime directory, which only the user can access.
+//!
+//! With `--dbus`, the daemon also owns `org.clovis.Launcher` on the session
+//! bus and signals the apps that started or stopped after each request.
+//! With `--http <port>`, it answers a small JSON API and Prometheus metrics
+//! on localhost, for home automation and stream decks, to clients sending
+//! the token kept in the state directory. When the config has an `mqtt:`
+//! section, it subscribes to the topics of its triggers to launch, stop or
+//! switch environments. The entries of the `schedule:` section are run at
+//! the minutes they match, and the `device_triggers:` when the kernel
+//! reports a matching device event. The environments of the `idle:` section
+//! are stopped or paused while the session is idle, and the
+//! `network_rules:` are run when NetworkManager joins their Wi-Fi network
+//! or VPN. The rules of the `calendar:` section are run ahead of the events
+//! they match. While an environment with `blocks:` runs, the programs it
+//! blocks are terminated.
+
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use time::OffsetDateTime;
+
+use crate::blocklist;
+use crate::calendar;
+use crate::config::{
+    CalendarConfig, CalendarRule, DeviceTrigger, IdleAction, IdleConfig, MqttConfig, NetworkRule,
+    ScheduledAction, TriggerAction,
+};
+use crate::crashes;
+use crate::dbus::{Connection, Message, Value};
+use crate::http;
+use crate::idle;
+use crate::json::Json;
+use crate::metrics;
+use crate::mqtt;
+use crate::network;
+use crate::paths;
+use crate::schedule::Cron;
+use crate::stats;
+#[cfg(target_os = "linux")]
+use crate::thresholds;
+use crate::uevent;
+
+/// How long a client of the socket may take to send its arguments.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest request accepted on the socket, in bytes.
+const MAX_REQUEST: usize = 1024 * 1024;
+/// Name of the socket in the runtime directory.
+const SOCKET_NAME: &str = "daemon.sock";
+const BUS_NAME: &str = "org.clovis.Launcher";
+const OBJECT_PATH: &str = "/org/clovis/Launcher";
+/// How long to wait before connecting again to an MQTT broker.
+const BROKER_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// How long a device trigger ignores events once run, as plugging a dock
+/// sends a burst of them.
+const DEVICE_TRIGGER_COOLDOWN: Duration = Duration::from_secs(10);
+/// How often logind is asked whether the session is idle.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// How often blocked programs are looked for.
+const BLOCKLIST_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.clovis.Launcher">
+    <method name="LaunchEnvironment">
+      <arg name="env" type="s" direction="in"/>
+      <arg name="success" type="b" direction="out"/>
+    </method>
+    <method name="StopEnvironment">
+      <arg name="env" type="s" direction="in"/>
+      <arg name="success" type="b" direction="out"/>
+    </method>
+    <method name="GetStatus">
+      <arg name="apps" type="a(ssb)" direction="out"/>
+    </method>
+    <signal name="AppStateChanged">
+      <arg name="env" type="s"/>
+      <arg name="app" type="s"/>
+      <arg name="state" type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+/// Whether an app of an environment is running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppState {
+    pub env: String,
+    pub app: String,
+    pub running: bool,
+}
+
+/// What the daemon serves besides its socket.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Own `org.clovis.Launcher` on the session bus.
+    pub dbus: bool,
+    /// Serve the HTTP API on this port of the loopback interface.
+    pub http_port: Option<u16>,
+    /// Subscribe to the topics of this broker and run its triggers.
+    pub mqtt: Option<MqttConfig>,
+    /// Launch counters reported on `/metrics`.
+    pub stats_path: PathBuf,
+    /// Actions to run at set times.
+    pub schedule: Vec<ScheduledAction>,
+    /// Actions to run on hardware events.
+    pub device_triggers: Vec<DeviceTrigger>,
+    /// Environments to stop or pause while the session is idle.
+    pub idle: Option<IdleConfig>,
+    /// Actions to run when joining or leaving a network.
+    pub network_rules: Vec<NetworkRule>,
+    /// Calendar to run actions ahead of the events of.
+    pub calendar: Option<CalendarConfig>,
+    /// Programs blocked while an environment runs, by environment.
+    pub blocks: Vec<(String, Vec<String>)>,
+    /// Config to watch the thresholds of the apps of, read again at every
+    /// check, when some app has any.
+    pub thresholds: Option<PathBuf>,
+    /// Names and aliases of the environments of the config, the only ones
+    /// the HTTP API and the bus launch or stop.
+    pub environments: Vec<String>,
+}
+
+/// Tags of the fields of a request.
+const DIR_FIELD: u8 = b'D';
+const VARIABLE_FIELD: u8 = b'E';
+const ARG_FIELD: u8 = b'A';
+
+/// The directory and environment a client runs its command with.
+#[derive(Default)]
+struct ClientContext {
+    dir: Option<PathBuf>,
+    variables: Vec<(OsString, OsString)>,
+}
+
+/// Runs the command in `args` through the daemon, printing its output.
+/// Returns the exit code of the command, or `None` when no daemon is
+/// listening.
+pub fn forward(args: impl IntoIterator<Item = OsString>) -> Option<i32> {
+    let dir = paths::runtime_dir();
+    if let Err(e) = paths::check_private_dir(&dir) {
+        if e.kind() != io::ErrorKind::NotFound {
+            warn!("Not using the clovis daemon: {}", e);
+        }
+        return None;
+    }
+    let mut stream = UnixStream::connect(dir.join(SOCKET_NAME)).ok()?;
+    let mut fields: Vec<Vec<u8>> = Vec::new();
+    if let Ok(cwd) = std::env::current_dir() {
+        fields.push([&[DIR_FIELD], cwd.as_os_str().as_bytes()].concat());
+    }
+    for (key, value) in std::env::vars_os() {
+        fields.push([&[VARIABLE_FIELD], key.as_bytes(), b"=", value.as_bytes()].concat());
+    }
+    for arg in args {
+        fields.push([&[ARG_FIELD], arg.as_bytes()].concat());
+    }
+    let request: Vec<u8> = fields
+        .into_iter()
+        .flat_map(|field| field.into_iter().chain([0]))
+        .collect();
+    stream.write_all(&request).ok()?;
+    stream.shutdown(std::net::Shutdown::Write).ok()?;
+
+    let mut response = Vec::new();
+    if let Err(e) = stream.read_to_end(&mut response) {
+        error!("Lost the connection to the clovis daemon: {}", e);
+        return Some(1);
+    }
+    let (output, code) = match response.iter().rposition(|b| *b == 0) {
+        Some(end) => (
+            &response[..end],
+            String::from_utf8_lossy(&response[end + 1..])
+                .trim()
+                .parse()
+                .unwrap_or(1),
+        ),
+        // The command died without reporting back
+        None => (&response[..], 1),
+    };
+    let _ = io::stdout().write_all(output);
+    Some(code)
+}
+
+/// Listens on the socket and runs every request with `handle`, which gets
+/// the arguments of the client and returns an exit code. `status` lists the
+/// apps of every environment, for D-Bus clients.
+pub fn serve(
+    handle: impl Fn(Vec<OsString>) -> i32,
+    status: impl Fn() -> Vec<AppState>,
+    options: &Options,
+) -> io::Result<()> {
+    let path = paths::create_runtime_dir()?.join(SOCKET_NAME);
+    if UnixStream::connect(&path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("a clovis daemon is already listening on {}", path.display()),
+        ));
+    }
+    // Left behind by a daemon that did not exit cleanly
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    println!("Listening on {}", path.display());
+    info!("Daemon listening on {}", path.display());
+
+    let mut bus = None;
+    if options.dbus {
+        let mut connection = Connection::session()?;
+        connection.request_name(BUS_NAME)?;
+        println!("Serving {} on the session bus", BUS_NAME);
+        info!("Daemon owns {} as {}", BUS_NAME, connection.unique_name);
+        bus = Some(connection);
+    }
+
+    let mut http = None;
+    if let Some(port) = options.http_port {
+        let token = http_token()?;
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        println!(
+            "HTTP API on http://127.0.0.1:{}, its token is in {}",
+            port,
+            http_token_path().display()
+        );
+        info!("Daemon serving HTTP on port {}", port);
+        http = Some((server, token));
+    }
+
+    let mut broker = None;
+    let mut broker_retry = Instant::now();
+
+    let schedule: Vec<(Cron, ScheduledAction)> = options
+        .schedule
+        .iter()
+        .filter_map(|entry| match Cron::parse(&entry.cron) {
+            Ok(cron) => Some((cron, entry.clone())),
+            Err(e) => {
+                error!("Ignoring the schedule '{}': {}", entry.cron, e);
+                None
+            }
+        })
+        .collect();
+    // Entries due when the daemon starts were run by the previous one
+    let mut last_minute = local_now().unix_timestamp() / 60;
+
+    let mut devices = None;
+    if !options.device_triggers.is_empty() {
+        match uevent::Monitor::open() {
+            Ok(monitor) => devices = Some(monitor),
+            Err(e) => error!(
+                "Device triggers are disabled, cannot watch device events: {}",
+                e
+            ),
+        }
+    }
+    let mut device_triggered: Vec<Option<Instant>> = vec![None; options.device_triggers.len()];
+
+    let mut idle_config = options.idle.as_ref();
+    let mut idle_checked: Option<Instant> = None;
+    // Environments stopped or paused while idle, once they were
+    let mut idled: Option<Vec<String>> = None;
+
+    let mut network_bus = None;
+    let mut network_matched = vec![false; options.network_rules.len()];
+    if !options.network_rules.is_empty() {
+        match watch_network_manager() {
+            Ok(connection) => {
+                network_bus = Some(connection);
+                // Rules already matching when the daemon starts apply too
+                check_network(
+                    &options.network_rules,
+                    &mut network_matched,
+                    &handle,
+                    &status,
+                );
+            }
+            Err(e) => error!(
+                "Network rules are disabled, cannot watch NetworkManager: {}",
+                e
+            ),
+        }
+    }
+
+    let mut calendar_events: Vec<calendar::Event> = Vec::new();
+    let mut calendar_fetched: Option<Instant> = None;
+    // Rules already run, by index, along with the event they ran for
+    let mut calendar_runs: Vec<(usize, calendar::Event)> = Vec::new();
+
+    let mut blocks_checked: Option<Instant> = None;
+    // Blocked processes asked to exit, killed when they are still running
+    let mut terminated: Vec<u32> = Vec::new();
+
+    let mut crash_watcher = crashes::Watcher::new();
+    let mut crashes_checked = Instant::now();
+
+    #[cfg(target_os = "linux")]
+    let mut monitor = thresholds::Monitor::new();
+    #[cfg(target_os = "linux")]
+    let mut thresholds_checked: Option<Instant> = None;
+    // What apps use is read from /proc
+    #[cfg(not(target_os = "linux"))]
+    if options.thresholds.is_some() {
+        warn!("App thresholds are ignored, measuring apps needs Linux");
+    }
+
+    loop {
+        // Wake up regularly to ping the broker or connect to it again, and
+        // at the start of every minute when something is scheduled
+        let mut timeout = options.mqtt.as_ref().map(|_| mqtt::KEEP_ALIVE / 2);
+        if idle_config.is_some() {
+            let next_check = idle_checked.map_or(Duration::ZERO, |t| {
+                IDLE_POLL_INTERVAL.saturating_sub(t.elapsed())
+            });
+            timeout = Some(timeout.map_or(next_check, |t| t.min(next_check)));
+        }
+        if !options.blocks.is_empty() {
+            let next_check = blocks_checked.map_or(Duration::ZERO, |t| {
+                BLOCKLIST_POLL_INTERVAL.saturating_sub(t.elapsed())
+            });
+            timeout = Some(timeout.map_or(next_check, |t| t.min(next_check)));
+        }
+        let next_check = crashes::POLL_INTERVAL.saturating_sub(crashes_checked.elapsed());
+        timeout = Some(timeout.map_or(next_check, |t| t.min(next_check)));
+        #[cfg(target_os = "linux")]
+        if options.thresholds.is_some() {
+            let next_check = thresholds_checked.map_or(Duration::ZERO, |t| {
+                thresholds::POLL_INTERVAL.saturating_sub(t.elapsed())
+            });
+            timeout = Some(timeout.map_or(next_check, |t| t.min(next_check)));
+        }
+        if !schedule.is_empty() {
+            let now = local_now();
+            let elapsed = u64::from(now.second()) * 1000 + u64::from(now.millisecond());
+            let next_minute = Duration::from_millis(60_000 - elapsed);
+            timeout = Some(timeout.map_or(next_minute, |t| t.min(next_minute)));
+        }
+        if let Some(config) = &options.calendar {
+            let mut next_check = calendar_fetched.map_or(Duration::ZERO, |t| {
+                calendar_refresh(config).saturating_sub(t.elapsed())
+            });
+            let due = pending_calendar_runs(config, &calendar_events, &calendar_runs, local_now())
+                .map(|(_, rule, event)| until_calendar_run(rule, event))
+                .min();
+            if let Some(due) = due {
+                next_check = next_check.min(due);
+            }
+            timeout = Some(timeout.map_or(next_check, |t| t.min(next_check)));
+        }
+
+        if let Some(config) = options.mqtt.as_ref().filter(|_| broker.is_none()) {
+            if Instant::now() >= broker_retry {
+                match mqtt::Client::connect(config) {
+                    Ok(client) => {
+                        info!("Daemon subscribed to the MQTT broker {}", config.broker);
+                        broker = Some(client);
+                    }
+                    Err(e) => {
+                        error!(
+                            "Failed to connect to the MQTT broker {}: {}",
+                            config.broker, e
+                        );
+                        broker_retry = Instant::now() + BROKER_RETRY_DELAY;
+                    }
+                }
+            }
+        }
+
+        let ready = wait_readable(
+            &[
+                Some(listener.as_raw_fd()),
+                bus.as_ref().map(Connection::as_raw_fd),
+                http.as_ref().map(|(server, _)| server.as_raw_fd()),
+                broker.as_ref().map(mqtt::Client::as_raw_fd),
+                devices.as_ref().map(uevent::Monitor::as_raw_fd),
+                network_bus.as_ref().map(Connection::as_raw_fd),
+            ],
+            timeout,
+        )?;
+
+        // Apps that change state are only worth tracking for D-Bus clients
+        let before = bus.as_ref().map(|_| status());
+        if ready[0] {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = serve_client(stream, &handle) {
+                        error!("Failed to serve a client: {}", e);
+                    }
+                }
+                Err(e) => error!("Failed to accept a client: {}", e),
+            }
+        }
+        if let (Some((server, token)), true) = (&http, ready[2]) {
+            match server.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = serve_http(&stream, token, options, &handle, &status) {
+                        error!("Failed to answer an HTTP request: {}", e);
+                    }
+                }
+                Err(e) => error!("Failed to accept an HTTP client: {}", e),
+            }
+        }
+        if let (Some(client), Some(config)) = (&mut broker, &options.mqtt) {
+            let received = if ready[3] { client.read() } else { Ok(None) };
+            match received.and_then(|message| client.keep_alive().map(|_| message)) {
+                Ok(Some(message)) => run_triggers(config, &message, &handle, &status),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Lost the MQTT broker {}: {}", config.broker, e);
+                    broker = None;
+                    broker_retry = Instant::now() + BROKER_RETRY_DELAY;
+                }
+            }
+        }
+        if !schedule.is_empty() {
+            let now = local_now();
+            let minute = now.unix_timestamp() / 60;
+            if minute != last_minute {
+                last_minute = minute;
+                run_schedule(&schedule, now, &handle, &status);
+            }
+        }
+        if let Some(config) = &options.calendar {
+            let now = local_now();
+            if calendar_fetched.is_none_or(|t| t.elapsed() >= calendar_refresh(config)) {
+                calendar_fetched = Some(Instant::now());
+                // Far enough to see the events due before the next fetch
+                let lookahead = config.rules.iter().map(|rule| rule.minutes_before).max();
+                let until = now
+                    + calendar_refresh(config)
+                    + Duration::from_secs(60 * (lookahead.unwrap_or(0) + 1));
+                match calendar::upcoming(config, now, until) {
+                    Ok(events) => calendar_events = events,
+                    Err(e) => error!("Failed to fetch the calendar {}: {}", config.url, e),
+                }
+            }
+            run_calendar(
+                config,
+                &calendar_events,
+                &mut calendar_runs,
+                now,
+                &handle,
+                &status,
+            );
+        }
+        if let Some(config) = idle_config {
+            if idle_checked.is_none_or(|t| t.elapsed() >= IDLE_POLL_INTERVAL) {
+                idle_checked = Some(Instant::now());
+                match idle::idle_time() {
+                    Ok(idle_time) => {
+                        check_idle(config, idle_time, &mut idled, &handle, &status);
+                    }
+                    Err(e) => {
+                        error!("Idle actions are disabled, cannot ask logind whether the session is idle: {}", e);
+                        idle_config = None;
+                    }
+                }
+            }
+        }
+        if !options.blocks.is_empty()
+            && blocks_checked.is_none_or(|t| t.elapsed() >= BLOCKLIST_POLL_INTERVAL)
+        {
+            blocks_checked = Some(Instant::now());
+            let states = status();
+            for (env, blocks) in &options.blocks {
+                if states
+                    .iter()
+                    .any(|state| state.env == *env && state.running)
+                {
+                    blocklist::enforce(env, blocks, &mut terminated);
+                }
+            }
+        }
+        // Right after a request too, to know the apps it launched before
+        // any of them exits
+        if ready[0] || crashes_checked.elapsed() >= crashes::POLL_INTERVAL {
+            crashes_checked = Instant::now();
+            crash_watcher.check();
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(config_path) = &options.thresholds {
+            if thresholds_checked.is_none_or(|t| t.elapsed() >= thresholds::POLL_INTERVAL) {
+                thresholds_checked = Some(Instant::now());
+                match crate::config::load_config(config_path) {
+                    Ok(config) => monitor.check(&config),
+                    Err(e) => error!("Failed to load the config to check thresholds: {}", e),
+                }
+            }
+        }
+        if let (Some(connection), true) = (&mut network_bus, ready[5]) {
+            match connection.read() {
+                // Whatever changed, the state is read again as a whole
+                Ok(_) => check_network(
+                    &options.network_rules,
+                    &mut network_matched,
+                    &handle,
+                    &status,
+                ),
+                Err(e) => {
+                    error!("Network rules are disabled, lost the system bus: {}", e);
+                    network_bus = None;
+                }
+            }
+        }
+        if let (Some(monitor), true) = (&devices, ready[4]) {
+            match monitor.read() {
+                Ok(Some(event)) => run_device_triggers(
+                    &options.device_triggers,
+                    &mut device_triggered,
+                    &event,
+                    &handle,
+                    &status,
+                ),
+                Ok(None) => {}
+                Err(e) => error!("Failed to read a device event: {}", e),
+            }
+        }
+        if let Some(connection) = &mut bus {
+            let mut result = Ok(());
+            if ready[1] {
+                result = connection.read().map(|message| {
+                    if !message.is_method_call() {
+                        return;
+                    }
+                    if let Err(e) = serve_call(
+                        connection,
+                        &message,
+                        &options.environments,
+                        &handle,
+                        &status,
+                    ) {
+                        error!("Failed to answer a D-Bus call: {}", e);
+                    }
+                });
+            }
+            if let (Ok(()), Some(before)) = (&result, before) {
+                result = signal_changes(connection, &before, &status());
+            }
+            if let Err(e) = result {
+                error!("D-Bus is disabled, lost the session bus: {}", e);
+                bus = None;
+            }
+        }
+    }
+}
+
+/// Runs the triggers matching a message received from the broker.
+fn run_triggers(
+    config: &MqttConfig,
+    message: &mqtt::Publish,
+    handle: &impl Fn(Vec<OsString>) -> i32,
+    status: &impl Fn() -> Vec<AppState>,
+) {
+    let triggers = config.triggers.iter().filter(|trigger| {
+        mqtt::topic_matches(&trigger.topic, &message.topic)
+            && trigger
+                .payload
+                .as_ref()
+                .is_none_or(|payload| *payload == message.payload)
+    });
+    for trigger in triggers {
+        info!(
+            "MQTT message on {}: {:?} {}",
+            message.topic, trigger.action, trigger.env
+        );
+        let cause = format!("the MQTT trigger on {}", trigger.topic);
+        run_action(trigger.action, &trigger.env, &cause, handle, status);
+    }
+}
+
+/// Runs the schedule entries due at the minute of `now`.
+fn run_schedule(
+    schedule: &[(Cron, ScheduledAction)],
+    now: OffsetDateTime,
+    handle: &impl Fn(Vec<OsString>) -> i32,
+    status: &impl Fn() -> Vec<AppState>,
+) {
+    for (cron, entry) in schedule {
+        if cron.matches(now) {
+            info!(
+                "Scheduled at '{}': {:?} {}",
+                entry.cron, entry.action, entry.env
+            );
+            let cause = format!("the schedule '{}'", entry.cron);
+            run_action(entry.action, &entry.env, &cause, handle, status);
+        }
+    }
+}
+
+/// Runs the device triggers matching `event`, except the ones that ran
+/// recently, whose last run is in `last_runs`.
+fn run_device_triggers(
+    triggers: &[DeviceTrigger],
+    last_runs: &mut [Option<Instant>],
+    event: &uevent::Uevent,
+    handle: &impl Fn(Vec<OsString>) -> i32,
+    status: &impl Fn() -> Vec<AppState>,
+) {
+    for (trigger, last_run) in triggers.iter().zip(last_runs) {
+        if !uevent::trigger_matches(trigger, event)
+            || last_run.is_some_and(|t| t.elapsed() < DEVICE_TRIGGER_COOLDOWN)
+        {
+            continue;
+        }
+        let kind = trigger.event.as_str();
+        info!(
+            "Device {} event: {:?} {}",
+            kind, trigger.action, trigger.env
+        );
+        let cause = format!("the device trigger on {} events", kind);
+        run_action(trigger.action, &trigger.env, &cause, handle, status);
+        *last_run = Some(Instant::now());
+    }
+}
+
+/// Stops or pauses the environments of `config` that have apps running once
+/// the session has been idle long enough, and brings them back when it is
+/// not idle anymore.
+fn check_idle(
+    config: &IdleConfig,
+    idle_time: Option<Duration>,
+    idled: &mut Option<Vec<String>>,
+    handle: &impl Fn(Vec<OsString>) -> i32,
+    status: &impl Fn() -> Vec<AppState>,
+) {
+    let (stop, resume) = match config.action {
+        IdleAction::Stop => ("stop", "launch"),
+        IdleAction::Pause => ("pause", "resume"),
+    };
+    match (idle_time, idled.as_ref()) {
+        (Some(idle_time), None) if idle_time >= Duration::from_secs(config.minutes * 60) => {
+            let states = status();
+            let running: Vec<String> = config
+                .environments
+                .iter()
+                .filter(|env| states.iter().any(|s| s.env == **env && s.running))
+                .cloned()
+                .collect();
+            info!("Session idle, running '{}' on {:?}", stop, running);
+            for env in &running {
+                match run_quietly(stop, env, handle) {
+                    Ok(true) => {}
+                    Ok(false) => warn!("Failed to {} {} while idle", stop, env),
+                    Err(e) => error!("Failed to {} {} while idle: {}", stop, env, e),
+                }
+            }
+            *idled = Some(running);
+        }
+        (None, Some(envs)) => {
+            info!("Session in use again, running '{}' on {:?}", resume, envs);
+            for env in envs {
+                match run_quietly(resume, env, handle) {
+                    Ok(true) => {}
+                    Ok(false) => warn!("Failed to {} {} after idling", resume, env),
+                    Err(e) => error!("Failed to {} {} after idling: {}", resume, env, e),
+                }
+            }
+            *idled = None;
+        }
+        _ => {}
+    }
+}
+
+/// Connects to the system bus to be told when NetworkManager changes its
+/// state or active connections.
+fn watch_network_manager() -> io::Result<Connection> {
+    let mut connection = Connection::system()?;
+    connection.add_match(
+        "type='signal',sender='org.freedesktop.NetworkManager',path='/org/freedesktop/NetworkManager'",
+    )?;
+    Ok(connection)
+}
+
+/// Runs the network rules that started matching since the last check, whose
+/// results are in `matched`.
+fn check_network(
+    rules: &[NetworkRule],
+    matched: &mut [bool],
+    handle: &impl Fn(Vec<OsString>) -> i32,
+    status: &impl Fn() -> Vec<AppState>,
+) {
+    let state = match network::current() {
+        Ok(state) => state,
+        Err(e) => {
+            error!("Failed to ask NetworkManager for its connections: {}", e);
+            return;
+        }
+    };
+    for (rule, was_matching) in rules.iter().zip(matched) {
+        let matching = network::rule_matches(rule, &state);
+        if matching && !*was_matching {
+            let network = network::describe(rule);
+            info!("Joined {}: {:?} {}", network, rule.action, rule.env);
+            let cause = format!("the network rule for '{}'", network);
+            run_action(rule.action, &rule.env, &cause, handle, status);
+        }
+        *was_matching = matching;
+    }
+}
+
+fn calendar_refresh(config: &CalendarConfig) -> Duration {
+    Duration::from_secs(config.refresh_minutes.max(1) * 60)
+}
+
+/// The rules of `config` that still have to run for the events of `events`
+/// that did not start yet, given the ones that ran already in `runs`.
+fn pending_calendar_runs<'a>(
+    config: &'a CalendarConfig,
+    events: &'a [calendar::Event],
+    runs: &'a [(usize, calendar::Event)],
+    now: OffsetDateTime,
+) -> impl Iterator<Item = (usize, &'a CalendarRule, &'a calendar::Event)> {
+    config
+        .rules
+        .iter()
+        .enumerate()
+        .flat_map(move |(i, rule)| events.iter().map(move |event| (i, rule, event)))
+        .filter(move |(i, rule, event)| {
+            event.start > now
+                && calendar::rule_matches(rule, event)
+                && !runs.iter().any(|(j, ran)| j == i && ran == *event)
+        })
+}
+
+/// How long until `rule` runs for `event`, zero when it is due.
+fn until_calendar_run(rule: &CalendarRule, event: &calendar::Event) -> Duration {
+    let run_at = event.start - Duration::from_secs(rule.minutes_before * 60);
+    (run_at - local_now()).try_into().unwrap_or(Duration::ZERO)
+}
+
+/// Runs the calendar rules due at `now`, remembering them in `runs`.
+fn run_calendar(
+    config: &CalendarConfig,
+    events: &[calendar::Event],
+    runs: &mut Vec<(usize, calendar::Event)>,
+    now: OffsetDateTime,
+    handle: &impl Fn(Vec<OsString>) -> i32,
+    status: &impl Fn() -> Vec<AppState>,
+) {
+    // Events that started can no longer be run for
+    runs.retain(|(_, event)| event.start > now);
+    let due: Vec<(usize, calendar::Event)> = pending_calendar_runs(config, events, runs, now)
+        .filter(|(_, rule, event)| until_calendar_run(rule, event).is_zero())
+        .map(|(i, _, event)| (i, event.clone()))
+        .collect();
+    for (i, event) in due {
+        let rule = &config.rules[i];
+        info!(
+            "Calendar event '{}' at {:02}:{:02}: {:?} {}",
+            event.summary,
+            event.start.hour(),
+            event.start.minute(),
+            rule.action,
+            rule.env
+        );
+        let cause = format!("the calendar rule for '{}'", rule.pattern);
+        run_action(rule.action, &rule.env, &cause, handle, status);
+        runs.push((i, event));
+    }
+}
+
+/// Runs an action of a trigger, logging failures along with their `cause`.
+fn run_action(
+    action: TriggerAction,
+    env: &str,
+    cause: &str,
+    handle: &impl Fn(Vec<OsString>) -> i32,
+    status: &impl Fn() -> Vec<AppState>,
+) {
+    let result = match action {
+        TriggerAction::Launch => run_quietly("launch", env, handle),
+        TriggerAction::Stop => run_quietly("stop", env, handle),
+        TriggerAction::Switch => {
+            let mut running: Vec<String> = status()
+                .into_iter()
+                .filter(|state| state.running && state.env != env)
+                .map(|state| state.env)
+                .collect();
+            running.dedup();
+            for env in running {
+                if let Err(e) = run_quietly("stop", &env, handle) {
+                    error!("Failed to stop {}: {}", env, e);
+                }
+            }
+            run_quietly("launch", env, handle)
+        }
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => warn!("{} failed to {:?} {}", cause, action, env),
+        Err(e) => error!("Failed to run {}: {}", cause, e),
+    }
+}
+
+/// Schedules are in local time, or UTC when the offset is unknown.
+fn local_now() -> OffsetDateTime {
+    OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc())
+}
+
+/// Waits until one of `fds` can be read or `timeout` has passed, and tells
+/// which ones can.
+fn wait_readable(fds: &[Option<RawFd>], timeout: Option<Duration>) -> io::Result<Vec<bool>> {
+    let mut polled: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|fd| libc::pollfd {
+            // Negative descriptors are skipped by poll
+            fd: fd.unwrap_or(-1),
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    loop {
+        let timeout = timeout.map_or(-1, |t| t.as_millis() as libc::c_int);
+        // SAFETY: polled is a valid array of polled.len() entries
+        if unsafe { libc::poll(polled.as_mut_ptr(), polled.len() as libc::nfds_t, timeout) } >= 0 {
+            return Ok(polled.iter().map(|fd| fd.revents != 0).collect());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+fn http_token_path() -> PathBuf {
+    paths::state_dir().join("http-token")
+}
+
+/// The token HTTP clients must send, created on first use and only
+/// readable by the user.
+fn http_token() -> io::Result<String> {
+    let path = http_token_path();
+    if let Ok(token) = fs::read_to_string(&path) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    let mut bytes = [0u8; 16];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    fs::create_dir_all(paths::state_dir())?;
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)?
+        .write_all(format!("{}\n", token).as_bytes())?;
+    Ok(token)
+}
+
+/// Compares an HTTP token with the expected one in constant time, so that
+/// how long it takes tells nothing about how much of it is right.
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Answers a request of the HTTP API:
+///
+/// - `GET /environments`: the environments and their apps
+/// - `GET /status`: whether each app is running
+/// - `POST /launch/<env>` and `POST /stop/<env>`: launch or stop an
+///   environment
+/// - `GET /metrics`: metrics for Prometheus
+fn serve_http(
+    stream: &TcpStream,
+    token: &str,
+    options: &Options,
+    handle: &impl Fn(Vec<OsString>) -> i32,
+    status: &impl Fn() -> Vec<AppState>,
+) -> io::Result<()> {
+    let request = match http::read_request(stream) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = Json::object([("error", Json::string(e.to_string()))]);
+            return http::respond(stream, 400, &error.to_string());
+        }
+    };
+    let reply = |code: u16, body: Json| http::respond(stream, code, &body.to_string());
+    let error = |code: u16, message: &str| {
+        http::respond(
+            stream,
+            code,
+            &Json::object([("error", Json::string(message))]).to_string(),
+        )
+    };
+    if !request
+        .bearer_token()
+        .is_some_and(|given| same_token(given, token))
+    {
+        warn!(
+            "Rejected an HTTP request without a valid token: {} {}",
+            request.method, request.path
+        );
+        return error(401, "missing or invalid token");
+    }
+    info!("HTTP request: {} {}", request.method, request.path);
+
+    let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+    let command = match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["environments"]) => {
+            let mut environments: Vec<(String, Vec<String>)> = Vec::new();
+            for state in status() {
+                match environments.last_mut() {
+                    Some((env, apps)) if *env == state.env => apps.push(state.app),
+                    _ => environments.push((state.env, vec![state.app])),
+                }
+            }
+            let environments = environments.into_iter().map(|(env, apps)| {
+                Json::object([("name", Json::string(env)), ("apps", Json::strings(apps))])
+            });
+            return reply(200, Json::Array(environments.collect()));
+        }
+        ("GET", ["status"]) => {
+            let apps = status().into_iter().map(|state| {
+                Json::object([
+                    ("env", Json::string(state.env)),
+                    ("app", Json::string(state.app)),
+                    ("running", Json::from(state.running)),
+                ])
+            });
+            return reply(200, Json::Array(apps.collect()));
+        }
+        ("GET", ["metrics"]) => {
+            let stats = stats::load_stats(&options.stats_path).unwrap_or_default();
+            let body = metrics::render(&status(), &stats);
+            return http::respond_with(stream, 200, metrics::CONTENT_TYPE, &body);
+        }
+        ("POST", ["launch" | "stop", env]) if !options.environments.iter().any(|e| e == *env) => {
+            return error(404, "unknown environment");
+        }
+        ("POST", ["launch", env]) => ("launch", *env),
+        ("POST", ["stop", env]) => ("stop", *env),
+        (_, ["environments" | "status" | "metrics"]) | (_, ["launch" | "stop", _]) => {
+            return error(405, "method not allowed");
+        }
+        _ => return error(404, "not found"),
+    };
+
+    let (command, env) = command;
+    let success = run_quietly(command, env, handle)?;
+    reply(
+        200,
+        Json::object([("env", Json::string(env)), ("success", Json::from(success))]),
+    )
+}
+
+/// Runs `command` (`launch`, `stop`, ...) on `env` for a client that cannot
+/// see the output nor answer prompts, and tells whether it succeeded.
+/// `env` comes after `--`, so it is never read as an option.
+fn run_quietly(
+    command: &str,
+    env: &str,
+    handle: &impl Fn(Vec<OsString>) -> i32,
+) -> io::Result<bool> {
+    let mut args = vec![OsString::from(command)];
+    if command == "launch" {
+        args.push(OsString::from("--yes"));
+    }
+    args.extend([OsString::from("--"), OsString::from(env)]);
+    let output = fs::OpenOptions::new().write(true).open("/dev/null")?;
+    let code = run_forked(
+        args,
+        &ClientContext::default(),
+        output.as_raw_fd(),
+        libc::STDERR_FILENO,
+        handle,
+    )?;
+    Ok(code == 0)
+}
+
+/// Answers a method call on the bus.
+fn serve_call(
+    connection: &mut Connection,
+    call: &Message,
+    environments: &[String],
+    handle: &impl Fn(Vec<OsString>) -> i32,
+    status: &impl Fn() -> Vec<AppState>,
+) -> io::Result<()> {
+    let member = call.member.as_deref().unwrap_or_default();
+    info!("D-Bus call: {} {:?}", member, call.args);
+    let command = match (call.interface.as_deref(), member) {
+        (Some("org.freedesktop.DBus.Introspectable") | None, "Introspect") => {
+            return connection.reply(call, &[Value::Str(INTROSPECTION.to_string())]);
+        }
+        (Some(BUS_NAME) | None, "GetStatus") => {
+            let apps = status()
+                .into_iter()
+                .map(|s| vec![Value::Str(s.env), Value::Str(s.app), Value::Bool(s.running)])
+                .collect();
+            return connection.reply(call, &[Value::Structs("ssb".to_string(), apps)]);
+        }
+        (Some(BUS_NAME) | None, "LaunchEnvironment") => "launch",
+        (Some(BUS_NAME) | None, "StopEnvironment") => "stop",
+        _ => {
+            return connection.reply_error(
+                call,
+                "org.freedesktop.DBus.Error.UnknownMethod",
+                &format!("No method {} on {}", member, OBJECT_PATH),
+            );
+        }
+    };
+    let Some(env) = call.args.first().filter(|env| !env.is_empty()) else {
+        return connection.reply_error(
+            call,
+            "org.freedesktop.DBus.Error.InvalidArgs",
+            "Expected the name of an environment",
+        );
+    };
+    if !environments.contains(env) {
+        return connection.reply_error(
+            call,
+            "org.freedesktop.DBus.Error.InvalidArgs",
+            &format!("No environment named {}", env),
+        );
+    }
+    let success = run_quietly(command, env, handle)?;
+    connection.reply(call, &[Value::Bool(success)])
+}
+
+/// Emits `AppStateChanged` for every app whose state differs between
+/// `before` and `after`.
+fn signal_changes(
+    connection: &mut Connection,
+    before: &[AppState],
+    after: &[AppState],
+) -> io::Result<()> {
+    for app in after {
+        let was_running = before
+            .iter()
+            .find(|b| b.env == app.env && b.app == app.app)
+            .is_some_and(|b| b.running);
+        if was_running == app.running {
+            continue;
+        }
+        let state = if app.running { "running" } else { "stopped" };
+        if let Err(e) = connection.emit(
+            OBJECT_PATH,
+            BUS_NAME,
+            "AppStateChanged",
+            &[
+                Value::Str(app.env.clone()),
+                Value::Str(app.app.clone()),
+                Value::Str(state.to_string()),
+            ],
+        ) {
+            warn!("Failed to signal that {} is {}: {}", app.app, state, e);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn serve_client(mut stream: UnixStream, handle: &impl Fn(Vec<OsString>) -> i32) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = Vec::new();
+    (&mut stream)
+        .take(MAX_REQUEST as u64 + 1)
+        .read_to_end(&mut request)?;
+    if request.len() > MAX_REQUEST {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request too large",
+        ));
+    }
+    let mut args: Vec<OsString> = Vec::new();
+    let mut context = ClientContext::default();
+    for field in request.split(|b| *b == 0) {
+        let Some((tag, value)) = field.split_first() else {
+            continue;
+        };
+        let text = || OsStr::from_bytes(value).to_os_string();
+        match *tag {
+            DIR_FIELD => context.dir = Some(PathBuf::from(text())),
+            VARIABLE_FIELD => {
+                if let Some(i) = value.iter().position(|b| *b == b'=') {
+                    context.variables.push((
+                        OsStr::from_bytes(&value[..i]).to_os_string(),
+                        OsStr::from_bytes(&value[i + 1..]).to_os_string(),
+                    ));
+                }
+            }
+            ARG_FIELD => args.push(text()),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unknown field in request",
+                ))
+            }
+        }
+    }
+    // Probes of a daemon starting up send nothing
+    if args.is_empty() {
+        return Ok(());
+    }
+    info!("Serving: {:?}", args);
+
+    let code = run_forked(
+        args,
+        &context,
+        stream.as_raw_fd(),
+        stream.as_raw_fd(),
+        handle,
+    )?;
+    stream.write_all(format!("\0{}", code).as_bytes())
+}
+
+/// Runs `handle` in a child process whose stdout is `output` and stderr
+/// `errors`, in the directory and environment of `context` when it has
+/// them, and returns its exit code.
+fn run_forked(
+    args: Vec<OsString>,
+    context: &ClientContext,
+    output: RawFd,
+    errors: RawFd,
+    handle: &impl Fn(Vec<OsString>) -> i32,
+) -> io::Result<i32> {
+    io::stdout().flush()?;
+    // SAFETY: the daemon is single-threaded, so the child can keep running
+    // Rust code after the fork
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if pid == 0 {
+        // The child must not return into the accept loop, whatever happens
+        if let Ok(null) = fs::File::open("/dev/null") {
+            // SAFETY: the descriptor is open for the duration of the call
+            unsafe { libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO) };
+        }
+        // SAFETY: the descriptor is open for the duration of the call
+        unsafe {
+            libc::dup2(output, libc::STDOUT_FILENO);
+            libc::dup2(errors, libc::STDERR_FILENO);
+        }
+        // Apps launched for the client go to its display, with its PATH
+        if !context.variables.is_empty() {
+            for (key, _) in std::env::vars_os() {
+                std::env::remove_var(key);
+            }
+            for (key, value) in &context.variables {
+                std::env::set_var(key, value);
+            }
+        }
+        if let Some(dir) = &context.dir {
+            if let Err(e) = std::env::set_current_dir(dir) {
+                warn!("Failed to enter {}: {}", dir.display(), e);
+            }
+        }
+        let code = handle(args);
+        let _ = io::stdout().flush();
+        std::process::exit(code);
+    }
+
+    let mut status = 0;
+    // SAFETY: pid is a child of this process
+    unsafe { libc::waitpid(pid, &mut status, 0) };
+    Ok(if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else {
+        1
+    })
+}
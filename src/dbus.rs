@@ -0,0 +1,529 @@
+//! Just enough of the D-Bus wire protocol to own a name on the session bus,
+//! answer method calls with string arguments, emit signals, watch the
+//! signals of system services and call their methods. Messages are
+//! always written little-endian; only little-endian ones are read.
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+
+/// Do not wait for replies to our signals and errors.
+const NO_REPLY_EXPECTED: u8 = 1;
+
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+const SYSTEM_BUS_ADDRESS: &str = "unix:path=/run/dbus/system_bus_socket";
+
+/// A value of a message body. Only the types clovis sends are supported.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    U32(u32),
+    Str(String),
+    /// An array of structs whose fields all have the given signature,
+    /// e.g. `ssb`.
+    Structs(String, Vec<Vec<Value>>),
+}
+
+impl Value {
+    fn signature(&self) -> String {
+        match self {
+            Value::Bool(_) => "b".to_string(),
+            Value::U32(_) => "u".to_string(),
+            Value::Str(_) => "s".to_string(),
+            Value::Structs(fields, _) => format!("a({})", fields),
+        }
+    }
+}
+
+/// A message received from the bus.
+#[derive(Debug, Default)]
+pub struct Message {
+    pub kind: u8,
+    pub serial: u32,
+    pub path: Option<String>,
+    pub interface: Option<String>,
+    pub member: Option<String>,
+    pub sender: Option<String>,
+    pub reply_serial: Option<u32>,
+    /// String arguments of the body; other arguments end the list.
+    pub args: Vec<String>,
+    /// The value of a body made of a single variant, as returned by
+    /// `org.freedesktop.DBus.Properties.Get`, when it is a boolean, an
+    /// integer or a string.
+    pub variant: Option<Value>,
+}
+
+impl Message {
+    pub fn is_method_call(&self) -> bool {
+        self.kind == METHOD_CALL
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, n: usize) {
+        while !self.buf.len().is_multiple_of(n) {
+            self.buf.push(0);
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, value: &str) {
+        self.u8(value.len() as u8);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Bool(b) => self.u32(u32::from(*b)),
+            Value::U32(n) => self.u32(*n),
+            Value::Str(s) => self.str(s),
+            Value::Structs(_, items) => {
+                self.u32(0);
+                let length_at = self.buf.len() - 4;
+                // The length excludes the padding before the first element
+                self.align(8);
+                let start = self.buf.len();
+                for fields in items {
+                    self.align(8);
+                    for field in fields {
+                        self.value(field);
+                    }
+                }
+                let length = (self.buf.len() - start) as u32;
+                self.buf[length_at..length_at + 4].copy_from_slice(&length.to_le_bytes());
+            }
+        }
+    }
+
+    /// A header field: a struct of the field code and a variant.
+    fn field(&mut self, code: u8, signature: &str, write: impl FnOnce(&mut Writer)) {
+        self.align(8);
+        self.u8(code);
+        self.signature(signature);
+        write(self);
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn align(&mut self, n: usize) {
+        self.pos = self.pos.div_ceil(n) * n;
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        let value = *self.buf.get(self.pos)?;
+        self.pos += 1;
+        Some(value)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.align(4);
+        let bytes = self.buf.get(self.pos..self.pos + 4)?;
+        self.pos += 4;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<String> {
+        let bytes = self.buf.get(self.pos..self.pos + len)?;
+        // Skip the terminating NUL as well
+        self.pos += len + 1;
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    fn signature(&mut self) -> Option<String> {
+        let len = usize::from(self.u8()?);
+        self.bytes(len)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Parses a message whose fixed header and header fields are in `header`
+/// and whose body is in `body`.
+fn parse(header: &[u8], body: &[u8]) -> io::Result<Message> {
+    let mut reader = Reader {
+        buf: header,
+        pos: 12,
+    };
+    let mut message = Message {
+        kind: header[1],
+        serial: u32::from_le_bytes(header[8..12].try_into().unwrap()),
+        ..Message::default()
+    };
+    let fields_end = 16 + reader.u32().ok_or_else(|| invalid("truncated header"))? as usize;
+    let mut signature = String::new();
+    while reader.pos < fields_end {
+        reader.align(8);
+        let (Some(code), Some(kind)) = (reader.u8(), reader.signature()) else {
+            return Err(invalid("truncated header field"));
+        };
+        match kind.as_str() {
+            "s" | "o" => {
+                let value = reader.str();
+                match code {
+                    FIELD_PATH => message.path = value,
+                    FIELD_INTERFACE => message.interface = value,
+                    FIELD_MEMBER => message.member = value,
+                    FIELD_SENDER => message.sender = value,
+                    _ => {}
+                }
+            }
+            "g" => {
+                let value = reader.signature();
+                if code == FIELD_SIGNATURE {
+                    signature = value.unwrap_or_default();
+                }
+            }
+            "u" => {
+                let value = reader.u32();
+                if code == FIELD_REPLY_SERIAL {
+                    message.reply_serial = value;
+                }
+            }
+            _ => return Err(invalid("unsupported header field")),
+        }
+    }
+
+    let mut reader = Reader { buf: body, pos: 0 };
+    if signature == "v" {
+        message.variant = match reader.signature().as_deref() {
+            Some("b") => reader.u32().map(|value| Value::Bool(value != 0)),
+            Some("u") => reader.u32().map(Value::U32),
+            Some("s" | "o") => reader.str().map(Value::Str),
+            _ => None,
+        };
+    }
+    for kind in signature.chars() {
+        if kind != 's' {
+            break;
+        }
+        match reader.str() {
+            Some(arg) => message.args.push(arg),
+            None => break,
+        }
+    }
+    Ok(message)
+}
+
+pub struct Connection {
+    stream: UnixStream,
+    serial: u32,
+    pub unique_name: String,
+}
+
+/// Connects to the socket of a bus address such as
+/// `unix:path=/run/user/1000/bus`.
+fn connect_address(address: &str) -> io::Result<UnixStream> {
+    for candidate in address.split(';') {
+        let Some(params) = candidate.strip_prefix("unix:") else {
+            continue;
+        };
+        for param in params.split(',') {
+            if let Some(path) = param.strip_prefix("path=") {
+                return UnixStream::connect(path);
+            }
+            // Abstract sockets only exist on Linux
+            #[cfg(target_os = "linux")]
+            if let Some(name) = param.strip_prefix("abstract=") {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                return UnixStream::connect_addr(&addr);
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("unsupported bus address '{}'", address),
+    ))
+}
+
+impl Connection {
+    /// Connects and authenticates to the session bus.
+    pub fn session() -> io::Result<Connection> {
+        let address = std::env::var("DBUS_SESSION_BUS_ADDRESS").map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "DBUS_SESSION_BUS_ADDRESS is not set",
+            )
+        })?;
+        Connection::open(&address)
+    }
+
+    /// Connects and authenticates to the system bus.
+    pub fn system() -> io::Result<Connection> {
+        let address = std::env::var("DBUS_SYSTEM_BUS_ADDRESS")
+            .unwrap_or_else(|_| SYSTEM_BUS_ADDRESS.to_string());
+        Connection::open(&address)
+    }
+
+    fn open(address: &str) -> io::Result<Connection> {
+        let mut stream = connect_address(address)?;
+
+        // SAFETY: getuid cannot fail
+        let uid = unsafe { libc::getuid() }.to_string();
+        let hex_uid: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+        stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex_uid).as_bytes())?;
+        let mut line = Vec::new();
+        let mut byte = [0u8];
+        while !line.ends_with(b"\r\n") {
+            stream.read_exact(&mut byte)?;
+            line.push(byte[0]);
+        }
+        if !line.starts_with(b"OK ") {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the bus rejected the authentication",
+            ));
+        }
+        stream.write_all(b"BEGIN\r\n")?;
+
+        let mut connection = Connection {
+            stream,
+            serial: 0,
+            unique_name: String::new(),
+        };
+        let serial = connection.call_bus("Hello", &[])?;
+        let reply = connection.wait_reply(serial)?;
+        connection.unique_name = reply.args.first().cloned().unwrap_or_default();
+        Ok(connection)
+    }
+
+    pub fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        use std::os::fd::AsRawFd;
+        self.stream.as_raw_fd()
+    }
+
+    /// Asks the bus to send the messages matching `rule`, e.g.
+    /// `type='signal',sender='org.freedesktop.NetworkManager'`.
+    pub fn add_match(&mut self, rule: &str) -> io::Result<()> {
+        let serial = self.call_bus("AddMatch", &[Value::Str(rule.to_string())])?;
+        self.wait_reply(serial)?;
+        Ok(())
+    }
+
+    /// Asks for a well-known name, failing when another connection has it.
+    pub fn request_name(&mut self, name: &str) -> io::Result<()> {
+        // DBUS_NAME_FLAG_DO_NOT_QUEUE
+        let serial = self.call_bus(
+            "RequestName",
+            &[Value::Str(name.to_string()), Value::U32(4)],
+        )?;
+        self.wait_reply(serial)?;
+        // The reply code is not decoded: owning the name is checked by
+        // asking who owns it instead
+        let serial = self.call_bus("GetNameOwner", &[Value::Str(name.to_string())])?;
+        let owner = self.wait_reply(serial)?;
+        if owner.args.first() != Some(&self.unique_name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is already owned on the session bus", name),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Calls `member` of `interface` on the object `path` of `destination`,
+    /// and waits for its reply.
+    pub fn call(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[Value],
+    ) -> io::Result<Message> {
+        let serial = self.send(
+            METHOD_CALL,
+            0,
+            &[
+                (FIELD_PATH, "o", path),
+                (FIELD_INTERFACE, "s", interface),
+                (FIELD_MEMBER, "s", member),
+                (FIELD_DESTINATION, "s", destination),
+            ],
+            None,
+            args,
+        )?;
+        self.wait_reply(serial)
+    }
+
+    fn call_bus(&mut self, member: &str, args: &[Value]) -> io::Result<u32> {
+        self.send(
+            METHOD_CALL,
+            0,
+            &[
+                (FIELD_PATH, "o", "/org/freedesktop/DBus"),
+                (FIELD_INTERFACE, "s", "org.freedesktop.DBus"),
+                (FIELD_MEMBER, "s", member),
+                (FIELD_DESTINATION, "s", "org.freedesktop.DBus"),
+            ],
+            None,
+            args,
+        )
+    }
+
+    fn wait_reply(&mut self, serial: u32) -> io::Result<Message> {
+        loop {
+            let message = self.read()?;
+            if message.reply_serial == Some(serial) {
+                if message.kind == ERROR {
+                    return Err(io::Error::other(
+                        message.args.first().cloned().unwrap_or_default(),
+                    ));
+                }
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Reads the next message, blocking until one arrives.
+    pub fn read(&mut self) -> io::Result<Message> {
+        let mut header = vec![0u8; 16];
+        self.stream.read_exact(&mut header)?;
+        if header[0] != b'l' {
+            return Err(invalid("big-endian messages are not supported"));
+        }
+        let body_len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        let fields_len = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+        let header_len = (16 + fields_len).div_ceil(8) * 8;
+        header.resize(header_len, 0);
+        self.stream.read_exact(&mut header[16..])?;
+        let mut body = vec![0u8; body_len];
+        self.stream.read_exact(&mut body)?;
+        parse(&header, &body)
+    }
+
+    fn send(
+        &mut self,
+        kind: u8,
+        flags: u8,
+        fields: &[(u8, &str, &str)],
+        reply_serial: Option<u32>,
+        args: &[Value],
+    ) -> io::Result<u32> {
+        self.serial += 1;
+        let mut body = Writer::default();
+        for arg in args {
+            body.value(arg);
+        }
+        let signature: String = args.iter().map(Value::signature).collect();
+
+        let mut message = Writer::default();
+        message.u8(b'l');
+        message.u8(kind);
+        message.u8(flags);
+        message.u8(1);
+        message.u32(body.buf.len() as u32);
+        message.u32(self.serial);
+        message.u32(0);
+        let fields_start = message.buf.len();
+        for (code, kind, value) in fields {
+            message.field(*code, kind, |w| w.str(value));
+        }
+        if let Some(reply_serial) = reply_serial {
+            message.field(FIELD_REPLY_SERIAL, "u", |w| w.u32(reply_serial));
+        }
+        if !signature.is_empty() {
+            message.field(FIELD_SIGNATURE, "g", |w| w.signature(&signature));
+        }
+        let fields_len = (message.buf.len() - fields_start) as u32;
+        message.buf[12..16].copy_from_slice(&fields_len.to_le_bytes());
+        message.align(8);
+        message.buf.extend_from_slice(&body.buf);
+        self.stream.write_all(&message.buf)?;
+        Ok(self.serial)
+    }
+
+    /// Replies to a method call with `args`.
+    pub fn reply(&mut self, call: &Message, args: &[Value]) -> io::Result<()> {
+        let destination = call.sender.clone().unwrap_or_default();
+        self.send(
+            METHOD_RETURN,
+            NO_REPLY_EXPECTED,
+            &[(FIELD_DESTINATION, "s", &destination)],
+            Some(call.serial),
+            args,
+        )?;
+        Ok(())
+    }
+
+    /// Replies to a method call with the error `name`.
+    pub fn reply_error(&mut self, call: &Message, name: &str, text: &str) -> io::Result<()> {
+        let destination = call.sender.clone().unwrap_or_default();
+        self.send(
+            ERROR,
+            NO_REPLY_EXPECTED,
+            &[
+                (FIELD_ERROR_NAME, "s", name),
+                (FIELD_DESTINATION, "s", &destination),
+            ],
+            Some(call.serial),
+            &[Value::Str(text.to_string())],
+        )?;
+        Ok(())
+    }
+
+    pub fn emit(
+        &mut self,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[Value],
+    ) -> io::Result<()> {
+        self.send(
+            SIGNAL,
+            NO_REPLY_EXPECTED,
+            &[
+                (FIELD_PATH, "o", path),
+                (FIELD_INTERFACE, "s", interface),
+                (FIELD_MEMBER, "s", member),
+            ],
+            None,
+            args,
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::paths;
+
+/// A parsed freedesktop `.desktop` file. Only the `[Desktop Entry]` group and
+/// the `[Desktop Action <name>]` ones are kept; localized keys (`Name[fr]`)
+/// are stored verbatim alongside the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DesktopEntry {
+    pub id: String,
+    pub fields: HashMap<String, String>,
+    pub actions: HashMap<String, HashMap<String, String>>,
+}
+
+impl DesktopEntry {
+    pub fn parse(path: &Path) -> Option<DesktopEntry> {
+        let contents = fs::read_to_string(path).ok()?;
+        let id = path.file_name()?.to_str()?.to_string();
+
+        let mut fields = HashMap::new();
+        let mut actions: HashMap<String, HashMap<String, String>> = HashMap::new();
+        let mut group: Option<&mut HashMap<String, String>> = None;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                group = if line == "[Desktop Entry]" {
+                    Some(&mut fields)
+                } else if let Some(action) = line
+                    .strip_prefix("[Desktop Action ")
+                    .and_then(|rest| rest.strip_suffix(']'))
+                {
+                    Some(actions.entry(action.to_string()).or_default())
+                } else {
+                    None
+                };
+                continue;
+            }
+            let Some(group) = group.as_mut() else {
+                continue;
+            };
+            if let Some((key, value)) = line.split_once('=') {
+                group
+                    .entry(key.trim().to_string())
+                    .or_insert_with(|| value.trim().to_string());
+            }
+        }
+
+        Some(DesktopEntry {
+            id,
+            fields,
+            actions,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(String::as_str)
+    }
+
+    pub fn is_true(&self, key: &str) -> bool {
+        self.get(key) == Some("true")
+    }
+
+    pub fn name(&self) -> &str {
+        self.get("Name").unwrap_or(&self.id)
+    }
+
+    pub fn exec(&self) -> &str {
+        self.get("Exec").unwrap_or("")
+    }
+
+    /// Splits `Exec` into program and arguments.
+    pub fn command_line(&self) -> Vec<String> {
+        split_exec(self.exec())
+    }
+
+    /// Program and arguments of the `Exec` of an action, if the entry
+    /// lists the action and it has one.
+    pub fn action_command_line(&self, action: &str) -> Option<Vec<String>> {
+        let listed = self
+            .get("Actions")
+            .is_some_and(|actions| actions.split(';').any(|a| a == action));
+        if !listed {
+            return None;
+        }
+        let exec = self.actions.get(action)?.get("Exec")?;
+        Some(split_exec(exec))
+    }
+
+    pub fn categories(&self) -> Vec<&str> {
+        self.get("Categories")
+            .map(|c| c.split(';').filter(|c| !c.is_empty()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn matches(&self, term: &str) -> bool {
+        let term = term.to_lowercase();
+        [self.id.as_str(), self.name(), self.exec()]
+            .iter()
+            .any(|field| field.to_lowercase().contains(&term))
+            || self
+                .categories()
+                .iter()
+                .any(|c| c.to_lowercase().contains(&term))
+    }
+}
+
+/// Splits an `Exec` value into program and arguments, following the quoting
+/// rules of the spec and dropping field codes (`%f`, `%U`, ...) since apps
+/// are launched without files.
+fn split_exec(exec: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            '\\' if quoted => word.extend(chars.next()),
+            ' ' | '\t' if !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                }
+                in_word = false;
+            }
+            '%' => match chars.next() {
+                Some('%') => word.push('%'),
+                // Field codes expand to nothing without files or URLs
+                _ => in_word = true,
+            },
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words.retain(|w| !w.is_empty());
+    words
+}
+
+/// Key marking the entries `clovis export desktop` writes for environments.
+pub const CLOVIS_ENV_KEY: &str = "X-Clovis-Env";
+
+/// Quotes an argument of an `Exec` value when it needs to be.
+fn quote_exec_arg(arg: &str) -> String {
+    let reserved = |c: char| c.is_whitespace() || "\"'\\><~|&;$*?#()`".contains(c);
+    if !arg.is_empty() && !arg.contains(reserved) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    // The value is unescaped once more as a string
+    quoted.replace('\\', "\\\\")
+}
+
+/// An `Exec` value running this clovis binary with `args`.
+pub fn clovis_exec(args: &[&str]) -> String {
+    let clovis = std::env::current_exe()
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| "clovis".to_string());
+    std::iter::once(clovis.as_str())
+        .chain(args.iter().copied())
+        .map(quote_exec_arg)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Nix profiles whose `bin` and `share/applications` hold installed apps,
+/// user profiles first. Binaries that need privileges are wrapped in
+/// `/run/wrappers/bin` on NixOS instead.
+pub fn nix_profiles() -> Vec<PathBuf> {
+    let home = PathBuf::from(std::env::var("HOME").unwrap_or_default());
+    let user = std::env::var("USER").unwrap_or_default();
+    vec![
+        home.join(".nix-profile"),
+        home.join(".local/state/nix/profile"),
+        PathBuf::from("/etc/profiles/per-user").join(user),
+        PathBuf::from("/run/current-system/sw"),
+        PathBuf::from("/nix/var/nix/profiles/default"),
+    ]
+}
+
+const SNAP_APPLICATIONS_DIR: &str = "/var/lib/snapd/desktop/applications";
+
+/// Application directories in lookup precedence order: the user's own
+/// entries first, then Flatpak and Nix exports, then the system ones.
+pub fn desktop_file_dirs() -> Vec<PathBuf> {
+    let home_dir = std::env::var("HOME").unwrap_or_default();
+    let home = Path::new(&home_dir);
+
+    let data_home = std::env::var("XDG_DATA_HOME")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".local/share"));
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    let mut dirs = vec![
+        data_home.join("applications"),
+        home.join(".local/share/flatpak/exports/share/applications"),
+        PathBuf::from("/var/lib/flatpak/exports/share/applications"),
+    ];
+    dirs.extend(
+        nix_profiles()
+            .into_iter()
+            .map(|profile| profile.join("share/applications")),
+    );
+    dirs.extend(
+        data_dirs
+            .split(':')
+            .filter(|d| !d.is_empty())
+            .map(|d| Path::new(d).join("applications")),
+    );
+    dirs.push(PathBuf::from("/usr/local/share/applications"));
+    dirs.push(PathBuf::from("/usr/share/applications"));
+    dirs.push(PathBuf::from(SNAP_APPLICATIONS_DIR));
+
+    let mut seen = HashSet::new();
+    dirs.retain(|d| seen.insert(d.clone()));
+    dirs
+}
+
+pub fn find_desktop_file(file: &str) -> Option<PathBuf> {
+    desktop_file_dirs()
+        .into_iter()
+        .map(|dir| dir.join(file))
+        .find(|path| path.exists())
+}
+
+/// Returns the desktop file id to launch for `file`. Snap exports its
+/// entries as `<snap>_<app>.desktop`, so `firefox.desktop` also matches the
+/// `firefox_firefox.desktop` of the Firefox snap.
+pub fn resolve_desktop_id(file: &str) -> Option<String> {
+    if find_desktop_file(file).is_some() {
+        return Some(file.to_string());
+    }
+    let stem = file.strip_suffix(".desktop")?;
+    let snap_id = format!("{}_{}.desktop", stem, stem);
+    Path::new(SNAP_APPLICATIONS_DIR)
+        .join(&snap_id)
+        .exists()
+        .then_some(snap_id)
+}
+
+/// The entries found by the last scan of the application directories, and
+/// when each directory was last modified then.
+#[derive(Serialize, Deserialize)]
+struct EntryCache {
+    dirs: Vec<DirStamp>,
+    entries: Vec<DesktopEntry>,
+}
+
+#[derive(PartialEq, Serialize, Deserialize)]
+struct DirStamp {
+    path: PathBuf,
+    modified: i64,
+    modified_nsec: i64,
+}
+
+fn entry_cache_path() -> PathBuf {
+    paths::cache_dir().join("desktop-entries.yaml")
+}
+
+/// Installing, removing or updating a package replaces files, which
+/// changes the modification time of their directory.
+fn dir_stamps(dirs: &[PathBuf]) -> Vec<DirStamp> {
+    dirs.iter()
+        .filter_map(|dir| {
+            let metadata = fs::metadata(dir).ok()?;
+            Some(DirStamp {
+                path: dir.clone(),
+                modified: metadata.mtime(),
+                modified_nsec: metadata.mtime_nsec(),
+            })
+        })
+        .collect()
+}
+
+/// The visible entries of the application directories sorted by id,
+/// leaving out the ones `clovis export desktop` wrote. When the same id
+/// exists in several directories, the one with the highest precedence
+/// wins. The entries are cached in `$XDG_CACHE_HOME/clovis` until one of
+/// the directories changes; localized keys are left out, as nothing looks
+/// them up.
+pub fn installed_desktop_entries() -> Vec<DesktopEntry> {
+    let dirs = desktop_file_dirs();
+    let stamps = dir_stamps(&dirs);
+    let cached = fs::read_to_string(entry_cache_path())
+        .ok()
+        .and_then(|contents| serde_yaml::from_str::<EntryCache>(&contents).ok());
+    if let Some(cache) = cached.filter(|cache| cache.dirs == stamps) {
+        return cache.entries;
+    }
+
+    let mut entries = scan_desktop_entries(&dirs);
+    for entry in &mut entries {
+        entry.fields.retain(|key, _| !key.contains('['));
+        for action in entry.actions.values_mut() {
+            action.retain(|key, _| !key.contains('['));
+        }
+    }
+    let cache = EntryCache {
+        dirs: stamps,
+        entries,
+    };
+    let saved = serde_yaml::to_string(&cache)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| {
+            fs::create_dir_all(paths::cache_dir())
+                .and_then(|()| fs::write(entry_cache_path(), contents))
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = saved {
+        debug!("Failed to cache the desktop entries: {}", e);
+    }
+    cache.entries
+}
+
+fn scan_desktop_entries(dirs: &[PathBuf]) -> Vec<DesktopEntry> {
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    for dir in dirs {
+        let Ok(read_dir) = fs::read_dir(dir) else {
+            continue;
+        };
+        for file in read_dir.filter_map(|e| e.ok()) {
+            let path = file.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("desktop") {
+                continue;
+            }
+            let Some(entry) = DesktopEntry::parse(&path) else {
+                continue;
+            };
+            // A hidden entry still shadows lower-precedence ones with its id
+            if seen.insert(entry.id.clone())
+                && !entry.is_true("Hidden")
+                && !entry.is_true("NoDisplay")
+                && !entry.fields.contains_key(CLOVIS_ENV_KEY)
+            {
+                entries.push(entry);
+            }
+        }
+    }
+    entries.sort_by(|a, b| a.id.cmp(&b.id));
+    entries
+}
+
+pub fn installed_desktop_files() -> Vec<String> {
+    installed_desktop_entries()
+        .into_iter()
+        .map(|entry| entry.id)
+        .collect()
+}
@@ -0,0 +1,42 @@
+//! Machine-readable events emitted while launching, as one JSON object per
+//! line on stdout (`--events ndjson`), for scripts and notifiers.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ValueEnum;
+use time::OffsetDateTime;
+
+use crate::json::Json;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum EventFormat {
+    /// Newline-delimited JSON
+    Ndjson,
+}
+
+pub fn init(format: Option<EventFormat>) {
+    ENABLED.store(format.is_some(), Ordering::Relaxed);
+}
+
+/// Whether events replace the human-readable progress on stdout.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Prints an `event` along with its fields, e.g. `app-spawned` with the
+/// environment and app names.
+pub fn emit<'a>(event: &str, fields: impl IntoIterator<Item = (&'a str, Json)>) {
+    if !enabled() {
+        return;
+    }
+    let now = OffsetDateTime::now_utc();
+    let timestamp = now.unix_timestamp() as f64 + f64::from(now.millisecond()) / 1000.0;
+    let mut object = vec![
+        ("event".to_string(), Json::from(event)),
+        ("timestamp".to_string(), Json::from(timestamp)),
+    ];
+    object.extend(fields.into_iter().map(|(k, v)| (k.to_string(), v)));
+    println!("{}", Json::Object(object));
+}
@@ -0,0 +1,469 @@
+//! `clovis export`: the environments turned into files other tools run.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command as ProcessCommand;
+
+use crate::config::{App, AppKind, Config, Environment, Instances, IoniceClass};
+use crate::desktop::{clovis_exec, CLOVIS_ENV_KEY};
+use crate::launch::{self, shell_quote};
+
+/// How long the exported script waits for the apps that clovis would wait
+/// for a window of.
+const WINDOW_WAIT_SECS: u64 = 3;
+
+/// `~/.local/share/applications`.
+fn applications_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("applications")
+}
+
+/// Writes a `clovis-<env>.desktop` entry launching each environment, with
+/// a Stop action, and removes the ones of environments that are gone.
+pub fn export_desktop(config: &Config) -> io::Result<()> {
+    let dir = applications_dir();
+    fs::create_dir_all(&dir)?;
+
+    let mut envs: Vec<&String> = config.environments.keys().collect();
+    envs.sort();
+    let mut written = Vec::new();
+    for env in envs {
+        let environment = &config.environments[env];
+        let comment = environment
+            .description
+            .clone()
+            .unwrap_or_else(|| format!("Launches the {} environment", env));
+        let mut entry = format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name={env}\n\
+             GenericName=clovis environment\n\
+             Comment={comment}\n\
+             Exec={}\n\
+             Terminal=false\n\
+             Categories=Utility;\n\
+             Keywords=clovis;environment;\n",
+            clovis_exec(&["launch", env]),
+        );
+        if let Some(icon) = &environment.icon {
+            entry.push_str(&format!("Icon={}\n", icon));
+        }
+        entry.push_str(&format!(
+            "Actions=stop;\n\
+             {CLOVIS_ENV_KEY}={env}\n\
+             \n\
+             [Desktop Action stop]\n\
+             Name=Stop\n\
+             Exec={}\n",
+            clovis_exec(&["stop", env]),
+        ));
+        let file = format!("clovis-{}.desktop", env.replace('/', "-"));
+        fs::write(dir.join(&file), entry)?;
+        println!("Wrote {}", dir.join(&file).display());
+        written.push(file);
+    }
+
+    // Stale entries are told apart from the user's own by their key
+    for dir_entry in fs::read_dir(&dir)?.flatten() {
+        let file = dir_entry.file_name().to_string_lossy().into_owned();
+        if !file.starts_with("clovis-") || written.contains(&file) {
+            continue;
+        }
+        let contents = fs::read_to_string(dir_entry.path()).unwrap_or_default();
+        if contents
+            .lines()
+            .any(|line| line.starts_with(&format!("{}=", CLOVIS_ENV_KEY)))
+        {
+            fs::remove_file(dir_entry.path())?;
+            println!("Removed {}", dir_entry.path().display());
+        }
+    }
+    Ok(())
+}
+
+/// Renders `command` as a shell command line, its variables set with
+/// `env`. `DISPLAY` is left out, the script sets it once.
+fn render_command(command: &ProcessCommand) -> String {
+    let mut words = Vec::new();
+    let mut assignments = Vec::new();
+    for (key, value) in command.get_envs() {
+        let key = key.to_string_lossy();
+        if key == "DISPLAY" {
+            continue;
+        }
+        // Options have to come before the assignments
+        match value {
+            Some(value) => {
+                assignments.push(shell_quote(&format!("{}={}", key, value.to_string_lossy())))
+            }
+            None => words.extend(["-u".to_string(), shell_quote(&key)]),
+        }
+    }
+    words.extend(assignments);
+    if !words.is_empty() {
+        words.insert(0, "env".to_string());
+    }
+    words.push(shell_quote(&command.get_program().to_string_lossy()));
+    words.extend(
+        command
+            .get_args()
+            .map(|arg| shell_quote(&arg.to_string_lossy())),
+    );
+    words.join(" ")
+}
+
+/// The `nice`, `ionice_class`, `cpu_affinity` and `oom_score_adj` settings
+/// of the app as commands prefixing its own.
+fn resource_prefix(app: &App) -> String {
+    let mut prefix = String::new();
+    if let Some(nice) = app.nice {
+        prefix.push_str(&format!("nice -n {} ", nice));
+    }
+    if let Some(class) = app.ionice_class {
+        prefix.push_str(match class {
+            IoniceClass::Realtime => "ionice -c 1 -n 4 ",
+            IoniceClass::BestEffort => "ionice -c 2 -n 4 ",
+            IoniceClass::Idle => "ionice -c 3 ",
+        });
+    }
+    if !app.cpu_affinity.is_empty() {
+        let cpus: Vec<String> = app.cpu_affinity.iter().map(usize::to_string).collect();
+        prefix.push_str(&format!("taskset -c {} ", cpus.join(",")));
+    }
+    if let Some(score) = app.oom_score_adj {
+        prefix.push_str(&format!(
+            "sh -c 'echo {} > /proc/self/oom_score_adj && exec \"$@\"' sh ",
+            score
+        ));
+    }
+    prefix
+}
+
+/// The lines launching `app` in the exported script.
+fn app_lines(config: &Config, environment: &Environment, app: &App) -> Vec<String> {
+    let mut lines = vec![format!("# {}", app.name)];
+    if let (AppKind::Ssh, Some(mac)) = (app.kind, &app.wake_on_lan) {
+        lines.push(format!(
+            "command -v wakeonlan >/dev/null && wakeonlan {}",
+            shell_quote(mac)
+        ));
+    }
+    if app.kind == AppKind::Vm {
+        let virsh = match &app.uri {
+            Some(uri) => format!("virsh --connect {}", shell_quote(uri)),
+            None => "virsh".to_string(),
+        };
+        let domain = shell_quote(&app.name);
+        lines.push(format!(
+            "{virsh} domstate {domain} 2>/dev/null | grep -qx running || {virsh} start {domain} >/dev/null"
+        ));
+    }
+    let command = match launch::command(config, environment, app) {
+        Ok(Some(command)) => command,
+        Ok(None) => return lines,
+        Err(e) => {
+            eprintln!("Leaving out '{}': {}", app.name, e);
+            lines.push(format!("# Left out: {}", e));
+            return lines;
+        }
+    };
+    let launch = format!(
+        "launch {}{}",
+        resource_prefix(app),
+        render_command(&command)
+    );
+    match launch::process_pattern(config, app) {
+        Some(pattern) if app.instances != Some(Instances::Multiple) => {
+            lines.push(format!(
+                "pgrep -f -- {} >/dev/null || {}",
+                shell_quote(&pattern),
+                launch
+            ));
+        }
+        _ => lines.push(launch),
+    }
+    if app.wait_for_window {
+        lines.push(format!(
+            "sleep {}  # clovis waits for a window of the app instead",
+            WINDOW_WAIT_SECS
+        ));
+    }
+    lines
+}
+
+/// Prints a shell script launching the apps of `env` the way `clovis
+/// launch` does, or writes it to `output`. Returns false when the
+/// environment does not exist.
+pub fn export_script(config: &Config, env: &str, output: Option<&Path>) -> io::Result<bool> {
+    let env = config.resolve_env(env);
+    let Some(environment) = config.environments.get(env) else {
+        eprintln!("Environment '{}' not found.", env);
+        return Ok(false);
+    };
+    let mut script = format!(
+        "#!/bin/sh\n\
+         # Launches the apps of the '{env}' environment as `clovis launch {env}` does.\n\
+         # Generated by clovis; its other launch steps (monitor layout, network,\n\
+         # mounts, appearance...) are left out.\n\
+         \n\
+         # Set for Electron apps, as clovis does\n\
+         export DISPLAY=:0\n\
+         \n\
+         launch() {{\n    \
+             nohup \"$@\" >/dev/null 2>&1 &\n\
+         }}\n",
+    );
+    for app in &environment.apps {
+        script.push('\n');
+        for line in app_lines(config, environment, app) {
+            script.push_str(&line);
+            script.push('\n');
+        }
+    }
+
+    match output {
+        Some(path) => {
+            fs::write(path, script)?;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+            println!("Wrote {}", path.display());
+        }
+        None => print!("{}", script),
+    }
+    Ok(true)
+}
+
+/// A unit name made of `parts`, with the characters systemd does not
+/// allow replaced.
+fn unit_name(parts: &[&str], suffix: &str) -> String {
+    let name: String = parts
+        .join("-")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.{}", name, suffix)
+}
+
+/// Escapes the `%` specifiers systemd expands in unit files.
+fn systemd_escape(text: &str) -> String {
+    text.replace('%', "%%")
+}
+
+/// Quotes a value of a unit file when it needs to be.
+fn systemd_quote(word: &str) -> String {
+    let word = systemd_escape(word);
+    if !word.is_empty() && !word.contains(|c: char| c.is_whitespace() || "\"'\\;".contains(c)) {
+        return word;
+    }
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn systemd_command(program: &str, args: &[String]) -> String {
+    // `Exec*=` lines expand variables too
+    std::iter::once(program.to_string())
+        .chain(args.iter().cloned())
+        .map(|word| systemd_quote(&word.replace('$', "$$")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A `/bin/sh -c` line running `script`.
+fn systemd_shell(script: &str) -> String {
+    systemd_command("/bin/sh", &["-c".to_string(), script.to_string()])
+}
+
+/// Whether `command` hands the app over to another process and exits, so
+/// the service is done once it ran.
+fn hands_over(app: &App, command: Option<&ProcessCommand>) -> bool {
+    let Some(command) = command else {
+        return true;
+    };
+    let program = command.get_program().to_string_lossy();
+    app.kind == AppKind::Compose || ["gtk-launch", "xdg-open", "gdbus"].contains(&program.as_ref())
+}
+
+/// The service unit running `app`, started after the unit `after`.
+fn service_unit(
+    config: &Config,
+    environment: &Environment,
+    env: &str,
+    app: &App,
+    after: Option<&str>,
+) -> io::Result<String> {
+    let command = launch::command(config, environment, app)?;
+    let target = unit_name(&["clovis", env], "target");
+    let mut unit = format!(
+        "[Unit]\n\
+         Description={} (clovis environment {})\n\
+         PartOf={target}\n\
+         After=graphical-session.target{}\n\
+         \n\
+         [Service]\n",
+        systemd_escape(&app.name),
+        systemd_escape(env),
+        after.map(|after| format!(" {}", after)).unwrap_or_default(),
+    );
+    if hands_over(app, command.as_ref()) {
+        unit.push_str("Type=oneshot\nRemainAfterExit=yes\n");
+    } else {
+        unit.push_str("Type=exec\n");
+    }
+    if let (AppKind::Ssh, Some(mac)) = (app.kind, &app.wake_on_lan) {
+        unit.push_str(&format!(
+            "ExecStartPre=-{}\n",
+            systemd_command("wakeonlan", std::slice::from_ref(mac))
+        ));
+    }
+    let virsh = || {
+        let mut words = Vec::new();
+        if let Some(uri) = &app.uri {
+            words.extend(["--connect".to_string(), uri.clone()]);
+        }
+        words
+    };
+    if app.kind == AppKind::Vm {
+        let mut start = virsh();
+        start.extend(["start".to_string(), app.name.clone()]);
+        let start = systemd_command("virsh", &start);
+        match &command {
+            Some(_) => unit.push_str(&format!("ExecStartPre=-{}\n", start)),
+            None => unit.push_str(&format!("ExecStart={}\n", start)),
+        }
+    }
+    if let Some(command) = &command {
+        for (key, value) in command.get_envs() {
+            match value {
+                Some(value) => unit.push_str(&format!(
+                    "Environment={}\n",
+                    systemd_quote(&format!(
+                        "{}={}",
+                        key.to_string_lossy(),
+                        value.to_string_lossy()
+                    ))
+                )),
+                None => unit.push_str(&format!("UnsetEnvironment={}\n", key.to_string_lossy())),
+            }
+        }
+        let args: Vec<String> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        unit.push_str(&format!(
+            "ExecStart={}\n",
+            systemd_command(&command.get_program().to_string_lossy(), &args)
+        ));
+    }
+    if let Some(stop) = &app.stop_command {
+        unit.push_str(&format!("ExecStop={}\n", systemd_shell(stop)));
+    } else if app.kind == AppKind::Vm {
+        let mut shutdown = virsh();
+        shutdown.extend(["shutdown".to_string(), app.name.clone()]);
+        unit.push_str(&format!(
+            "ExecStop={}\n",
+            systemd_command("virsh", &shutdown)
+        ));
+    }
+    if let Some(timeout) = app.stop_timeout {
+        unit.push_str(&format!("TimeoutStopSec={}\n", timeout));
+    }
+    if let Some(signal) = app.stop_signal {
+        unit.push_str(&format!("KillSignal={}\n", signal.name()));
+    }
+    if let Some(nice) = app.nice {
+        unit.push_str(&format!("Nice={}\n", nice));
+    }
+    if let Some(class) = app.ionice_class {
+        unit.push_str(match class {
+            IoniceClass::Realtime => "IOSchedulingClass=realtime\n",
+            IoniceClass::BestEffort => "IOSchedulingClass=best-effort\n",
+            IoniceClass::Idle => "IOSchedulingClass=idle\n",
+        });
+    }
+    if !app.cpu_affinity.is_empty() {
+        let cpus: Vec<String> = app.cpu_affinity.iter().map(usize::to_string).collect();
+        unit.push_str(&format!("CPUAffinity={}\n", cpus.join(" ")));
+    }
+    if let Some(score) = app.oom_score_adj {
+        unit.push_str(&format!("OOMScoreAdjust={}\n", score));
+    }
+    if let Some(slice) = &environment.slice {
+        unit.push_str(&format!("Slice={}\n", slice.unit()));
+    }
+    unit.push_str(&format!("\n[Install]\nWantedBy={}\n", target));
+    Ok(unit)
+}
+
+/// Writes a user service per app of `env` to `dir`, along with a
+/// `clovis-<env>.target` starting them all and the slice of the
+/// environment. Returns false when the environment does not exist.
+pub fn export_systemd(config: &Config, env: &str, dir: &Path) -> io::Result<bool> {
+    let env = config.resolve_env(env);
+    let Some(environment) = config.environments.get(env) else {
+        eprintln!("Environment '{}' not found.", env);
+        return Ok(false);
+    };
+    fs::create_dir_all(dir)?;
+    let write = |name: &str, contents: &str| -> io::Result<()> {
+        fs::write(dir.join(name), contents)?;
+        println!("Wrote {}", dir.join(name).display());
+        Ok(())
+    };
+
+    let mut services = Vec::new();
+    for app in &environment.apps {
+        let name = unit_name(&["clovis", env, &app.name], "service");
+        let after = services.last().map(String::as_str);
+        let unit = match service_unit(config, environment, env, app, after) {
+            Ok(unit) => unit,
+            Err(e) => {
+                eprintln!("Leaving out '{}': {}", app.name, e);
+                continue;
+            }
+        };
+        write(&name, &unit)?;
+        services.push(name);
+    }
+
+    if let Some(slice) = &environment.slice {
+        let mut unit = format!(
+            "[Unit]\nDescription=Apps of the clovis environment {}\n\n[Slice]\n",
+            systemd_escape(env)
+        );
+        if let Some(memory_max) = &slice.memory_max {
+            unit.push_str(&format!("MemoryMax={}\n", memory_max));
+        }
+        if let Some(cpu_quota) = &slice.cpu_quota {
+            unit.push_str(&format!("CPUQuota={}\n", cpu_quota));
+        }
+        write(&slice.unit(), &unit)?;
+    }
+
+    let target = unit_name(&["clovis", env], "target");
+    write(
+        &target,
+        &format!(
+            "[Unit]\n\
+             Description=clovis environment {}\n\
+             Wants={}\n\
+             After=graphical-session.target\n\
+             \n\
+             [Install]\n\
+             WantedBy=graphical-session.target\n",
+            systemd_escape(env),
+            services.join(" "),
+        ),
+    )?;
+    println!(
+        "Copy them to ~/.config/systemd/user, then run `systemctl --user daemon-reload` and `systemctl --user start {}`.",
+        target
+    );
+    Ok(true)
+}
@@ -0,0 +1,79 @@
+//! `clovis focus-session`: a timer during which an environment runs with
+//! the programs it blocks terminated and do-not-disturb on.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::blocklist;
+
+/// How often blocked programs are looked for during a session.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Parses a duration such as `50m`, `1h30m` or `90s`. A bare number is a
+/// number of minutes.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    if let Ok(minutes) = text.parse::<u64>() {
+        return Ok(Duration::from_secs(minutes * 60));
+    }
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(format!("unknown unit '{}', use h, m or s", c)),
+        };
+        let value: u64 = number
+            .parse()
+            .map_err(|_| format!("missing number before '{}'", c))?;
+        seconds += value * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(format!("missing unit after {}", number));
+    }
+    if seconds == 0 {
+        return Err("the duration must not be zero".to_string());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Waits for `duration`, terminating the programs `env` blocks meanwhile.
+/// Returns false when interrupted early with Ctrl-C or SIGTERM.
+pub fn wait(env: &str, blocks: &[String], duration: Duration) -> bool {
+    let handler = interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only stores to an atomic
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+    let end = Instant::now() + duration;
+    let mut checked: Option<Instant> = None;
+    let mut terminated = Vec::new();
+    while Instant::now() < end {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            return false;
+        }
+        if checked.is_none_or(|t| t.elapsed() >= POLL_INTERVAL) {
+            checked = Some(Instant::now());
+            blocklist::enforce(env, blocks, &mut terminated);
+        }
+        // Short sleeps, to notice interruptions
+        std::thread::sleep(
+            end.saturating_duration_since(Instant::now())
+                .min(Duration::from_millis(200)),
+        );
+    }
+    true
+}
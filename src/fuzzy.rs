@@ -0,0 +1,114 @@
+use std::io::{self, Write};
+use std::process::{Command as ProcessCommand, Stdio};
+
+use log::info;
+
+use crate::prompt;
+
+const MAX_RESULTS: usize = 15;
+
+/// Scores `candidate` against `query` the way skim/fzf do: every query
+/// character must appear in order, consecutive runs and matches at word
+/// boundaries score higher, gaps cost a little. Returns `None` when the
+/// query is not a subsequence of the candidate.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut last_match: Option<usize> = None;
+
+    for qc in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = candidate[pos..].iter().position(|&c| c == qc)? + pos;
+        score += 10;
+        match last_match {
+            Some(last) if found == last + 1 => score += 15,
+            Some(last) => score -= (found - last - 1).min(10) as i64,
+            None => score -= found.min(10) as i64,
+        }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() {
+            score += 10;
+        }
+        last_match = Some(found);
+        pos = found + 1;
+    }
+    Some(score)
+}
+
+/// Returns the candidates matching `query`, best match first.
+pub fn rank<'a>(query: &str, candidates: &'a [String]) -> Vec<&'a String> {
+    let mut scored: Vec<(i64, &String)> = candidates
+        .iter()
+        .filter_map(|c| fuzzy_score(query, c).map(|score| (score, c)))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.len().cmp(&b.1.len())));
+    scored.into_iter().map(|(_, c)| c).collect()
+}
+
+/// Lets the user pick one of `candidates`. Uses `sk` or `fzf` when one is
+/// installed, otherwise falls back to a simple prompt-driven search.
+pub fn pick(candidates: &[String]) -> io::Result<Option<String>> {
+    for picker in ["sk", "fzf"] {
+        if let Some(selection) = pick_with_external(picker, candidates)? {
+            return Ok(selection);
+        }
+    }
+    pick_with_prompt(candidates)
+}
+
+/// Runs an external picker. The outer `Option` is `None` when the picker is
+/// not installed, the inner one when the user cancelled.
+fn pick_with_external(picker: &str, candidates: &[String]) -> io::Result<Option<Option<String>>> {
+    let child = ProcessCommand::new(picker)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    info!("Picking application with {}", picker);
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(candidates.join("\n").as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    let selection = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || selection.is_empty() {
+        return Ok(Some(None));
+    }
+    Ok(Some(Some(selection)))
+}
+
+fn pick_with_prompt(candidates: &[String]) -> io::Result<Option<String>> {
+    loop {
+        let query = prompt("Search (leave empty to cancel): ")?;
+        if query.is_empty() {
+            return Ok(None);
+        }
+
+        let matches = rank(&query, candidates);
+        if matches.is_empty() {
+            println!("No match for '{}'", query);
+            continue;
+        }
+        for (i, candidate) in matches.iter().take(MAX_RESULTS).enumerate() {
+            println!("  {:>2}) {}", i + 1, candidate);
+        }
+        if matches.len() > MAX_RESULTS {
+            println!(
+                "  ... {} more, refine the search",
+                matches.len() - MAX_RESULTS
+            );
+        }
+
+        let answer = prompt("Pick a number, or press enter to search again: ")?;
+        if let Some(choice) = answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| matches.get(n.wrapping_sub(1)))
+        {
+            return Ok(Some(choice.to_string()));
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! `clovis graph`: the steps of launching an environment, in the order
+//! `clovis launch` runs them, as a Graphviz or Mermaid graph. Each step
+//! waits for the one before it; apps with `wait_for_window` hold the next
+//! ones until their window appears.
+
+use clap::ValueEnum;
+
+use crate::config::{Config, Environment};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+/// A step of the launch, and what the next one waits for after it.
+struct Step {
+    label: String,
+    waits_for: Option<&'static str>,
+}
+
+fn step(label: String) -> Step {
+    Step {
+        label,
+        waits_for: None,
+    }
+}
+
+fn steps(env: &str, environment: &Environment) -> Vec<Step> {
+    let mut steps = vec![step(format!("launch {}", env))];
+    for other in &environment.conflicts_with {
+        steps.push(step(format!("stop {}", other)));
+    }
+    if let Some(layout) = &environment.layout {
+        steps.push(step(format!("monitors {}", layout.outputs.join(", "))));
+    }
+    for connection in &environment.network {
+        steps.push(step(format!("network {}", connection.name)));
+    }
+    for mount in &environment.mounts {
+        let target = mount
+            .source
+            .as_deref()
+            .or(mount.path.as_deref())
+            .unwrap_or(mount.kind.as_str());
+        steps.push(step(format!("mount {}", target)));
+    }
+    if environment.appearance.is_some() {
+        steps.push(step("appearance".to_string()));
+    }
+    if environment.do_not_disturb {
+        steps.push(step("do not disturb".to_string()));
+    }
+    if environment.audio.is_some() {
+        steps.push(step("audio".to_string()));
+    }
+    if let Some(keyboard) = &environment.keyboard {
+        steps.push(step(format!("keyboard {}", keyboard.layout)));
+    }
+    for device in &environment.bluetooth {
+        steps.push(step(format!("bluetooth {}", device)));
+    }
+    for app in &environment.apps {
+        steps.push(Step {
+            label: app.name.clone(),
+            waits_for: app.wait_for_window.then_some("window"),
+        });
+    }
+    steps
+}
+
+fn dot(steps: &[Step]) -> String {
+    let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut out = "digraph launch {\n    rankdir=LR;\n".to_string();
+    for (i, step) in steps.iter().enumerate() {
+        out.push_str(&format!("    n{} [label={}];\n", i, quote(&step.label)));
+    }
+    for (i, pair) in steps.windows(2).enumerate() {
+        match pair[0].waits_for {
+            Some(what) => out.push_str(&format!(
+                "    n{} -> n{} [label={}];\n",
+                i,
+                i + 1,
+                quote(what)
+            )),
+            None => out.push_str(&format!("    n{} -> n{};\n", i, i + 1)),
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn mermaid(steps: &[Step]) -> String {
+    let quote = |text: &str| format!("\"{}\"", text.replace('"', "#quot;"));
+    let mut out = "flowchart LR\n".to_string();
+    for (i, step) in steps.iter().enumerate() {
+        out.push_str(&format!("    n{}[{}]\n", i, quote(&step.label)));
+    }
+    for (i, pair) in steps.windows(2).enumerate() {
+        match pair[0].waits_for {
+            Some(what) => out.push_str(&format!("    n{} -->|{}| n{}\n", i, what, i + 1)),
+            None => out.push_str(&format!("    n{} --> n{}\n", i, i + 1)),
+        }
+    }
+    out
+}
+
+/// The launch graph of `env` in `format`, `None` when `env` is not
+/// configured.
+pub fn graph(config: &Config, env: &str, format: GraphFormat) -> Option<String> {
+    let env = config.resolve_env(env);
+    let steps = steps(env, config.environments.get(env)?);
+    Some(match format {
+        GraphFormat::Dot => dot(&steps),
+        GraphFormat::Mermaid => mermaid(&steps),
+    })
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::config::{migrate_contents, save_config, Config};
+use crate::migrate::CONFIG_VERSION;
+use crate::paths;
+
+/// How many config changes are kept in the journal.
+const MAX_JOURNAL_ENTRIES: usize = 100;
+
+/// One programmatic change of the config file, with enough context to show
+/// it in `clovis history` and to revert it with `clovis undo`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigChange {
+    pub timestamp: i64,
+    pub user: String,
+    pub command: String,
+    pub previous: String,
+    pub updated: String,
+}
+
+pub fn journal_path() -> PathBuf {
+    paths::state_dir().join("history.yaml")
+}
+
+pub fn load_journal(path: &Path) -> io::Result<Vec<ConfigChange>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    serde_yaml::from_str(&contents).map_err(|e| {
+        error!("Failed to parse history file: {}", e);
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })
+}
+
+fn save_journal(path: &Path, journal: &[ConfigChange]) -> io::Result<()> {
+    let contents = serde_yaml::to_string(journal).map_err(|e| {
+        error!("Failed to serialize history: {}", e);
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })?;
+    fs::create_dir_all(paths::state_dir())?;
+    fs::write(path, contents)
+}
+
+/// Saves `config` and records the change in the journal.
+pub fn save_config_with_history(config_path: &Path, config: &Config) -> io::Result<()> {
+    let previous = fs::read_to_string(config_path).unwrap_or_default();
+    save_config(config_path, config)?;
+    let updated = fs::read_to_string(config_path)?;
+    record_change(previous, updated)
+}
+
+/// Upgrades the config file to the current format version. Recorded in the
+/// journal like any other change so it can be undone.
+pub fn migrate_config(config_path: &Path) -> io::Result<()> {
+    let previous = fs::read_to_string(config_path)?;
+    let Some(updated) = migrate_contents(&previous)? else {
+        println!("Config is already at version {}", CONFIG_VERSION);
+        return Ok(());
+    };
+    fs::write(config_path, &updated)?;
+    println!("Migrated config to version {}", CONFIG_VERSION);
+    info!("Config migrated to version {}", CONFIG_VERSION);
+    record_change(previous, updated)
+}
+
+fn record_change(previous: String, updated: String) -> io::Result<()> {
+    let journal_path = journal_path();
+    let mut journal = load_journal(&journal_path)?;
+    journal.push(ConfigChange {
+        timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+        user: std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()),
+        command: std::iter::once("clovis".to_string())
+            .chain(std::env::args().skip(1))
+            .collect::<Vec<_>>()
+            .join(" "),
+        previous,
+        updated,
+    });
+    let excess = journal.len().saturating_sub(MAX_JOURNAL_ENTRIES);
+    journal.drain(..excess);
+    save_journal(&journal_path, &journal)
+}
+
+pub fn show_history() -> io::Result<()> {
+    let journal = load_journal(&journal_path())?;
+    if journal.is_empty() {
+        println!("No configuration changes recorded yet.");
+        return Ok(());
+    }
+    for (i, change) in journal.iter().enumerate() {
+        println!(
+            "{:>3}  {}  {:<10}  {}",
+            i + 1,
+            format_timestamp(change.timestamp),
+            change.user,
+            change.command
+        );
+    }
+    Ok(())
+}
+
+/// Restores the config file as it was before the last recorded change.
+/// Refuses to do so when the file was modified by hand since, unless
+/// `force` is set.
+pub fn undo_last_change(config_path: &Path, force: bool) -> io::Result<()> {
+    let journal_path = journal_path();
+    let mut journal = load_journal(&journal_path)?;
+    let Some(change) = journal.pop() else {
+        println!("Nothing to undo.");
+        return Ok(());
+    };
+
+    let current = fs::read_to_string(config_path).unwrap_or_default();
+    if current != change.updated && !force {
+        println!(
+            "The config file changed since '{}' was run. Use --force to undo anyway.",
+            change.command
+        );
+        return Ok(());
+    }
+
+    fs::write(config_path, &change.previous)?;
+    save_journal(&journal_path, &journal)?;
+    println!(
+        "Reverted '{}' from {}",
+        change.command,
+        format_timestamp(change.timestamp)
+    );
+    info!("Undid config change '{}'", change.command);
+    Ok(())
+}
+
+/// Formats a unix timestamp in local time, falling back to UTC when the
+/// local offset cannot be determined.
+pub fn format_timestamp(timestamp: i64) -> String {
+    let Ok(utc) = OffsetDateTime::from_unix_timestamp(timestamp) else {
+        return timestamp.to_string();
+    };
+    let local = time::UtcOffset::current_local_offset()
+        .map(|offset| utc.to_offset(offset))
+        .unwrap_or(utc);
+    local
+        .format(time::macros::format_description!(
+            "[year]-[month]-[day] [hour]:[minute]:[second]"
+        ))
+        .unwrap_or_else(|_| timestamp.to_string())
+}
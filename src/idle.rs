@@ -0,0 +1,66 @@
+//! Whether the user is idle, from the `IdleHint` logind keeps for the
+//! session. Desktops set it themselves, swayidle with its `idlehint`
+//! option.
+
+use std::io;
+use std::process::Command as ProcessCommand;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn loginctl(args: &[&str]) -> io::Result<String> {
+    let output = ProcessCommand::new("loginctl").args(args).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The session clovis runs in, or the graphical session of the user when
+/// started outside of one, e.g. by a systemd user service.
+fn session_id() -> io::Result<String> {
+    if let Some(id) = std::env::var("XDG_SESSION_ID")
+        .ok()
+        .filter(|id| !id.is_empty())
+    {
+        return Ok(id);
+    }
+    // SAFETY: getuid cannot fail
+    let uid = unsafe { libc::getuid() }.to_string();
+    let id = loginctl(&["show-user", &uid, "--property=Display", "--value"])?;
+    if id.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no graphical session found",
+        ));
+    }
+    Ok(id)
+}
+
+/// How long the session has been idle, or `None` when it is in use.
+pub fn idle_time() -> io::Result<Option<Duration>> {
+    let id = session_id()?;
+    let output = loginctl(&[
+        "show-session",
+        &id,
+        "--property=IdleHint",
+        "--property=IdleSinceHint",
+    ])?;
+    let mut idle = false;
+    let mut since = None;
+    for line in output.lines() {
+        match line.split_once('=') {
+            Some(("IdleHint", value)) => idle = value == "yes",
+            Some(("IdleSinceHint", value)) => since = value.parse::<u64>().ok(),
+            _ => {}
+        }
+    }
+    if !idle {
+        return Ok(None);
+    }
+    // IdleSinceHint is in microseconds since the epoch
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let since = Duration::from_micros(since.unwrap_or_default());
+    Ok(Some(now.saturating_sub(since)))
+}
@@ -0,0 +1,287 @@
+//! `clovis import`: turns what already starts with the session, XDG
+//! autostart entries or the `exec` lines of a window manager config, into
+//! an environment.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use log::info;
+
+use crate::config::{App, AppKind, Config, Environment};
+use crate::desktop::{find_desktop_file, DesktopEntry};
+use crate::launch::{expand_home, is_command_available, shell_quote};
+
+/// Commands of startup configs that set up the session rather than start
+/// an app.
+const SESSION_COMMANDS: &[&str] = &["clovis", "dbus-update-activation-environment", "systemctl"];
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ImportSource {
+    /// The entries of `~/.config/autostart` and `/etc/xdg/autostart`.
+    Autostart,
+    /// The `exec` and `exec_always` lines of `~/.config/sway/config`.
+    Sway,
+    /// The `exec` and `exec_always` lines of `~/.config/i3/config`.
+    I3,
+    /// The `exec-once` and `exec` lines of `~/.config/hypr/hyprland.conf`.
+    Hyprland,
+}
+
+impl ImportSource {
+    fn name(self) -> &'static str {
+        match self {
+            ImportSource::Autostart => "autostart",
+            ImportSource::Sway => "sway",
+            ImportSource::I3 => "i3",
+            ImportSource::Hyprland => "hyprland",
+        }
+    }
+
+    /// The config file read for a window manager.
+    fn default_path(self) -> Option<PathBuf> {
+        let config_dir = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
+        match self {
+            ImportSource::Autostart => None,
+            ImportSource::Sway => Some(config_dir.join("sway/config")),
+            ImportSource::I3 => Some(config_dir.join("i3/config"))
+                .filter(|path| path.exists())
+                .or_else(|| Some(expand_home("~/.i3/config"))),
+            ImportSource::Hyprland => Some(config_dir.join("hypr/hyprland.conf")),
+        }
+    }
+}
+
+/// The app running `command`: by its program name when that is all there
+/// is to it, as a script otherwise.
+fn app_for_command(command: &str) -> App {
+    let command = command.trim();
+    if !command.contains(char::is_whitespace) && is_command_available(command) {
+        return App::new(command);
+    }
+    let program = command.split_whitespace().next().unwrap_or(command);
+    let name = Path::new(program)
+        .file_name()
+        .map_or(program.to_string(), |name| {
+            name.to_string_lossy().into_owned()
+        });
+    App {
+        kind: AppKind::Script,
+        command: Some(command.to_string()),
+        ..App::new(&name)
+    }
+}
+
+fn is_session_command(command: &str) -> bool {
+    let program = command.split_whitespace().next().unwrap_or_default();
+    let program = Path::new(program)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    SESSION_COMMANDS.contains(&program.as_str())
+}
+
+/// The autostart entries in use, the user's own shadowing the system ones
+/// of the same name.
+fn autostart_apps() -> Vec<App> {
+    let mut dirs = vec![dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("autostart")];
+    let system_dirs = std::env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".to_string());
+    dirs.extend(
+        system_dirs
+            .split(':')
+            .map(|dir| Path::new(dir).join("autostart")),
+    );
+
+    let mut seen = Vec::new();
+    let mut entries = Vec::new();
+    for dir in dirs {
+        let Ok(read_dir) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut paths: Vec<PathBuf> = read_dir.flatten().map(|e| e.path()).collect();
+        paths.sort();
+        for path in paths {
+            if path.extension().and_then(|e| e.to_str()) != Some("desktop") {
+                continue;
+            }
+            let Some(entry) = DesktopEntry::parse(&path) else {
+                continue;
+            };
+            if seen.contains(&entry.id) {
+                continue;
+            }
+            seen.push(entry.id.clone());
+            if entry.is_true("Hidden") || entry.get("X-GNOME-Autostart-enabled") == Some("false") {
+                continue;
+            }
+            entries.push(entry);
+        }
+    }
+
+    let mut apps = Vec::new();
+    for entry in entries {
+        let words = entry.command_line();
+        let command: Vec<String> = words.iter().map(|word| shell_quote(word)).collect();
+        let command = command.join(" ");
+        if command.is_empty() || is_session_command(&command) {
+            continue;
+        }
+        // Entries that are also installed apps are launched as such
+        if find_desktop_file(&entry.id).is_some() {
+            apps.push(App::new(&entry.id));
+        } else {
+            let name = entry.id.trim_end_matches(".desktop");
+            apps.push(App {
+                name: name.to_string(),
+                ..app_for_command(&command)
+            });
+        }
+    }
+    apps
+}
+
+/// Replaces the variables set in the config, e.g. `$term`.
+fn substitute(command: &str, variables: &HashMap<String, String>) -> String {
+    let mut names: Vec<&String> = variables.keys().collect();
+    // `$terminal` before `$term`
+    names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+    let mut command = command.to_string();
+    for name in names {
+        command = command.replace(&format!("${}", name), &variables[name]);
+    }
+    command
+}
+
+/// The commands a window manager config starts, in order.
+fn window_manager_commands(source: ImportSource, contents: &str) -> Vec<String> {
+    let mut variables = HashMap::new();
+    let mut commands = Vec::new();
+    // Lines ending with a backslash go on with the next one
+    let joined = contents
+        .split("\\\n")
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ");
+    for line in joined.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let command = if source == ImportSource::Hyprland {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if let Some(name) = key.strip_prefix('$') {
+                variables.insert(name.to_string(), value.to_string());
+                continue;
+            }
+            if key != "exec-once" && key != "exec" {
+                continue;
+            }
+            value.to_string()
+        } else {
+            let mut words = line.splitn(2, char::is_whitespace);
+            let keyword = words.next().unwrap_or_default();
+            let rest = words.next().unwrap_or_default().trim();
+            if keyword == "set" {
+                if let Some((name, value)) = rest
+                    .strip_prefix('$')
+                    .and_then(|r| r.split_once(char::is_whitespace))
+                {
+                    variables.insert(name.to_string(), value.trim().to_string());
+                }
+                continue;
+            }
+            if keyword != "exec" && keyword != "exec_always" {
+                continue;
+            }
+            rest.trim_start_matches("--no-startup-id")
+                .trim()
+                .to_string()
+        };
+        let command = substitute(&command, &variables);
+        if !command.is_empty() {
+            commands.push(command);
+        }
+    }
+    commands
+}
+
+/// Saves what `source` starts as environment `env`, the name of the source
+/// by default. Returns whether the config changed.
+pub fn import_environment(
+    config: &mut Config,
+    source: ImportSource,
+    env: Option<&str>,
+    path: Option<&Path>,
+    force: bool,
+) -> bool {
+    let env = env.unwrap_or(source.name());
+    if config.environments.contains_key(env) && !force {
+        println!(
+            "Environment '{}' already exists, use --force to replace it.",
+            env
+        );
+        return false;
+    }
+
+    let mut apps = match source {
+        ImportSource::Autostart => autostart_apps(),
+        _ => {
+            let Some(path) = path
+                .map(Path::to_path_buf)
+                .or_else(|| source.default_path())
+            else {
+                return false;
+            };
+            let contents = match fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    println!("Cannot read {}: {}", path.display(), e);
+                    return false;
+                }
+            };
+            window_manager_commands(source, &contents)
+                .iter()
+                .filter(|command| !is_session_command(command))
+                .map(|command| app_for_command(command))
+                .collect()
+        }
+    };
+    // Names must stay unique to be stopped or edited
+    let mut names: Vec<String> = Vec::new();
+    for app in &mut apps {
+        let name = app.name.clone();
+        let mut n = 2;
+        while names.contains(&app.name) {
+            app.name = format!("{}-{}", name, n);
+            n += 1;
+        }
+        names.push(app.name.clone());
+    }
+    if apps.is_empty() {
+        println!("Nothing to import from {}.", source.name());
+        return false;
+    }
+
+    for app in &apps {
+        println!("Imported: {}", app.name);
+    }
+    config.environments.insert(
+        env.to_string(),
+        Environment {
+            apps,
+            ..Environment::default()
+        },
+    );
+    println!("Saved environment '{}'", env);
+    info!("Imported environment '{}' from {}", env, source.name());
+    true
+}
@@ -0,0 +1,264 @@
+//! Keyboard layouts environments switch to when launched, set through
+//! Hyprland or Sway IPC, GNOME's input sources or setxkbmap. The layout
+//! they replace is kept in the state directory, to be restored once the
+//! environment is stopped; Sway cannot report it, so it is not restored
+//! there.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command as ProcessCommand;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::appearance::gvariant_string;
+use crate::config::Keyboard;
+use crate::launch::is_command_available;
+use crate::paths;
+
+const INPUT_SOURCES: &str = "org.gnome.desktop.input-sources";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Hyprland,
+    Sway,
+    Gnome,
+    X11,
+}
+
+fn backend() -> Option<Backend> {
+    let has = |var: &str| std::env::var_os(var).is_some_and(|value| !value.is_empty());
+    if has("HYPRLAND_INSTANCE_SIGNATURE") && is_command_available("hyprctl") {
+        Some(Backend::Hyprland)
+    } else if has("SWAYSOCK") && is_command_available("swaymsg") {
+        Some(Backend::Sway)
+    } else if std::env::var("XDG_CURRENT_DESKTOP").is_ok_and(|desktop| desktop.contains("GNOME"))
+        && is_command_available("gsettings")
+    {
+        Some(Backend::Gnome)
+    } else if has("DISPLAY") && is_command_available("setxkbmap") {
+        Some(Backend::X11)
+    } else {
+        None
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> io::Result<String> {
+    let output = ProcessCommand::new(program).args(args).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The strings quoted in a GVariant text, e.g. `['ctrl:nocaps']`.
+fn quoted_strings(text: &str) -> Vec<String> {
+    text.split('\'')
+        .skip(1)
+        .step_by(2)
+        .map(str::to_string)
+        .collect()
+}
+
+fn hyprland_option(name: &str) -> io::Result<String> {
+    let output = run("hyprctl", &["getoption", name, "-j"])?;
+    let value: serde_yaml::Value = serde_yaml::from_str(&output).map_err(io::Error::other)?;
+    Ok(value
+        .get("str")
+        .and_then(serde_yaml::Value::as_str)
+        .unwrap_or_default()
+        .trim()
+        .to_string())
+}
+
+/// The keyboard layout in use, or `None` when it cannot be read.
+fn current(backend: Backend) -> io::Result<Option<Keyboard>> {
+    let keyboard = match backend {
+        Backend::Hyprland => Keyboard {
+            layout: hyprland_option("input:kb_layout")?,
+            variant: Some(hyprland_option("input:kb_variant")?),
+            options: Some(hyprland_option("input:kb_options")?),
+        },
+        Backend::Sway => return Ok(None),
+        Backend::Gnome => {
+            // `[('xkb', 'us'), ('xkb', 'fr+azerty')]`
+            let sources = quoted_strings(&run("gsettings", &["get", INPUT_SOURCES, "sources"])?);
+            let (layouts, variants): (Vec<&str>, Vec<&str>) = sources
+                .chunks(2)
+                .filter(|source| source[0] == "xkb" && source.len() == 2)
+                .map(|source| source[1].split_once('+').unwrap_or((&source[1], "")))
+                .unzip();
+            let options =
+                quoted_strings(&run("gsettings", &["get", INPUT_SOURCES, "xkb-options"])?);
+            Keyboard {
+                layout: layouts.join(","),
+                variant: Some(variants.join(",")),
+                options: Some(options.join(",")),
+            }
+        }
+        Backend::X11 => {
+            let mut keyboard = Keyboard {
+                variant: Some(String::new()),
+                options: Some(String::new()),
+                ..Keyboard::default()
+            };
+            for line in run("setxkbmap", &["-query"])?.lines() {
+                match line.split_once(':') {
+                    Some(("layout", value)) => keyboard.layout = value.trim().to_string(),
+                    Some(("variant", value)) => keyboard.variant = Some(value.trim().to_string()),
+                    Some(("options", value)) => keyboard.options = Some(value.trim().to_string()),
+                    _ => {}
+                }
+            }
+            keyboard
+        }
+    };
+    Ok(Some(keyboard))
+}
+
+/// Switches to `keyboard`. Without a variant, the layouts use their
+/// default one; without options, the current options are kept.
+fn set(backend: Backend, keyboard: &Keyboard) -> io::Result<()> {
+    let variant = keyboard.variant.as_deref().unwrap_or_default();
+    match backend {
+        Backend::Hyprland => {
+            run("hyprctl", &["keyword", "input:kb_layout", &keyboard.layout])?;
+            run("hyprctl", &["keyword", "input:kb_variant", variant])?;
+            if let Some(options) = &keyboard.options {
+                run("hyprctl", &["keyword", "input:kb_options", options])?;
+            }
+        }
+        Backend::Sway => {
+            let input = |setting: &str, value: &str| {
+                run("swaymsg", &["input", "type:keyboard", setting, value])
+            };
+            input("xkb_layout", &keyboard.layout)?;
+            input("xkb_variant", variant)?;
+            if let Some(options) = &keyboard.options {
+                input("xkb_options", options)?;
+            }
+        }
+        Backend::Gnome => {
+            let mut variants = variant.split(',');
+            let sources: Vec<String> = keyboard
+                .layout
+                .split(',')
+                .map(|layout| match variants.next().filter(|v| !v.is_empty()) {
+                    Some(variant) => format!("{}+{}", layout, variant),
+                    None => layout.to_string(),
+                })
+                .map(|source| format!("('xkb', {})", gvariant_string(&source)))
+                .collect();
+            run(
+                "gsettings",
+                &[
+                    "set",
+                    INPUT_SOURCES,
+                    "sources",
+                    &format!("[{}]", sources.join(", ")),
+                ],
+            )?;
+            if let Some(options) = &keyboard.options {
+                let options: Vec<String> = options
+                    .split(',')
+                    .filter(|option| !option.is_empty())
+                    .map(gvariant_string)
+                    .collect();
+                run(
+                    "gsettings",
+                    &[
+                        "set",
+                        INPUT_SOURCES,
+                        "xkb-options",
+                        &format!("[{}]", options.join(", ")),
+                    ],
+                )?;
+            }
+        }
+        Backend::X11 => {
+            let mut args = vec!["-layout", &keyboard.layout, "-variant", variant];
+            if let Some(options) = &keyboard.options {
+                // An empty option clears the current ones first
+                args.extend(["-option", "", "-option", options]);
+            }
+            run("setxkbmap", &args)?;
+        }
+    }
+    Ok(())
+}
+
+/// The environment whose layout is in use, and the layout from before the
+/// first environment switched it.
+#[derive(Debug, Serialize, Deserialize)]
+struct Switched {
+    env: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous: Option<Keyboard>,
+}
+
+fn switched_path() -> PathBuf {
+    paths::state_dir().join("keyboard.yaml")
+}
+
+fn load_switched() -> Option<Switched> {
+    let contents = fs::read_to_string(switched_path()).ok()?;
+    serde_yaml::from_str(&contents).ok()
+}
+
+/// Switches to the keyboard layout of `env`.
+pub fn apply(env: &str, keyboard: &Keyboard) -> io::Result<()> {
+    let Some(backend) = backend() else {
+        warn!(
+            "Cannot set the keyboard layout of '{}', no Hyprland, Sway, GNOME or setxkbmap found",
+            env
+        );
+        return Ok(());
+    };
+    let previous = match load_switched() {
+        // Another environment switched it already
+        Some(switched) => switched.previous,
+        None => current(backend)?,
+    };
+    set(backend, keyboard)?;
+
+    let switched = Switched {
+        env: env.to_string(),
+        previous,
+    };
+    let path = switched_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(
+        &path,
+        serde_yaml::to_string(&switched).map_err(io::Error::other)?,
+    )?;
+    info!(
+        "Switched to the keyboard layout {} for {}",
+        keyboard.layout, env
+    );
+    Ok(())
+}
+
+/// The environment whose keyboard layout is in use, if any.
+pub fn switched_env() -> Option<String> {
+    load_switched().map(|switched| switched.env)
+}
+
+/// Restores the layout from before `env` switched it, unless another
+/// environment switched it since.
+pub fn restore(env: &str) -> io::Result<()> {
+    let Some(switched) = load_switched().filter(|switched| switched.env == env) else {
+        return Ok(());
+    };
+    if let (Some(previous), Some(backend)) = (&switched.previous, backend()) {
+        set(backend, previous)?;
+        info!(
+            "Restored the keyboard layout {} after {}",
+            previous.layout, env
+        );
+    }
+    fs::remove_file(switched_path())
+}
@@ -0,0 +1,2024 @@
+//! How each kind of app entry is checked, started and detected.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::net::UdpSocket;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command as ProcessCommand, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::config::{App, AppKind, Config, Environment, Gpu, Sandbox, Slice};
+use crate::crashes;
+use crate::desktop::{find_desktop_file, nix_profiles, resolve_desktop_id, DesktopEntry};
+use crate::paths;
+use crate::platform;
+use crate::process;
+use crate::resources;
+
+/// Searched for AppImages when the config lists no `appimage_dirs`.
+const DEFAULT_APPIMAGE_DIR: &str = "~/Applications";
+
+/// Whether `cmd` is in PATH or, as clovis may be started with a minimal
+/// PATH, installed in one of the Nix profiles, the NixOS security wrappers
+/// or `~/.local/bin` (where `distrobox-export --bin` puts binaries).
+pub fn is_command_available(cmd: &str) -> bool {
+    succeeds(ProcessCommand::new("which").arg(cmd))
+        || nix_profiles()
+            .into_iter()
+            .map(|profile| profile.join("bin"))
+            .chain([
+                PathBuf::from("/run/wrappers/bin"),
+                expand_home("~/.local/bin"),
+            ])
+            .any(|dir| is_executable(&dir.join(cmd)))
+}
+
+/// Runs `command` quietly and returns whether it exited successfully.
+fn succeeds(command: &mut ProcessCommand) -> bool {
+    process::status(command.stdout(Stdio::null()).stderr(Stdio::null()))
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+fn is_snap_installed(name: &str) -> bool {
+    succeeds(ProcessCommand::new("snap").arg("list").arg(name))
+}
+
+/// Replaces a leading `~` with the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = std::env::var("HOME").unwrap_or_default();
+            PathBuf::from(format!("{}{}", home, rest))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Locates the AppImage of an entry: its `path` when set, otherwise the
+/// first file in the search directories whose name starts with the entry
+/// name (ignoring case) and ends in `.AppImage`.
+fn find_appimage(config: &Config, app: &App) -> Option<PathBuf> {
+    if let Some(path) = &app.path {
+        return Some(expand_home(path));
+    }
+    let default_dirs = [DEFAULT_APPIMAGE_DIR.to_string()];
+    let dirs = if config.appimage_dirs.is_empty() {
+        &default_dirs[..]
+    } else {
+        &config.appimage_dirs[..]
+    };
+    let prefix = app.name.to_lowercase();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(expand_home(dir)) else {
+            continue;
+        };
+        let mut candidates: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let file_name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                file_name.starts_with(&prefix) && file_name.ends_with(".appimage")
+            })
+            .collect();
+        candidates.sort();
+        if let Some(path) = candidates.into_iter().next() {
+            return Some(path);
+        }
+    }
+    None
+}
+
+pub fn shell(config: &Config) -> String {
+    config
+        .shell
+        .clone()
+        .or_else(|| std::env::var("SHELL").ok().filter(|s| !s.is_empty()))
+        .unwrap_or_else(|| "/bin/sh".to_string())
+}
+
+/// Quotes `word` for a POSIX shell when it needs to be.
+pub fn shell_quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+pub fn script_command(app: &App) -> io::Result<&str> {
+    app.command.as_deref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("script '{}' has no command", app.name),
+        )
+    })
+}
+
+/// Whether the script parses with the configured shell, without running it.
+fn is_script_valid(config: &Config, app: &App) -> bool {
+    let Ok(script) = script_command(app) else {
+        return false;
+    };
+    succeeds(
+        ProcessCommand::new(shell(config))
+            .arg("-n")
+            .arg("-c")
+            .arg(script),
+    )
+}
+
+/// The terminal emulator program and the arguments preceding the command
+/// it should run.
+fn terminal(config: &Config) -> Vec<String> {
+    let configured = config.terminal.clone().or_else(|| {
+        std::env::var("TERMINAL")
+            .ok()
+            .filter(|t| !t.is_empty())
+            .map(|t| format!("{} -e", t))
+    });
+    let words: Vec<String> = configured
+        .unwrap_or_else(|| "xterm -e".to_string())
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    if words.is_empty() {
+        vec!["xterm".to_string(), "-e".to_string()]
+    } else {
+        words
+    }
+}
+
+/// Builds a command running `program` with `args` in the terminal emulator.
+fn in_terminal(config: &Config, program: &str, args: &[&str]) -> ProcessCommand {
+    let terminal = terminal(config);
+    let mut command = ProcessCommand::new(&terminal[0]);
+    command.args(&terminal[1..]).arg(program).args(args);
+    command
+}
+
+/// Whether an `auto` entry names a URL or a file (or folder) to open with
+/// its default handler rather than a program.
+pub fn is_openable(name: &str) -> bool {
+    is_url(name) || (is_path(name) && !is_executable(&expand_home(name)))
+}
+
+pub fn is_url(name: &str) -> bool {
+    name.contains("://") || name.starts_with("mailto:")
+}
+
+pub fn is_path(name: &str) -> bool {
+    name.starts_with('/') || name.starts_with("~/")
+}
+
+fn xdg_open(name: &str) -> ProcessCommand {
+    let mut command = ProcessCommand::new("xdg-open");
+    if is_path(name) {
+        command.arg(expand_home(name));
+    } else {
+        command.arg(name);
+    }
+    command
+}
+
+/// Builds the command of an `auto` entry. Desktop files go through
+/// gtk-launch, except terminal apps whose `Exec` is run in the configured
+/// terminal emulator, since gtk-launch would start them without a window,
+/// and privileged apps whose `Exec` is run by root directly.
+fn auto_command(config: &Config, app: &App) -> ProcessCommand {
+    let id = resolve_desktop_id(&app.name);
+    let entry = id.as_deref().and_then(load_desktop_entry);
+    match entry {
+        Some(entry) if app.terminal || app.privileged || entry.is_true("Terminal") => {
+            exec_command(config, app, &entry, &entry.command_line())
+                .unwrap_or_else(|| gtk_launch(&entry.id))
+        }
+        Some(entry) if entry.is_true("DBusActivatable") => dbus_activate(&entry, None),
+        Some(entry) => gtk_launch(&entry.id),
+        None if app.terminal => in_terminal(config, &app.name, &[]),
+        None => gtk_launch(id.as_deref().unwrap_or(&app.name)),
+    }
+}
+
+/// Bus name of a D-Bus activatable entry, its id without `.desktop`.
+fn bus_name(entry: &DesktopEntry) -> &str {
+    entry.id.strip_suffix(".desktop").unwrap_or(&entry.id)
+}
+
+/// Object path the application exports, derived from its bus name as the
+/// spec requires (`org.gnome.Maps` serves `/org/gnome/Maps`).
+fn object_path(bus_name: &str) -> String {
+    format!("/{}", bus_name.replace('.', "/").replace('-', "_"))
+}
+
+/// Calls `org.freedesktop.Application.Activate` (or `ActivateAction`) on
+/// the session bus, letting D-Bus start the app or bring its running
+/// instance forward.
+fn dbus_activate(entry: &DesktopEntry, action: Option<&str>) -> ProcessCommand {
+    let name = bus_name(entry);
+    let mut command = ProcessCommand::new("gdbus");
+    command
+        .args(["call", "--session", "--dest", name, "--object-path"])
+        .arg(object_path(name));
+    match action {
+        Some(action) => command
+            .args(["--method", "org.freedesktop.Application.ActivateAction"])
+            .arg(format!("'{}'", action))
+            .args(["[]", "{}"]),
+        None => command
+            .args(["--method", "org.freedesktop.Application.Activate"])
+            .arg("{}"),
+    };
+    command
+}
+
+/// Whether the bus name of a D-Bus activatable entry is taken, i.e. the app
+/// runs.
+fn has_bus_owner(name: &str) -> bool {
+    let mut command = ProcessCommand::new("gdbus");
+    command
+        .args([
+            "call",
+            "--session",
+            "--dest",
+            "org.freedesktop.DBus",
+            "--object-path",
+            "/org/freedesktop/DBus",
+            "--method",
+            "org.freedesktop.DBus.NameHasOwner",
+            name,
+        ])
+        .stderr(Stdio::null());
+    process::output(&mut command)
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains("true"))
+        .unwrap_or(false)
+}
+
+/// The activatable entry an `auto` app launches, if it is launched over
+/// D-Bus.
+fn dbus_entry(app: &App) -> Option<DesktopEntry> {
+    if app.kind != AppKind::Auto || app.terminal || app.privileged {
+        return None;
+    }
+    let file = desktop_action(&app.name).map_or(app.name.as_str(), |(file, _)| file);
+    resolve_desktop_id(file)
+        .and_then(|id| load_desktop_entry(&id))
+        .filter(|entry| entry.is_true("DBusActivatable"))
+}
+
+fn load_desktop_entry(id: &str) -> Option<DesktopEntry> {
+    find_desktop_file(id).and_then(|path| DesktopEntry::parse(&path))
+}
+
+/// Builds the command running an `Exec` command line of the entry
+/// directly, in the terminal emulator for terminal apps.
+fn exec_command(
+    config: &Config,
+    app: &App,
+    entry: &DesktopEntry,
+    command_line: &[String],
+) -> Option<ProcessCommand> {
+    let (program, args) = command_line.split_first()?;
+    if app.terminal || entry.is_true("Terminal") {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        Some(in_terminal(config, program, &args))
+    } else {
+        let mut command = ProcessCommand::new(program);
+        command.args(args);
+        Some(command)
+    }
+}
+
+/// Splits a `firefox.desktop#new-private-window` entry into the desktop
+/// file and the action to run.
+fn desktop_action(name: &str) -> Option<(&str, &str)> {
+    name.split_once('#')
+        .filter(|(file, _)| file.ends_with(".desktop"))
+}
+
+/// Builds the command of an entry naming a desktop action, which gtk-launch
+/// cannot start.
+fn action_command(config: &Config, app: &App) -> io::Result<ProcessCommand> {
+    let not_found = || {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no desktop action '{}'", app.name),
+        )
+    };
+    let (file, action) = desktop_action(&app.name).ok_or_else(not_found)?;
+    let entry = resolve_desktop_id(file)
+        .and_then(|id| load_desktop_entry(&id))
+        .ok_or_else(not_found)?;
+    if dbus_entry(app).is_some() && entry.actions.contains_key(action) {
+        return Ok(dbus_activate(&entry, Some(action)));
+    }
+    let command_line = entry.action_command_line(action).ok_or_else(not_found)?;
+    exec_command(config, app, &entry, &command_line).ok_or_else(not_found)
+}
+
+fn has_desktop_action(name: &str) -> bool {
+    desktop_action(name).is_some_and(|(file, action)| {
+        resolve_desktop_id(file)
+            .and_then(|id| load_desktop_entry(&id))
+            .is_some_and(|entry| {
+                entry.action_command_line(action).is_some()
+                    || (entry.is_true("DBusActivatable") && entry.actions.contains_key(action))
+            })
+    })
+}
+
+fn gtk_launch(id: &str) -> ProcessCommand {
+    let mut command = ProcessCommand::new("gtk-launch");
+    command.arg(id);
+    command
+}
+
+fn session_name(app: &App) -> &str {
+    app.session.as_deref().unwrap_or(&app.name)
+}
+
+/// Whether the multiplexer, the terminal emulator and the layout file (if
+/// any) of a session entry are all there.
+fn is_session_available(config: &Config, app: &App, multiplexer: &str) -> bool {
+    is_command_available(multiplexer)
+        && is_command_available(&terminal(config)[0])
+        && app
+            .layout
+            .as_ref()
+            .is_none_or(|layout| expand_home(layout).is_file())
+}
+
+fn tmux_session(config: &Config, app: &App) -> ProcessCommand {
+    let session = session_name(app);
+    match &app.layout {
+        Some(layout) => {
+            let layout = expand_home(layout).to_string_lossy().into_owned();
+            in_terminal(
+                config,
+                "tmux",
+                &[
+                    "new-session",
+                    "-A",
+                    "-s",
+                    session,
+                    ";",
+                    "source-file",
+                    &layout,
+                ],
+            )
+        }
+        None => in_terminal(config, "tmux", &["new-session", "-A", "-s", session]),
+    }
+}
+
+fn zellij_session(config: &Config, app: &App) -> ProcessCommand {
+    let session = session_name(app);
+    match &app.layout {
+        Some(layout) => {
+            let layout = expand_home(layout).to_string_lossy().into_owned();
+            in_terminal(
+                config,
+                "zellij",
+                &["--session", session, "--layout", &layout],
+            )
+        }
+        None => in_terminal(config, "zellij", &["attach", "--create", session]),
+    }
+}
+
+fn has_zellij_session(name: &str) -> bool {
+    let mut command = ProcessCommand::new("zellij");
+    command
+        .args(["list-sessions", "--short"])
+        .stderr(Stdio::null());
+    process::output(&mut command)
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .any(|line| line.trim() == name)
+        })
+        .unwrap_or(false)
+}
+
+/// Escapes `text` so pgrep matches it literally.
+pub fn regex_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.^$|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Whether the browser takes Firefox style arguments rather than Chromium
+/// ones.
+fn is_firefox_like(browser: &str) -> bool {
+    let program = Path::new(browser)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    ["firefox", "librewolf", "waterfox", "floorp", "zen"]
+        .iter()
+        .any(|name| program.contains(name))
+}
+
+/// Arguments selecting the profile of a `browser` entry.
+fn browser_profile_args(app: &App) -> Vec<String> {
+    match &app.profile {
+        // A second Firefox instance is needed when another profile is open
+        Some(profile) if is_firefox_like(&app.name) => vec![
+            "-P".to_string(),
+            profile.clone(),
+            "--new-instance".to_string(),
+        ],
+        Some(profile) => vec![format!("--profile-directory={}", profile)],
+        None => Vec::new(),
+    }
+}
+
+fn browser_command(app: &App) -> ProcessCommand {
+    let mut command = ProcessCommand::new(&app.name);
+    command.args(browser_profile_args(app));
+    if !app.urls.is_empty() {
+        command.arg("--new-window").args(&app.urls);
+    }
+    command
+}
+
+/// `docker compose` when Docker is installed, `podman compose` otherwise.
+fn compose_engine() -> &'static str {
+    if is_command_available("docker") {
+        "docker"
+    } else {
+        "podman"
+    }
+}
+
+fn compose_file(app: &App) -> io::Result<PathBuf> {
+    app.path.as_deref().map(expand_home).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("compose entry '{}' has no path", app.name),
+        )
+    })
+}
+
+/// A compose command for the project of the entry, to which the action is
+/// appended.
+fn compose_command(app: &App) -> io::Result<ProcessCommand> {
+    let mut command = ProcessCommand::new(compose_engine());
+    command
+        .arg("compose")
+        .arg("-f")
+        .arg(compose_file(app)?)
+        .arg("-p")
+        .arg(&app.name);
+    Ok(command)
+}
+
+/// Services of the compose entry that are currently running.
+fn running_services(app: &App) -> Vec<String> {
+    let Ok(mut command) = compose_command(app) else {
+        return Vec::new();
+    };
+    command
+        .args(["ps", "--services", "--filter", "status=running"])
+        .stderr(Stdio::null());
+    let Ok(output) = process::output(&mut command) else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|service| !service.is_empty())
+        .filter(|service| app.services.is_empty() || app.services.iter().any(|s| s == service))
+        .map(str::to_string)
+        .collect()
+}
+
+fn container_name(app: &App) -> io::Result<&str> {
+    app.container.as_deref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("distrobox entry '{}' has no container", app.name),
+        )
+    })
+}
+
+/// Whether `distrobox list` knows the container.
+fn has_distrobox_container(name: &str) -> bool {
+    let mut command = ProcessCommand::new("distrobox");
+    command.arg("list").arg("--no-color").stderr(Stdio::null());
+    process::output(&mut command)
+        .map(|output| {
+            // Rows look like `ID | NAME | STATUS | IMAGE`
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .skip(1)
+                .any(|line| line.split('|').nth(1).map(str::trim) == Some(name))
+        })
+        .unwrap_or(false)
+}
+
+fn ssh_host(app: &App) -> io::Result<&str> {
+    app.host.as_deref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("ssh entry '{}' has no host", app.name),
+        )
+    })
+}
+
+fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let bytes: Vec<u8> = mac
+        .split([':', '-'])
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect::<Option<_>>()?;
+    bytes.try_into().ok()
+}
+
+/// Broadcasts a Wake-on-LAN magic packet: six 0xff bytes followed by the
+/// MAC address repeated sixteen times.
+fn wake_on_lan(mac: &str) -> io::Result<()> {
+    let mac = parse_mac(mac).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid MAC address '{}'", mac),
+        )
+    })?;
+    let mut packet = vec![0xff; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&mac);
+    }
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&packet, "255.255.255.255:9")?;
+    info!("Sent Wake-on-LAN packet");
+    Ok(())
+}
+
+fn ssh_command(app: &App) -> io::Result<ProcessCommand> {
+    let host = ssh_host(app)?;
+    let mut command = ProcessCommand::new("ssh");
+    // Never prompt, nobody would see it
+    command.arg("-n").arg("-o").arg("BatchMode=yes");
+    if app.wake_on_lan.is_some() {
+        // Keep retrying while the host boots
+        command.arg("-o").arg("ConnectionAttempts=60");
+    }
+    command.arg(host).arg(script_command(app)?);
+    Ok(command)
+}
+
+/// A virsh command for the connection of a `vm` entry.
+fn virsh(app: &App) -> ProcessCommand {
+    let mut command = ProcessCommand::new("virsh");
+    if let Some(uri) = &app.uri {
+        command.arg("--connect").arg(uri);
+    }
+    command
+}
+
+fn vm_state(app: &App) -> Option<String> {
+    let mut command = virsh(app);
+    command.arg("domstate").arg(&app.name).stderr(Stdio::null());
+    let output = process::output(&mut command).ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Starts the domain of a `vm` entry, unless it is running already.
+fn start_vm(app: &App) -> io::Result<()> {
+    if vm_state(app).as_deref() != Some("running") {
+        let mut command = virsh(app);
+        command
+            .arg("start")
+            .arg(&app.name)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let status = process::status(&mut command)?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "virsh start {} failed with {}",
+                app.name, status
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The virt-viewer command showing the console of a `vm` entry.
+fn vm_viewer(app: &App) -> ProcessCommand {
+    let mut command = ProcessCommand::new("virt-viewer");
+    if let Some(uri) = &app.uri {
+        command.arg("--connect").arg(uri);
+    }
+    command.arg("--wait").arg(&app.name);
+    command
+}
+
+/// Whether the app can be started in a systemd scope. Entries whose
+/// processes are managed elsewhere (containers, VMs, multiplexer servers,
+/// default handlers) are left alone.
+fn can_scope(app: &App) -> bool {
+    match app.kind {
+        // D-Bus activated apps are started by the bus, not by us
+        AppKind::Auto => !is_openable(&app.name) && dbus_entry(app).is_none(),
+        AppKind::TmuxSession | AppKind::ZellijSession | AppKind::Compose | AppKind::Vm => false,
+        _ => true,
+    }
+}
+
+/// Whether apps may have been started in scopes, which are then the most
+/// reliable way to tell whether they run.
+fn scopes_in_use(config: &Config) -> bool {
+    config.systemd_scope || config.environments.values().any(|env| env.slice.is_some())
+}
+
+fn uses_scope(config: &Config, app: &App) -> bool {
+    scopes_in_use(config) && can_scope(app)
+}
+
+/// Name of the transient scope unit an app is started in.
+fn scope_unit(app: &App) -> String {
+    let name: String = app
+        .name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("clovis-{}.scope", name)
+}
+
+/// Builds a command running `command` through the wrapper `program`, which
+/// is passed `args` first. Environment changes of `command` are kept.
+fn wrap<S: AsRef<OsStr>>(program: &str, args: &[S], command: &ProcessCommand) -> ProcessCommand {
+    let mut wrapped = ProcessCommand::new(program);
+    wrapped
+        .args(args)
+        .arg(command.get_program())
+        .args(command.get_args());
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => wrapped.env(key, value),
+            None => wrapped.env_remove(key),
+        };
+    }
+    wrapped
+}
+
+fn in_scope(app: &App, slice: Option<&Slice>, command: &ProcessCommand) -> ProcessCommand {
+    let mut args = vec![
+        "--user".to_string(),
+        "--scope".to_string(),
+        "--collect".to_string(),
+        format!("--unit={}", scope_unit(app)),
+    ];
+    if let Some(slice) = slice {
+        args.push(format!("--slice={}", slice.unit()));
+    }
+    args.push("--".to_string());
+    wrap("systemd-run", &args, command)
+}
+
+/// Variables making NVIDIA drivers render an app on the discrete GPU.
+const NV_PRIME_OFFLOAD: [(&str, &str); 3] = [
+    ("__NV_PRIME_RENDER_OFFLOAD", "1"),
+    ("__GLX_VENDOR_LIBRARY_NAME", "nvidia"),
+    ("__VK_LAYER_NV_optimus", "NVIDIA_only"),
+];
+
+/// Selects the GPU the app renders on. The discrete GPU is picked with
+/// `prime-run` when installed, with the NVIDIA offload variables when the
+/// NVIDIA driver is loaded, and with `DRI_PRIME` for Mesa drivers otherwise.
+fn select_gpu(app: &App, command: ProcessCommand) -> ProcessCommand {
+    match app.gpu {
+        None => command,
+        Some(Gpu::Discrete) if is_command_available("prime-run") => {
+            wrap::<&str>("prime-run", &[], &command)
+        }
+        Some(Gpu::Discrete) if Path::new("/proc/driver/nvidia").exists() => {
+            let mut command = command;
+            command.envs(NV_PRIME_OFFLOAD);
+            command
+        }
+        Some(Gpu::Discrete) => {
+            let mut command = command;
+            command.env("DRI_PRIME", "1");
+            command
+        }
+        Some(Gpu::Integrated) => {
+            let mut command = command;
+            command.env("DRI_PRIME", "0");
+            for (key, _) in NV_PRIME_OFFLOAD {
+                command.env_remove(key);
+            }
+            command
+        }
+    }
+}
+
+/// Applies the resource limits of the slice. The slice has to exist, so
+/// this is done once apps were started in it.
+pub fn apply_slice_limits(slice: &Slice) -> io::Result<()> {
+    let properties: Vec<String> = [
+        ("MemoryMax", &slice.memory_max),
+        ("CPUQuota", &slice.cpu_quota),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| value.as_ref().map(|value| format!("{}={}", key, value)))
+    .collect();
+    if properties.is_empty() {
+        return Ok(());
+    }
+    let status = process::status(
+        ProcessCommand::new("systemctl")
+            .arg("--user")
+            .arg("set-property")
+            .arg("--runtime")
+            .arg(slice.unit())
+            .args(&properties),
+    )?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "setting limits of {} failed with {}",
+            slice.unit(),
+            status
+        )));
+    }
+    info!("Applied {} to {}", properties.join(" "), slice.unit());
+    Ok(())
+}
+
+fn is_scope_active(app: &App) -> bool {
+    succeeds(
+        ProcessCommand::new("systemctl")
+            .arg("--user")
+            .arg("is-active")
+            .arg("--quiet")
+            .arg(scope_unit(app)),
+    )
+}
+
+/// Environment variable naming the app a spawned process was started for.
+pub const APP_VARIABLE: &str = "CLOVIS_APP";
+
+/// The app a process was launched for, from its environment.
+pub fn app_of(pid: u32) -> Option<String> {
+    let environ = fs::read(format!("/proc/{}/environ", pid)).ok()?;
+    let prefix = format!("{}=", APP_VARIABLE);
+    environ
+        .split(|byte| *byte == 0)
+        .find_map(|variable| variable.strip_prefix(prefix.as_bytes()))
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+}
+
+/// `name` with what cannot be in a file name replaced, for the files kept
+/// about an app.
+pub fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Where the output of an app is kept, across its launches.
+pub fn log_path(name: &str) -> PathBuf {
+    paths::state_dir()
+        .join("logs")
+        .join(format!("{}.log", file_name(name)))
+}
+
+/// Size past which the log of an app starts over at its next launch, in
+/// bytes.
+const MAX_APP_LOG_SIZE: u64 = 1024 * 1024;
+
+/// Opens the log of an app to append to it, so that instances running side
+/// by side do not wipe the output of each other.
+fn open_log(app: &App) -> io::Result<fs::File> {
+    let path = log_path(&app.name);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    if log.metadata()?.len() >= MAX_APP_LOG_SIZE {
+        log.set_len(0)?;
+    }
+    Ok(log)
+}
+
+/// Makes the spawned process a daemon: it gets its own session, so closing
+/// the terminal clovis runs in does not take it down, and it is forked once
+/// more so that it is adopted by init instead of remaining our child.
+fn detach(command: &mut ProcessCommand) {
+    // SAFETY: only async-signal-safe functions are called between fork and
+    // exec.
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            match libc::fork() {
+                -1 => Err(io::Error::last_os_error()),
+                // The intermediate process exits right away, the grandchild
+                // goes on to exec the app
+                0 => Ok(()),
+                _ => libc::_exit(0),
+            }
+        });
+    }
+}
+
+fn flake_ref(app: &App) -> String {
+    app.flake
+        .clone()
+        .unwrap_or_else(|| format!("nixpkgs#{}", app.name))
+}
+
+/// Whether the wrappers the app is configured to run in are installed.
+fn are_wrappers_available(app: &App) -> bool {
+    let sandbox_available = match &app.sandbox {
+        None => true,
+        Some(Sandbox {
+            firejail: Some(_),
+            bwrap: Some(_),
+        }) => false,
+        Some(sandbox) => {
+            (sandbox.firejail.is_none() || is_command_available("firejail"))
+                && (sandbox.bwrap.is_none() || is_command_available("bwrap"))
+        }
+    };
+    sandbox_available
+        && (!app.privileged || is_command_available("pkexec"))
+        && (!app.gamemode || is_command_available("gamemoderun"))
+        && (app.gamescope.is_none() || is_command_available("gamescope"))
+}
+
+/// Variables passed through pkexec, which clears the environment, so that
+/// GUI apps running as root can reach the display.
+const DISPLAY_VARIABLES: [&str; 5] = [
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "XDG_RUNTIME_DIR",
+    "XDG_SESSION_TYPE",
+];
+
+/// Runs the command as root through pkexec, which asks for authentication
+/// with the polkit agent of the session. [`APP_VARIABLE`] is passed through
+/// as well, for the crashes of the app to be noticed.
+fn elevate(app: &App, command: ProcessCommand) -> ProcessCommand {
+    let mut args = vec!["env".to_string(), format!("{}={}", APP_VARIABLE, app.name)];
+    for name in DISPLAY_VARIABLES {
+        let value = command
+            .get_envs()
+            .find(|(key, _)| *key == name)
+            .and_then(|(_, value)| value.map(|v| v.to_string_lossy().into_owned()))
+            .or_else(|| std::env::var(name).ok());
+        if let Some(value) = value {
+            args.push(format!("{}={}", name, value));
+        }
+    }
+    wrap("pkexec", &args, &command)
+}
+
+/// Variables always inherited by apps when the environment is cleaned.
+const SESSION_VARIABLES: [&str; 12] = [
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "PATH",
+    "SHELL",
+    "LANG",
+    "LANGUAGE",
+    "TZ",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "DBUS_SESSION_BUS_ADDRESS",
+];
+
+fn is_session_variable(name: &str) -> bool {
+    SESSION_VARIABLES.contains(&name) || name.starts_with("XDG_") || name.starts_with("LC_")
+}
+
+/// Drops the variables clovis inherited that are neither session variables
+/// nor allowed by the config, keeping those set for the app itself.
+fn clean_environment(allowed: &[String], command: &mut ProcessCommand) {
+    let explicit: Vec<_> = command
+        .get_envs()
+        .map(|(key, value)| (key.to_owned(), value.map(OsStr::to_owned)))
+        .collect();
+    command.env_clear();
+    command.envs(std::env::vars_os().filter(|(key, _)| {
+        let key = key.to_string_lossy();
+        is_session_variable(&key) || allowed.iter().any(|name| *name == key)
+    }));
+    for (key, value) in explicit {
+        if let Some(value) = value {
+            command.env(key, value);
+        }
+    }
+}
+
+/// Confines the app in its sandbox, if any.
+fn in_sandbox(app: &App, command: ProcessCommand) -> io::Result<ProcessCommand> {
+    let Some(sandbox) = &app.sandbox else {
+        return Ok(command);
+    };
+    match (&sandbox.firejail, &sandbox.bwrap) {
+        (Some(_), Some(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' has both a firejail and a bwrap sandbox", app.name),
+        )),
+        (Some(profile), None) => Ok(wrap(
+            "firejail",
+            &[format!("--profile={}", profile)],
+            &command,
+        )),
+        (None, Some(args)) => Ok(wrap("bwrap", args, &command)),
+        (None, None) => Ok(command),
+    }
+}
+
+/// Runs a game in gamemode and/or a gamescope session, gamemode applying
+/// to the game itself rather than to the compositor.
+fn game_wrappers(app: &App, command: ProcessCommand) -> ProcessCommand {
+    let command = if app.gamemode {
+        wrap::<&str>("gamemoderun", &[], &command)
+    } else {
+        command
+    };
+    match &app.gamescope {
+        Some(args) => {
+            let mut args = args.clone();
+            args.push("--".to_string());
+            wrap("gamescope", &args, &command)
+        }
+        None => command,
+    }
+}
+
+/// Whether the app can be launched on this machine.
+pub fn is_available(config: &Config, app: &App) -> bool {
+    check_available(config, app).is_ok()
+}
+
+/// Checks that the app can be launched on this machine, explaining why not
+/// when it can't.
+pub fn check_available(config: &Config, app: &App) -> Result<(), String> {
+    platform::current().find_app(config, app)
+}
+
+/// [`check_available`] where apps have desktop files.
+pub fn freedesktop_check(config: &Config, app: &App) -> Result<(), String> {
+    if !are_wrappers_available(app) || !is_kind_available(config, app) {
+        return Err("is not installed or not in PATH".to_string());
+    }
+    if app.kind == AppKind::Auto {
+        let file = desktop_action(&app.name).map_or(app.name.as_str(), |(file, _)| file);
+        if let Some(entry) = resolve_desktop_id(file).and_then(|id| load_desktop_entry(&id)) {
+            return check_desktop_entry(&entry);
+        }
+    }
+    Ok(())
+}
+
+/// Applies the rules of the desktop entry spec deciding whether an entry
+/// is usable in the current session. `NoDisplay` only hides an entry from
+/// menus, so it does not make it unavailable.
+fn check_desktop_entry(entry: &DesktopEntry) -> Result<(), String> {
+    if entry.is_true("Hidden") {
+        return Err("is deleted by its desktop file (Hidden=true)".to_string());
+    }
+    if let Some(try_exec) = entry.get("TryExec") {
+        let found = if is_path(try_exec) {
+            is_executable(Path::new(try_exec))
+        } else {
+            is_command_available(try_exec)
+        };
+        if !found {
+            return Err(format!(
+                "is not installed (TryExec '{}' not found)",
+                try_exec
+            ));
+        }
+    }
+    let current: Vec<String> = std::env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|d| !d.is_empty())
+        .map(str::to_string)
+        .collect();
+    // Outside of a session, as in cron or CI, there is no desktop to check
+    if current.is_empty() {
+        return Ok(());
+    }
+    let lists_current = |key: &str| {
+        entry.get(key).map(|desktops| {
+            desktops
+                .split(';')
+                .any(|desktop| current.iter().any(|c| c == desktop))
+        })
+    };
+    if lists_current("OnlyShowIn") == Some(false) {
+        return Err(format!(
+            "is only meant for other desktops (OnlyShowIn={})",
+            entry.get("OnlyShowIn").unwrap_or_default()
+        ));
+    }
+    if lists_current("NotShowIn") == Some(true) {
+        return Err(format!(
+            "is not meant for this desktop (NotShowIn={})",
+            entry.get("NotShowIn").unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
+
+fn is_kind_available(config: &Config, app: &App) -> bool {
+    match app.kind {
+        AppKind::Auto if desktop_action(&app.name).is_some() => has_desktop_action(&app.name),
+        AppKind::Auto if app.name.ends_with(".desktop") => resolve_desktop_id(&app.name).is_some(),
+        AppKind::Auto if is_openable(&app.name) => {
+            is_command_available("xdg-open")
+                && (is_url(&app.name) || expand_home(&app.name).exists())
+        }
+        AppKind::Auto => is_command_available(&app.name),
+        AppKind::Snap => is_snap_installed(&app.name),
+        AppKind::Appimage => find_appimage(config, app).is_some_and(|path| is_executable(&path)),
+        // Evaluating the flake could mean a download, so only `nix` itself
+        // is checked.
+        AppKind::Nix => is_command_available("nix"),
+        AppKind::Script => is_script_valid(config, app),
+        AppKind::TmuxSession => is_session_available(config, app, "tmux"),
+        AppKind::ZellijSession => is_session_available(config, app, "zellij"),
+        AppKind::Browser => is_command_available(&app.name),
+        AppKind::Compose => {
+            is_command_available(compose_engine())
+                && compose_file(app).is_ok_and(|path| path.is_file())
+        }
+        AppKind::Distrobox => container_name(app).is_ok_and(has_distrobox_container),
+        AppKind::Ssh => {
+            is_command_available("ssh")
+                && ssh_host(app).is_ok()
+                && app.command.is_some()
+                && app
+                    .wake_on_lan
+                    .as_deref()
+                    .is_none_or(|mac| parse_mac(mac).is_some())
+        }
+        AppKind::Vm => {
+            vm_state(app).is_some() && (!app.viewer || is_command_available("virt-viewer"))
+        }
+    }
+}
+
+/// Builds the command launching the app, its wrappers and variables
+/// included (but not the systemd scope), without running anything. `None`
+/// for `vm` entries without a viewer, which only have their domain
+/// started.
+pub fn command(
+    config: &Config,
+    environment: &Environment,
+    app: &App,
+) -> io::Result<Option<ProcessCommand>> {
+    platform::current().command(config, environment, app)
+}
+
+/// [`command`] where apps have desktop files.
+pub fn freedesktop_command(
+    config: &Config,
+    environment: &Environment,
+    app: &App,
+) -> io::Result<Option<ProcessCommand>> {
+    let mut command = match app.kind {
+        AppKind::Auto if is_openable(&app.name) => xdg_open(&app.name),
+        AppKind::Auto if desktop_action(&app.name).is_some() => action_command(config, app)?,
+        AppKind::Auto => auto_command(config, app),
+        AppKind::Snap => {
+            let mut command = ProcessCommand::new("snap");
+            command.arg("run").arg(&app.name);
+            command
+        }
+        AppKind::Appimage => {
+            let path = find_appimage(config, app).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no AppImage found for '{}'", app.name),
+                )
+            })?;
+            ProcessCommand::new(path)
+        }
+        AppKind::Nix => {
+            let mut command = ProcessCommand::new("nix");
+            command.arg("run").arg(flake_ref(app));
+            command
+        }
+        AppKind::Script => {
+            let mut command = ProcessCommand::new(shell(config));
+            command.arg("-c").arg(script_command(app)?);
+            command
+        }
+        AppKind::TmuxSession => tmux_session(config, app),
+        AppKind::ZellijSession => zellij_session(config, app),
+        AppKind::Browser => browser_command(app),
+        AppKind::Compose => {
+            let mut command = compose_command(app)?;
+            command.arg("up").arg("-d").args(&app.services);
+            command
+        }
+        AppKind::Distrobox => {
+            let mut command = ProcessCommand::new("distrobox");
+            command
+                .arg("enter")
+                .arg("-n")
+                .arg(container_name(app)?)
+                .arg("--")
+                .arg(&app.name);
+            command
+        }
+        AppKind::Ssh => ssh_command(app)?,
+        AppKind::Vm if app.viewer => vm_viewer(app),
+        AppKind::Vm => return Ok(None),
+    };
+
+    // Electron apps need a DISPLAY, the first one when the session has none
+    if std::env::var_os("DISPLAY").is_none() {
+        command.env("DISPLAY", ":0");
+    }
+    if let Some(style) = environment
+        .appearance
+        .as_ref()
+        .and_then(|a| a.qt_style.as_ref())
+    {
+        command.env("QT_STYLE_OVERRIDE", style);
+    }
+    if let Some(sink) = environment
+        .audio
+        .as_ref()
+        .and_then(|audio| audio.app_sinks.get(&app.name))
+    {
+        command.env("PULSE_SINK", sink);
+    }
+
+    if app.privileged {
+        command = elevate(app, command);
+    }
+    command = in_sandbox(app, command)?;
+    command = game_wrappers(app, command);
+    command = select_gpu(app, command);
+    Ok(Some(command))
+}
+
+/// Starts the app of `environment` in the background, detached from our
+/// stdout and stderr.
+pub fn spawn(config: &Config, environment: &Environment, app: &App) -> io::Result<()> {
+    platform::current().spawn(config, environment, app, &[])
+}
+
+/// [`spawn`] with `args` appended to the command of the app.
+pub fn spawn_with_args(
+    config: &Config,
+    environment: &Environment,
+    app: &App,
+    args: &[String],
+) -> io::Result<()> {
+    platform::current().spawn(config, environment, app, args)
+}
+
+/// Appends `args` to `command`, built for `app`, so that they reach the
+/// app through its wrappers. Errors for apps that take no arguments.
+pub fn append_args(
+    app: &App,
+    mut command: ProcessCommand,
+    args: &[String],
+) -> io::Result<ProcessCommand> {
+    if args.is_empty() {
+        return Ok(command);
+    }
+    let unsupported = match app.kind {
+        AppKind::TmuxSession | AppKind::ZellijSession | AppKind::Compose | AppKind::Vm => true,
+        // Activation over D-Bus opens no files
+        AppKind::Auto => command.get_program() == "gdbus",
+        _ => false,
+    };
+    if unsupported {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} takes no arguments", app.name),
+        ));
+    }
+    match app.kind {
+        // The arguments of `sh -c` start at $0, the snippet sees "$@"
+        AppKind::Script => command.arg(&app.name),
+        AppKind::Nix => command.arg("--"),
+        _ => &mut command,
+    };
+    command.args(args);
+    Ok(command)
+}
+
+/// Runs `command`, built for `app`, as a daemon, in a systemd scope when
+/// the config asks for one and `systemd` runs the session.
+pub fn spawn_detached(
+    config: &Config,
+    environment: &Environment,
+    app: &App,
+    command: Option<ProcessCommand>,
+    systemd: bool,
+) -> io::Result<()> {
+    if app.kind == AppKind::Ssh {
+        if let Some(mac) = &app.wake_on_lan {
+            wake_on_lan(mac)?;
+        }
+    }
+    if app.kind == AppKind::Vm {
+        start_vm(app)?;
+    }
+    let Some(mut command) = command else {
+        info!("Started {}", app.name);
+        return Ok(());
+    };
+    let slice = environment.slice.as_ref();
+    if (config.systemd_scope || slice.is_some())
+        && systemd
+        && can_scope(app)
+        && is_command_available("systemd-run")
+    {
+        command = in_scope(app, slice, &command);
+    }
+
+    if let Some(allowed) = &config.clean_env {
+        clean_environment(allowed, &mut command);
+    }
+
+    // Tells `clovis daemon` which app the process is, should it exit
+    command.env(APP_VARIABLE, &app.name);
+
+    // Keep stdout and stderr for crash reports, stdin comes from /dev/null
+    command.stdin(Stdio::null());
+    match open_log(app) {
+        Ok(log) => {
+            command.stdout(log.try_clone()?);
+            command.stderr(log);
+        }
+        Err(e) => {
+            warn!("Failed to open the log of {}: {}", app.name, e);
+            command.stdout(Stdio::null());
+            command.stderr(Stdio::null());
+        }
+    }
+
+    // Spawn the process in the background, reaping the intermediate process
+    resources::apply(&mut command, app)?;
+    detach(&mut command);
+    process::status(&mut command)?;
+    info!("Launched {} in the background", app.name);
+    Ok(())
+}
+
+/// The Wayland app id or X11 `WM_CLASS` the windows of an app are
+/// expected to have: `window_class`, else the `StartupWMClass` of its
+/// desktop file, else its name. Unknown for apps running in a terminal or
+/// handled by another program.
+pub fn window_class(app: &App) -> Option<String> {
+    if let Some(class) = &app.window_class {
+        return Some(class.clone());
+    }
+    if app.terminal {
+        return None;
+    }
+    match app.kind {
+        AppKind::Auto if is_openable(&app.name) => None,
+        AppKind::Auto | AppKind::Nix | AppKind::Distrobox | AppKind::Snap | AppKind::Browser => {
+            let name = desktop_action(&app.name).map_or(app.name.as_str(), |(file, _)| file);
+            let entry = resolve_desktop_id(name).and_then(|id| load_desktop_entry(&id));
+            if let Some(class) = entry.as_ref().and_then(|e| e.get("StartupWMClass")) {
+                return Some(class.to_string());
+            }
+            let name = name.strip_suffix(".desktop").unwrap_or(name);
+            Some(name.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// The `pgrep -f` pattern matching the processes of an app, if it runs as
+/// a process of its own. Apps known by name match the executable of that
+/// name only, not every command line containing it.
+pub fn process_pattern(config: &Config, app: &App) -> Option<String> {
+    let pattern = match app.kind {
+        // Whatever handles a URL or a file is not known up front
+        AppKind::Auto if is_openable(&app.name) => return None,
+        AppKind::Auto | AppKind::Nix | AppKind::Distrobox => {
+            let name = desktop_action(&app.name).map_or(app.name.as_str(), |(file, _)| file);
+            let name = name.strip_suffix(".desktop").unwrap_or(name);
+            format!("(^|/){}( |$)", regex_escape(name))
+        }
+        AppKind::Snap => format!("/snap/{}/", app.name),
+        AppKind::Appimage => find_appimage(config, app)?.to_string_lossy().into_owned(),
+        AppKind::Script => regex_escape(app.command.as_ref()?),
+        AppKind::TmuxSession | AppKind::ZellijSession | AppKind::Compose | AppKind::Vm => {
+            return None
+        }
+        AppKind::Ssh => format!(
+            "ssh .*{}.*{}",
+            regex_escape(app.host.as_ref()?),
+            regex_escape(app.command.as_ref()?)
+        ),
+        // Only a browser running with the same profile counts
+        AppKind::Browser => std::iter::once(regex_escape(&app.name))
+            .chain(
+                browser_profile_args(app)
+                    .iter()
+                    .take(2)
+                    .map(|a| regex_escape(a)),
+            )
+            .collect::<Vec<_>>()
+            .join(".*"),
+    };
+    Some(pattern)
+}
+
+/// The processes of a running app: the ones in its systemd scope, else the
+/// ones matching its process pattern. Empty for apps that do not run as
+/// processes of their own, such as sessions, containers and VMs.
+#[cfg(target_os = "linux")]
+pub fn pids(config: &Config, app: &App) -> Vec<u32> {
+    let parse = |text: &str| -> Vec<u32> {
+        text.split_whitespace()
+            .filter_map(|pid| pid.parse().ok())
+            .collect()
+    };
+    if uses_scope(config, app) && is_scope_active(app) {
+        let cgroup = process::output(
+            ProcessCommand::new("systemctl")
+                .arg("--user")
+                .arg("show")
+                .arg("--property=ControlGroup")
+                .arg("--value")
+                .arg(scope_unit(app)),
+        )
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+        if let Ok(cgroup) = cgroup.as_deref() {
+            if let Ok(procs) = fs::read_to_string(format!("/sys/fs/cgroup{}/cgroup.procs", cgroup))
+            {
+                return parse(&procs);
+            }
+        }
+    }
+    let Some(pattern) = process_pattern(config, app) else {
+        return Vec::new();
+    };
+    process::output(ProcessCommand::new("pgrep").arg("-f").arg(pattern))
+        .map(|output| parse(&String::from_utf8_lossy(&output.stdout)))
+        .unwrap_or_default()
+}
+
+pub fn is_running(config: &Config, app: &App) -> bool {
+    platform::current().is_running(config, app)
+}
+
+/// [`is_running`] where apps have desktop files, looking at their systemd
+/// scope first when `systemd` runs the session.
+pub fn freedesktop_is_running(config: &Config, app: &App, systemd: bool) -> bool {
+    if systemd && uses_scope(config, app) && is_scope_active(app) {
+        return true;
+    }
+    if let Some(entry) = dbus_entry(app) {
+        return has_bus_owner(bus_name(&entry));
+    }
+    match app.kind {
+        AppKind::TmuxSession => succeeds(
+            ProcessCommand::new("tmux")
+                .arg("has-session")
+                .arg("-t")
+                .arg(format!("={}", session_name(app))),
+        ),
+        AppKind::ZellijSession => has_zellij_session(session_name(app)),
+        AppKind::Compose => !running_services(app).is_empty(),
+        AppKind::Vm => vm_state(app).as_deref() == Some("running"),
+        _ => {
+            let Some(pattern) = process_pattern(config, app) else {
+                return false;
+            };
+            match process::output(ProcessCommand::new("pgrep").arg("-f").arg(pattern)) {
+                Ok(output) => !output.stdout.is_empty(),
+                Err(e) => {
+                    warn!("Failed to run pgrep to find {}: {}", app.name, e);
+                    false
+                }
+            }
+        }
+    }
+}
+
+/// Suspends a running app (`pause`), or lets a suspended one carry on.
+/// Returns whether there was anything to pause or resume; sessions of
+/// terminal multiplexers cannot be paused.
+pub fn pause(config: &Config, app: &App, pause: bool) -> io::Result<bool> {
+    platform::current().pause(config, app, pause)
+}
+
+/// [`pause`] with signals, sent to the whole systemd scope of the app when
+/// `systemd` runs the session.
+pub fn freedesktop_pause(
+    config: &Config,
+    app: &App,
+    pause: bool,
+    systemd: bool,
+) -> io::Result<bool> {
+    let active = match app.kind {
+        AppKind::Vm => vm_state(app).as_deref() == Some(if pause { "running" } else { "paused" }),
+        // Paused containers do not count as running
+        AppKind::Compose if !pause => true,
+        _ => is_running(config, app),
+    };
+    if !active {
+        return Ok(false);
+    }
+    let signal = if pause { "SIGSTOP" } else { "SIGCONT" };
+    let mut command = match app.kind {
+        // Signal the children of the app as well
+        _ if systemd && uses_scope(config, app) && is_scope_active(app) => {
+            let mut command = ProcessCommand::new("systemctl");
+            command
+                .arg("--user")
+                .arg("kill")
+                .arg(format!("--signal={}", signal))
+                .arg(scope_unit(app));
+            command
+        }
+        AppKind::TmuxSession | AppKind::ZellijSession => return Ok(false),
+        AppKind::Compose => {
+            let mut command = compose_command(app)?;
+            command
+                .arg(if pause { "pause" } else { "unpause" })
+                .args(&app.services);
+            command
+        }
+        AppKind::Vm => {
+            let mut command = virsh(app);
+            command
+                .arg(if pause { "suspend" } else { "resume" })
+                .arg(&app.name);
+            command
+        }
+        _ => {
+            let Some(pattern) = process_pattern(config, app) else {
+                return Ok(false);
+            };
+            let mut command = ProcessCommand::new("pkill");
+            command.arg(format!("-{}", signal)).arg("-f").arg(pattern);
+            command
+        }
+    };
+    let status = process::status(command.stdout(Stdio::null()).stderr(Stdio::null()))?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "{} {} failed with {}",
+            if pause { "pausing" } else { "resuming" },
+            app.name,
+            status
+        )));
+    }
+    Ok(true)
+}
+
+/// How long `stop` waits for an app to exit when it sets no
+/// `stop_timeout`.
+const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a killed app is given to be gone.
+const KILL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Stops a running app and waits for it to exit, killing it when it is
+/// still running after its `stop_timeout` and has `kill_on_timeout`.
+/// Returns whether there was anything to stop.
+pub fn stop(config: &Config, app: &App) -> io::Result<bool> {
+    let platform = platform::current();
+    if !is_running(config, app) || !platform.stop(config, app)? {
+        return Ok(false);
+    }
+    let timeout = app
+        .stop_timeout
+        .map_or(DEFAULT_STOP_TIMEOUT, Duration::from_secs);
+    match wait_for_exit(config, app, timeout) {
+        Err(e) if e.kind() == io::ErrorKind::TimedOut && app.kill_on_timeout => {
+            warn!("{}, killing it", e);
+            crashes::note_kill(&app.name);
+            if !platform.kill(config, app)? {
+                return Err(e);
+            }
+            wait_for_exit(config, app, KILL_TIMEOUT)?;
+        }
+        result => result?,
+    }
+    Ok(true)
+}
+
+/// Asks a running app to stop where apps have desktop files, stopping its
+/// systemd scope when `systemd` runs the session. Returns whether it could
+/// be told to.
+pub fn freedesktop_stop(config: &Config, app: &App, systemd: bool) -> io::Result<bool> {
+    let mut command = match app.kind {
+        _ if app.stop_command.is_some() => {
+            let mut command = ProcessCommand::new(shell(config));
+            command.arg("-c").args(&app.stop_command);
+            command
+        }
+        // Stopping the scope takes the children of the app down as well
+        _ if systemd && uses_scope(config, app) && is_scope_active(app) => {
+            let mut command = ProcessCommand::new("systemctl");
+            match app.stop_signal {
+                Some(signal) => command
+                    .arg("--user")
+                    .arg("kill")
+                    .arg(format!("--signal={}", signal.name())),
+                None => command.arg("--user").arg("stop"),
+            };
+            command.arg(scope_unit(app));
+            command
+        }
+        AppKind::TmuxSession => {
+            let mut command = ProcessCommand::new("tmux");
+            command
+                .arg("kill-session")
+                .arg("-t")
+                .arg(format!("={}", session_name(app)));
+            command
+        }
+        AppKind::ZellijSession => {
+            let mut command = ProcessCommand::new("zellij");
+            command.arg("kill-session").arg(session_name(app));
+            command
+        }
+        AppKind::Compose => {
+            let mut command = compose_command(app)?;
+            command.arg("stop").args(&app.services);
+            command
+        }
+        AppKind::Vm => {
+            let mut command = virsh(app);
+            command.arg("shutdown").arg(&app.name);
+            command
+        }
+        _ => {
+            let signal = app.stop_signal.map_or("SIGTERM", |signal| signal.name());
+            match kill_command(config, app, signal)? {
+                Some(command) => command,
+                None => return Ok(false),
+            }
+        }
+    };
+    let status = process::status(command.stdout(Stdio::null()).stderr(Stdio::null()))?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "stopping {} failed with {}",
+            app.name, status
+        )));
+    }
+    Ok(true)
+}
+
+/// Kills the processes of an app that did not stop when asked, with the
+/// rest of its systemd scope when `systemd` runs the session. Returns
+/// false for apps that are not processes clovis can find, such as sessions
+/// and VMs.
+pub fn freedesktop_kill(config: &Config, app: &App, systemd: bool) -> io::Result<bool> {
+    let mut command = if systemd && uses_scope(config, app) && is_scope_active(app) {
+        let mut command = ProcessCommand::new("systemctl");
+        command
+            .arg("--user")
+            .arg("kill")
+            .arg("--signal=SIGKILL")
+            .arg(scope_unit(app));
+        command
+    } else {
+        match app.kind {
+            AppKind::TmuxSession | AppKind::ZellijSession | AppKind::Compose | AppKind::Vm => {
+                return Ok(false)
+            }
+            _ => {}
+        }
+        match kill_command(config, app, "SIGKILL")? {
+            Some(command) => command,
+            None => return Ok(false),
+        }
+    };
+    let status = process::status(command.stdout(Stdio::null()).stderr(Stdio::null()))?;
+    // Exits with 1 when the processes exited in the meantime
+    Ok(status.success() || status.code() == Some(1))
+}
+
+/// The process group and session of a process, from `/proc/<pid>/stat`.
+fn process_group(pid: u32) -> Option<(u32, u32)> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    Some((fields.get(2)?.parse().ok()?, fields.get(3)?.parse().ok()?))
+}
+
+/// What `kill` is given to signal `pids`: the process groups clovis
+/// started them in, so that the helpers they spawned go with them, and
+/// the processes themselves otherwise. Apps are detached in a session of
+/// their own whose leader exits right away; a group is only signalled
+/// when it is such a session, so an app started from a shell does not
+/// take the shell down.
+fn kill_targets(pids: &[u32]) -> Vec<String> {
+    // SAFETY: getpgrp cannot fail
+    let own_group = unsafe { libc::getpgrp() } as u32;
+    let mut targets = Vec::new();
+    for pid in pids {
+        let target = match process_group(*pid) {
+            Some((group, session))
+                if group == session
+                    && group != own_group
+                    && (pids.contains(&group) || process_group(group).is_none()) =>
+            {
+                format!("-{}", group)
+            }
+            _ => pid.to_string(),
+        };
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    targets
+}
+
+/// The processes matching the process pattern of an app that clovis
+/// launched for it, so that the same program started by hand is left
+/// alone. All of the matching ones where the environment of processes
+/// cannot be read.
+fn launched_pids(config: &Config, app: &App) -> io::Result<Vec<u32>> {
+    let Some(pattern) = process_pattern(config, app) else {
+        return Ok(Vec::new());
+    };
+    let output = process::output(ProcessCommand::new("pgrep").arg("-f").arg(pattern))?;
+    let pids: Vec<u32> = String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .filter_map(|pid| pid.parse().ok())
+        .collect();
+    if !Path::new("/proc/self/environ").exists() {
+        return Ok(pids);
+    }
+    Ok(pids
+        .into_iter()
+        .filter(|pid| app_of(*pid).as_deref() == Some(app.name.as_str()))
+        .collect())
+}
+
+/// The `kill` command sending `signal`, such as `SIGTERM`, to the processes
+/// clovis launched for an app and to their groups. `None` when there are
+/// none.
+fn kill_command(config: &Config, app: &App, signal: &str) -> io::Result<Option<ProcessCommand>> {
+    let pids = launched_pids(config, app)?;
+    if pids.is_empty() {
+        return Ok(None);
+    }
+    let mut command = ProcessCommand::new("kill");
+    command
+        .arg("-s")
+        .arg(signal.trim_start_matches("SIG"))
+        .arg("--")
+        .args(kill_targets(&pids));
+    Ok(Some(command))
+}
+
+/// Whether a stopped app is still running. Of the processes matching its
+/// pattern, only the ones clovis launched count, as the others are not
+/// signalled.
+fn is_still_running(config: &Config, app: &App) -> bool {
+    is_running(config, app)
+        && (process_pattern(config, app).is_none()
+            || launched_pids(config, app).map_or(true, |pids| !pids.is_empty()))
+}
+
+/// Waits up to `timeout` for a stopped app to exit.
+fn wait_for_exit(config: &Config, app: &App, timeout: Duration) -> io::Result<()> {
+    let start = Instant::now();
+    while is_still_running(config, app) {
+        if start.elapsed() >= timeout {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{} still running after {}s", app.name, timeout.as_secs()),
+            ));
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    info!("Stopped {}", app.name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StopSignal;
+    use crate::process::fake::FakeRunner;
+
+    fn script(command: &str) -> App {
+        App {
+            kind: AppKind::Script,
+            command: Some(command.to_string()),
+            ..App::new("script")
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            shell: Some("/bin/sh".to_string()),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn scripts_are_run_by_the_shell() {
+        let runner = FakeRunner::install();
+        spawn(&config(), &Environment::default(), &script("sleep 10")).unwrap();
+        assert_eq!(runner.runs_of("/bin/sh"), [["/bin/sh", "-c", "sleep 10"]]);
+    }
+
+    #[test]
+    fn extra_arguments_reach_scripts_as_positional_parameters() {
+        let runner = FakeRunner::install();
+        let args = ["notes.md".to_string()];
+        spawn_with_args(
+            &config(),
+            &Environment::default(),
+            &script("vi \"$@\""),
+            &args,
+        )
+        .unwrap();
+        assert_eq!(
+            runner.runs_of("/bin/sh"),
+            [["/bin/sh", "-c", "vi \"$@\"", "script", "notes.md"]]
+        );
+    }
+
+    #[test]
+    fn sessions_take_no_extra_arguments() {
+        let app = App {
+            kind: AppKind::TmuxSession,
+            ..App::new("notes")
+        };
+        let args = ["notes.md".to_string()];
+        let error = append_args(&app, ProcessCommand::new("tmux"), &args).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn running_scripts_are_found_by_their_command() {
+        let runner = FakeRunner::install();
+        runner.reply("pgrep", 0, "4242\n");
+        assert!(is_running(&config(), &script("sleep 10 && echo done.")));
+        assert_eq!(
+            runner.runs_of("pgrep"),
+            [["pgrep", "-f", r"sleep 10 && echo done\."]]
+        );
+    }
+
+    #[test]
+    fn apps_known_by_name_match_their_executable() {
+        let runner = FakeRunner::install();
+        let app = App {
+            kind: AppKind::Nix,
+            ..App::new("org.mozilla.firefox")
+        };
+        assert!(!is_running(&config(), &app));
+        assert_eq!(
+            runner.runs_of("pgrep"),
+            [["pgrep", "-f", r"(^|/)org\.mozilla\.firefox( |$)"]]
+        );
+    }
+
+    /// A process running `sleep` as if clovis launched it for `app`, and
+    /// what pgrep prints when it finds it.
+    fn launched(app: &App) -> (std::process::Child, String) {
+        let child = ProcessCommand::new("sleep")
+            .arg("30")
+            .env(APP_VARIABLE, &app.name)
+            .spawn()
+            .unwrap();
+        // Its environment can only be read once it has started
+        for _ in 0..100 {
+            if app_of(child.id()).is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let pid = format!("{}\n", child.id());
+        (child, pid)
+    }
+
+    fn reap(mut child: std::process::Child) {
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn stopped_apps_are_waited_for() {
+        let runner = FakeRunner::install();
+        let app = script("sleep 10");
+        let (child, pid) = launched(&app);
+        for _ in 0..4 {
+            runner.reply("pgrep", 0, &pid);
+        }
+        runner.reply("pgrep", 1, "");
+        runner.reply("kill", 0, "");
+        assert!(stop(&config(), &app).unwrap());
+        assert_eq!(runner.runs_of("pgrep")[1], ["pgrep", "-f", "sleep 10"]);
+        assert_eq!(
+            runner.runs_of("kill"),
+            [["kill", "-s", "TERM", "--", pid.trim()]]
+        );
+        assert_eq!(runner.runs_of("pgrep").len(), 5);
+        reap(child);
+    }
+
+    #[test]
+    fn stop_commands_replace_signals() {
+        let runner = FakeRunner::install();
+        runner.reply("pgrep", 0, "4242\n");
+        runner.reply("pgrep", 1, "");
+        runner.reply("/bin/sh", 0, "");
+        let app = App {
+            stop_command: Some("tmux kill-session -t notes".to_string()),
+            ..script("tmux new-session -d -s notes")
+        };
+        assert!(stop(&config(), &app).unwrap());
+        assert_eq!(
+            runner.runs_of("/bin/sh"),
+            [["/bin/sh", "-c", "tmux kill-session -t notes"]]
+        );
+        assert!(runner.runs_of("kill").is_empty());
+    }
+
+    #[test]
+    fn stop_signals_are_sent() {
+        let runner = FakeRunner::install();
+        let app = App {
+            stop_signal: Some(StopSignal::Sigint),
+            ..script("htop")
+        };
+        let (child, pid) = launched(&app);
+        runner.reply("pgrep", 0, &pid);
+        runner.reply("pgrep", 0, &pid);
+        runner.reply("pgrep", 1, "");
+        runner.reply("kill", 0, "");
+        assert!(stop(&config(), &app).unwrap());
+        assert_eq!(
+            runner.runs_of("kill"),
+            [["kill", "-s", "INT", "--", pid.trim()]]
+        );
+        reap(child);
+    }
+
+    #[test]
+    fn apps_outliving_their_timeout_are_killed() {
+        let runner = FakeRunner::install();
+        let app = App {
+            stop_timeout: Some(0),
+            kill_on_timeout: true,
+            ..script("sleep 10")
+        };
+        let (child, pid) = launched(&app);
+        // Still running once the first timeout is up, then killed
+        for _ in 0..5 {
+            runner.reply("pgrep", 0, &pid);
+        }
+        runner.reply("pgrep", 1, "");
+        runner.reply("kill", 0, "");
+        assert!(stop(&config(), &app).unwrap());
+        assert_eq!(
+            runner.runs_of("kill"),
+            [
+                ["kill", "-s", "TERM", "--", pid.trim()],
+                ["kill", "-s", "KILL", "--", pid.trim()]
+            ]
+        );
+
+        let runner = FakeRunner::install();
+        runner.reply("pgrep", 0, &pid);
+        runner.reply("kill", 0, "");
+        let app = App {
+            kill_on_timeout: false,
+            ..app
+        };
+        assert!(stop(&config(), &app).is_err());
+        assert_eq!(runner.runs_of("kill").len(), 1);
+        reap(child);
+    }
+
+    #[test]
+    fn processes_started_by_hand_are_left_alone() {
+        let runner = FakeRunner::install();
+        let mut child = ProcessCommand::new("sleep")
+            .arg("30")
+            .env_remove(APP_VARIABLE)
+            .spawn()
+            .unwrap();
+        runner.reply("pgrep", 0, &format!("{}\n", child.id()));
+        assert!(!stop(&config(), &script("sleep 30")).unwrap());
+        assert!(runner.runs_of("kill").is_empty());
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn stopping_what_does_not_run_does_nothing() {
+        let runner = FakeRunner::install();
+        assert!(!stop(&config(), &script("sleep 10")).unwrap());
+        assert!(runner.runs_of("kill").is_empty());
+    }
+
+    #[test]
+    fn failed_stops_are_errors() {
+        let runner = FakeRunner::install();
+        let app = script("sleep 10");
+        let (child, pid) = launched(&app);
+        runner.reply("pgrep", 0, &pid);
+        runner.reply("kill", 1, "");
+        assert!(stop(&config(), &app).is_err());
+        reap(child);
+    }
+
+    #[test]
+    fn detached_apps_are_signalled_with_their_group() {
+        let mut command = ProcessCommand::new("sleep");
+        command.arg("30");
+        // SAFETY: setsid is async-signal-safe
+        unsafe {
+            command.pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            });
+        }
+        let mut detached = command.spawn().unwrap();
+        let mut attached = ProcessCommand::new("sleep").arg("30").spawn().unwrap();
+        let targets = kill_targets(&[detached.id(), attached.id()]);
+        detached.kill().unwrap();
+        attached.kill().unwrap();
+        let _ = (detached.wait(), attached.wait());
+        assert_eq!(
+            targets,
+            [format!("-{}", detached.id()), attached.id().to_string()]
+        );
+    }
+
+    #[test]
+    fn commands_missing_from_path_are_unavailable() {
+        let runner = FakeRunner::install();
+        let app = App::new("clovis-test-missing-app");
+        assert_eq!(
+            check_available(&config(), &app),
+            Err("is not installed or not in PATH".to_string())
+        );
+        assert_eq!(
+            runner.runs_of("which"),
+            [["which", "clovis-test-missing-app"]]
+        );
+        runner.reply("which", 0, "");
+        assert_eq!(check_available(&config(), &app), Ok(()));
+    }
+
+    #[test]
+    fn scripts_must_parse() {
+        let runner = FakeRunner::install();
+        runner.reply("/bin/sh", 2, "");
+        assert!(check_available(&config(), &script("if")).is_err());
+        assert_eq!(runner.runs_of("/bin/sh"), [["/bin/sh", "-n", "-c", "if"]]);
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::history::format_timestamp;
+use crate::paths;
+use crate::style::{paint, Style};
+
+/// How many launches are kept in the log.
+const MAX_LAUNCH_RECORDS: usize = 200;
+
+/// What happened to one app of a launched environment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Launched,
+    AlreadyRunning,
+    Failed(String),
+    /// Found running by `clovis adopt`, started outside of clovis.
+    Adopted,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppLaunch {
+    pub app: String,
+    pub outcome: Outcome,
+    /// How long starting the app took, in milliseconds. Missing for apps
+    /// that were not started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// How long its window took to appear once started, in milliseconds,
+    /// when it was waited for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LaunchRecord {
+    pub timestamp: i64,
+    pub env: String,
+    pub apps: Vec<AppLaunch>,
+}
+
+impl LaunchRecord {
+    pub fn failures(&self) -> usize {
+        self.apps
+            .iter()
+            .filter(|a| matches!(a.outcome, Outcome::Failed(_)))
+            .count()
+    }
+}
+
+pub fn launch_log_path() -> PathBuf {
+    paths::state_dir().join("launches.yaml")
+}
+
+pub fn load_launches(path: &Path) -> io::Result<Vec<LaunchRecord>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    serde_yaml::from_str(&contents).map_err(|e| {
+        error!("Failed to parse launch log: {}", e);
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })
+}
+
+/// Appends a launch of `env` to the log.
+pub fn record_launch(env: &str, apps: Vec<AppLaunch>) -> io::Result<()> {
+    let path = launch_log_path();
+    let mut launches = load_launches(&path)?;
+    launches.push(LaunchRecord {
+        timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+        env: env.to_string(),
+        apps,
+    });
+    let excess = launches.len().saturating_sub(MAX_LAUNCH_RECORDS);
+    launches.drain(..excess);
+
+    let contents = serde_yaml::to_string(&launches).map_err(|e| {
+        error!("Failed to serialize launch log: {}", e);
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })?;
+    fs::create_dir_all(paths::state_dir())?;
+    fs::write(path, contents)
+}
+
+/// Returns the environment launched most recently, if any.
+pub fn last_launched_env() -> io::Result<Option<String>> {
+    Ok(load_launches(&launch_log_path())?
+        .pop()
+        .map(|record| record.env))
+}
+
+/// The apps clovis started, as `(environment, app)` pairs, the most
+/// recently launched first and each app once.
+pub fn launched_apps() -> io::Result<Vec<(String, String)>> {
+    let mut apps: Vec<(String, String)> = Vec::new();
+    for record in load_launches(&launch_log_path())?.iter().rev() {
+        for app in record.apps.iter().rev() {
+            let started = matches!(app.outcome, Outcome::Launched | Outcome::Adopted);
+            if started && !apps.iter().any(|(_, a)| *a == app.app) {
+                apps.push((record.env.clone(), app.app.clone()));
+            }
+        }
+    }
+    Ok(apps)
+}
+
+/// Prints a table of what happened to each app of a launch.
+pub fn print_summary(apps: &[AppLaunch]) {
+    if apps.is_empty() {
+        return;
+    }
+    let app_width = apps.iter().map(|a| a.app.len()).max().unwrap_or(0).max(3);
+    println!();
+    let heading = format!("{:<app_width$}  {:<15}  {:>7}", "APP", "STATUS", "TIME");
+    println!("{}", paint(Style::Heading, heading));
+    for app in apps {
+        let status = match &app.outcome {
+            Outcome::Launched => paint(Style::Running, format!("{:<15}", "launched")),
+            Outcome::AlreadyRunning => paint(Style::Dim, format!("{:<15}", "already running")),
+            Outcome::Failed(_) => paint(Style::Failed, format!("{:<15}", "failed")),
+            Outcome::Adopted => paint(Style::Running, format!("{:<15}", "adopted")),
+        };
+        let time = app
+            .duration_ms
+            .map_or("-".to_string(), |ms| format!("{:.2}s", ms as f64 / 1000.0));
+        let reason = match &app.outcome {
+            Outcome::Failed(reason) => format!("  {}", reason),
+            _ => String::new(),
+        };
+        println!("{:<app_width$}  {}  {:>7}{}", app.app, status, time, reason);
+    }
+}
+
+pub fn show_launches(limit: usize) -> io::Result<()> {
+    let launches = load_launches(&launch_log_path())?;
+    if launches.is_empty() {
+        println!("No launches recorded yet.");
+        return Ok(());
+    }
+    let start = launches.len().saturating_sub(limit);
+    for record in &launches[start..] {
+        let launched = record
+            .apps
+            .iter()
+            .filter(|a| a.outcome == Outcome::Launched)
+            .count();
+        let skipped = record
+            .apps
+            .iter()
+            .filter(|a| a.outcome == Outcome::AlreadyRunning)
+            .count();
+        let adopted = record
+            .apps
+            .iter()
+            .filter(|a| a.outcome == Outcome::Adopted)
+            .count();
+        if adopted > 0 {
+            println!(
+                "{}  {:<15}  {} adopted",
+                format_timestamp(record.timestamp),
+                record.env,
+                adopted
+            );
+            continue;
+        }
+        println!(
+            "{}  {:<15}  {} launched, {} already running, {} failed",
+            format_timestamp(record.timestamp),
+            record.env,
+            launched,
+            skipped,
+            record.failures()
+        );
+        for app in &record.apps {
+            if let Outcome::Failed(reason) = &app.outcome {
+                println!("    {} failed: {}", app.app, reason);
+            }
+        }
+    }
+    Ok(())
+}
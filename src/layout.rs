@@ -0,0 +1,132 @@
+//! Monitor layouts environments require: which outputs are enabled, as
+//! reported by the compositor (Hyprland or Sway), wlr-randr or xrandr.
+
+use std::io;
+use std::process::{Command as ProcessCommand, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde_yaml::Value;
+
+use crate::config::{Config, Layout};
+use crate::launch::{is_command_available, shell};
+
+/// How long the `apply` command of a layout gets to take effect.
+const APPLY_TIMEOUT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+fn output_of(command: &mut ProcessCommand) -> Option<String> {
+    let output = command.stderr(Stdio::null()).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Names of the outputs of a JSON list whose entries are enabled according
+/// to `enabled_key`, or all of them when it is not given.
+fn json_outputs(output: &str, enabled_key: Option<&str>) -> Option<Vec<String>> {
+    let outputs: Value = serde_yaml::from_str(output).ok()?;
+    let names = outputs
+        .as_sequence()?
+        .iter()
+        .filter(|output| {
+            enabled_key.is_none_or(|key| output.get(key).and_then(Value::as_bool) == Some(true))
+        })
+        .filter_map(|output| output.get("name")?.as_str().map(str::to_string))
+        .collect();
+    Some(names)
+}
+
+/// Lists the names of the enabled outputs, e.g. `eDP-1`, or `None` when no
+/// supported tool is available.
+pub fn enabled_outputs() -> Option<Vec<String>> {
+    let mut outputs = if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some()
+        && is_command_available("hyprctl")
+    {
+        // Disabled monitors are only listed with `all`
+        json_outputs(
+            &output_of(ProcessCommand::new("hyprctl").args(["monitors", "-j"]))?,
+            None,
+        )?
+    } else if std::env::var_os("SWAYSOCK").is_some() && is_command_available("swaymsg") {
+        json_outputs(
+            &output_of(ProcessCommand::new("swaymsg").args(["-t", "get_outputs", "-r"]))?,
+            Some("active"),
+        )?
+    } else if std::env::var_os("WAYLAND_DISPLAY").is_some() && is_command_available("wlr-randr") {
+        json_outputs(
+            &output_of(ProcessCommand::new("wlr-randr").arg("--json"))?,
+            Some("enabled"),
+        )?
+    } else if is_command_available("xrandr") {
+        // ` 0: +*eDP-1 1920/344x1080/194+0+0  eDP-1`, after a count line
+        output_of(ProcessCommand::new("xrandr").arg("--listactivemonitors"))?
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().last().map(str::to_string))
+            .collect()
+    } else {
+        return None;
+    };
+    outputs.sort();
+    Some(outputs)
+}
+
+/// Whether exactly the outputs of `layout` are enabled.
+fn matches(layout: &Layout, outputs: &[String]) -> bool {
+    let mut wanted = layout.outputs.clone();
+    wanted.sort();
+    wanted == outputs
+}
+
+/// Makes sure the outputs of `layout` are the enabled ones, running its
+/// `apply` command when they are not. Returns false when the layout could
+/// not be set up.
+pub fn ensure(config: &Config, layout: &Layout) -> io::Result<bool> {
+    let Some(outputs) = enabled_outputs() else {
+        warn!("Cannot check the monitor layout, no compositor, wlr-randr or xrandr found");
+        return Ok(true);
+    };
+    if matches(layout, &outputs) {
+        return Ok(true);
+    }
+    let Some(apply) = &layout.apply else {
+        println!(
+            "The enabled outputs are {}, this environment needs {}.",
+            outputs.join(", "),
+            layout.outputs.join(", ")
+        );
+        return Ok(false);
+    };
+
+    info!("Applying the monitor layout: {}", apply);
+    let status = ProcessCommand::new(shell(config))
+        .arg("-c")
+        .arg(apply)
+        .stdout(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "the layout command failed with {}",
+            status
+        )));
+    }
+    let start = Instant::now();
+    loop {
+        let outputs = enabled_outputs().unwrap_or_default();
+        if matches(layout, &outputs) {
+            return Ok(true);
+        }
+        if start.elapsed() >= APPLY_TIMEOUT {
+            println!(
+                "The enabled outputs are still {} after applying the layout, this environment needs {}.",
+                outputs.join(", "),
+                layout.outputs.join(", ")
+            );
+            return Ok(false);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
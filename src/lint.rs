@@ -0,0 +1,382 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use log::info;
+use serde_yaml::Value;
+
+use crate::config::{parse_config, Config};
+use crate::history::save_config_with_history;
+use crate::migrate::migrate;
+use crate::network;
+use crate::schedule::Cron;
+use crate::schema::unknown_fields;
+use crate::style::{paint, Style};
+use crate::validate::{Problem, ProblemKind};
+
+/// A config hygiene problem found by `clovis lint`.
+#[derive(Debug)]
+enum Issue {
+    DuplicateApp {
+        env: String,
+        app: String,
+        count: usize,
+    },
+    EmptyEnvironment {
+        env: String,
+    },
+    UnknownField {
+        path: String,
+    },
+    UnknownDefault {
+        env: String,
+    },
+    DanglingAlias {
+        alias: String,
+        env: String,
+    },
+    ShadowingAlias {
+        alias: String,
+    },
+    DanglingConflict {
+        env: String,
+        other: String,
+    },
+    /// `app` is in both `env` and `other`, which stop each other.
+    ConflictingApp {
+        env: String,
+        other: String,
+        app: String,
+    },
+    /// `trigger` describes what runs the action, e.g. a schedule entry.
+    UnknownTriggerEnv {
+        trigger: String,
+        env: String,
+    },
+    InvalidSchedule {
+        cron: String,
+        reason: String,
+    },
+    /// `app` is both `privileged` and in a `sandbox`, where pkexec cannot
+    /// gain privileges.
+    PrivilegedSandbox {
+        env: String,
+        app: String,
+    },
+}
+
+impl Issue {
+    /// Whether `--fix` can resolve the issue without changing what any
+    /// environment launches.
+    fn is_fixable(&self) -> bool {
+        matches!(self, Issue::DuplicateApp { .. })
+    }
+
+    /// Whether the config refers to something it lacks or cannot be
+    /// understood, rather than being untidy.
+    fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Issue::UnknownDefault { .. }
+                | Issue::DanglingAlias { .. }
+                | Issue::DanglingConflict { .. }
+                | Issue::UnknownTriggerEnv { .. }
+                | Issue::InvalidSchedule { .. }
+                | Issue::PrivilegedSandbox { .. }
+        )
+    }
+
+    /// The environment and app the issue is about, when it is about one.
+    fn location(&self) -> (Option<String>, Option<String>) {
+        match self {
+            Issue::DuplicateApp { env, app, .. }
+            | Issue::ConflictingApp { env, app, .. }
+            | Issue::PrivilegedSandbox { env, app } => (Some(env.clone()), Some(app.clone())),
+            Issue::EmptyEnvironment { env } | Issue::DanglingConflict { env, .. } => {
+                (Some(env.clone()), None)
+            }
+            _ => (None, None),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Issue::DuplicateApp { env, app, count } => {
+                format!("environment '{}' lists '{}' {} times", env, app, count)
+            }
+            Issue::EmptyEnvironment { env } => format!("environment '{}' has no apps", env),
+            Issue::UnknownField { path } => format!("unknown field '{}'", path),
+            Issue::UnknownDefault { env } => {
+                format!("default environment '{}' does not exist", env)
+            }
+            Issue::DanglingAlias { alias, env } => {
+                format!("alias '{}' points to missing environment '{}'", alias, env)
+            }
+            Issue::ShadowingAlias { alias } => format!(
+                "alias '{}' has the same name as an environment and hides it",
+                alias
+            ),
+            Issue::DanglingConflict { env, other } => format!(
+                "environment '{}' conflicts with missing environment '{}'",
+                env, other
+            ),
+            Issue::ConflictingApp { env, other, app } => format!(
+                "'{}' is in both '{}' and '{}', which conflict, so launching one stops it for the other",
+                app, env, other
+            ),
+            Issue::UnknownTriggerEnv { trigger, env } => {
+                format!("{} acts on missing environment '{}'", trigger, env)
+            }
+            Issue::InvalidSchedule { cron, reason } => {
+                format!("invalid schedule '{}': {}", cron, reason)
+            }
+            Issue::PrivilegedSandbox { env, app } => format!(
+                "'{}' in environment '{}' is both privileged and sandboxed, pkexec cannot run in a sandbox",
+                app, env
+            ),
+        }
+    }
+}
+
+fn find_issues(raw: &Value, config: &Config) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = unknown_fields(raw)
+        .into_iter()
+        .map(|path| Issue::UnknownField { path })
+        .collect();
+
+    if let Some(default) = &config.default {
+        if !config
+            .environments
+            .contains_key(config.resolve_env(default))
+        {
+            issues.push(Issue::UnknownDefault {
+                env: default.clone(),
+            });
+        }
+    }
+    let mut aliases: Vec<_> = config.aliases.iter().collect();
+    aliases.sort();
+    for (alias, env) in aliases {
+        if config.environments.contains_key(alias) {
+            issues.push(Issue::ShadowingAlias {
+                alias: alias.clone(),
+            });
+        } else if !config.environments.contains_key(env) {
+            issues.push(Issue::DanglingAlias {
+                alias: alias.clone(),
+                env: env.clone(),
+            });
+        }
+    }
+
+    for entry in &config.schedule {
+        if let Err(reason) = Cron::parse(&entry.cron) {
+            issues.push(Issue::InvalidSchedule {
+                cron: entry.cron.clone(),
+                reason,
+            });
+        }
+        if !config
+            .environments
+            .contains_key(config.resolve_env(&entry.env))
+        {
+            issues.push(Issue::UnknownTriggerEnv {
+                trigger: format!("schedule '{}'", entry.cron),
+                env: entry.env.clone(),
+            });
+        }
+    }
+
+    for trigger in &config.device_triggers {
+        if !config
+            .environments
+            .contains_key(config.resolve_env(&trigger.env))
+        {
+            issues.push(Issue::UnknownTriggerEnv {
+                trigger: format!("a device trigger on {} events", trigger.event.as_str()),
+                env: trigger.env.clone(),
+            });
+        }
+    }
+
+    for rule in &config.network_rules {
+        if !config
+            .environments
+            .contains_key(config.resolve_env(&rule.env))
+        {
+            issues.push(Issue::UnknownTriggerEnv {
+                trigger: format!("the network rule for '{}'", network::describe(rule)),
+                env: rule.env.clone(),
+            });
+        }
+    }
+    for rule in config.calendar.iter().flat_map(|calendar| &calendar.rules) {
+        if !config
+            .environments
+            .contains_key(config.resolve_env(&rule.env))
+        {
+            issues.push(Issue::UnknownTriggerEnv {
+                trigger: format!("the calendar rule for '{}'", rule.pattern),
+                env: rule.env.clone(),
+            });
+        }
+    }
+    for env in config.idle.iter().flat_map(|idle| &idle.environments) {
+        if !config.environments.contains_key(env) {
+            issues.push(Issue::UnknownTriggerEnv {
+                trigger: "the idle section".to_string(),
+                env: env.clone(),
+            });
+        }
+    }
+
+    let mut envs: Vec<_> = config.environments.iter().collect();
+    envs.sort_by_key(|(name, _)| name.as_str());
+    for (name, env) in envs {
+        if env.apps.is_empty() {
+            issues.push(Issue::EmptyEnvironment { env: name.clone() });
+        }
+        for other in &env.conflicts_with {
+            let Some((other_name, other_env)) =
+                config.environments.get_key_value(config.resolve_env(other))
+            else {
+                issues.push(Issue::DanglingConflict {
+                    env: name.clone(),
+                    other: other.clone(),
+                });
+                continue;
+            };
+            // A conflict listed on both sides is reported once
+            let mutual = other_env
+                .conflicts_with
+                .iter()
+                .any(|back| config.resolve_env(back) == name);
+            if other_name == name || (mutual && other_name < name) {
+                continue;
+            }
+            let mut shared: Vec<&str> = env
+                .apps
+                .iter()
+                .map(|app| app.name.as_str())
+                .filter(|app| other_env.apps.iter().any(|a| a.name == *app))
+                .collect();
+            shared.sort();
+            shared.dedup();
+            for app in shared {
+                issues.push(Issue::ConflictingApp {
+                    env: name.clone(),
+                    other: other_name.clone(),
+                    app: app.to_string(),
+                });
+            }
+        }
+        for app in &env.apps {
+            if app.privileged && app.sandbox.is_some() {
+                issues.push(Issue::PrivilegedSandbox {
+                    env: name.clone(),
+                    app: app.name.clone(),
+                });
+            }
+        }
+        let mut seen: Vec<&str> = Vec::new();
+        for app in &env.apps {
+            if seen.contains(&app.name.as_str()) {
+                continue;
+            }
+            seen.push(&app.name);
+            let count = env.apps.iter().filter(|a| a.name == app.name).count();
+            if count > 1 {
+                issues.push(Issue::DuplicateApp {
+                    env: name.clone(),
+                    app: app.name.clone(),
+                    count,
+                });
+            }
+        }
+    }
+    issues
+}
+
+/// Removes repeated entries of an app, keeping the first one.
+fn remove_duplicates(config: &mut Config) {
+    for env in config.environments.values_mut() {
+        let mut seen = Vec::new();
+        env.apps.retain(|app| {
+            if seen.contains(&app.name) {
+                false
+            } else {
+                seen.push(app.name.clone());
+                true
+            }
+        });
+    }
+}
+
+/// The issues of the config `contents` was parsed into, for `clovis
+/// validate`.
+pub fn config_problems(contents: &str, config: &Config) -> Vec<Problem> {
+    let mut raw: Value = match serde_yaml::from_str(contents) {
+        Ok(raw) => raw,
+        Err(_) => return Vec::new(),
+    };
+    if migrate(&mut raw).is_err() {
+        return Vec::new();
+    }
+    find_issues(&raw, config)
+        .iter()
+        .map(|issue| {
+            let (env, app) = issue.location();
+            Problem {
+                kind: if issue.is_error() {
+                    ProblemKind::ConfigError
+                } else {
+                    ProblemKind::Warning
+                },
+                env,
+                app,
+                reason: issue.describe(),
+                suggestions: Vec::new(),
+            }
+        })
+        .collect()
+}
+
+/// Checks the config for hygiene problems and returns whether any remain.
+pub fn lint_config(config_path: &Path, fix: bool) -> io::Result<bool> {
+    let contents = fs::read_to_string(config_path)?;
+    let mut raw: Value = serde_yaml::from_str(&contents)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    migrate(&mut raw).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let (mut config, _) = parse_config(&contents)?;
+
+    let issues = find_issues(&raw, &config);
+    let mut remaining = 0;
+    for issue in &issues {
+        if fix && issue.is_fixable() {
+            println!("{} {}", paint(Style::Running, "fixed:"), issue.describe());
+        } else {
+            let hint = if issue.is_fixable() {
+                " (fixable with --fix)"
+            } else {
+                ""
+            };
+            println!(
+                "{} {}{}",
+                paint(Style::Warning, "warning:"),
+                issue.describe(),
+                hint
+            );
+            remaining += 1;
+        }
+    }
+
+    if fix && issues.iter().any(Issue::is_fixable) {
+        remove_duplicates(&mut config);
+        save_config_with_history(config_path, &config)?;
+        info!("Applied lint fixes");
+    }
+    if issues.is_empty() {
+        println!("No issues found.");
+    }
+    Ok(remaining > 0)
+}
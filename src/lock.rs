@@ -0,0 +1,41 @@
+//! A lock held while environments are launched or stopped, so that a
+//! command run twice in a row (a keybinding pressed twice) does not launch
+//! the same apps twice or write the state files at the same time.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use crate::paths;
+
+/// Released when dropped, or when the process exits.
+pub struct Lock {
+    _file: File,
+}
+
+fn flock(operation: libc::c_int) -> io::Result<Option<Lock>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(paths::create_runtime_dir()?.join("lock"))?;
+    // SAFETY: the descriptor is open for the duration of the call
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+        let e = io::Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(libc::EWOULDBLOCK) => Ok(None),
+            _ => Err(e),
+        };
+    }
+    Ok(Some(Lock { _file: file }))
+}
+
+/// Takes the lock, or returns `None` when another clovis holds it.
+pub fn try_lock() -> io::Result<Option<Lock>> {
+    flock(libc::LOCK_EX | libc::LOCK_NB)
+}
+
+/// Takes the lock, waiting for the clovis holding it to be done.
+pub fn lock() -> io::Result<Lock> {
+    flock(libc::LOCK_EX)?.ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))
+}
@@ -0,0 +1,203 @@
+//! Log records go to stderr, filtered by `-v`/`-q`, and are kept in a log
+//! file in the XDG state directory or in the systemd journal. stdout is
+//! left to the output of the commands.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use clap::ValueEnum;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use time::OffsetDateTime;
+
+use crate::history::format_timestamp;
+use crate::launch;
+use crate::paths::state_dir;
+use crate::style::{epaint, Style};
+
+/// Size past which the log file is rotated, in bytes.
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+/// How many rotated log files are kept besides the current one.
+const ROTATED_LOGS: usize = 3;
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Where log records are kept.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum LogTarget {
+    /// `clovis.log` in the state directory
+    File,
+    /// The systemd journal
+    Journald,
+    /// Nowhere, only stderr
+    None,
+}
+
+enum Sink {
+    File(Mutex<File>),
+    Journald(UnixDatagram),
+    None,
+}
+
+struct Logger {
+    console: LevelFilter,
+    kept: LevelFilter,
+    sink: Sink,
+}
+
+pub fn log_path() -> PathBuf {
+    state_dir().join("clovis.log")
+}
+
+/// Prints what `app` printed in its last launches, or the log of clovis
+/// itself without an app.
+pub fn show_log(app: Option<&str>) -> io::Result<()> {
+    let path = app.map_or_else(log_path, launch::log_path);
+    match fs::read(&path) {
+        Ok(log) => io::stdout().write_all(&log),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            println!("Nothing logged in {} yet.", path.display());
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Moves `clovis.log` to `clovis.log.1` (and so on) once it is too large.
+fn rotate(path: &PathBuf) -> io::Result<()> {
+    if fs::metadata(path).map_or(true, |m| m.len() < MAX_LOG_SIZE) {
+        return Ok(());
+    }
+    let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+    for n in (1..ROTATED_LOGS).rev() {
+        if rotated(n).exists() {
+            fs::rename(rotated(n), rotated(n + 1))?;
+        }
+    }
+    fs::rename(path, rotated(1))
+}
+
+fn open_log_file() -> io::Result<File> {
+    let path = log_path();
+    fs::create_dir_all(state_dir())?;
+    rotate(&path)?;
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn open_journal() -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(JOURNAL_SOCKET)?;
+    Ok(socket)
+}
+
+/// Sets up logging: `verbosity` is the number of `-v` flags, or -1 for
+/// `-q`. Falls back to logging to stderr only when the target cannot be
+/// opened.
+pub fn init(verbosity: i8, target: LogTarget) {
+    let console = match verbosity {
+        i8::MIN..=-1 => LevelFilter::Error,
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    let sink = match target {
+        LogTarget::File => open_log_file().map(|file| Sink::File(Mutex::new(file))),
+        LogTarget::Journald => open_journal().map(Sink::Journald),
+        LogTarget::None => Ok(Sink::None),
+    };
+    let sink = sink.unwrap_or_else(|e| {
+        eprintln!("warning: cannot keep logs ({}), logging to stderr only", e);
+        Sink::None
+    });
+    let kept = match sink {
+        Sink::None => LevelFilter::Off,
+        _ => console.max(LevelFilter::Info),
+    };
+    let logger = Logger {
+        console,
+        kept,
+        sink,
+    };
+    log::set_max_level(console.max(kept));
+    // Only fails when a logger is already set
+    let _ = log::set_boxed_logger(Box::new(logger));
+}
+
+/// Journal priority of a level, as in syslog.
+fn priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Appends a field in the journal native protocol, which needs a length
+/// prefix for values spanning several lines.
+fn journal_field(datagram: &mut Vec<u8>, key: &str, value: &str) {
+    datagram.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.console.max(self.kept)
+    }
+
+    fn log(&self, record: &Record) {
+        let level = record.level();
+        if level <= self.console {
+            let prefix = match level {
+                Level::Error => epaint(Style::Failed, "error:"),
+                Level::Warn => epaint(Style::Warning, "warning:"),
+                _ => epaint(Style::Dim, format!("{}:", level.as_str().to_lowercase())),
+            };
+            eprintln!("{} {}", prefix, record.args());
+        }
+        if level > self.kept {
+            return;
+        }
+        match &self.sink {
+            Sink::File(file) => {
+                let timestamp = format_timestamp(OffsetDateTime::now_utc().unix_timestamp());
+                if let Ok(mut file) = file.lock() {
+                    let _ = writeln!(
+                        file,
+                        "{} {:<5} [{}] {}",
+                        timestamp,
+                        level,
+                        record.target(),
+                        record.args()
+                    );
+                }
+            }
+            Sink::Journald(socket) => {
+                let mut datagram = Vec::new();
+                journal_field(&mut datagram, "PRIORITY", &priority(level).to_string());
+                journal_field(&mut datagram, "SYSLOG_IDENTIFIER", "clovis");
+                journal_field(&mut datagram, "CODE_MODULE", record.target());
+                journal_field(&mut datagram, "MESSAGE", &record.args().to_string());
+                let _ = socket.send(&datagram);
+            }
+            Sink::None => {}
+        }
+    }
+
+    fn flush(&self) {
+        if let Sink::File(file) = &self.sink {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
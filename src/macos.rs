@@ -0,0 +1,184 @@
+//! How apps are found, started and detected on macOS: as `.app` bundles in
+//! the Applications folders or found by Spotlight, started with `open -a`
+//! and detected through Launch Services by their bundle identifier. Only
+//! the standard library is used, so that this builds wherever the rest of
+//! clovis does.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command as ProcessCommand, Stdio};
+
+use crate::launch;
+
+/// Folders holding bundles, searched before asking Spotlight.
+const APPLICATIONS_DIRS: &[&str] = &[
+    "/Applications",
+    "/Applications/Utilities",
+    "/System/Applications",
+    "/System/Applications/Utilities",
+];
+
+fn applications_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = APPLICATIONS_DIRS.iter().map(PathBuf::from).collect();
+    if let Some(home) = dirs::home_dir() {
+        dirs.push(home.join("Applications"));
+    }
+    dirs
+}
+
+/// Looks for `<name>.app` in `dir`, ignoring case as the file system does.
+fn find_bundle(dir: &Path, name: &str) -> Option<PathBuf> {
+    let wanted = format!("{}.app", name).to_lowercase();
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .find(|entry| entry.file_name().to_string_lossy().to_lowercase() == wanted)
+        .map(|entry| entry.path())
+}
+
+/// Asks Spotlight for a bundle named `name`, or with `name` as its bundle
+/// identifier (e.g. `org.mozilla.firefox`).
+fn find_with_spotlight(name: &str) -> Option<PathBuf> {
+    let quoted = name.replace('\\', "\\\\").replace('\'', "\\'");
+    let query = format!(
+        "kMDItemContentType == 'com.apple.application-bundle' \
+         && (kMDItemFSName == '{}.app'c || kMDItemCFBundleIdentifier == '{}'c)",
+        quoted, quoted
+    );
+    let output = ProcessCommand::new("mdfind")
+        .arg(query)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(PathBuf::from)
+        .find(|path| path.is_dir())
+}
+
+/// Locates the bundle of `name`: a path to a bundle, a bundle of that name
+/// in the Applications folders, or one Spotlight knows by its name or
+/// bundle identifier. Commands in PATH are not bundles and are left to the
+/// caller.
+pub fn find_app(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.is_absolute() {
+        return (name.ends_with(".app") && path.is_dir()).then(|| path.to_path_buf());
+    }
+    let name = name.strip_suffix(".app").unwrap_or(name);
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    applications_dirs()
+        .iter()
+        .find_map(|dir| find_bundle(dir, name))
+        .or_else(|| find_with_spotlight(name))
+}
+
+/// Starts the app of `bundle`, or brings it forward when it runs already.
+pub fn start_command(bundle: &Path) -> ProcessCommand {
+    let mut command = ProcessCommand::new("open");
+    command.arg("-a").arg(bundle);
+    command
+}
+
+/// Opens a file or URL with the app the user prefers for it.
+pub fn open_command(target: &str) -> ProcessCommand {
+    let mut command = ProcessCommand::new("open");
+    match target.strip_prefix("~/") {
+        Some(rest) => command.arg(dirs::home_dir().unwrap_or_default().join(rest)),
+        None => command.arg(target),
+    };
+    command
+}
+
+/// The identifier of `bundle`, e.g. `org.mozilla.firefox`.
+fn bundle_id(bundle: &Path) -> Option<String> {
+    let output = ProcessCommand::new("defaults")
+        .arg("read")
+        .arg(bundle.join("Contents/Info"))
+        .arg("CFBundleIdentifier")
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !id.is_empty()).then_some(id)
+}
+
+/// The pgrep pattern of the processes running the executable of `bundle`,
+/// which lives in its `Contents/MacOS` folder.
+fn executable_pattern(bundle: &Path) -> String {
+    format!(
+        "^{}/Contents/MacOS/",
+        launch::regex_escape(&bundle.to_string_lossy())
+    )
+}
+
+/// Whether the app of `bundle` runs, as Launch Services reports it to
+/// `NSRunningApplication`. Bundles without an identifier are looked for
+/// among the processes instead.
+pub fn is_running(bundle: &Path) -> bool {
+    if let Some(id) = bundle_id(bundle) {
+        let output = ProcessCommand::new("lsappinfo")
+            .arg("find")
+            .arg(format!("bundleid={}", id))
+            .stderr(Stdio::null())
+            .output();
+        if let Ok(output) = output {
+            // One application serial number per running instance
+            return output.status.success() && !output.stdout.trim_ascii().is_empty();
+        }
+    }
+    ProcessCommand::new("pgrep")
+        .arg("-f")
+        .arg(executable_pattern(bundle))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Kills the processes of the app of `bundle`.
+pub fn kill(bundle: &Path) -> io::Result<()> {
+    let status = ProcessCommand::new("pkill")
+        .arg("-KILL")
+        .arg("-f")
+        .arg(executable_pattern(bundle))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    // pkill exits with 1 when the app exited in the meantime
+    if !status.success() && status.code() != Some(1) {
+        return Err(io::Error::other(format!(
+            "killing {} failed with {}",
+            bundle.display(),
+            status
+        )));
+    }
+    Ok(())
+}
+
+/// Asks the app of `bundle` to quit, as Cmd-Q would, so that it can save
+/// its state or ask about unsaved documents.
+pub fn stop(bundle: &Path) -> io::Result<()> {
+    let status = ProcessCommand::new("osascript")
+        .arg("-e")
+        .arg("on run argv")
+        .arg("-e")
+        .arg("tell application (item 1 of argv) to quit")
+        .arg("-e")
+        .arg("end run")
+        .arg(bundle)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "quitting {} failed with {}",
+            bundle.display(),
+            status
+        )));
+    }
+    Ok(())
+}
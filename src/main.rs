@@ -1,19 +1,83 @@
-use std::collections::HashMap;
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::{self, Read, Write};
-use std::path::PathBuf;
-use std::process::{Command as ProcessCommand, Stdio};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command as ProcessCommand;
+use std::time::{Duration, Instant};
 
 use clap::{CommandFactory, Parser, Subcommand};
-use log::{error, info};
-use serde::{Deserialize, Serialize};
-use serde_yaml;
-use simple_logger::SimpleLogger;
+use log::{error, info, warn};
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Config {
-    environments: HashMap<String, Vec<String>>,
-}
+mod appearance;
+mod audio;
+mod autostart;
+mod blocklist;
+mod bluetooth;
+mod calendar;
+mod config;
+mod crashes;
+mod daemon;
+mod dbus;
+mod desktop;
+mod events;
+mod export;
+mod focus;
+mod fuzzy;
+mod graph;
+mod history;
+mod http;
+mod idle;
+mod import;
+mod json;
+mod keyboard;
+mod launch;
+mod launches;
+mod layout;
+mod lint;
+mod lock;
+mod logging;
+#[cfg(target_os = "macos")]
+mod macos;
+mod metrics;
+mod migrate;
+mod mounts;
+mod mqtt;
+mod network;
+mod notifications;
+mod pager;
+mod paths;
+mod platform;
+mod process;
+mod profile;
+mod resources;
+mod schedule;
+mod schema;
+mod shell_init;
+mod show;
+mod snapshot;
+mod stats;
+mod style;
+mod sync;
+#[cfg(target_os = "linux")]
+mod thresholds;
+#[cfg(target_os = "linux")]
+mod top;
+mod uevent;
+mod validate;
+mod windows;
+mod yaml_edit;
+
+use config::{load_config, App, Config, Instances};
+use events::EventFormat;
+use history::save_config_with_history;
+use json::Json;
+use launches::{AppLaunch, Outcome};
+use logging::LogTarget;
+use migrate::CONFIG_VERSION;
+use style::{paint, ColorChoice, Style};
+use yaml_edit::render_scalar;
+
+use desktop::{installed_desktop_entries, installed_desktop_files};
 
 #[derive(Parser)]
 #[clap(
@@ -24,157 +88,1354 @@ struct Config {
 struct Cli {
     #[clap(subcommand)]
     command: Commands,
+    #[clap(
+        short,
+        long,
+        global = true,
+        action = clap::ArgAction::Count,
+        help = "Print more log messages on stderr (-vv for debug messages)"
+    )]
+    verbose: u8,
+    #[clap(
+        short,
+        long,
+        global = true,
+        conflicts_with = "verbose",
+        help = "Only print errors on stderr"
+    )]
+    quiet: bool,
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value = "file",
+        help = "Where log messages are kept: clovis.log in the XDG state directory, the systemd journal, or nowhere"
+    )]
+    log_target: LogTarget,
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value = "auto",
+        help = "Color the output: when writing to a terminal and NO_COLOR is not set, always or never"
+    )]
+    color: ColorChoice,
+    #[clap(
+        long,
+        global = true,
+        help = "Run the command here even when a clovis daemon is listening"
+    )]
+    no_daemon: bool,
+    #[clap(
+        long,
+        global = true,
+        help = "Print long outputs straight to the terminal instead of through $PAGER"
+    )]
+    no_pager: bool,
+}
+
+impl Commands {
+    /// Whether a running daemon handles the command.
+    fn is_served_by_daemon(&self) -> bool {
+        matches!(
+            self,
+            // Toggling apps reads the answers from this terminal
+            Commands::Launch {
+                interactive: false,
+                ..
+            } | Commands::Stop { .. }
+                | Commands::Restart { .. }
+                | Commands::Adopt { .. }
+                | Commands::Pause { .. }
+                | Commands::Resume { .. }
+                | Commands::Status { .. }
+                | Commands::Recent { .. }
+        )
+    }
+
+    /// Whether the command launches or stops apps, which two clovis must
+    /// not do at the same time.
+    fn takes_lock(&self) -> bool {
+        matches!(
+            self,
+            Commands::Launch { .. }
+                | Commands::Stop { .. }
+                | Commands::Restart { .. }
+                | Commands::Run { .. }
+                | Commands::Adopt { .. }
+                | Commands::Switch { .. }
+                | Commands::Pause { .. }
+                | Commands::Resume { .. }
+                | Commands::Recent { .. }
+        )
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    #[clap(about = "Shows the current configuration")]
-    Show,
+    #[clap(about = "Shows the configured environments and their apps")]
+    Show {
+        #[clap(help = "The name or alias of the environment to show, all of them when left out")]
+        env: Option<String>,
+        #[clap(
+            long,
+            value_enum,
+            default_value = "list",
+            help = "How to lay the environments out"
+        )]
+        format: show::ShowFormat,
+        #[clap(long, help = "Also show whether each app is running")]
+        status: bool,
+    },
 
     #[clap(about = "Launches all apps in the specified environment")]
     Launch {
-        #[clap(help = "The name of the environment to launch")]
+        #[clap(help = "The name or alias of the environment to launch (defaults to `default:`)")]
         env: Option<String>,
         #[clap(
             long,
             help = "Force launch applications even if they are already running"
         )]
         force: bool,
+        #[clap(
+            long,
+            short,
+            help = "Stop conflicting environments without asking for confirmation"
+        )]
+        yes: bool,
+        #[clap(
+            long,
+            value_enum,
+            value_name = "FORMAT",
+            help = "Print an event per launch step on stdout instead of the progress and summary"
+        )]
+        events: Option<EventFormat>,
+        #[clap(
+            long,
+            help = "Time how long each app takes to start and to show a window, print a breakdown and keep it"
+        )]
+        profile: bool,
+        #[clap(
+            long,
+            short,
+            help = "List the apps and pick which ones to launch, all of them at first"
+        )]
+        interactive: bool,
+    },
+
+    #[clap(about = "Stops the running apps of the specified environment")]
+    Stop {
+        #[clap(help = "The name or alias of the environment to stop (defaults to `default:`)")]
+        env: Option<String>,
+        #[clap(
+            long,
+            conflicts_with = "env",
+            help = "Stop every app clovis has launched, in all environments"
+        )]
+        all: bool,
+    },
+
+    #[clap(
+        about = "Stops and launches again an environment, or one of its apps",
+        long_about = "Stops and launches again an environment, or one of its apps.\n\n\
+                      The apps are launched in the order of the config, waiting for the windows \
+                      of the ones with `wait_for_window`, as `clovis launch` does. Restarting a \
+                      single app leaves the rest of the environment as it is."
+    )]
+    Restart {
+        #[clap(help = "The name or alias of the environment")]
+        env: String,
+        #[clap(help = "The app of the environment to restart, all of them when left out")]
+        app: Option<String>,
+    },
+
+    #[clap(
+        about = "Launches one app of an environment with extra arguments",
+        long_about = "Launches one app of an environment with extra arguments.\n\n\
+                      The app is started the way `clovis launch` starts it, with the variables \
+                      and wrappers of the environment, and the arguments after `--` appended to \
+                      its command, e.g. `clovis run work editor.desktop -- notes.md` to open a \
+                      file in it. It is started even when it already runs. Script entries get \
+                      the arguments as \"$@\"."
+    )]
+    Run {
+        #[clap(help = "The name or alias of the environment")]
+        env: String,
+        #[clap(help = "The app of the environment to launch")]
+        app: String,
+        #[clap(last = true, help = "Arguments appended to the command of the app")]
+        args: Vec<String>,
+    },
+
+    #[clap(
+        about = "Records the running apps of an environment as launched by clovis",
+        long_about = "Records the running apps of an environment as launched by clovis.\n\n\
+                      Apps started by hand are found as `clovis status` finds them, and recorded \
+                      in the launch log, so that `clovis stop --all` stops them and the \
+                      environment shows as active in the shell prompt."
+    )]
+    Adopt {
+        #[clap(help = "The name or alias of the environment")]
+        env: String,
+    },
+
+    #[clap(about = "Stops the running environments and launches another one")]
+    Switch {
+        #[clap(
+            help = "The name or alias of the environment to switch to (picked from a list when left out)"
+        )]
+        env: Option<String>,
+        #[clap(
+            long,
+            help = "Force launch applications even if they are already running"
+        )]
+        force: bool,
+    },
+
+    #[clap(
+        about = "Runs an environment for a set time with its blocklist and do-not-disturb on",
+        long_about = "Runs an environment for a set time with its blocklist and do-not-disturb on.\n\n\
+                      The environment is launched, the programs in its `blocks:` are terminated \
+                      while the session lasts and the notification daemon is set to do-not-disturb. \
+                      Once the time is up, or on Ctrl-C, the environment is stopped and \
+                      do-not-disturb is turned back off, with a notification when the time is up."
+    )]
+    FocusSession {
+        #[clap(help = "The name or alias of the environment to focus in")]
+        env: String,
+        #[clap(
+            long,
+            short,
+            default_value = "25m",
+            value_parser = focus::parse_duration,
+            help = "How long the session lasts, e.g. 50m or 1h30m"
+        )]
+        duration: Duration,
+        #[clap(
+            long,
+            short,
+            help = "Stop conflicting environments without asking for confirmation"
+        )]
+        yes: bool,
+    },
+
+    #[clap(about = "Suspends the running apps of an environment without stopping them")]
+    Pause {
+        #[clap(help = "The name or alias of the environment to pause")]
+        env: String,
+    },
+
+    #[clap(about = "Lets the paused apps of an environment carry on")]
+    Resume {
+        #[clap(help = "The name or alias of the environment to resume")]
+        env: String,
+    },
+
+    #[clap(about = "Shows which apps of each environment are running")]
+    Status {
+        #[clap(help = "Only show this environment (name or alias)")]
+        env: Option<String>,
+    },
+
+    #[clap(
+        about = "Shows the CPU and memory used by the running apps, with keys to stop or restart them (Linux only)"
+    )]
+    #[cfg(target_os = "linux")]
+    Top {
+        #[clap(help = "Only show this environment (name or alias)")]
+        env: Option<String>,
+    },
+
+    #[clap(
+        about = "Serves launch, stop and status requests over a Unix socket",
+        long_about = "Serves launch, stop and status requests over a Unix socket.\n\n\
+                      While it runs, `clovis launch`, `stop`, `status` and `recent` are handled by \
+                      the daemon one at a time, so they never race over the state files, with the \
+                      directory and environment of the client. The socket is \
+                      $XDG_RUNTIME_DIR/clovis/daemon.sock; without $XDG_RUNTIME_DIR, it is in \
+                      /tmp/clovis-<uid>, which must be a directory only the user can access.\n\n\
+                      With --dbus, the daemon also owns org.clovis.Launcher on the session bus, \
+                      with the LaunchEnvironment, StopEnvironment and GetStatus methods and an \
+                      AppStateChanged signal.\n\n\
+                      With --http <port>, it also answers GET /environments, GET /status, \
+                      GET /metrics (for Prometheus), POST /launch/<env> and POST /stop/<env> on \
+                      127.0.0.1. Requests need an \
+                      `Authorization: Bearer <token>` header, with the token from \
+                      $XDG_STATE_HOME/clovis/http-token.\n\n\
+                      When the config has an `mqtt:` section, the daemon subscribes to the topics \
+                      of its triggers and launches, stops or switches environments on the messages \
+                      received. The entries of the `schedule:` section are run at the times they \
+                      match, and the `device_triggers:` when a dock, monitor or USB device is \
+                      plugged or unplugged, and the environments of the `idle:` section are stopped \
+                      or paused while the session is idle. The `network_rules:` are run when \
+                      NetworkManager joins their Wi-Fi network or VPN, and the rules of the \
+                      `calendar:` section ahead of the events of an ICS feed or CalDAV calendar \
+                      matching their pattern. While an environment with `blocks:` runs, the \
+                      programs it lists are terminated, with a notification. Apps it launches \
+                      that exit with an error or are killed are notified about, along with the end \
+                      of their log, and recorded for `clovis history crashes`. Apps with \
+                      `thresholds:` get a notification, a restart or their hook run once their \
+                      memory or CPU use has stayed above them for their `seconds`, on Linux only. Restart the daemon \
+                      after changing any of them."
+    )]
+    Daemon {
+        #[clap(long, help = "Also serve org.clovis.Launcher on the D-Bus session bus")]
+        dbus: bool,
+        #[clap(
+            long,
+            value_name = "PORT",
+            help = "Also serve the HTTP API on this localhost port"
+        )]
+        http: Option<u16>,
+    },
+
+    #[clap(about = "Creates an environment from the applications running in the session")]
+    Snapshot {
+        #[clap(help = "The name of the environment to create")]
+        env: String,
+        #[clap(long, help = "Replace the environment if it already exists")]
+        force: bool,
+    },
+
+    #[clap(
+        about = "Creates an environment from the apps already started at login",
+        long_about = "Creates an environment from the apps already started at login.\n\n\
+                      `autostart` reads the XDG autostart entries of ~/.config/autostart and \
+                      /etc/xdg/autostart, `sway` and `i3` the `exec` and `exec_always` lines of \
+                      their config, and `hyprland` its `exec-once` and `exec` lines. Commands \
+                      setting up the session, such as systemctl, are left out."
+    )]
+    Import {
+        #[clap(value_enum, help = "Where to read the startup apps from")]
+        source: import::ImportSource,
+        #[clap(
+            long,
+            help = "The name of the environment to create (defaults to the source)"
+        )]
+        env: Option<String>,
+        #[clap(
+            long,
+            help = "Window manager config to read instead of the default one"
+        )]
+        config: Option<PathBuf>,
+        #[clap(long, help = "Replace the environment if it already exists")]
+        force: bool,
     },
 
-    #[clap(about = "Validates the configuration to ensure all apps are installed")]
-    Validate,
+    #[clap(
+        about = "Validates the configuration to ensure all apps are installed",
+        long_about = "Validates the configuration to ensure all apps are installed.\n\n\
+                      Exits with 0 when all is well, 1 when apps cannot be launched on this \
+                      machine and 2 when the config cannot be loaded or refers to environments it \
+                      lacks. The issues `clovis lint` reports about a tidy config are warnings, \
+                      which only fail with --strict."
+    )]
+    Validate {
+        #[clap(long, help = "Also fail on warnings, with exit code 2")]
+        strict: bool,
+        #[clap(long, help = "Print a JSON report of the problems found")]
+        json: bool,
+        #[clap(
+            long,
+            conflicts_with = "json",
+            help = "Offer to replace missing apps with similar installed ones"
+        )]
+        fix: bool,
+    },
 
     #[clap(about = "Edits the configuration for a specific environment")]
     Edit {
         #[clap(help = "The name of the environment to edit")]
         env: String,
-        #[clap(help = "Action to perform: add or remove")]
+        #[clap(help = "Action to perform: add, remove or move")]
         action: String,
         #[clap(help = "The name of the application to add or remove")]
+        app: Option<String>,
+        #[clap(
+            long,
+            short,
+            help = "Fuzzy-search installed applications instead of naming one"
+        )]
+        interactive: bool,
+        #[clap(
+            long,
+            value_name = "INDEX",
+            conflicts_with_all = ["before", "after"],
+            help = "With 'move': the 0-based position to move the application to"
+        )]
+        to: Option<usize>,
+        #[clap(
+            long,
+            value_name = "APP",
+            conflicts_with = "after",
+            help = "With 'move': place the application right before this one"
+        )]
+        before: Option<String>,
+        #[clap(
+            long,
+            value_name = "APP",
+            help = "With 'move': place the application right after this one"
+        )]
+        after: Option<String>,
+    },
+
+    #[clap(
+        about = "Opens the configuration file in the default editor",
+        long_about = "Opens the configuration file in the default editor.\n\n\
+                      The editor is the `editor:` of the config, otherwise $VISUAL, then $EDITOR. \
+                      Without any of them, the file is opened with vi from a terminal and with \
+                      xdg-open from a graphical session. A starter config is created first when \
+                      there is none yet."
+    )]
+    Config,
+
+    #[clap(about = "Lists installed desktop applications")]
+    Apps {
+        #[clap(long, help = "Only show applications matching this term")]
+        filter: Option<String>,
+        #[clap(
+            long,
+            value_name = "ENV",
+            help = "Pick applications from the list and add them to this environment"
+        )]
+        add_to: Option<String>,
+    },
+
+    #[clap(about = "Relaunches the most recently launched environment")]
+    Recent {
+        #[clap(
+            long,
+            help = "Force launch applications even if they are already running"
+        )]
+        force: bool,
+        #[clap(
+            long,
+            short,
+            help = "Stop conflicting environments without asking for confirmation"
+        )]
+        yes: bool,
+        #[clap(
+            long,
+            value_enum,
+            value_name = "FORMAT",
+            help = "Print an event per launch step on stdout instead of the progress and summary"
+        )]
+        events: Option<EventFormat>,
+    },
+
+    #[clap(about = "Shows how often environments and apps are launched")]
+    Stats,
+
+    #[clap(
+        about = "Prints the steps of launching an environment as a graph",
+        long_about = "Prints the steps of launching an environment as a graph.\n\n\
+                      The steps are the ones `clovis launch` runs, in order: stopping the \
+                      conflicting environments, the monitor layout, network, mounts and other \
+                      settings, then the apps. Edges out of apps with `wait_for_window` are \
+                      labelled, the next step waiting for their window. Render DOT with \
+                      `dot -Tsvg`, Mermaid in Markdown or with `mmdc`."
+    )]
+    Graph {
+        #[clap(help = "The name or alias of the environment")]
+        env: String,
+        #[clap(long, value_enum, default_value = "dot", help = "The graph language")]
+        format: graph::GraphFormat,
+    },
+
+    #[clap(
+        about = "Launches environments at login through XDG autostart entries",
+        long_about = "Launches environments at login through XDG autostart entries.\n\n\
+                      The entries are written to ~/.config/autostart as clovis-<env>.desktop and \
+                      run `clovis launch <env>`, on any desktop following the freedesktop \
+                      autostart specification."
+    )]
+    Autostart {
+        #[clap(subcommand)]
+        action: AutostartAction,
+    },
+
+    #[clap(about = "Turns environments into files other tools run")]
+    Export {
+        #[clap(subcommand)]
+        kind: ExportKind,
+    },
+
+    #[clap(about = "Shows the journal of configuration changes, or past launches")]
+    History {
+        #[clap(subcommand)]
+        kind: Option<HistoryKind>,
+    },
+
+    #[clap(about = "Shows what an app printed when clovis launched it, or the log of clovis")]
+    Logs {
+        #[clap(help = "The app whose output to show, clovis itself when left out")]
+        app: Option<String>,
+    },
+
+    #[clap(about = "Reverts the last configuration change")]
+    Undo {
+        #[clap(long, help = "Undo even if the config file was edited by hand since")]
+        force: bool,
+    },
+
+    #[clap(about = "Commits config changes and syncs them with the configured git remote")]
+    Sync {
+        #[clap(short, long, help = "Commit message to use instead of a generated one")]
+        message: Option<String>,
+        #[clap(long, help = "Only commit locally, do not pull or push")]
+        local: bool,
+    },
+
+    #[clap(
+        about = "Prints a JSON Schema of the config file for editor completion and validation",
+        long_about = "Prints a JSON Schema of the config file for editor completion and validation.\n\n\
+                      Save it (e.g. `clovis schema > ~/.config/clovis/schema.json`) and reference it \
+                      from config.yaml with a `# yaml-language-server: $schema=schema.json` comment."
+    )]
+    Schema,
+
+    #[clap(
+        about = "Prints shell code with quick-switch aliases, a prompt segment and a picker key",
+        long_about = "Prints shell code with quick-switch aliases, a prompt segment and a picker key.\n\n\
+                      Each environment gets an alias switching to it, `c` followed by the shortest \
+                      prefix of its name that is unique (e.g. `cw` for `clovis switch work`), unless \
+                      a command of that name exists. The active environments are shown in front of \
+                      the prompt, and Alt-E picks an environment to switch to.\n\n\
+                      bash and zsh: add `eval \"$(clovis shell-init bash)\"` to the rc file.\n\
+                      fish: add `clovis shell-init fish | source` to config.fish."
+    )]
+    ShellInit {
+        #[clap(value_enum, help = "The shell to print the code for")]
+        shell: shell_init::Shell,
+        #[clap(
+            long,
+            help = "Leave the prompt alone, `__clovis_prompt` can be added to it by hand"
+        )]
+        no_prompt: bool,
+    },
+
+    #[clap(about = "Shows which environments contain an application and whether it is running")]
+    Which {
+        #[clap(help = "The application to look for (the .desktop suffix is optional)")]
         app: String,
     },
 
-    #[clap(about = "Opens the configuration file in the default editor")]
+    #[clap(about = "Checks the config for duplicates, empty environments and unknown fields")]
+    Lint {
+        #[clap(long, help = "Fix the issues that can be fixed safely")]
+        fix: bool,
+    },
+
+    #[clap(about = "Upgrades the config file to the current format version")]
+    Migrate,
+
+    #[clap(about = "Creates a starter configuration file")]
+    Init {
+        #[clap(long, help = "Overwrite the configuration file if it already exists")]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum AutostartAction {
+    #[clap(about = "Launches an environment at every login")]
+    Enable {
+        #[clap(help = "The name or alias of the environment to launch at login")]
+        env: String,
+    },
+
+    #[clap(about = "Stops launching an environment at login")]
+    Disable {
+        #[clap(help = "The name or alias of the environment")]
+        env: String,
+    },
+
+    #[clap(about = "Shows which environments are launched at login")]
+    Status,
+}
+
+#[derive(Subcommand)]
+enum ExportKind {
+    #[clap(
+        about = "Writes a desktop entry per environment, for app launchers",
+        long_about = "Writes a desktop entry per environment, for app launchers.\n\n\
+                      The entries are written to ~/.local/share/applications as \
+                      clovis-<env>.desktop, with the `description:` and `icon:` of the \
+                      environment, and a Stop action. Entries of environments removed from the \
+                      config are deleted."
+    )]
+    Desktop,
+
+    #[clap(
+        about = "Prints a shell script launching an environment without clovis",
+        long_about = "Prints a shell script launching an environment without clovis.\n\n\
+                      The script starts the apps as `clovis launch` would, with their wrappers, \
+                      variables and scheduling settings, skipping the ones already running. The \
+                      other launch steps, such as the monitor layout, network or mounts, are \
+                      left out."
+    )]
+    Script {
+        #[clap(help = "The name or alias of the environment to export")]
+        env: String,
+        #[clap(long, short, help = "Write the script to this file, made executable")]
+        output: Option<PathBuf>,
+    },
+
+    #[clap(
+        about = "Writes systemd user units launching an environment",
+        long_about = "Writes systemd user units launching an environment.\n\n\
+                      Each app gets a clovis-<env>-<app>.service, started in order by \
+                      clovis-<env>.target, with its variables, stop command and scheduling \
+                      settings. The slice of the environment is written too. Copy the units to \
+                      ~/.config/systemd/user to use them."
+    )]
+    Systemd {
+        #[clap(help = "The name or alias of the environment to export")]
+        env: String,
+        #[clap(long, default_value = ".", help = "Directory to write the units to")]
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum HistoryKind {
+    #[clap(about = "Shows the journal of configuration changes (the default)")]
     Config,
+
+    #[clap(about = "Shows past environment launches and their outcome")]
+    Launches {
+        #[clap(long, default_value_t = 20, help = "How many launches to show")]
+        limit: usize,
+    },
+
+    #[clap(about = "Shows the apps `clovis daemon` saw crash, with the end of their log")]
+    Crashes {
+        #[clap(long, default_value_t = 20, help = "How many crashes to show")]
+        limit: usize,
+    },
+}
+
+fn main() -> io::Result<()> {
+    let cli = Cli::parse();
+    let verbosity = if cli.quiet {
+        -1
+    } else {
+        cli.verbose.min(3) as i8
+    };
+    style::init(cli.color);
+    logging::init(verbosity, cli.log_target);
+    info!("Starting application");
+
+    if !cli.no_daemon && cli.command.is_served_by_daemon() {
+        if let Some(code) = daemon::forward(std::env::args_os().skip(1)) {
+            std::process::exit(code);
+        }
+    }
+    run(&cli)
+}
+
+/// Runs a request of a daemon client and returns its exit code.
+fn serve_request(args: Vec<OsString>) -> i32 {
+    let args = std::iter::once(OsString::from("clovis")).chain(args);
+    let cli = match Cli::try_parse_from(args) {
+        Ok(cli) => cli,
+        Err(e) => {
+            print!("{}", e);
+            return 2;
+        }
+    };
+    // Colors depend on the client's terminal, which is out of sight
+    style::init(ColorChoice::Never);
+    match run(&cli) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            1
+        }
+    }
+}
+
+fn run(cli: &Cli) -> io::Result<()> {
+    let config_dir = paths::config_dir();
+    let config_path = config_dir.join("config.yaml");
+    if let Err(e) = paths::move_state_files(&config_dir) {
+        error!(
+            "Failed to move the state files to {}: {}",
+            paths::state_dir().display(),
+            e
+        );
+    }
+
+    let mut config = load_config(&config_path).unwrap_or_else(|_| {
+        info!("Creating new config as loading failed");
+        Config::default()
+    });
+
+    let _lock = if cli.command.takes_lock() {
+        Some(lock_or_exit()?)
+    } else {
+        None
+    };
+    match &cli.command {
+        Commands::Show {
+            env,
+            format,
+            status,
+        } => {
+            let pager = pager::start(cli.no_pager);
+            if !show::show(&config, env.as_deref(), *format, *status) {
+                drop(pager);
+                std::process::exit(1);
+            }
+        }
+        Commands::Launch {
+            env,
+            force,
+            yes,
+            events,
+            profile,
+            interactive,
+        } => {
+            events::init(*events);
+            if !handle_launch_command(&config, env, *force, *yes, *profile, *interactive)? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Stop { all: true, .. } => stop_all(&config)?,
+        Commands::Stop { env, .. } => match env.as_deref().or(config.default.as_deref()) {
+            Some(env) => stop_environment(&config, env)?,
+            None => println!("No environment given and no `default:` environment configured."),
+        },
+        Commands::Restart { env, app } => {
+            if !restart(&config, env, app.as_deref())? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Run { env, app, args } => {
+            if !run_app(&config, env, app, args)? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Adopt { env } => {
+            if !adopt_environment(&config, env)? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Switch { env, force } => {
+            if !switch_environment(&config, env.as_deref(), *force)? {
+                std::process::exit(1);
+            }
+        }
+        Commands::FocusSession { env, duration, yes } => {
+            if !focus_session(&config, env, *duration, *yes)? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Pause { env } => pause_environment(&config, env, true),
+        Commands::Resume { env } => pause_environment(&config, env, false),
+        Commands::Status { env } => show_status(&config, env.as_deref()),
+        Commands::Daemon { dbus, http } => daemon::serve(
+            serve_request,
+            || app_states(&config_path),
+            &daemon::Options {
+                dbus: *dbus,
+                http_port: *http,
+                mqtt: config.mqtt.clone(),
+                stats_path: stats::stats_path(),
+                schedule: config.schedule.clone(),
+                device_triggers: config.device_triggers.clone(),
+                idle: config.idle.clone(),
+                network_rules: config.network_rules.clone(),
+                calendar: config.calendar.clone(),
+                blocks: config
+                    .environments
+                    .iter()
+                    .filter(|(_, environment)| !environment.blocks.is_empty())
+                    .map(|(env, environment)| (env.clone(), environment.blocks.clone()))
+                    .collect(),
+                thresholds: config
+                    .environments
+                    .values()
+                    .flat_map(|environment| &environment.apps)
+                    .any(|app| app.thresholds.is_some())
+                    .then(|| config_path.clone()),
+                environments: config
+                    .environments
+                    .keys()
+                    .chain(config.aliases.keys())
+                    .cloned()
+                    .collect(),
+            },
+        )?,
+        Commands::Import {
+            source,
+            env,
+            config: path,
+            force,
+        } => {
+            if import::import_environment(
+                &mut config,
+                *source,
+                env.as_deref(),
+                path.as_deref(),
+                *force,
+            ) {
+                save_config_with_history(&config_path, &config)?;
+            }
+        }
+        Commands::Snapshot { env, force } => {
+            if snapshot::snapshot_environment(&mut config, env, *force) {
+                save_config_with_history(&config_path, &config)?;
+            }
+        }
+        Commands::Validate { strict, json, fix } => {
+            let code = validate::validate_config(&config_path, *strict, *json, *fix)?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+        Commands::Edit {
+            env,
+            action,
+            app,
+            interactive,
+            to,
+            before,
+            after,
+        } => {
+            let app = match (app, *interactive) {
+                (Some(app), false) => Some(app.clone()),
+                (None, true) if action == "add" => pick_installed_app()?,
+                (None, true) => {
+                    println!("--interactive is only supported with the 'add' action");
+                    None
+                }
+                (Some(_), true) => {
+                    println!("Either name an application or use --interactive, not both");
+                    None
+                }
+                (None, false) => {
+                    println!("Missing application name (or use --interactive)");
+                    None
+                }
+            };
+            let changed = match app {
+                Some(app) => {
+                    let target = match (to, before, after) {
+                        (Some(index), _, _) => Some(MoveTarget::Index(*index)),
+                        (_, Some(other), _) => Some(MoveTarget::Before(other.clone())),
+                        (_, _, Some(other)) => Some(MoveTarget::After(other.clone())),
+                        _ => None,
+                    };
+                    handle_edit_command(&mut config, env, action, &app, target)?
+                }
+                None => false,
+            };
+            if changed {
+                save_config_with_history(&config_path, &config)?;
+            } else {
+                info!("No changes made to the config");
+            }
+        }
+        Commands::Config => open_config_in_editor(&config, &config_path)?,
+        Commands::Init { force } => init_config(&config_path, *force)?,
+        Commands::Sync { message, local } => {
+            sync::sync_config(&config_dir, &config, message.as_deref(), *local)?
+        }
+        Commands::Schema => println!("{}", schema::config_schema().pretty()),
+        Commands::ShellInit { shell, no_prompt } => {
+            print!("{}", shell_init::script(&config, *shell, !no_prompt))
+        }
+        #[cfg(target_os = "linux")]
+        Commands::Top { env } => {
+            if !top::top(&config, env.as_deref())? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Which { app } => {
+            if !which_environments(&config, app) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Lint { fix } => {
+            if lint::lint_config(&config_path, *fix)? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Migrate => history::migrate_config(&config_path)?,
+        Commands::Recent { force, yes, events } => match launches::last_launched_env()? {
+            Some(env) => {
+                events::init(*events);
+                if !launch_and_record(&config, &env, *force, *yes, false, None)? {
+                    std::process::exit(1);
+                }
+            }
+            None => println!("No environment has been launched yet."),
+        },
+        Commands::Stats => stats::show_stats(&config)?,
+        Commands::Graph { env, format } => match graph::graph(&config, env, *format) {
+            Some(graph) => print!("{}", graph),
+            None => {
+                eprintln!("Environment '{}' not found.", env);
+                std::process::exit(1);
+            }
+        },
+        Commands::Export { kind } => match kind {
+            ExportKind::Desktop => export::export_desktop(&config)?,
+            ExportKind::Systemd { env, dir } => {
+                if !export::export_systemd(&config, env, dir)? {
+                    std::process::exit(1);
+                }
+            }
+            ExportKind::Script { env, output } => {
+                if !export::export_script(&config, env, output.as_deref())? {
+                    std::process::exit(1);
+                }
+            }
+        },
+        Commands::Autostart { action } => match action {
+            AutostartAction::Enable { env } => {
+                if !autostart::enable(&config, env)? {
+                    std::process::exit(1);
+                }
+            }
+            AutostartAction::Disable { env } => autostart::disable(&config, env)?,
+            AutostartAction::Status => autostart::show_status(&config),
+        },
+        Commands::History { kind } => {
+            let _pager = pager::start(cli.no_pager);
+            match kind {
+                None | Some(HistoryKind::Config) => history::show_history()?,
+                Some(HistoryKind::Launches { limit }) => launches::show_launches(*limit)?,
+                Some(HistoryKind::Crashes { limit }) => crashes::show_crashes(*limit)?,
+            }
+        }
+        Commands::Logs { app } => {
+            let _pager = pager::start(cli.no_pager);
+            logging::show_log(app.as_deref())?;
+        }
+        Commands::Undo { force } => history::undo_last_change(&config_path, *force)?,
+        Commands::Apps { filter, add_to } => {
+            // Picking an app to add reads the answer from the terminal
+            let _pager = pager::start(cli.no_pager || add_to.is_some());
+            if handle_apps_command(&mut config, filter.as_deref(), add_to.as_deref())? {
+                save_config_with_history(&config_path, &config)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Takes the lock on launching and stopping, exiting when another clovis
+/// holds it.
+fn lock_or_exit() -> io::Result<lock::Lock> {
+    match lock::try_lock()? {
+        Some(lock) => Ok(lock),
+        None => {
+            println!("Another clovis launch or stop is in progress, try again once it is done.");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn handle_launch_command(
+    config: &Config,
+    env: &Option<String>,
+    force: bool,
+    yes: bool,
+    profile: bool,
+    interactive: bool,
+) -> io::Result<bool> {
+    let env = env.as_deref().or(config.default.as_deref());
+    match env {
+        Some(env) if !std::env::args().any(|arg| arg == "--help" || arg == "-h") => {
+            let only = if interactive {
+                match pick_apps(config, env)? {
+                    Some(names) => Some(names),
+                    None => return Ok(true),
+                }
+            } else {
+                None
+            };
+            launch_and_record(config, env, force, yes, profile, only.as_deref())
+        }
+        _ => {
+            print_launch_help_and_available_environments(config)?;
+            Ok(true)
+        }
+    }
+}
+
+/// Where `edit <env> move` should put an application.
+enum MoveTarget {
+    Index(usize),
+    Before(String),
+    After(String),
+}
+
+fn handle_edit_command(
+    config: &mut Config,
+    env: &str,
+    action: &str,
+    app: &str,
+    target: Option<MoveTarget>,
+) -> io::Result<bool> {
+    let env = &config.resolve_env(env).to_string();
+    if !config.environments.contains_key(env) {
+        error!("Environment '{}' does not exist.", env);
+        return Ok(false);
+    }
+
+    if !launch::is_available(config, &App::new(app)) {
+        println!(
+            "{} Application '{}' is not installed or not in PATH.",
+            paint(Style::Warning, "Warning:"),
+            app
+        );
+    }
+
+    match action {
+        "add" => {
+            let environment = config.environments.entry(env.to_string()).or_default();
+            if environment.contains(app) {
+                error!("Application '{}' is already in environment '{}'", app, env);
+                return Ok(false);
+            }
+            environment.apps.push(App::new(app));
+            println!("Added '{}' to environment '{}'", app, env);
+            info!("Added '{}' to environment '{}'", app, env);
+        }
+        "remove" => {
+            if let Some(environment) = config.environments.get_mut(env) {
+                if let Some(pos) = environment.position(app) {
+                    environment.apps.remove(pos);
+                    println!("Removed '{}' from environment '{}'", app, env);
+                    info!("Removed '{}' from environment '{}'", app, env);
+                } else {
+                    println!("App '{}' not found in environment '{}'", app, env);
+                    return Ok(false);
+                }
+            }
+        }
+        "move" => {
+            let Some(target) = target else {
+                println!("Missing destination for 'move'. Use --to, --before or --after.");
+                return Ok(false);
+            };
+            let environment = config.environments.get_mut(env).unwrap();
+            let Some(from) = environment.position(app) else {
+                println!("App '{}' not found in environment '{}'", app, env);
+                return Ok(false);
+            };
+            let apps = &mut environment.apps;
+            let moved = apps.remove(from);
+            let to = match &target {
+                MoveTarget::Index(index) => (*index).min(apps.len()),
+                MoveTarget::Before(other) | MoveTarget::After(other) => {
+                    let Some(pos) = apps.iter().position(|x| x.name == *other) else {
+                        apps.insert(from, moved);
+                        println!("App '{}' not found in environment '{}'", other, env);
+                        return Ok(false);
+                    };
+                    if matches!(target, MoveTarget::After(_)) {
+                        pos + 1
+                    } else {
+                        pos
+                    }
+                }
+            };
+            if to == from {
+                apps.insert(from, moved);
+                println!("'{}' is already at that position", app);
+                return Ok(false);
+            }
+            apps.insert(to, moved);
+            println!(
+                "Moved '{}' to position {} in environment '{}'",
+                app, to, env
+            );
+            info!(
+                "Moved '{}' from {} to {} in environment '{}'",
+                app, from, to, env
+            );
+        }
+        _ => {
+            println!(
+                "Invalid action '{}'. Use 'add', 'remove' or 'move'.",
+                action
+            );
+            error!(
+                "Invalid action '{}'. Use 'add', 'remove' or 'move'.",
+                action
+            );
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// The editor to open the config with: the `editor:` setting, `$VISUAL`,
+/// `$EDITOR`, then vi in a terminal or xdg-open in a graphical session.
+fn config_editor(config: &Config) -> String {
+    let from_env = |name| {
+        std::env::var(name)
+            .ok()
+            .filter(|v: &String| !v.trim().is_empty())
+    };
+    config
+        .editor
+        .clone()
+        .or_else(|| from_env("VISUAL"))
+        .or_else(|| from_env("EDITOR"))
+        .unwrap_or_else(|| {
+            let graphical = std::env::var_os("WAYLAND_DISPLAY").is_some()
+                || std::env::var_os("DISPLAY").is_some();
+            if graphical && !io::stdin().is_terminal() && launch::is_command_available("xdg-open") {
+                "xdg-open".to_string()
+            } else {
+                "vi".to_string()
+            }
+        })
+}
+
+fn open_config_in_editor(config: &Config, config_path: &Path) -> io::Result<()> {
+    if !config_path.exists() {
+        if let Some(parent) = config_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(config_path, starter_config(None))?;
+        println!("Created config file at {}", config_path.display());
+    }
+    let editor = config_editor(config);
+    // The editor may come with arguments, e.g. `code --wait`
+    let status = ProcessCommand::new("/bin/sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(config_path)
+        .status()?;
+
+    if !status.success() {
+        error!("Failed to open config file with editor '{}'", editor);
+    }
+
+    Ok(())
+}
+
+fn handle_apps_command(
+    config: &mut Config,
+    filter: Option<&str>,
+    add_to: Option<&str>,
+) -> io::Result<bool> {
+    let entries: Vec<_> = installed_desktop_entries()
+        .into_iter()
+        .filter(|entry| filter.is_none_or(|term| entry.matches(term)))
+        .collect();
+    if entries.is_empty() {
+        println!("No installed applications found.");
+        return Ok(false);
+    }
+
+    let id_width = entries.iter().map(|e| e.id.len()).max().unwrap_or(0);
+    let name_width = entries.iter().map(|e| e.name().len()).max().unwrap_or(0);
+    for (i, entry) in entries.iter().enumerate() {
+        let index = if add_to.is_some() {
+            format!("{:>3}) ", i + 1)
+        } else {
+            String::new()
+        };
+        println!(
+            "{}{:<id_width$}  {:<name_width$}  {}  [{}]",
+            index,
+            entry.id,
+            entry.name(),
+            entry.exec(),
+            entry.categories().join(", "),
+        );
+    }
+
+    let Some(env) = add_to else {
+        return Ok(false);
+    };
+    let selection = prompt(&format!("Applications to add to '{}' (e.g. 1,3,5): ", env))?;
+    let mut changed = false;
+    for app in parse_selection(&selection, &entries) {
+        changed |= handle_edit_command(config, env, "add", &app.id, None)?;
+    }
+    Ok(changed)
 }
 
-fn main() -> io::Result<()> {
-    SimpleLogger::new().init().unwrap();
-    info!("Starting application");
+/// Fuzzy-picks one of the installed desktop applications and returns its
+/// desktop file name.
+fn pick_installed_app() -> io::Result<Option<String>> {
+    let candidates: Vec<String> = installed_desktop_entries()
+        .iter()
+        .map(|entry| format!("{}  ({})", entry.id, entry.name()))
+        .collect();
+    if candidates.is_empty() {
+        println!("No installed applications found.");
+        return Ok(None);
+    }
+    let selection = fuzzy::pick(&candidates)?;
+    Ok(selection.and_then(|line| line.split_whitespace().next().map(str::to_string)))
+}
 
-    let config_dir = dirs::config_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("clovis");
-    let config_path = config_dir.join("config.yaml");
+/// Maps a comma or space separated list of 1-based indices onto `items`,
+/// silently ignoring anything out of range.
+fn parse_selection<'a, T>(selection: &str, items: &'a [T]) -> Vec<&'a T> {
+    selection
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter_map(|n| n.parse::<usize>().ok())
+        .filter_map(|n| items.get(n.wrapping_sub(1)))
+        .collect()
+}
 
-    let mut config = load_config(&config_path).unwrap_or_else(|_| {
-        info!("Creating new config as loading failed");
-        Config {
-            environments: HashMap::new(),
-        }
-    });
+const STARTER_CONFIG_HEADER: &str = "\
+# Clovis configuration
+#
+# Each environment lists the applications to launch together under `apps`.
+# An app whose `name` ends in `.desktop` is started through its desktop
+# file, anything else is treated as a command that must be available in
+# PATH.
+#
+# Launch an environment with `clovis launch <env>`.
+";
 
-    let cli = Cli::parse();
+const STARTER_CONFIG_EXAMPLE: &str = "\
+environments:
+  example:
+    apps:
+      - name: firefox.desktop
+      - name: alacritty
+";
 
-    match &cli.command {
-        Commands::Show => show_config(&config),
-        Commands::Launch { env, force } => {
-            handle_launch_command(&config, env, *force)?;
-        }
-        Commands::Validate => validate_config(&config),
-        Commands::Edit { env, action, app } => {
-            if handle_edit_command(&mut config, env, action, app)? {
-                save_config(&config_path, &config)?;
-            } else {
-                info!("No changes made to the config");
+/// The contents of a new config file, with `first` as its environment and
+/// its apps, or an example one.
+fn starter_config(first: Option<(String, Vec<String>)>) -> String {
+    let mut contents = STARTER_CONFIG_HEADER.to_string();
+    contents.push('\n');
+    contents.push_str(&format!("version: {}\n", CONFIG_VERSION));
+    match first {
+        Some((env, apps)) => {
+            contents.push_str("environments:\n");
+            contents.push_str(&format!("  {}:\n", render_scalar(&env)));
+            contents.push_str("    apps:\n");
+            for app in apps {
+                contents.push_str(&format!("      - name: {}\n", render_scalar(&app)));
             }
         }
-        Commands::Config => open_config_in_editor(&config_path)?,
+        None => contents.push_str(STARTER_CONFIG_EXAMPLE),
     }
-
-    Ok(())
+    contents
 }
 
-fn handle_launch_command(config: &Config, env: &Option<String>, force: bool) -> io::Result<()> {
-    if env.is_none() || std::env::args().any(|arg| arg == "--help" || arg == "-h") {
-        print_launch_help_and_available_environments(&config)?;
-    } else {
-        launch_apps(&config, env.as_deref().unwrap(), force)?;
+fn init_config(config_path: &Path, force: bool) -> io::Result<()> {
+    if config_path.exists() && !force {
+        println!(
+            "Config file already exists at {} (use --force to overwrite)",
+            config_path.display()
+        );
+        return Ok(());
+    }
+    if let Some(parent) = config_path.parent() {
+        std::fs::create_dir_all(parent)?;
     }
+
+    let contents = starter_config(prompt_first_environment()?);
+
+    File::create(config_path)?.write_all(contents.as_bytes())?;
+    println!("Created config file at {}", config_path.display());
+    info!("Starter config written to {}", config_path.display());
     Ok(())
 }
 
-fn handle_edit_command(
-    config: &mut Config,
-    env: &str,
-    action: &str,
-    app: &str,
-) -> io::Result<bool> {
-    if !config.environments.contains_key(env) {
-        error!("Environment '{}' does not exist.", env);
-        return Ok(false);
+/// Walks the user through picking a first environment from the installed
+/// desktop applications. Returns `None` when not running interactively or
+/// when the user declines.
+fn prompt_first_environment() -> io::Result<Option<(String, Vec<String>)>> {
+    if !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let answer = prompt("Create a first environment now? [y/N] ")?;
+    if !answer.eq_ignore_ascii_case("y") && !answer.eq_ignore_ascii_case("yes") {
+        return Ok(None);
     }
 
-    let app_available = if app.ends_with(".desktop") {
-        is_desktop_file_available(app)
-    } else {
-        is_command_available(app)
+    let env = loop {
+        let name = prompt("Environment name: ")?;
+        if !name.is_empty() {
+            break name;
+        }
     };
 
-    if !app_available {
-        println!(
-            "Warning: Application '{}' is not installed or not in PATH.",
-            app
-        );
+    let filter = prompt("Filter installed applications (leave empty to list all): ")?;
+    let filter = filter.to_lowercase();
+    let candidates: Vec<String> = installed_desktop_files()
+        .into_iter()
+        .filter(|app| app.to_lowercase().contains(&filter))
+        .collect();
+    if candidates.is_empty() {
+        println!("No installed applications match '{}'", filter);
+        return Ok(Some((env, Vec::new())));
+    }
+    for (i, app) in candidates.iter().enumerate() {
+        println!("  {:>3}) {}", i + 1, app);
     }
 
-    match action {
-        "add" => {
-            let apps = config
-                .environments
-                .entry(env.to_string())
-                .or_insert_with(Vec::new);
-            if apps.contains(&app.to_string()) {
-                error!("Application '{}' is already in environment '{}'", app, env);
-                return Ok(false);
-            }
-            apps.push(app.to_string());
-            println!("Added '{}' to environment '{}'", app, env);
-            info!("Added '{}' to environment '{}'", app, env);
+    let selection = prompt("Applications to add (e.g. 1,3,5): ")?;
+    let apps = parse_selection(&selection, &candidates)
+        .into_iter()
+        .cloned()
+        .collect();
+    Ok(Some((env, apps)))
+}
+
+/// Lists the apps of `env` and lets the user toggle them on and off, all
+/// on at first. Returns the names of the apps left on, or `None` when the
+/// user cancelled.
+fn pick_apps(config: &Config, env: &str) -> io::Result<Option<Vec<String>>> {
+    let env = config.resolve_env(env);
+    let Some(environment) = config.environments.get(env) else {
+        // Launching reports the unknown environment
+        return Ok(Some(Vec::new()));
+    };
+    let indices: Vec<usize> = (0..environment.apps.len()).collect();
+    let mut on = vec![true; environment.apps.len()];
+    loop {
+        for (i, app) in environment.apps.iter().enumerate() {
+            let mark = if on[i] { "[x]" } else { "[ ]" };
+            println!("  {:>3}) {} {}", i + 1, mark, app.name);
         }
-        "remove" => {
-            if let Some(apps) = config.environments.get_mut(env) {
-                if let Some(pos) = apps.iter().position(|x| x == app) {
-                    apps.remove(pos);
-                    println!("Removed '{}' from environment '{}'", app, env);
-                    info!("Removed '{}' from environment '{}'", app, env);
-                } else {
-                    println!("App '{}' not found in environment '{}'", app, env);
-                    return Ok(false);
-                }
-            }
+        let answer = prompt("Apps to toggle (e.g. 2,4), enter to launch, q to cancel: ")?;
+        if answer.is_empty() {
+            break;
         }
-        _ => {
-            println!("Invalid action '{}'. Use 'add' or 'remove'.", action);
-            error!("Invalid action '{}'. Use 'add' or 'remove'.", action);
-            return Ok(false);
+        if answer.eq_ignore_ascii_case("q") {
+            println!("Not launching '{}'.", env);
+            return Ok(None);
+        }
+        for i in parse_selection(&answer, &indices) {
+            on[*i] = !on[*i];
         }
     }
-    Ok(true)
+    Ok(Some(
+        environment
+            .apps
+            .iter()
+            .zip(on)
+            .filter(|(_, on)| *on)
+            .map(|(app, _)| app.name.clone())
+            .collect(),
+    ))
 }
 
-fn open_config_in_editor(config_path: &PathBuf) -> io::Result<()> {
-    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
-    let status = ProcessCommand::new(editor.clone())
-        .arg(config_path)
-        .status()?;
-
-    if !status.success() {
-        error!("Failed to open config file with editor '{}'", editor);
-    }
-
-    Ok(())
+fn prompt(question: &str) -> io::Result<String> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
 }
 
 fn print_launch_help_and_available_environments(config: &Config) -> io::Result<()> {
@@ -190,131 +1451,949 @@ fn print_launch_help_and_available_environments(config: &Config) -> io::Result<(
 
     println!("\nAvailable environments:");
     for env in config.environments.keys() {
-        println!("  - {}", env);
+        let mut notes = Vec::new();
+        if config.default.as_deref() == Some(env) {
+            notes.push("default".to_string());
+        }
+        let mut aliases: Vec<&str> = config
+            .aliases
+            .iter()
+            .filter(|(_, target)| *target == env)
+            .map(|(alias, _)| alias.as_str())
+            .collect();
+        if !aliases.is_empty() {
+            aliases.sort();
+            notes.push(format!("aliases: {}", aliases.join(", ")));
+        }
+        if notes.is_empty() {
+            println!("  - {}", paint(Style::Env, env));
+        } else {
+            println!(
+                "  - {} {}",
+                paint(Style::Env, env),
+                paint(Style::Dim, format!("({})", notes.join("; ")))
+            );
+        }
     }
     Ok(())
 }
 
-fn load_config(path: &PathBuf) -> io::Result<Config> {
-    let mut file = File::open(path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    let config: Config = serde_yaml::from_str(&contents).map_err(|e| {
-        error!("Failed to parse config file: {}", e);
-        io::Error::new(io::ErrorKind::InvalidData, e)
-    })?;
-    info!("Config loaded successfully");
-    Ok(config)
-}
-
-fn save_config(path: &PathBuf, config: &Config) -> io::Result<()> {
-    let mut file = File::create(path)?;
-    let contents = serde_yaml::to_string(config).map_err(|e| {
-        error!("Failed to serialize config: {}", e);
-        io::Error::new(io::ErrorKind::InvalidData, e)
-    })?;
-    file.write_all(contents.as_bytes())?;
-    info!("Config saved successfully");
-    Ok(())
+/// The state of every app, from the config as it is on disk now.
+fn app_states(config_path: &Path) -> Vec<daemon::AppState> {
+    let Ok(config) = load_config(config_path) else {
+        return Vec::new();
+    };
+    let mut envs: Vec<&String> = config.environments.keys().collect();
+    envs.sort();
+    envs.into_iter()
+        .flat_map(|env| {
+            let config = &config;
+            config.environments[env]
+                .apps
+                .iter()
+                .map(move |app| daemon::AppState {
+                    env: env.clone(),
+                    app: app.name.clone(),
+                    running: launch::is_running(config, app),
+                })
+        })
+        .collect()
 }
 
-fn show_config(config: &Config) {
-    for (env, apps) in &config.environments {
-        println!("{}:", env);
-        for app in apps {
-            println!("  - {}", app);
+/// Lists the apps of every environment, or of `env` only, with whether
+/// they are running.
+fn show_status(config: &Config, env: Option<&str>) {
+    let mut envs: Vec<&String> = match env {
+        Some(env) => {
+            let env = config.resolve_env(env);
+            match config.environments.get_key_value(env) {
+                Some((name, _)) => vec![name],
+                None => {
+                    println!("Environment '{}' not found.", env);
+                    return;
+                }
+            }
+        }
+        None => config.environments.keys().collect(),
+    };
+    envs.sort();
+    for env in envs {
+        let environment = &config.environments[env];
+        match &environment.description {
+            Some(description) => println!(
+                "{}: {}",
+                paint(Style::env(environment.color), env),
+                paint(Style::Dim, description)
+            ),
+            None => println!("{}:", paint(Style::env(environment.color), env)),
+        }
+        for app in &environment.apps {
+            let state = if launch::is_running(config, app) {
+                paint(Style::Running, "running")
+            } else {
+                paint(Style::Dim, "not running")
+            };
+            println!("  - {} ({})", app.name, state);
         }
     }
 }
 
-fn launch_apps(config: &Config, env: &str, force: bool) -> io::Result<()> {
-    if let Some(apps) = config.environments.get(env) {
-        for app in apps {
-            if !force && is_app_running(app) {
-                println!("Skipping: {} (already running)", app);
-                continue;
+/// Prints the environments containing `app` and returns whether any did.
+fn which_environments(config: &Config, app: &str) -> bool {
+    let bare = app.strip_suffix(".desktop").unwrap_or(app);
+    let mut matches: Vec<(&App, &str)> = config
+        .environments
+        .iter()
+        .flat_map(|(env, environment)| {
+            environment
+                .apps
+                .iter()
+                .filter(|entry| entry.name.strip_suffix(".desktop").unwrap_or(&entry.name) == bare)
+                .map(move |entry| (entry, env.as_str()))
+        })
+        .collect();
+    if matches.is_empty() {
+        println!("'{}' is not part of any environment", app);
+        return false;
+    }
+    matches.sort_by_key(|(entry, env)| (&entry.name, *env));
+
+    let mut entries: Vec<&App> = matches.iter().map(|(entry, _)| *entry).collect();
+    entries.dedup_by_key(|entry| &entry.name);
+    for entry in entries {
+        let state = if launch::is_running(config, entry) {
+            paint(Style::Running, "running")
+        } else {
+            paint(Style::Dim, "not running")
+        };
+        println!("{} ({})", entry.name, state);
+        for (_, env) in matches.iter().filter(|(e, _)| e.name == entry.name) {
+            println!("  - {}", paint(Style::Env, env));
+        }
+    }
+    true
+}
+
+/// Launches `env` (an environment name or alias) with everything it sets up
+/// first, records the outcome in the launch log and prints a summary. With
+/// `profile`, the windows of all apps are waited for and a timing breakdown
+/// is printed and kept. Returns false when an app failed to launch or the
+/// environment does not exist.
+fn launch_and_record(
+    config: &Config,
+    env: &str,
+    force: bool,
+    yes: bool,
+    profile: bool,
+    only: Option<&[String]>,
+) -> io::Result<bool> {
+    let start = Instant::now();
+    let env = config.resolve_env(env);
+    if !stop_conflicting_environments(config, env, yes)? {
+        println!("Not launching '{}'.", env);
+        return Ok(true);
+    }
+    if let Some(layout) = config.environments.get(env).and_then(|e| e.layout.as_ref()) {
+        if !layout::ensure(config, layout)? {
+            println!("Not launching '{}' without its monitor layout.", env);
+            return Ok(false);
+        }
+    }
+    if !bring_up_network(config, env) {
+        println!("Not launching '{}' without its network.", env);
+        return Ok(false);
+    }
+    if !mount_all(config, env) {
+        println!("Not launching '{}' without its mounts.", env);
+        return Ok(false);
+    }
+    if let Some(look) = config
+        .environments
+        .get(env)
+        .and_then(|e| e.appearance.as_ref())
+    {
+        if let Err(e) = appearance::apply(env, look) {
+            error!("Failed to switch to the appearance of {}: {}", env, e);
+        }
+    }
+    if config
+        .environments
+        .get(env)
+        .is_some_and(|e| e.do_not_disturb)
+    {
+        if let Err(e) = notifications::enable(env) {
+            error!("Failed to turn on do-not-disturb for {}: {}", env, e);
+        }
+    }
+    if let Some(audio) = config.environments.get(env).and_then(|e| e.audio.as_ref()) {
+        if let Err(e) = audio::apply(env, audio) {
+            error!("Failed to set the audio devices of {}: {}", env, e);
+        }
+    }
+    if let Some(layout) = config
+        .environments
+        .get(env)
+        .and_then(|e| e.keyboard.as_ref())
+    {
+        if let Err(e) = keyboard::apply(env, layout) {
+            error!("Failed to set the keyboard layout of {}: {}", env, e);
+        }
+    }
+    for device in config
+        .environments
+        .get(env)
+        .iter()
+        .flat_map(|e| &e.bluetooth)
+    {
+        match bluetooth::set_connected(device, true) {
+            Ok(true) => progress(format!("Connected {}", device)),
+            Ok(false) => {}
+            Err(e) => {
+                println!("{} {}", paint(Style::Failed, "Failed to connect:"), device);
+                error!("Failed to connect {}: {}", device, e);
+            }
+        }
+    }
+    let Some(outcomes) = launch_apps(config, env, force, profile, only)? else {
+        return Ok(false);
+    };
+    if let Err(e) = shell_init::set_active(env, true) {
+        error!("Failed to record {} as active: {}", env, e);
+    }
+    let count = |wanted: fn(&Outcome) -> bool| {
+        Json::from(outcomes.iter().filter(|a| wanted(&a.outcome)).count() as u64)
+    };
+    events::emit(
+        "launch-finished",
+        [
+            ("env", Json::from(env)),
+            ("launched", count(|o| *o == Outcome::Launched)),
+            ("skipped", count(|o| *o == Outcome::AlreadyRunning)),
+            ("failed", count(|o| matches!(o, Outcome::Failed(_)))),
+        ],
+    );
+    if !events::enabled() {
+        launches::print_summary(&outcomes);
+    }
+    if profile {
+        if let Err(e) = profile::record_profile(env, &outcomes, start.elapsed()) {
+            error!("Failed to record the profile of {}: {}", env, e);
+        }
+    }
+    let succeeded = !outcomes
+        .iter()
+        .any(|a| matches!(a.outcome, Outcome::Failed(_)));
+    stats::record_usage(env, &outcomes)?;
+    launches::record_launch(env, outcomes)?;
+    Ok(succeeded)
+}
+
+/// Brings up the connections of `env` that are down, in order. Returns
+/// false when one of them could not be brought up.
+fn bring_up_network(config: &Config, env: &str) -> bool {
+    let Some(environment) = config.environments.get(env) else {
+        return true;
+    };
+    for action in &environment.network {
+        let connection = network::describe_action(action);
+        let result = network::is_up(action).and_then(|up| {
+            if up {
+                return Ok(true);
+            }
+            progress(format!("Bringing up {}", connection));
+            network::set_up(action, true)?;
+            info!("Brought up {}", connection);
+            network::is_up(action)
+        });
+        match result {
+            Ok(true) => {}
+            Ok(false) => {
+                println!(
+                    "{} {} is still down",
+                    paint(Style::Failed, "Failed:"),
+                    connection
+                );
+                return false;
             }
-            println!("Launching: {}", app);
-            let mut command = ProcessCommand::new("gtk-launch");
-            command.arg(app);
+            Err(e) => {
+                println!(
+                    "{} {}",
+                    paint(Style::Failed, "Failed to bring up:"),
+                    connection
+                );
+                error!("Failed to bring up {}: {}", connection, e);
+                return false;
+            }
+        }
+    }
+    true
+}
 
-            // Redirect stdout and stderr to /dev/null
-            command.stdout(Stdio::null());
-            command.stderr(Stdio::null());
+/// Mounts the shares and disks of `env` that are not mounted yet. Returns
+/// false when one could not be.
+fn mount_all(config: &Config, env: &str) -> bool {
+    let Some(environment) = config.environments.get(env) else {
+        return true;
+    };
+    for mount in &environment.mounts {
+        let name = mounts::describe(mount);
+        let result = mounts::is_mounted(mount).and_then(|mounted| {
+            if mounted {
+                return Ok(true);
+            }
+            progress(format!("Mounting {}", name));
+            mounts::set_mounted(config, mount, true)?;
+            info!("Mounted {}", name);
+            mounts::is_mounted(mount)
+        });
+        match result {
+            Ok(true) => {}
+            Ok(false) => {
+                println!(
+                    "{} {} is still not mounted",
+                    paint(Style::Failed, "Failed:"),
+                    name
+                );
+                return false;
+            }
+            Err(e) => {
+                println!("{} {}", paint(Style::Failed, "Failed to mount:"), name);
+                error!("Failed to mount {}: {}", name, e);
+                return false;
+            }
+        }
+    }
+    true
+}
 
-            // Set DISPLAY environment variable for Electron apps
-            command.env("DISPLAY", ":0");
+/// Stops the running environments that conflict with `env`, after asking
+/// unless `yes` is set. Returns false when the user declined.
+fn stop_conflicting_environments(config: &Config, env: &str, yes: bool) -> io::Result<bool> {
+    for other in config.conflicting_envs(env) {
+        let running = config.environments[other]
+            .apps
+            .iter()
+            .any(|app| launch::is_running(config, app));
+        if !running {
+            continue;
+        }
+        if !yes {
+            if !io::stdin().is_terminal() {
+                println!(
+                    "Environment '{}' conflicts with the running '{}', use --yes to stop it.",
+                    env, other
+                );
+                return Ok(false);
+            }
+            let answer = prompt(&format!(
+                "Environment '{}' conflicts with the running '{}'. Stop it? [y/N] ",
+                env, other
+            ))?;
+            if !answer.eq_ignore_ascii_case("y") && !answer.eq_ignore_ascii_case("yes") {
+                return Ok(false);
+            }
+        }
+        stop_environment(config, other)?;
+    }
+    Ok(true)
+}
 
-            // Spawn the process in the background
-            match command.spawn() {
-                Ok(_) => info!("Launched {} in the background", app),
-                Err(e) => error!("Failed to launch {}: {}", app, e),
+/// Starts the apps of `env` that are not running yet, or only the ones named
+/// in `only`, returning what happened to each of them, or `None` when the
+/// environment does not exist. With `profile`, how long each window takes
+/// to appear is measured too, once all apps are started.
+fn launch_apps(
+    config: &Config,
+    env: &str,
+    force: bool,
+    profile: bool,
+    only: Option<&[String]>,
+) -> io::Result<Option<Vec<AppLaunch>>> {
+    let mut outcomes = Vec::new();
+    // Outcome index, window class, windows open before and launch start
+    let mut watched: Vec<(usize, String, usize, Instant)> = Vec::new();
+    if let Some(environment) = config.environments.get(env) {
+        let apps: Vec<&App> = environment
+            .apps
+            .iter()
+            .filter(|app| only.is_none_or(|names| names.contains(&app.name)))
+            .collect();
+        let existing = windows::windows_before_launch(&apps);
+        let mut launched: Vec<&App> = Vec::new();
+        events::emit(
+            "launch-started",
+            [
+                ("env", Json::from(env)),
+                ("apps", Json::from(apps.len() as u64)),
+            ],
+        );
+        for app in apps {
+            let app_fields = || {
+                [
+                    ("env", Json::from(env)),
+                    ("app", Json::from(app.name.as_str())),
+                ]
+            };
+            let relaunch = match app.instances {
+                Some(Instances::Single) => false,
+                Some(Instances::Multiple) => true,
+                None => force,
+            };
+            if !relaunch && launch::is_running(config, app) {
+                progress(format!(
+                    "{} {} (already running)",
+                    paint(Style::Dim, "Skipping:"),
+                    app.name
+                ));
+                events::emit(
+                    "app-skipped",
+                    app_fields()
+                        .into_iter()
+                        .chain([("reason", Json::from("already running"))]),
+                );
+                outcomes.push(AppLaunch {
+                    app: app.name.clone(),
+                    outcome: Outcome::AlreadyRunning,
+                    duration_ms: None,
+                    window_ms: None,
+                });
+                continue;
+            }
+            progress(format!("Launching: {}", app.name));
+            let start = Instant::now();
+            let awaited = if app.wait_for_window {
+                window_watch(app)
+            } else {
+                None
+            };
+            // Apps without a known window are not timed
+            let timed = match &awaited {
+                Some(_) => None,
+                None if profile => launch::window_class(app)
+                    .and_then(|class| windows::count(&class).map(|open| (class, open))),
+                None => None,
+            };
+            let mut window_ms = None;
+            let outcome = match launch::spawn(config, environment, app) {
+                Ok(()) => {
+                    events::emit(
+                        "app-spawned",
+                        app_fields().into_iter().chain([(
+                            "duration_ms",
+                            Json::from(start.elapsed().as_millis() as u64),
+                        )]),
+                    );
+                    launched.push(app);
+                    if let Some((class, open)) = timed {
+                        watched.push((outcomes.len(), class, open, start));
+                    }
+                    if let Some((class, open)) = awaited {
+                        if windows::wait_for_window(&class, open, WINDOW_TIMEOUT) {
+                            window_ms = Some(start.elapsed().as_millis() as u64);
+                            events::emit("health-ok", app_fields());
+                        } else {
+                            warn!(
+                                "No window of '{}' appeared after {}s",
+                                app.name,
+                                WINDOW_TIMEOUT.as_secs()
+                            );
+                            events::emit(
+                                "health-failed",
+                                app_fields()
+                                    .into_iter()
+                                    .chain([("reason", Json::from("no window appeared"))]),
+                            );
+                        }
+                    }
+                    Outcome::Launched
+                }
+                Err(e) => {
+                    error!("Failed to launch {}: {}", app.name, e);
+                    events::emit(
+                        "app-failed",
+                        app_fields()
+                            .into_iter()
+                            .chain([("error", Json::from(e.to_string()))]),
+                    );
+                    Outcome::Failed(e.to_string())
+                }
+            };
+            outcomes.push(AppLaunch {
+                app: app.name.clone(),
+                outcome,
+                duration_ms: Some(start.elapsed().as_millis() as u64),
+                window_ms,
+            });
+        }
+        let watches: Vec<(String, usize)> = watched
+            .iter()
+            .map(|(_, class, open, _)| (class.clone(), *open))
+            .collect();
+        let appeared = windows::wait_for_windows(&watches, WINDOW_TIMEOUT);
+        for ((index, _, _, start), appeared) in watched.iter().zip(appeared) {
+            outcomes[*index].window_ms =
+                appeared.map(|at| at.duration_since(*start).as_millis() as u64);
+        }
+        windows::restore_layout(&launched, existing, WINDOW_TIMEOUT);
+        if let Some(slice) = &environment.slice {
+            if let Err(e) = launch::apply_slice_limits(slice) {
+                error!("Failed to limit the resources of {}: {}", slice.unit(), e);
             }
         }
         info!("Launched apps for environment: {}", env);
     } else {
         eprintln!("Environment '{}' not found.", env);
         error!("Environment '{}' not found", env);
+        return Ok(None);
     }
-    Ok(())
+    Ok(Some(outcomes))
 }
 
-fn validate_config(config: &Config) {
-    let mut all_valid = true;
-    for (env, apps) in &config.environments {
-        for app in apps {
-            if app.ends_with(".desktop") {
-                if !is_desktop_file_available(app) {
-                    println!("Warning: Application '{}' in environment '{}' is not installed or not in PATH.", app, env);
-                    all_valid = false;
-                }
-            } else if !is_command_available(app) {
-                println!("Warning: Application '{}' in environment '{}' is not installed or not in PATH.", app, env);
-                all_valid = false;
+/// Prints a progress line of a launch, unless events are printed instead.
+fn progress(line: String) {
+    if !events::enabled() {
+        println!("{}", line);
+    }
+}
+
+/// How long launching waits for the window of a `wait_for_window` app, or
+/// for the windows whose layout is restored.
+const WINDOW_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The window class to wait for once `app` is launched, along with how many
+/// windows of it are already open.
+fn window_watch(app: &App) -> Option<(String, usize)> {
+    let Some(class) = launch::window_class(app) else {
+        warn!(
+            "Not waiting for a window of '{}', set its `window_class`",
+            app.name
+        );
+        return None;
+    };
+    let Some(open) = windows::count(&class) else {
+        warn!(
+            "Not waiting for a window of '{}', windows can only be listed on Hyprland, Sway or with wmctrl",
+            app.name
+        );
+        return None;
+    };
+    Some((class, open))
+}
+
+/// Stops the environments with running apps other than `env`, then
+/// launches it. Without `env`, it is picked from the list of environments.
+fn switch_environment(config: &Config, env: Option<&str>, force: bool) -> io::Result<bool> {
+    let mut envs: Vec<String> = config.environments.keys().cloned().collect();
+    envs.sort();
+    let env = match env {
+        Some(env) => config.resolve_env(env).to_string(),
+        None => {
+            // Descriptions tell the environments apart in the picker
+            let labels: Vec<String> = envs
+                .iter()
+                .map(|env| match &config.environments[env].description {
+                    Some(description) => format!("{}  {}", env, description.trim()),
+                    None => env.clone(),
+                })
+                .collect();
+            let picked =
+                fuzzy::pick(&labels)?.and_then(|label| labels.iter().position(|l| *l == label));
+            match picked {
+                Some(i) => envs[i].clone(),
+                None => return Ok(true),
             }
         }
+    };
+    if !config.environments.contains_key(&env) {
+        eprintln!("Environment '{}' not found.", env);
+        return Ok(false);
+    }
+    for other in envs.iter().filter(|other| **other != env) {
+        let running = config.environments[other]
+            .apps
+            .iter()
+            .any(|app| launch::is_running(config, app));
+        if running {
+            stop_environment(config, other)?;
+        }
+    }
+    launch_and_record(config, &env, force, true, false, None)
+}
+
+/// Stops `env` and launches it again, or only its app named `app`.
+/// Returns false when something could not be stopped or launched.
+fn restart(config: &Config, env: &str, app: Option<&str>) -> io::Result<bool> {
+    let env = config.resolve_env(env);
+    let Some(environment) = config.environments.get(env) else {
+        eprintln!("Environment '{}' not found.", env);
+        return Ok(false);
+    };
+    let Some(name) = app else {
+        let running: Vec<&str> = environment
+            .apps
+            .iter()
+            .filter(|app| launch::is_running(config, app))
+            .map(|app| app.name.as_str())
+            .collect();
+        stop_environment(config, env)?;
+        let launched = launch_and_record(config, env, false, true, false, None)?;
+        if launched {
+            stats::record_restarts(env, &running)?;
+        }
+        return Ok(launched);
+    };
+    let Some(app) = environment.apps.iter().find(|a| a.name == name) else {
+        eprintln!("App '{}' not found in '{}'.", name, env);
+        return Ok(false);
+    };
+    match launch::stop(config, app) {
+        Ok(true) => println!("{} {}", paint(Style::Running, "Stopped:"), app.name),
+        Ok(false) => {}
+        Err(e) => {
+            println!("{} {}", paint(Style::Failed, "Failed to stop:"), app.name);
+            error!("Failed to stop {}: {}", app.name, e);
+            return Ok(false);
+        }
     }
-    if all_valid {
-        println!("All applications are properly installed.");
+    let Some(outcomes) = launch_apps(
+        config,
+        env,
+        false,
+        false,
+        Some(std::slice::from_ref(&app.name)),
+    )?
+    else {
+        return Ok(false);
+    };
+    if !events::enabled() {
+        launches::print_summary(&outcomes);
     }
+    let succeeded = !outcomes
+        .iter()
+        .any(|a| matches!(a.outcome, Outcome::Failed(_)));
+    if succeeded {
+        stats::record_restarts(env, &[&app.name])?;
+    }
+    launches::record_launch(env, outcomes)?;
+    Ok(succeeded)
+}
+
+/// Launches the app named `name` of `env` with `args` appended to its
+/// command, whether it runs or not. Returns false when it could not be
+/// launched.
+fn run_app(config: &Config, env: &str, name: &str, args: &[String]) -> io::Result<bool> {
+    let env = config.resolve_env(env);
+    let Some(environment) = config.environments.get(env) else {
+        eprintln!("Environment '{}' not found.", env);
+        return Ok(false);
+    };
+    let Some(app) = environment.apps.iter().find(|a| a.name == name) else {
+        eprintln!("App '{}' not found in '{}'.", name, env);
+        return Ok(false);
+    };
+    let start = Instant::now();
+    let outcome = match launch::spawn_with_args(config, environment, app, args) {
+        Ok(()) => {
+            println!("{} {}", paint(Style::Running, "Launched:"), app.name);
+            Outcome::Launched
+        }
+        Err(e) => {
+            println!("{} {}", paint(Style::Failed, "Failed to launch:"), app.name);
+            error!("Failed to launch {}: {}", app.name, e);
+            Outcome::Failed(e.to_string())
+        }
+    };
+    let succeeded = outcome == Outcome::Launched;
+    let outcomes = vec![AppLaunch {
+        app: app.name.clone(),
+        outcome,
+        duration_ms: Some(start.elapsed().as_millis() as u64),
+        window_ms: None,
+    }];
+    stats::record_usage(env, &outcomes)?;
+    launches::record_launch(env, outcomes)?;
+    Ok(succeeded)
 }
 
-fn is_command_available(cmd: &str) -> bool {
-    ProcessCommand::new("which")
-        .arg(cmd)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .map(|status| status.success())
-        .unwrap_or(false)
+/// Records the apps of `env` that are running as launched by clovis.
+/// Returns false when `env` is not configured.
+fn adopt_environment(config: &Config, env: &str) -> io::Result<bool> {
+    let env = config.resolve_env(env);
+    let Some(environment) = config.environments.get(env) else {
+        eprintln!("Environment '{}' not found.", env);
+        return Ok(false);
+    };
+    let mut adopted = Vec::new();
+    for app in &environment.apps {
+        if launch::is_running(config, app) {
+            println!("{} {}", paint(Style::Running, "Adopted:"), app.name);
+            adopted.push(AppLaunch {
+                app: app.name.clone(),
+                outcome: Outcome::Adopted,
+                duration_ms: None,
+                window_ms: None,
+            });
+            continue;
+        }
+        let has_window = launch::window_class(app)
+            .and_then(|class| windows::count(&class))
+            .is_some_and(|open| open > 0);
+        if has_window {
+            println!(
+                "{} {} (a window of it is open, but clovis cannot find its process)",
+                paint(Style::Dim, "Skipping:"),
+                app.name
+            );
+        } else {
+            println!(
+                "{} {} (not running)",
+                paint(Style::Dim, "Skipping:"),
+                app.name
+            );
+        }
+    }
+    if adopted.is_empty() {
+        println!("No app of '{}' is running.", env);
+        return Ok(true);
+    }
+    if let Err(e) = shell_init::set_active(env, true) {
+        error!("Failed to record {} as active: {}", env, e);
+    }
+    launches::record_launch(env, adopted)?;
+    Ok(true)
 }
 
-fn is_desktop_file_available(file: &str) -> bool {
-    let home_dir = std::env::var("HOME").unwrap();
-    let paths = [
-        "/usr/share/applications",
-        "/usr/local/share/applications",
-        &format!("{}/.local/share/applications", home_dir),
-        "/run/current-system/sw/share/applications",
-        &format!("{}/.nix-profile/share/applications", home_dir),
-    ];
+/// Launches `env` with do-not-disturb on, terminates the programs it
+/// blocks for `duration`, then stops it. Returns false when it could not
+/// be launched.
+fn focus_session(config: &Config, env: &str, duration: Duration, yes: bool) -> io::Result<bool> {
+    let env = config.resolve_env(env);
+    let Some(environment) = config.environments.get(env) else {
+        eprintln!("Environment '{}' not found.", env);
+        return Ok(false);
+    };
+    let lock = lock_or_exit()?;
+    if !launch_and_record(config, env, false, yes, false, None)? {
+        return Ok(false);
+    }
+    drop(lock);
+    if let Err(e) = notifications::enable(env) {
+        error!("Failed to turn on do-not-disturb for {}: {}", env, e);
+    }
+    let end = time::OffsetDateTime::now_local().unwrap_or_else(|_| time::OffsetDateTime::now_utc())
+        + duration;
+    println!(
+        "Focus session on '{}' until {:02}:{:02}, Ctrl-C ends it early.",
+        env,
+        end.hour(),
+        end.minute()
+    );
+    let completed = focus::wait(env, &environment.blocks, duration);
+    // Once the time is up, wait for a launch in progress rather than leave
+    // the environment running
+    let _lock = lock::lock()?;
+    stop_environment(config, env)?;
+    if completed {
+        println!("Focus session over.");
+        notifications::send("Focus session over", &format!("'{}' was stopped.", env));
+    } else {
+        println!("Focus session ended early.");
+    }
+    Ok(true)
+}
 
-    for path in paths.iter() {
-        let desktop_file_path = format!("{}/{}", path, file);
-        if std::path::Path::new(&desktop_file_path).exists() {
-            return true;
+/// Stops the apps of `env` (an environment name or alias) that are running,
+/// last launched first, so apps go down before the ones they depend on.
+fn stop_environment(config: &Config, env: &str) -> io::Result<()> {
+    let env = config.resolve_env(env);
+    let Some(environment) = config.environments.get(env) else {
+        eprintln!("Environment '{}' not found.", env);
+        error!("Environment '{}' not found", env);
+        return Ok(());
+    };
+    for app in environment.apps.iter().rev() {
+        match launch::stop(config, app) {
+            Ok(true) => println!("{} {}", paint(Style::Running, "Stopped:"), app.name),
+            Ok(false) => println!(
+                "{} {} (not running)",
+                paint(Style::Dim, "Skipping:"),
+                app.name
+            ),
+            Err(e) => {
+                println!("{} {}", paint(Style::Failed, "Failed to stop:"), app.name);
+                error!("Failed to stop {}: {}", app.name, e);
+            }
+        }
+    }
+    info!("Stopped apps for environment: {}", env);
+    if let Err(e) = shell_init::set_active(env, false) {
+        error!("Failed to record {} as stopped: {}", env, e);
+    }
+    if let Err(e) = appearance::revert(env) {
+        error!(
+            "Failed to restore the appearance from before {}: {}",
+            env, e
+        );
+    }
+    if let Err(e) = notifications::restore(env) {
+        error!("Failed to restore do-not-disturb after {}: {}", env, e);
+    }
+    if let Err(e) = keyboard::restore(env) {
+        error!("Failed to restore the keyboard layout after {}: {}", env, e);
+    }
+    if environment.bluetooth_disconnect_on_stop {
+        for device in environment.bluetooth.iter().rev() {
+            match bluetooth::set_connected(device, false) {
+                Ok(true) => println!("{} {}", paint(Style::Running, "Disconnected:"), device),
+                Ok(false) => {}
+                Err(e) => {
+                    println!(
+                        "{} {}",
+                        paint(Style::Failed, "Failed to disconnect:"),
+                        device
+                    );
+                    error!("Failed to disconnect {}: {}", device, e);
+                }
+            }
+        }
+    }
+    for mount in environment.mounts.iter().rev().filter(|m| !m.keep_mounted) {
+        let name = mounts::describe(mount);
+        let result = mounts::is_mounted(mount).and_then(|mounted| {
+            mounted
+                .then(|| mounts::set_mounted(config, mount, false))
+                .transpose()
+        });
+        match result {
+            Ok(Some(())) => println!("{} {}", paint(Style::Running, "Unmounted:"), name),
+            Ok(None) => {}
+            Err(e) => {
+                println!("{} {}", paint(Style::Failed, "Failed to unmount:"), name);
+                error!("Failed to unmount {}: {}", name, e);
+            }
         }
     }
-    false
+    for action in environment.network.iter().rev().filter(|a| a.down_on_stop) {
+        let connection = network::describe_action(action);
+        let result = network::is_up(action)
+            .and_then(|up| up.then(|| network::set_up(action, false)).transpose());
+        match result {
+            Ok(Some(())) => println!("{} {}", paint(Style::Running, "Brought down:"), connection),
+            Ok(None) => {}
+            Err(e) => {
+                println!(
+                    "{} {}",
+                    paint(Style::Failed, "Failed to bring down:"),
+                    connection
+                );
+                error!("Failed to bring down {}: {}", connection, e);
+            }
+        }
+    }
+    Ok(())
 }
 
-fn is_app_running(app: &str) -> bool {
-    let app_name = app.strip_suffix(".desktop").unwrap_or(app);
-    let output = ProcessCommand::new("pgrep")
-        .arg("-f")
-        .arg(app_name)
-        .output()
-        .expect("Failed to execute pgrep");
+/// Pauses (or resumes) the apps of `env`, in reverse order when pausing as
+/// for stopping.
+fn pause_environment(config: &Config, env: &str, pause: bool) {
+    let env = config.resolve_env(env);
+    let Some(environment) = config.environments.get(env) else {
+        eprintln!("Environment '{}' not found.", env);
+        error!("Environment '{}' not found", env);
+        std::process::exit(1);
+    };
+    let (done, verb) = if pause {
+        ("Paused:", "pause")
+    } else {
+        ("Resumed:", "resume")
+    };
+    let mut apps: Vec<&App> = environment.apps.iter().collect();
+    if pause {
+        apps.reverse();
+    }
+    let mut failed = false;
+    for app in apps {
+        match launch::pause(config, app, pause) {
+            Ok(true) => println!("{} {}", paint(Style::Running, done), app.name),
+            Ok(false) => println!(
+                "{} {} (not running or cannot be paused)",
+                paint(Style::Dim, "Skipping:"),
+                app.name
+            ),
+            Err(e) => {
+                println!(
+                    "{} {}",
+                    paint(Style::Failed, format!("Failed to {}:", verb)),
+                    app.name
+                );
+                error!("Failed to {} {}: {}", verb, app.name, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
 
-    !output.stdout.is_empty()
+/// Stops every app recorded in the launch log that is still in the config,
+/// the most recently launched first.
+fn stop_all(config: &Config) -> io::Result<()> {
+    let mut stopped = 0;
+    for (env, name) in launches::launched_apps()? {
+        let Some(app) = config
+            .environments
+            .get(&env)
+            .and_then(|environment| environment.apps.iter().find(|app| app.name == name))
+        else {
+            continue;
+        };
+        match launch::stop(config, app) {
+            Ok(true) => {
+                println!(
+                    "{} {} ({})",
+                    paint(Style::Running, "Stopped:"),
+                    app.name,
+                    paint(Style::Env, &env)
+                );
+                stopped += 1;
+            }
+            Ok(false) => {}
+            Err(e) => {
+                println!(
+                    "{} {} ({})",
+                    paint(Style::Failed, "Failed to stop:"),
+                    app.name,
+                    paint(Style::Env, &env)
+                );
+                error!("Failed to stop {}: {}", app.name, e);
+            }
+        }
+    }
+    if stopped == 0 {
+        println!("No app launched by clovis is running.");
+    }
+    if let Err(e) = shell_init::clear_active() {
+        error!("Failed to record the environments as stopped: {}", e);
+    }
+    if let Some(env) = appearance::applied_env() {
+        if let Err(e) = appearance::revert(&env) {
+            error!(
+                "Failed to restore the appearance from before {}: {}",
+                env, e
+            );
+        }
+    }
+    if let Some(env) = notifications::enabled_env() {
+        if let Err(e) = notifications::restore(&env) {
+            error!("Failed to restore do-not-disturb after {}: {}", env, e);
+        }
+    }
+    if let Some(env) = keyboard::switched_env() {
+        if let Err(e) = keyboard::restore(&env) {
+            error!("Failed to restore the keyboard layout after {}: {}", env, e);
+        }
+    }
+    info!("Stopped {} apps", stopped);
+    Ok(())
 }
@@ -0,0 +1,163 @@
+//! Metrics of the daemon's `/metrics` endpoint, in the Prometheus text
+//! exposition format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::daemon::AppState;
+use crate::stats::Stats;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Escapes a label value.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Renders the state of the apps and the launch counters kept in `stats`.
+pub fn render(states: &[AppState], stats: &Stats) -> String {
+    let mut out = String::new();
+
+    let mut environments: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for state in states {
+        let (running, total) = environments.entry(&state.env).or_default();
+        *total += 1;
+        *running += usize::from(state.running);
+    }
+    header(
+        &mut out,
+        "clovis_apps_running",
+        "gauge",
+        "Apps of an environment that are running.",
+    );
+    for (env, (running, _)) in &environments {
+        let _ = writeln!(
+            out,
+            "clovis_apps_running{{env=\"{}\"}} {}",
+            label(env),
+            running
+        );
+    }
+    header(
+        &mut out,
+        "clovis_apps",
+        "gauge",
+        "Apps configured in an environment.",
+    );
+    for (env, (_, total)) in &environments {
+        let _ = writeln!(out, "clovis_apps{{env=\"{}\"}} {}", label(env), total);
+    }
+    header(
+        &mut out,
+        "clovis_app_up",
+        "gauge",
+        "Whether an app of an environment is running.",
+    );
+    for state in states {
+        let _ = writeln!(
+            out,
+            "clovis_app_up{{env=\"{}\",app=\"{}\"}} {}",
+            label(&state.env),
+            label(&state.app),
+            u8::from(state.running)
+        );
+    }
+
+    let envs: BTreeMap<_, _> = stats.environments.iter().collect();
+    header(
+        &mut out,
+        "clovis_environment_launches_total",
+        "counter",
+        "Launches of an environment.",
+    );
+    for (env, usage) in &envs {
+        let _ = writeln!(
+            out,
+            "clovis_environment_launches_total{{env=\"{}\"}} {}",
+            label(env),
+            usage.launches
+        );
+    }
+
+    let apps: BTreeMap<_, _> = stats.apps.iter().collect();
+    header(
+        &mut out,
+        "clovis_app_launches_total",
+        "counter",
+        "Times an app was started.",
+    );
+    for (app, usage) in &apps {
+        let _ = writeln!(
+            out,
+            "clovis_app_launches_total{{app=\"{}\"}} {}",
+            label(app),
+            usage.launches
+        );
+    }
+    header(
+        &mut out,
+        "clovis_app_launch_failures_total",
+        "counter",
+        "Times an app failed to start.",
+    );
+    for (app, usage) in &apps {
+        let _ = writeln!(
+            out,
+            "clovis_app_launch_failures_total{{app=\"{}\"}} {}",
+            label(app),
+            usage.failures
+        );
+    }
+    header(
+        &mut out,
+        "clovis_app_launch_duration_seconds",
+        "summary",
+        "Time spent starting an app.",
+    );
+    for (app, usage) in &apps {
+        let _ = writeln!(
+            out,
+            "clovis_app_launch_duration_seconds_sum{{app=\"{}\"}} {}",
+            label(app),
+            usage.launch_ms as f64 / 1000.0
+        );
+        let _ = writeln!(
+            out,
+            "clovis_app_launch_duration_seconds_count{{app=\"{}\"}} {}",
+            label(app),
+            usage.launches
+        );
+    }
+
+    let restarts: BTreeMap<_, BTreeMap<_, _>> = stats
+        .restarts
+        .iter()
+        .map(|(env, apps)| (env, apps.iter().collect()))
+        .collect();
+    header(
+        &mut out,
+        "clovis_restarts_total",
+        "counter",
+        "Times an app of an environment was restarted.",
+    );
+    for (env, apps) in &restarts {
+        for (app, count) in apps {
+            let _ = writeln!(
+                out,
+                "clovis_restarts_total{{env=\"{}\",app=\"{}\"}} {}",
+                label(env),
+                label(app),
+                count
+            );
+        }
+    }
+    out
+}
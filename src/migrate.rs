@@ -0,0 +1,90 @@
+//! Upgrades of older config formats.
+//!
+//! Every config file carries a `version:` field; files written before it
+//! existed are version 1. On load the raw YAML is run through the migrations
+//! below until it reaches [`CONFIG_VERSION`], so the rest of clovis only ever
+//! deals with the current format. The file itself is only rewritten by an
+//! explicit `clovis migrate`.
+
+use serde_yaml::{Mapping, Value};
+
+pub const CONFIG_VERSION: u32 = 2;
+
+type Migration = fn(&mut Mapping) -> Result<(), String>;
+
+/// Converts a config of version `n` (the index + 1) to version `n + 1`.
+const MIGRATIONS: &[Migration] = &[v1_to_v2];
+
+fn key(name: &str) -> Value {
+    Value::String(name.to_string())
+}
+
+/// Returns the format version declared by the raw config text, treating
+/// unreadable files as current so callers do not refuse to touch them.
+pub fn document_version(contents: &str) -> u32 {
+    serde_yaml::from_str::<Value>(contents)
+        .ok()
+        .and_then(|value| version_of(&value).ok())
+        .unwrap_or(CONFIG_VERSION)
+}
+
+fn version_of(value: &Value) -> Result<u32, String> {
+    match value.get("version") {
+        None => Ok(1),
+        Some(Value::Number(n)) => n
+            .as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .ok_or_else(|| format!("invalid config version {:?}", n)),
+        Some(other) => Err(format!("invalid config version {:?}", other)),
+    }
+}
+
+/// Upgrades `value` in place to the current format and returns the version
+/// it was in.
+pub fn migrate(value: &mut Value) -> Result<u32, String> {
+    let original = version_of(value)?;
+    if original > CONFIG_VERSION {
+        return Err(format!(
+            "config version {} is newer than the supported version {}, upgrade clovis",
+            original, CONFIG_VERSION
+        ));
+    }
+    let Value::Mapping(mapping) = value else {
+        return Err("the config file must be a mapping".to_string());
+    };
+    for migration in &MIGRATIONS[original.saturating_sub(1) as usize..] {
+        migration(mapping)?;
+    }
+    mapping.insert(key("version"), Value::from(CONFIG_VERSION));
+    Ok(original)
+}
+
+/// Version 1 environments are plain lists of application names, version 2
+/// turns them into mappings with an `apps` list of structured entries.
+fn v1_to_v2(config: &mut Mapping) -> Result<(), String> {
+    let Some(Value::Mapping(environments)) = config.get_mut(&key("environments")) else {
+        return Ok(());
+    };
+    for (name, env) in environments.iter_mut() {
+        let apps = match env {
+            Value::Sequence(apps) => apps,
+            Value::Null => {
+                *env = Value::Mapping(Mapping::new());
+                continue;
+            }
+            _ => return Err(format!("environment {:?} must be a list of apps", name)),
+        };
+        let apps = apps
+            .iter()
+            .map(|app| {
+                let mut entry = Mapping::new();
+                entry.insert(key("name"), app.clone());
+                Value::Mapping(entry)
+            })
+            .collect();
+        let mut migrated = Mapping::new();
+        migrated.insert(key("apps"), Value::Sequence(apps));
+        *env = Value::Mapping(migrated);
+    }
+    Ok(())
+}
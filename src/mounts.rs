@@ -0,0 +1,130 @@
+//! Shares and disks environments mount before their apps are launched:
+//! GVfs locations with `gio mount`, block devices with `udisksctl`, or
+//! anything else with a pair of shell commands.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command as ProcessCommand, Stdio};
+
+use crate::config::{Config, Mount, MountKind};
+use crate::launch::{expand_home, shell};
+
+/// The mount points of `/proc/self/mounts` along with their sources.
+fn mount_table() -> io::Result<Vec<(String, PathBuf)>> {
+    // Spaces and other special characters are escaped as `\040`
+    let unescape = |field: &str| {
+        let mut unescaped = String::new();
+        let mut rest = field;
+        while let Some(i) = rest.find('\\') {
+            unescaped.push_str(&rest[..i]);
+            match rest
+                .get(i + 1..i + 4)
+                .and_then(|o| u8::from_str_radix(o, 8).ok())
+            {
+                Some(byte) => {
+                    unescaped.push(byte as char);
+                    rest = &rest[i + 4..];
+                }
+                None => {
+                    unescaped.push('\\');
+                    rest = &rest[i + 1..];
+                }
+            }
+        }
+        unescaped.push_str(rest);
+        unescaped
+    };
+    Ok(fs::read_to_string("/proc/self/mounts")?
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let source = unescape(fields.next()?);
+            let target = PathBuf::from(unescape(fields.next()?));
+            Some((source, target))
+        })
+        .collect())
+}
+
+fn run(command: &mut ProcessCommand) -> io::Result<String> {
+    let output = command.stdin(Stdio::null()).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn required<'a>(mount: &'a Mount, field: &'a Option<String>, name: &str) -> io::Result<&'a str> {
+    field.as_deref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("`{}` mounts need `{}`", mount.kind.as_str(), name),
+        )
+    })
+}
+
+/// What `mount` mounts, e.g. `smb://nas/share`.
+pub fn describe(mount: &Mount) -> String {
+    mount
+        .source
+        .clone()
+        .or_else(|| mount.path.clone())
+        .unwrap_or_else(|| mount.kind.as_str().to_string())
+}
+
+pub fn is_mounted(mount: &Mount) -> io::Result<bool> {
+    match mount.kind {
+        MountKind::Gio => {
+            let location = required(mount, &mount.source, "source")?;
+            // `Mount(0): share on nas -> smb://nas/share/`
+            let mounts = run(ProcessCommand::new("gio").args(["mount", "--list"]))?;
+            Ok(mounts.lines().any(|line| {
+                line.rsplit_once(" -> ").is_some_and(|(_, uri)| {
+                    uri.trim_end_matches('/') == location.trim_end_matches('/')
+                })
+            }))
+        }
+        MountKind::Udisks => {
+            let device = fs::canonicalize(required(mount, &mount.source, "source")?)?;
+            Ok(mount_table()?
+                .iter()
+                .any(|(source, _)| Path::new(source) == device))
+        }
+        MountKind::Command => {
+            let path = expand_home(required(mount, &mount.path, "path")?);
+            Ok(mount_table()?.iter().any(|(_, target)| *target == path))
+        }
+    }
+}
+
+/// Mounts `mount`, or unmounts it when `mounted` is false.
+pub fn set_mounted(config: &Config, mount: &Mount, mounted: bool) -> io::Result<()> {
+    match mount.kind {
+        MountKind::Gio => {
+            let location = required(mount, &mount.source, "source")?;
+            let mut command = ProcessCommand::new("gio");
+            command.arg("mount");
+            if !mounted {
+                command.arg("--unmount");
+            }
+            run(command.arg(location))?;
+        }
+        MountKind::Udisks => {
+            let device = required(mount, &mount.source, "source")?;
+            run(ProcessCommand::new("udisksctl")
+                .arg(if mounted { "mount" } else { "unmount" })
+                .args(["--no-user-interaction", "--block-device", device]))?;
+        }
+        MountKind::Command => {
+            let (field, name) = if mounted {
+                (&mount.mount, "mount")
+            } else {
+                (&mount.unmount, "unmount")
+            };
+            let command = required(mount, field, name)?;
+            run(ProcessCommand::new(shell(config)).arg("-c").arg(command))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,262 @@
+//! Just enough of MQTT 3.1.1 to subscribe to topics at QoS 0 and receive
+//! the messages published on them.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use crate::config::MqttConfig;
+
+/// The broker drops clients silent for one and a half times this long.
+pub const KEEP_ALIVE: Duration = Duration::from_secs(60);
+const DEFAULT_PORT: u16 = 1883;
+/// How long connecting, and reading the rest of a packet once it started
+/// arriving, may take: the daemon waits on the broker meanwhile.
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// The largest packet accepted, far above any trigger payload.
+const MAX_PACKET: usize = 1024 * 1024;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+
+/// A message received on a subscribed topic.
+#[derive(Debug)]
+pub struct Publish {
+    pub topic: String,
+    pub payload: String,
+}
+
+pub struct Client {
+    stream: TcpStream,
+    last_sent: Instant,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Appends a length-prefixed string.
+fn put_str(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Whether `topic` matches the subscription `filter`, which may contain the
+/// `+` (one level) and `#` (any remaining levels) wildcards.
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut topic_levels = topic.split('/');
+    for level in filter.split('/') {
+        match (level, topic_levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (level, Some(actual)) if level == actual => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+impl Client {
+    /// Connects to the broker of `config` and subscribes to the topics of
+    /// its triggers.
+    pub fn connect(config: &MqttConfig) -> io::Result<Client> {
+        let address = if config.broker.contains(':') {
+            config.broker.clone()
+        } else {
+            format!("{}:{}", config.broker, DEFAULT_PORT)
+        };
+        let mut last_error = None;
+        let mut stream = None;
+        for socket_address in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&socket_address, TIMEOUT) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        let stream = stream.ok_or_else(|| {
+            last_error.unwrap_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} has no address", config.broker),
+                )
+            })
+        })?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut client = Client {
+            stream,
+            last_sent: Instant::now(),
+        };
+
+        let mut body = Vec::new();
+        put_str(&mut body, "MQTT");
+        body.push(4);
+        // Clean session, plus the credentials that are set
+        let mut flags = 0x02;
+        if config.username.is_some() {
+            flags |= 0x80;
+        }
+        if config.password.is_some() {
+            flags |= 0x40;
+        }
+        body.push(flags);
+        body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+        put_str(&mut body, &config.client_id);
+        if let Some(username) = &config.username {
+            put_str(&mut body, username);
+        }
+        if let Some(password) = &config.password {
+            put_str(&mut body, password);
+        }
+        client.send(CONNECT << 4, &body)?;
+
+        let (kind, body) = client.read_packet()?;
+        if kind >> 4 != CONNACK || body.len() < 2 {
+            return Err(invalid("the broker did not acknowledge the connection"));
+        }
+        if body[1] != 0 {
+            let reason = match body[1] {
+                1 => "unsupported protocol version",
+                2 => "client id rejected",
+                3 => "broker unavailable",
+                4 => "bad username or password",
+                5 => "not authorized",
+                _ => "unknown reason",
+            };
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("the broker refused the connection: {}", reason),
+            ));
+        }
+
+        let mut topics: Vec<&str> = config.triggers.iter().map(|t| t.topic.as_str()).collect();
+        topics.sort_unstable();
+        topics.dedup();
+        if !topics.is_empty() {
+            // Packet identifier 1, then each filter with QoS 0
+            let mut body = vec![0, 1];
+            for topic in topics {
+                put_str(&mut body, topic);
+                body.push(0);
+            }
+            client.send(SUBSCRIBE << 4 | 0x02, &body)?;
+            loop {
+                let (kind, body) = client.read_packet()?;
+                if kind >> 4 == SUBACK {
+                    let codes = body
+                        .get(2..)
+                        .ok_or_else(|| invalid("truncated subscription acknowledgement"))?;
+                    if codes.contains(&0x80) {
+                        return Err(invalid("the broker refused a subscription"));
+                    }
+                    break;
+                }
+            }
+        }
+        Ok(client)
+    }
+
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+
+    fn send(&mut self, header: u8, body: &[u8]) -> io::Result<()> {
+        let mut packet = vec![header];
+        let mut length = body.len();
+        loop {
+            let mut byte = (length % 128) as u8;
+            length /= 128;
+            if length > 0 {
+                byte |= 0x80;
+            }
+            packet.push(byte);
+            if length == 0 {
+                break;
+            }
+        }
+        packet.extend_from_slice(body);
+        self.stream.write_all(&packet)?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    fn read_packet(&mut self) -> io::Result<(u8, Vec<u8>)> {
+        let mut byte = [0u8];
+        if self.stream.read(&mut byte)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the broker closed the connection",
+            ));
+        }
+        let kind = byte[0];
+        let mut length = 0usize;
+        for shift in (0..28).step_by(7) {
+            self.stream.read_exact(&mut byte)?;
+            length |= usize::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        if length > MAX_PACKET {
+            return Err(invalid("the broker sent a packet too large"));
+        }
+        let mut body = vec![0u8; length];
+        self.stream.read_exact(&mut body)?;
+        Ok((kind, body))
+    }
+
+    /// Reads the next packet, to be called once the connection is readable:
+    /// waiting for the rest of it times out after a few seconds. Returns the
+    /// message when it is a publication.
+    pub fn read(&mut self) -> io::Result<Option<Publish>> {
+        let (kind, body) = self.read_packet()?;
+        if kind >> 4 != PUBLISH {
+            return Ok(None);
+        }
+        let qos = (kind >> 1) & 0x03;
+        let topic_len = usize::from(u16::from_be_bytes(
+            body.get(..2)
+                .ok_or_else(|| invalid("truncated publication"))?
+                .try_into()
+                .unwrap(),
+        ));
+        let topic = body
+            .get(2..2 + topic_len)
+            .ok_or_else(|| invalid("truncated publication"))?;
+        let topic = String::from_utf8_lossy(topic).into_owned();
+        let mut payload_start = 2 + topic_len;
+        // Subscriptions are QoS 0, but brokers may still deliver retained
+        // messages at a higher level
+        if qos > 0 {
+            let id = body
+                .get(payload_start..payload_start + 2)
+                .ok_or_else(|| invalid("truncated publication"))?
+                .to_vec();
+            payload_start += 2;
+            self.send(PUBACK << 4, &id)?;
+        }
+        let payload = String::from_utf8_lossy(&body[payload_start.min(body.len())..]);
+        Ok(Some(Publish {
+            topic,
+            payload: payload.trim().to_string(),
+        }))
+    }
+
+    /// Pings the broker when nothing was sent for a while, so that it keeps
+    /// the connection open.
+    pub fn keep_alive(&mut self) -> io::Result<()> {
+        if self.last_sent.elapsed() >= KEEP_ALIVE / 2 {
+            self.send(PINGREQ << 4, &[])?;
+        }
+        Ok(())
+    }
+}
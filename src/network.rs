@@ -0,0 +1,149 @@
+//! The Wi-Fi networks and VPNs NetworkManager is connected to, for the
+//! `network_rules:` of `clovis daemon`, and the connections environments
+//! bring up before launching.
+
+use std::io;
+use std::path::Path;
+use std::process::Command as ProcessCommand;
+
+use crate::config::{NetworkAction, NetworkKind, NetworkRule};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct NetworkState {
+    /// SSIDs of the Wi-Fi networks in use.
+    pub ssids: Vec<String>,
+    /// Names of the active VPN and WireGuard connections.
+    pub vpns: Vec<String>,
+}
+
+fn nmcli(args: &[&str]) -> io::Result<String> {
+    let output = ProcessCommand::new("nmcli").args(args).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Undoes the escaping of `nmcli --terse`, which puts a backslash before
+/// colons and backslashes within fields.
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Splits a terse line at its last unescaped colon.
+fn split_last_field(line: &str) -> Option<(&str, &str)> {
+    let bytes = line.as_bytes();
+    (0..bytes.len()).rev().find_map(|i| {
+        let escapes = bytes[..i].iter().rev().take_while(|b| **b == b'\\').count();
+        (bytes[i] == b':' && escapes % 2 == 0).then(|| (&line[..i], &line[i + 1..]))
+    })
+}
+
+pub fn current() -> io::Result<NetworkState> {
+    let ssids = nmcli(&[
+        "--terse",
+        "--fields",
+        "ACTIVE,SSID",
+        "device",
+        "wifi",
+        "list",
+        "--rescan",
+        "no",
+    ])?
+    .lines()
+    .filter_map(|line| line.strip_prefix("yes:"))
+    .map(unescape)
+    .collect();
+    let vpns = nmcli(&[
+        "--terse",
+        "--fields",
+        "NAME,TYPE",
+        "connection",
+        "show",
+        "--active",
+    ])?
+    .lines()
+    .filter_map(split_last_field)
+    .filter(|(_, kind)| *kind == "vpn" || *kind == "wireguard")
+    .map(|(name, _)| unescape(name))
+    .collect();
+    Ok(NetworkState { ssids, vpns })
+}
+
+/// The connection of `action`, e.g. `VPN connection 'corp'`.
+pub fn describe_action(action: &NetworkAction) -> String {
+    match action.kind {
+        NetworkKind::Nmcli => format!("connection '{}'", action.name),
+        NetworkKind::Wireguard => format!("WireGuard interface '{}'", action.name),
+    }
+}
+
+/// Whether the connection of `action` is up.
+pub fn is_up(action: &NetworkAction) -> io::Result<bool> {
+    match action.kind {
+        NetworkKind::Nmcli => {
+            let active = nmcli(&[
+                "--terse",
+                "--fields",
+                "NAME",
+                "connection",
+                "show",
+                "--active",
+            ])?;
+            Ok(active.lines().any(|name| unescape(name) == action.name))
+        }
+        NetworkKind::Wireguard => Ok(Path::new("/sys/class/net").join(&action.name).exists()),
+    }
+}
+
+fn wg_quick(command: &str, interface: &str) -> io::Result<()> {
+    // SAFETY: geteuid cannot fail
+    let mut process = if unsafe { libc::geteuid() } == 0 {
+        ProcessCommand::new("wg-quick")
+    } else {
+        let mut sudo = ProcessCommand::new("sudo");
+        sudo.args(["--non-interactive", "wg-quick"]);
+        sudo
+    };
+    let output = process.args([command, interface]).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+    Ok(())
+}
+
+/// Brings the connection of `action` up, or down when `up` is false.
+pub fn set_up(action: &NetworkAction, up: bool) -> io::Result<()> {
+    let command = if up { "up" } else { "down" };
+    match action.kind {
+        NetworkKind::Nmcli => nmcli(&["connection", command, "id", &action.name]).map(|_| ()),
+        NetworkKind::Wireguard => wg_quick(command, &action.name),
+    }
+}
+
+/// The network of `rule`, e.g. `Office + corp-vpn`.
+pub fn describe(rule: &NetworkRule) -> String {
+    [rule.ssid.as_deref(), rule.vpn.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+/// Whether the conditions of `rule` all hold in `state`.
+pub fn rule_matches(rule: &NetworkRule, state: &NetworkState) -> bool {
+    rule.ssid
+        .as_ref()
+        .is_none_or(|ssid| state.ssids.contains(ssid))
+        && rule.vpn.as_ref().is_none_or(|vpn| state.vpns.contains(vpn))
+}
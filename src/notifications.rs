@@ -0,0 +1,175 @@
+//! Do-not-disturb mode of the notification daemon (swaync, dunst, mako or
+//! GNOME Shell), turned on by the environments that ask for it. Whether it
+//! was on before is kept in the state directory, to be restored once the
+//! environment is stopped. Notifications are sent with `notify-send`.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command as ProcessCommand, Stdio};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::launch::is_command_available;
+use crate::paths;
+
+/// The mako mode hiding notifications, which the mako config has to
+/// define, e.g. with `[mode=do-not-disturb]` and `invisible=1`.
+const MAKO_MODE: &str = "do-not-disturb";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Daemon {
+    Swaync,
+    Dunst,
+    Mako,
+    Gnome,
+}
+
+fn run(program: &str, args: &[&str]) -> io::Result<String> {
+    let output = ProcessCommand::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl Daemon {
+    /// Whether do-not-disturb is on, or an error when this daemon is not
+    /// the one running.
+    fn is_on(self) -> io::Result<bool> {
+        Ok(match self {
+            Daemon::Swaync => run("swaync-client", &["--get-dnd"])? == "true",
+            Daemon::Dunst => run("dunstctl", &["is-paused"])? == "true",
+            Daemon::Mako => run("makoctl", &["mode"])?
+                .lines()
+                .any(|mode| mode.trim() == MAKO_MODE),
+            Daemon::Gnome => {
+                run(
+                    "gsettings",
+                    &["get", "org.gnome.desktop.notifications", "show-banners"],
+                )? == "false"
+            }
+        })
+    }
+
+    fn set(self, on: bool) -> io::Result<()> {
+        match self {
+            Daemon::Swaync => run(
+                "swaync-client",
+                &[if on { "--dnd-on" } else { "--dnd-off" }],
+            ),
+            Daemon::Dunst => run(
+                "dunstctl",
+                &["set-paused", if on { "true" } else { "false" }],
+            ),
+            Daemon::Mako => run(
+                "makoctl",
+                &["mode", if on { "-a" } else { "-r" }, MAKO_MODE],
+            ),
+            Daemon::Gnome => run(
+                "gsettings",
+                &[
+                    "set",
+                    "org.gnome.desktop.notifications",
+                    "show-banners",
+                    if on { "false" } else { "true" },
+                ],
+            ),
+        }
+        .map(|_| ())
+    }
+}
+
+/// The notification daemon running, along with whether do-not-disturb is
+/// on.
+fn detect() -> Option<(Daemon, bool)> {
+    [Daemon::Swaync, Daemon::Dunst, Daemon::Mako, Daemon::Gnome]
+        .into_iter()
+        .find_map(|daemon| Some((daemon, daemon.is_on().ok()?)))
+}
+
+/// The environment that turned do-not-disturb on, and whether it was on
+/// before.
+#[derive(Debug, Serialize, Deserialize)]
+struct Enabled {
+    env: String,
+    was_on: bool,
+}
+
+fn enabled_path() -> PathBuf {
+    paths::state_dir().join("do-not-disturb.yaml")
+}
+
+fn load_enabled() -> Option<Enabled> {
+    let contents = fs::read_to_string(enabled_path()).ok()?;
+    serde_yaml::from_str(&contents).ok()
+}
+
+/// Turns do-not-disturb on for `env`.
+pub fn enable(env: &str) -> io::Result<()> {
+    let Some((daemon, on)) = detect() else {
+        warn!(
+            "Cannot turn on do-not-disturb for '{}', no swaync, dunst, mako or GNOME Shell found",
+            env
+        );
+        return Ok(());
+    };
+    // Another environment may have turned it on already
+    let was_on = load_enabled().map_or(on, |enabled| enabled.was_on);
+    if !on {
+        daemon.set(true)?;
+    }
+    let enabled = Enabled {
+        env: env.to_string(),
+        was_on,
+    };
+    let path = enabled_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(
+        &path,
+        serde_yaml::to_string(&enabled).map_err(io::Error::other)?,
+    )?;
+    info!("Turned on do-not-disturb ({:?}) for {}", daemon, env);
+    Ok(())
+}
+
+/// The environment that turned do-not-disturb on, if any.
+pub fn enabled_env() -> Option<String> {
+    load_enabled().map(|enabled| enabled.env)
+}
+
+/// Turns do-not-disturb back off after `env`, unless it was on before or
+/// another environment turned it on since.
+pub fn restore(env: &str) -> io::Result<()> {
+    let Some(enabled) = load_enabled().filter(|enabled| enabled.env == env) else {
+        return Ok(());
+    };
+    if !enabled.was_on {
+        if let Some((daemon, true)) = detect() {
+            daemon.set(false)?;
+            info!("Turned off do-not-disturb ({:?}) after {}", daemon, env);
+        }
+    }
+    fs::remove_file(enabled_path())
+}
+
+/// Shows a notification, when `notify-send` is installed.
+pub fn send(summary: &str, body: &str) {
+    if !is_command_available("notify-send") {
+        return;
+    }
+    let sent = ProcessCommand::new("notify-send")
+        .args(["--app-name=clovis", summary, body])
+        .stdin(Stdio::null())
+        .status();
+    if let Err(e) = sent {
+        warn!("Failed to send the notification '{}': {}", summary, e);
+    }
+}
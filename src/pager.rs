@@ -0,0 +1,91 @@
+//! Paging long outputs, the way git does: when stdout is a terminal, what
+//! a command prints goes through `$CLOVIS_PAGER` or `$PAGER`, `less` by
+//! default, which exits right away when it all fits on the screen.
+
+use std::io::{self, IsTerminal, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::process::{Child, Command as ProcessCommand, Stdio};
+
+use log::warn;
+
+/// The pager stdout is sent to, until it is dropped.
+pub struct Pager {
+    child: Child,
+    /// The terminal stdout was before the pager took it.
+    stdout: RawFd,
+}
+
+/// The pager command, `None` when paging is turned off with an empty
+/// pager or `cat`.
+fn command() -> Option<String> {
+    let command = ["CLOVIS_PAGER", "PAGER"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .unwrap_or_else(|| "less".to_string());
+    let command = command.trim();
+    (!command.is_empty() && command != "cat").then(|| command.to_string())
+}
+
+/// Sends stdout to the pager until the returned [`Pager`] is dropped.
+/// Nothing is paged when `disabled`, when stdout is not a terminal, or when
+/// the pager cannot be started.
+pub fn start(disabled: bool) -> Option<Pager> {
+    if disabled || !io::stdout().is_terminal() {
+        return None;
+    }
+    let command = command()?;
+    let mut pager = ProcessCommand::new("sh");
+    pager.arg("-c").arg(&command).stdin(Stdio::piped());
+    // Quit when it fits on the screen, keep colors and leave the output on
+    // the terminal, unless the user configured less otherwise
+    if std::env::var_os("LESS").is_none() {
+        pager.env("LESS", "FRX");
+    }
+    if std::env::var_os("LV").is_none() {
+        pager.env("LV", "-c");
+    }
+    let mut child = match pager.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to start the pager '{}': {}", command, e);
+            return None;
+        }
+    };
+    let stdin = child.stdin.take()?;
+    let _ = io::stdout().flush();
+    // SAFETY: dup and dup2 only duplicate descriptors this process owns.
+    // Quitting the pager early ends clovis quietly, as it would git, rather
+    // than on a failed write.
+    let stdout = unsafe {
+        let stdout = libc::dup(libc::STDOUT_FILENO);
+        if stdout < 0 || libc::dup2(stdin.as_raw_fd(), libc::STDOUT_FILENO) < 0 {
+            warn!(
+                "Failed to send the output to the pager: {}",
+                io::Error::last_os_error()
+            );
+            if stdout >= 0 {
+                libc::close(stdout);
+            }
+            drop(stdin);
+            let _ = child.wait();
+            return None;
+        }
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+        stdout
+    };
+    Some(Pager { child, stdout })
+}
+
+impl Drop for Pager {
+    /// Closes the pager's input and waits for the user to quit it.
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        // SAFETY: puts back the descriptor saved by start, which closes the
+        // last write end of the pipe
+        unsafe {
+            libc::dup2(self.stdout, libc::STDOUT_FILENO);
+            libc::close(self.stdout);
+        }
+        let _ = self.child.wait();
+    }
+}
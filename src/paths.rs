@@ -0,0 +1,113 @@
+//! Where clovis keeps its files, following the XDG base directory
+//! specification: the config in `$XDG_CONFIG_HOME`, what it records across
+//! sessions in `$XDG_STATE_HOME`, what only lasts until logout in
+//! `$XDG_RUNTIME_DIR` and what can be rebuilt in `$XDG_CACHE_HOME`.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+/// Files kept next to the config by earlier versions, moved to the state
+/// directory.
+const STATE_FILES: &[&str] = &["history.yaml", "launches.yaml", "stats.yaml"];
+
+fn xdg_dir(variable: &str, default: &str) -> PathBuf {
+    std::env::var(variable)
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(default)))
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("clovis")
+}
+
+/// `$XDG_CONFIG_HOME/clovis`, holding `config.yaml`.
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("clovis")
+}
+
+/// `$XDG_STATE_HOME/clovis`: the log, the journal of config changes, past
+/// launches and usage statistics, and the settings to restore on stop.
+pub fn state_dir() -> PathBuf {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// `$XDG_CACHE_HOME/clovis`, for what is only kept to be faster.
+pub fn cache_dir() -> PathBuf {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// `$XDG_RUNTIME_DIR/clovis`, or a per-user directory in `/tmp` when there
+/// is no runtime directory, for what must not outlive the session.
+pub fn runtime_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("clovis"),
+        None => {
+            // SAFETY: getuid cannot fail
+            let uid = unsafe { libc::getuid() };
+            PathBuf::from(format!("/tmp/clovis-{}", uid))
+        }
+    }
+}
+
+/// Creates the runtime directory, readable by the user only. One that
+/// already exists must be such a directory: in `/tmp`, another user could
+/// have made it first.
+pub fn create_runtime_dir() -> io::Result<PathBuf> {
+    let dir = runtime_dir();
+    match fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+    {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+    check_private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Fails unless `dir` is a directory, not a symlink, owned by the current
+/// user and only accessible to them.
+pub fn check_private_dir(dir: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(dir)?;
+    // SAFETY: getuid cannot fail
+    let uid = unsafe { libc::getuid() };
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o777 != 0o700 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} is not a directory only the current user can access",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Moves the state files earlier versions kept in `config_dir` to the
+/// state directory, unless it already has its own.
+pub fn move_state_files(config_dir: &Path) -> io::Result<()> {
+    let state_dir = state_dir();
+    for file in STATE_FILES {
+        let old = config_dir.join(file);
+        let new = state_dir.join(file);
+        if !old.exists() || new.exists() {
+            continue;
+        }
+        fs::create_dir_all(&state_dir)?;
+        // Across file systems, renaming fails
+        if fs::rename(&old, &new).is_err() {
+            fs::copy(&old, &new)?;
+            fs::remove_file(&old)?;
+        }
+        info!("Moved {} to {}", old.display(), new.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,215 @@
+//! What finding, starting and stopping apps takes on each system, the
+//! backend being chosen when clovis is built for it. The functions of
+//! `launch` go through [`current`], so a new backend is a new
+//! implementation of [`Platform`] rather than more branches at every call
+//! site.
+
+use std::io;
+#[cfg(target_os = "macos")]
+use std::path::PathBuf;
+use std::process::Command as ProcessCommand;
+
+#[cfg(target_os = "macos")]
+use crate::config::AppKind;
+use crate::config::{App, Config, Environment};
+use crate::launch;
+#[cfg(target_os = "macos")]
+use crate::macos;
+
+pub trait Platform {
+    /// Checks that `app` can be launched here, explaining why not when it
+    /// can't.
+    fn find_app(&self, config: &Config, app: &App) -> Result<(), String>;
+
+    /// Builds the command launching `app`, `None` when starting it runs
+    /// nothing more.
+    fn command(
+        &self,
+        config: &Config,
+        environment: &Environment,
+        app: &App,
+    ) -> io::Result<Option<ProcessCommand>>;
+
+    /// Starts `app` in the background, `args` appended to its command.
+    fn spawn(
+        &self,
+        config: &Config,
+        environment: &Environment,
+        app: &App,
+        args: &[String],
+    ) -> io::Result<()>;
+
+    fn is_running(&self, config: &Config, app: &App) -> bool;
+
+    /// Asks a running app to stop, without waiting for it to exit. Returns
+    /// whether it could be told to.
+    fn stop(&self, config: &Config, app: &App) -> io::Result<bool>;
+
+    /// Kills an app that did not stop when asked. Returns whether it could
+    /// be killed.
+    fn kill(&self, _config: &Config, _app: &App) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Suspends or resumes a running app. Returns whether there was
+    /// anything to pause or resume, never the case where apps cannot be
+    /// paused.
+    fn pause(&self, _config: &Config, _app: &App, _pause: bool) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Systems where apps have desktop files and are opened with `xdg-open`:
+/// Linux, where the session is run by systemd, and the BSDs.
+pub struct Freedesktop {
+    /// Whether apps may be started in systemd scopes.
+    pub systemd: bool,
+}
+
+#[cfg(target_os = "linux")]
+pub const LINUX: Freedesktop = Freedesktop { systemd: true };
+#[cfg(not(target_os = "linux"))]
+pub const UNIX: Freedesktop = Freedesktop { systemd: false };
+
+impl Platform for Freedesktop {
+    fn find_app(&self, config: &Config, app: &App) -> Result<(), String> {
+        launch::freedesktop_check(config, app)
+    }
+
+    fn command(
+        &self,
+        config: &Config,
+        environment: &Environment,
+        app: &App,
+    ) -> io::Result<Option<ProcessCommand>> {
+        launch::freedesktop_command(config, environment, app)
+    }
+
+    fn spawn(
+        &self,
+        config: &Config,
+        environment: &Environment,
+        app: &App,
+        args: &[String],
+    ) -> io::Result<()> {
+        let command = self
+            .command(config, environment, app)?
+            .map(|command| launch::append_args(app, command, args))
+            .transpose()?;
+        launch::spawn_detached(config, environment, app, command, self.systemd)
+    }
+
+    fn is_running(&self, config: &Config, app: &App) -> bool {
+        launch::freedesktop_is_running(config, app, self.systemd)
+    }
+
+    fn stop(&self, config: &Config, app: &App) -> io::Result<bool> {
+        launch::freedesktop_stop(config, app, self.systemd)
+    }
+
+    fn kill(&self, config: &Config, app: &App) -> io::Result<bool> {
+        launch::freedesktop_kill(config, app, self.systemd)
+    }
+
+    fn pause(&self, config: &Config, app: &App, pause: bool) -> io::Result<bool> {
+        launch::freedesktop_pause(config, app, pause, self.systemd)
+    }
+}
+
+/// macOS, where apps are `.app` bundles; commands, scripts and the other
+/// kinds of entries are handled as on the BSDs.
+#[cfg(target_os = "macos")]
+pub struct MacOs;
+
+#[cfg(target_os = "macos")]
+impl MacOs {
+    fn bundle(app: &App) -> Option<PathBuf> {
+        if app.kind != AppKind::Auto {
+            return None;
+        }
+        macos::find_app(&app.name)
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl Platform for MacOs {
+    fn find_app(&self, config: &Config, app: &App) -> Result<(), String> {
+        if app.kind == AppKind::Auto && launch::is_openable(&app.name) {
+            return if launch::is_url(&app.name) || launch::expand_home(&app.name).exists() {
+                Ok(())
+            } else {
+                Err("does not exist".to_string())
+            };
+        }
+        match MacOs::bundle(app) {
+            Some(_) => Ok(()),
+            None => UNIX.find_app(config, app),
+        }
+    }
+
+    fn command(
+        &self,
+        config: &Config,
+        environment: &Environment,
+        app: &App,
+    ) -> io::Result<Option<ProcessCommand>> {
+        if app.kind == AppKind::Auto && launch::is_openable(&app.name) {
+            return Ok(Some(macos::open_command(&app.name)));
+        }
+        match MacOs::bundle(app) {
+            Some(bundle) => Ok(Some(macos::start_command(&bundle))),
+            None => UNIX.command(config, environment, app),
+        }
+    }
+
+    fn spawn(
+        &self,
+        config: &Config,
+        environment: &Environment,
+        app: &App,
+        args: &[String],
+    ) -> io::Result<()> {
+        let command = self
+            .command(config, environment, app)?
+            .map(|command| launch::append_args(app, command, args))
+            .transpose()?;
+        launch::spawn_detached(config, environment, app, command, false)
+    }
+
+    fn is_running(&self, config: &Config, app: &App) -> bool {
+        match MacOs::bundle(app) {
+            Some(bundle) => macos::is_running(&bundle),
+            None => UNIX.is_running(config, app),
+        }
+    }
+
+    fn stop(&self, config: &Config, app: &App) -> io::Result<bool> {
+        match MacOs::bundle(app).filter(|_| app.stop_command.is_none()) {
+            Some(bundle) => macos::stop(&bundle).map(|()| true),
+            None => UNIX.stop(config, app),
+        }
+    }
+
+    fn kill(&self, config: &Config, app: &App) -> io::Result<bool> {
+        match MacOs::bundle(app) {
+            Some(bundle) => macos::kill(&bundle).map(|()| true),
+            None => UNIX.kill(config, app),
+        }
+    }
+
+    fn pause(&self, config: &Config, app: &App, pause: bool) -> io::Result<bool> {
+        UNIX.pause(config, app, pause)
+    }
+}
+
+#[cfg(target_os = "linux")]
+const CURRENT: &dyn Platform = &LINUX;
+#[cfg(target_os = "macos")]
+const CURRENT: &dyn Platform = &MacOs;
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+const CURRENT: &dyn Platform = &UNIX;
+
+/// The backend of the system clovis was built for.
+pub fn current() -> &'static dyn Platform {
+    CURRENT
+}
@@ -0,0 +1,136 @@
+//! Where launching and detecting apps runs external programs, through a
+//! [`ProcessRunner`] that tests replace to check what would be run and
+//! pretend how it went, without starting anything.
+
+use std::cell::RefCell;
+use std::io;
+use std::process::{Command as ProcessCommand, ExitStatus, Output};
+use std::rc::Rc;
+
+pub trait ProcessRunner {
+    /// Runs `command` to completion, its stdout and stderr captured.
+    fn output(&self, command: &mut ProcessCommand) -> io::Result<Output>;
+
+    /// Runs `command` to completion, its stdio left as set.
+    fn status(&self, command: &mut ProcessCommand) -> io::Result<ExitStatus>;
+}
+
+/// Runs commands for real.
+pub struct System;
+
+impl ProcessRunner for System {
+    fn output(&self, command: &mut ProcessCommand) -> io::Result<Output> {
+        command.output()
+    }
+
+    fn status(&self, command: &mut ProcessCommand) -> io::Result<ExitStatus> {
+        command.status()
+    }
+}
+
+thread_local! {
+    static RUNNER: RefCell<Rc<dyn ProcessRunner>> = RefCell::new(Rc::new(System));
+}
+
+/// Makes `runner` run the commands of this thread, returning the one it
+/// replaces.
+#[cfg(test)]
+pub fn set_runner(runner: Rc<dyn ProcessRunner>) -> Rc<dyn ProcessRunner> {
+    RUNNER.with(|current| current.replace(runner))
+}
+
+fn runner() -> Rc<dyn ProcessRunner> {
+    RUNNER.with(|current| current.borrow().clone())
+}
+
+pub fn output(command: &mut ProcessCommand) -> io::Result<Output> {
+    runner().output(command)
+}
+
+pub fn status(command: &mut ProcessCommand) -> io::Result<ExitStatus> {
+    runner().status(command)
+}
+
+/// A runner recording the commands it is given, answering them from a
+/// list of (program, exit code, stdout) replies.
+#[cfg(test)]
+pub mod fake {
+    use std::cell::RefCell;
+    use std::io;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{Command as ProcessCommand, ExitStatus, Output};
+    use std::rc::Rc;
+
+    use super::{set_runner, ProcessRunner};
+
+    #[derive(Default)]
+    pub struct FakeRunner {
+        /// Each command run, as its program followed by its arguments.
+        pub commands: RefCell<Vec<Vec<String>>>,
+        /// Replies used in turn for the commands of a program; the last one
+        /// is kept for the commands after it. Unknown programs fail with
+        /// exit code 1.
+        replies: RefCell<Vec<(String, i32, String)>>,
+    }
+
+    impl FakeRunner {
+        /// Installs a new fake runner for the current thread.
+        pub fn install() -> Rc<FakeRunner> {
+            let runner = Rc::new(FakeRunner::default());
+            set_runner(runner.clone());
+            runner
+        }
+
+        pub fn reply(&self, program: &str, code: i32, stdout: &str) {
+            self.replies
+                .borrow_mut()
+                .push((program.to_string(), code, stdout.to_string()));
+        }
+
+        /// The commands run of `program`.
+        pub fn runs_of(&self, program: &str) -> Vec<Vec<String>> {
+            self.commands
+                .borrow()
+                .iter()
+                .filter(|command| command[0] == program)
+                .cloned()
+                .collect()
+        }
+
+        fn run(&self, command: &ProcessCommand) -> (ExitStatus, Vec<u8>) {
+            let program = command.get_program().to_string_lossy().into_owned();
+            let mut line = vec![program.clone()];
+            line.extend(command.get_args().map(|a| a.to_string_lossy().into_owned()));
+            self.commands.borrow_mut().push(line);
+
+            let mut replies = self.replies.borrow_mut();
+            let matching: Vec<usize> = (0..replies.len())
+                .filter(|i| replies[*i].0 == program)
+                .collect();
+            let (code, stdout) = match matching.as_slice() {
+                [] => (1, String::new()),
+                [only] => (replies[*only].1, replies[*only].2.clone()),
+                [first, ..] => {
+                    let (_, code, stdout) = replies.remove(*first);
+                    (code, stdout)
+                }
+            };
+            (ExitStatus::from_raw(code << 8), stdout.into_bytes())
+        }
+    }
+
+    impl ProcessRunner for FakeRunner {
+        fn output(&self, command: &mut ProcessCommand) -> io::Result<Output> {
+            let (status, stdout) = self.run(command);
+            Ok(Output {
+                status,
+                stdout,
+                stderr: Vec::new(),
+            })
+        }
+
+        fn status(&self, command: &mut ProcessCommand) -> io::Result<ExitStatus> {
+            Ok(self.run(command).0)
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! `clovis launch --profile`: how long each app of a launch took to start
+//! and to show a window, and the launch as a whole, kept across runs to
+//! tell whether an environment gets slower to come up.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::history::format_timestamp;
+use crate::launches::{AppLaunch, Outcome};
+use crate::paths;
+use crate::style::{paint, Style};
+
+/// How many profiles are kept, across all environments.
+const MAX_PROFILES: usize = 500;
+/// How many earlier profiles of the environment are shown for comparison.
+const SHOWN_PROFILES: usize = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppTiming {
+    pub app: String,
+    /// How long starting the app took, in milliseconds.
+    pub spawn_ms: u64,
+    /// How long its window took to appear after it was started, when the
+    /// window could be watched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub timestamp: i64,
+    pub env: String,
+    /// From the start of the launch until the last window appeared.
+    pub total_ms: u64,
+    /// The apps that were started, the ones already running left out.
+    pub apps: Vec<AppTiming>,
+}
+
+pub fn profiles_path() -> PathBuf {
+    paths::state_dir().join("profiles.yaml")
+}
+
+pub fn load_profiles(path: &Path) -> io::Result<Vec<Profile>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    serde_yaml::from_str(&contents).map_err(|e| {
+        error!("Failed to parse profiles: {}", e);
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{:.2}s", ms as f64 / 1000.0)
+}
+
+/// Prints the timing breakdown of a launch of `env` that took `total`,
+/// next to its earlier profiles, and keeps it.
+pub fn record_profile(env: &str, apps: &[AppLaunch], total: Duration) -> io::Result<()> {
+    let profile = Profile {
+        timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+        env: env.to_string(),
+        total_ms: total.as_millis() as u64,
+        apps: apps
+            .iter()
+            .filter(|a| a.outcome == Outcome::Launched)
+            .map(|a| AppTiming {
+                app: a.app.clone(),
+                spawn_ms: a.duration_ms.unwrap_or_default(),
+                window_ms: a.window_ms,
+            })
+            .collect(),
+    };
+    let path = profiles_path();
+    let mut profiles = load_profiles(&path)?;
+    print_profile(&profile, &profiles);
+
+    profiles.push(profile);
+    let excess = profiles.len().saturating_sub(MAX_PROFILES);
+    profiles.drain(..excess);
+    let contents = serde_yaml::to_string(&profiles).map_err(|e| {
+        error!("Failed to serialize profiles: {}", e);
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })?;
+    fs::create_dir_all(paths::state_dir())?;
+    fs::write(path, contents)
+}
+
+fn print_profile(profile: &Profile, earlier: &[Profile]) {
+    println!();
+    if profile.apps.is_empty() {
+        println!("No app was started, there is nothing to profile.");
+    } else {
+        let width = profile
+            .apps
+            .iter()
+            .map(|a| a.app.len())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+        let heading = format!("{:<width$}  {:>7}  {:>7}", "APP", "SPAWN", "WINDOW");
+        println!("{}", paint(Style::Heading, heading));
+        for app in &profile.apps {
+            let window = app.window_ms.map_or("-".to_string(), seconds);
+            println!(
+                "{:<width$}  {:>7}  {:>7}",
+                app.app,
+                seconds(app.spawn_ms),
+                window
+            );
+        }
+    }
+    println!("Total: {}", seconds(profile.total_ms));
+
+    let earlier: Vec<&Profile> = earlier.iter().filter(|p| p.env == profile.env).collect();
+    if earlier.is_empty() {
+        return;
+    }
+    let average = earlier.iter().map(|p| p.total_ms).sum::<u64>() / earlier.len() as u64;
+    let (difference, style, word) = if profile.total_ms > average {
+        (profile.total_ms - average, Style::Failed, "slower")
+    } else {
+        (average - profile.total_ms, Style::Running, "faster")
+    };
+    println!(
+        "{} than the average of {} earlier {} ({}).",
+        paint(style, format!("{} {}", seconds(difference), word)),
+        earlier.len(),
+        if earlier.len() == 1 {
+            "launch"
+        } else {
+            "launches"
+        },
+        seconds(average)
+    );
+    for earlier in earlier.iter().rev().take(SHOWN_PROFILES) {
+        println!(
+            "  {}  {:>7}",
+            format_timestamp(earlier.timestamp),
+            seconds(earlier.total_ms)
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! Scheduling and memory settings applied to apps as they are spawned.
+//! Only `nice` exists outside of Linux.
+
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command as ProcessCommand;
+
+use crate::config::App;
+#[cfg(target_os = "linux")]
+use crate::config::IoniceClass;
+
+#[cfg(target_os = "linux")]
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+/// Priority level used within the realtime and best-effort classes.
+#[cfg(target_os = "linux")]
+const IOPRIO_DEFAULT_LEVEL: libc::c_int = 4;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn check_nice(app: &App) -> io::Result<()> {
+    match app.nice {
+        Some(nice) if !(-20..=19).contains(&nice) => {
+            Err(invalid(format!("nice {} is not between -20 and 19", nice)))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn ioprio(class: IoniceClass) -> libc::c_int {
+    match class {
+        IoniceClass::Realtime => (1 << IOPRIO_CLASS_SHIFT) | IOPRIO_DEFAULT_LEVEL,
+        IoniceClass::BestEffort => (2 << IOPRIO_CLASS_SHIFT) | IOPRIO_DEFAULT_LEVEL,
+        IoniceClass::Idle => 3 << IOPRIO_CLASS_SHIFT,
+    }
+}
+
+/// Arranges for the `nice`, `ionice_class`, `oom_score_adj` and
+/// `cpu_affinity` settings of the app to be applied to the process before
+/// it runs. Settings the kernel refuses make the launch fail.
+#[cfg(target_os = "linux")]
+pub fn apply(command: &mut ProcessCommand, app: &App) -> io::Result<()> {
+    check_nice(app)?;
+    let oom_score_adj = match app.oom_score_adj {
+        Some(score) if !(-1000..=1000).contains(&score) => {
+            return Err(invalid(format!(
+                "oom_score_adj {} is not between -1000 and 1000",
+                score
+            )))
+        }
+        // Prepared here, allocating between fork and exec is not allowed
+        Some(score) => Some(CString::new(score.to_string()).expect("no NUL in a number")),
+        None => None,
+    };
+    let cpu_set = if app.cpu_affinity.is_empty() {
+        None
+    } else {
+        // SAFETY: cpu_set_t is a plain bit set, all zeroes is the empty set
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        for &cpu in &app.cpu_affinity {
+            if cpu >= libc::CPU_SETSIZE as usize {
+                return Err(invalid(format!("CPU {} is out of range", cpu)));
+            }
+            // SAFETY: the index was checked against the set size
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+        Some(set)
+    };
+    let nice = app.nice;
+    let ioprio = app.ionice_class.map(ioprio);
+    if nice.is_none() && ioprio.is_none() && oom_score_adj.is_none() && cpu_set.is_none() {
+        return Ok(());
+    }
+
+    // SAFETY: only async-signal-safe system calls are made between fork and
+    // exec.
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(ioprio) = ioprio {
+                if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(score) = &oom_score_adj {
+                let fd = libc::open(c"/proc/self/oom_score_adj".as_ptr(), libc::O_WRONLY);
+                if fd == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                let bytes = score.as_bytes();
+                let written = libc::write(fd, bytes.as_ptr().cast(), bytes.len());
+                libc::close(fd);
+                if written == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(set) = &cpu_set {
+                if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), set) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Arranges for the `nice` setting of the app to be applied to the process
+/// before it runs, refusing the settings that need Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn apply(command: &mut ProcessCommand, app: &App) -> io::Result<()> {
+    check_nice(app)?;
+    if app.ionice_class.is_some() || app.oom_score_adj.is_some() || !app.cpu_affinity.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "ionice_class, oom_score_adj and cpu_affinity are only supported on Linux",
+        ));
+    }
+    let Some(nice) = app.nice else {
+        return Ok(());
+    };
+    // SAFETY: setpriority is async-signal-safe
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setpriority(libc::PRIO_PROCESS, 0, nice) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+//! Cron expressions of the `schedule:` section, run by `clovis daemon`.
+//!
+//! The five usual fields are supported (minute, hour, day of month, month,
+//! day of week) with `*`, lists, ranges, steps and English names for months
+//! and days. As in cron, when both days are restricted either one matching
+//! is enough.
+
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Parses a value of a field, which may be one of `names` (numbered from
+/// `min`).
+fn parse_value(value: &str, min: u32, names: &[&str]) -> Result<u32, String> {
+    let lower = value.to_lowercase();
+    if let Some(i) = names.iter().position(|name| *name == lower) {
+        return Ok(min + i as u32);
+    }
+    value
+        .parse()
+        .map_err(|_| format!("'{}' is not a number", value))
+}
+
+/// Parses a field into the set of values it allows, indexed from 0.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<Vec<bool>, String> {
+    let mut allowed = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("invalid step '{}'", step))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (
+                    parse_value(start, min, names)?,
+                    parse_value(end, min, names)?,
+                ),
+                None => {
+                    let start = parse_value(range, min, names)?;
+                    // `5/15` means from 5 to the end, every 15
+                    (start, if part.contains('/') { max } else { start })
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("'{}' is out of the {}-{} range", range, min, max));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            allowed[value as usize] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Cron, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "expected 5 fields (minute hour day month weekday), got {}",
+                fields.len()
+            ));
+        };
+        let mut weekdays = parse_field(weekday, 0, 7, &WEEKDAYS)?;
+        // Sunday is both 0 and 7
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        Ok(Cron {
+            minutes: parse_field(minute, 0, 59, &[])?,
+            hours: parse_field(hour, 0, 23, &[])?,
+            days: parse_field(day, 1, 31, &[])?,
+            months: parse_field(month, 1, 12, &MONTHS)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    /// Whether the minute of `time` is one the expression runs at.
+    pub fn matches(&self, time: OffsetDateTime) -> bool {
+        let day = self.days[usize::from(time.day())];
+        let weekday = self.weekdays[usize::from(time.weekday().number_days_from_sunday())];
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (false, true) => day,
+            (true, false) => weekday,
+            (false, false) => day || weekday,
+        };
+        self.minutes[usize::from(time.minute())]
+            && self.hours[usize::from(time.hour())]
+            && self.months[u8::from(time.month()) as usize]
+            && day_matches
+    }
+}
@@ -0,0 +1,1304 @@
+//! JSON Schema describing `config.yaml`, for editors running
+//! yaml-language-server. Keep it in sync with the types in `config.rs`.
+
+use crate::json::Json;
+use crate::migrate::CONFIG_VERSION;
+
+fn string_type(description: &str) -> Json {
+    Json::object([
+        ("type", Json::from("string")),
+        ("description", Json::from(description)),
+    ])
+}
+
+fn integer_type(description: &str, minimum: i64, maximum: i64) -> Json {
+    Json::object([
+        ("type", Json::from("integer")),
+        ("description", Json::from(description)),
+        ("minimum", Json::from(minimum)),
+        ("maximum", Json::from(maximum)),
+    ])
+}
+
+fn app_kind() -> Json {
+    Json::object([
+        ("type", Json::from("string")),
+        (
+            "description",
+            Json::from(
+                "How the app is launched: `auto` (desktop file or command), `snap`, `appimage`, `nix` (with `nix run`), `script`, `tmux_session`, `zellij_session`, `browser`, `compose`, `distrobox`, `ssh` or `vm` (libvirt domain)",
+            ),
+        ),
+        ("enum", Json::strings(["auto", "snap", "appimage", "nix", "script", "tmux_session", "zellij_session", "browser", "compose", "distrobox", "ssh", "vm"])),
+        ("default", Json::from("auto")),
+    ])
+}
+
+fn sandbox() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "description",
+            Json::from("Confinement the app runs in, with either firejail or bubblewrap"),
+        ),
+        (
+            "properties",
+            Json::object([
+                ("firejail", string_type("Firejail profile name or file")),
+                (
+                    "bwrap",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Arguments passed to bwrap before the command"),
+                        ),
+                        ("items", Json::object([("type", Json::from("string"))])),
+                    ]),
+                ),
+            ]),
+        ),
+        ("maxProperties", Json::from(1u64)),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn geometry() -> Json {
+    let pixels = |description: &str| {
+        Json::object([
+            ("type", Json::from("integer")),
+            ("description", Json::from(description)),
+        ])
+    };
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "description",
+            Json::from("Position and size the app's window is given once launched"),
+        ),
+        (
+            "properties",
+            Json::object([
+                ("x", pixels("Horizontal position, in pixels")),
+                ("y", pixels("Vertical position, in pixels")),
+                ("width", pixels("Width, in pixels")),
+                ("height", pixels("Height, in pixels")),
+            ]),
+        ),
+        ("required", Json::strings(["x", "y", "width", "height"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn thresholds() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "description",
+            Json::from("Memory and CPU use `clovis daemon` acts on once the app has kept above them for `seconds`"),
+        ),
+        (
+            "properties",
+            Json::object([
+                (
+                    "memory_mb",
+                    Json::object([
+                        ("type", Json::from("integer")),
+                        (
+                            "description",
+                            Json::from("Resident memory of the app and its children, in megabytes"),
+                        ),
+                        ("minimum", Json::from(1i64)),
+                    ]),
+                ),
+                (
+                    "cpu_percent",
+                    Json::object([
+                        ("type", Json::from("number")),
+                        (
+                            "description",
+                            Json::from("CPU use of the app and its children, in percent of one CPU"),
+                        ),
+                        ("exclusiveMinimum", Json::from(0i64)),
+                    ]),
+                ),
+                (
+                    "seconds",
+                    Json::object([
+                        ("type", Json::from("integer")),
+                        (
+                            "description",
+                            Json::from("How long a threshold must be exceeded before acting"),
+                        ),
+                        ("minimum", Json::from(0i64)),
+                        ("default", Json::from(30i64)),
+                    ]),
+                ),
+                (
+                    "action",
+                    Json::object([
+                        ("enum", Json::strings(["notify", "restart", "hook"])),
+                        (
+                            "description",
+                            Json::from("Send a notification, stop and launch the app again, or run `hook`"),
+                        ),
+                        ("default", Json::from("notify")),
+                    ]),
+                ),
+                (
+                    "hook",
+                    string_type("Shell snippet run by the `hook` action, with CLOVIS_ENV, CLOVIS_APP, CLOVIS_MEMORY_MB and CLOVIS_CPU_PERCENT set"),
+                ),
+            ]),
+        ),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn app() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "properties",
+            Json::object([
+                (
+                    "name",
+                    string_type(
+                        "Desktop file (ending in `.desktop`), command in PATH, or URL or file path opened with its default handler",
+                    ),
+                ),
+                ("type", app_kind()),
+                (
+                    "path",
+                    string_type(
+                        "AppImage file to run (looked up in `appimage_dirs` when missing), or compose file of `compose` apps",
+                    ),
+                ),
+                (
+                    "flake",
+                    string_type("Flake output run by `nix` apps, `nixpkgs#<name>` when missing"),
+                ),
+                ("command", string_type("Shell snippet run by `script` apps, or remote command of `ssh` apps")),
+                (
+                    "session",
+                    string_type("Multiplexer session name, the app name when missing"),
+                ),
+                (
+                    "layout",
+                    string_type("Layout file a tmux or zellij session is created from"),
+                ),
+                (
+                    "terminal",
+                    Json::object([
+                        ("type", Json::from("boolean")),
+                        (
+                            "description",
+                            Json::from("Run the app in the terminal emulator from `terminal`"),
+                        ),
+                        ("default", Json::from(false)),
+                    ]),
+                ),
+                ("profile", string_type("Profile opened by `browser` apps")),
+                (
+                    "urls",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Pages opened in a new window by `browser` apps"),
+                        ),
+                        ("items", Json::object([("type", Json::from("string"))])),
+                    ]),
+                ),
+                (
+                    "services",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Services started by `compose` apps, all of them when empty"),
+                        ),
+                        ("items", Json::object([("type", Json::from("string"))])),
+                    ]),
+                ),
+                (
+                    "container",
+                    string_type("Distrobox container `distrobox` apps run in"),
+                ),
+                ("host", string_type("Host `ssh` apps run their command on")),
+                (
+                    "wake_on_lan",
+                    string_type("MAC address woken up with Wake-on-LAN before connecting"),
+                ),
+                (
+                    "uri",
+                    string_type("Libvirt connection URI of `vm` apps, e.g. `qemu:///system`"),
+                ),
+                (
+                    "viewer",
+                    Json::object([
+                        ("type", Json::from("boolean")),
+                        (
+                            "description",
+                            Json::from("Open the console of `vm` apps with virt-viewer"),
+                        ),
+                        ("default", Json::from(false)),
+                    ]),
+                ),
+                (
+                    "nice",
+                    integer_type("Scheduling priority, from -20 (favorable) to 19", -20, 19),
+                ),
+                (
+                    "ionice_class",
+                    Json::object([
+                        ("type", Json::from("string")),
+                        ("description", Json::from("I/O scheduling class")),
+                        ("enum", Json::strings(["realtime", "best_effort", "idle"])),
+                    ]),
+                ),
+                (
+                    "oom_score_adj",
+                    integer_type(
+                        "OOM killer score adjustment, from -1000 (never killed) to 1000",
+                        -1000,
+                        1000,
+                    ),
+                ),
+                (
+                    "cpu_affinity",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("CPUs the app may run on, all of them when empty"),
+                        ),
+                        (
+                            "items",
+                            Json::object([
+                                ("type", Json::from("integer")),
+                                ("minimum", Json::from(0i64)),
+                            ]),
+                        ),
+                    ]),
+                ),
+                (
+                    "gpu",
+                    Json::object([
+                        ("type", Json::from("string")),
+                        (
+                            "description",
+                            Json::from("GPU the app renders on, the system default when missing"),
+                        ),
+                        ("enum", Json::strings(["discrete", "integrated"])),
+                    ]),
+                ),
+                (
+                    "gamemode",
+                    Json::object([
+                        ("type", Json::from("boolean")),
+                        ("description", Json::from("Run the app with `gamemoderun`")),
+                        ("default", Json::from(false)),
+                    ]),
+                ),
+                (
+                    "gamescope",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from(
+                                "Run the app in gamescope, started with these arguments (e.g. `[-W, \"2560\", -f]`)",
+                            ),
+                        ),
+                        ("items", Json::object([("type", Json::from("string"))])),
+                    ]),
+                ),
+                ("sandbox", sandbox()),
+                (
+                    "privileged",
+                    Json::object([
+                        ("type", Json::from("boolean")),
+                        ("description", Json::from("Run the app as root through pkexec")),
+                        ("default", Json::from(false)),
+                    ]),
+                ),
+                (
+                    "wait_for_window",
+                    Json::object([
+                        ("type", Json::from("boolean")),
+                        (
+                            "description",
+                            Json::from("Wait for a window of the app to open before launching the next one"),
+                        ),
+                        ("default", Json::from(false)),
+                    ]),
+                ),
+                (
+                    "window_class",
+                    string_type(
+                        "Wayland app id or X11 `WM_CLASS` of the app's windows, guessed from the desktop file when missing",
+                    ),
+                ),
+                (
+                    "workspace",
+                    string_type("Workspace the app's window is moved to once launched (desktop number on X11)"),
+                ),
+                ("geometry", geometry()),
+                (
+                    "stop_command",
+                    string_type("Shell snippet stopping the app, instead of the default for its kind"),
+                ),
+                (
+                    "stop_timeout",
+                    Json::object([
+                        ("type", Json::from("integer")),
+                        (
+                            "description",
+                            Json::from("Seconds `clovis stop` waits for the app to exit before stopping the next one"),
+                        ),
+                        ("minimum", Json::from(0i64)),
+                        ("default", Json::from(10i64)),
+                    ]),
+                ),
+                (
+                    "stop_signal",
+                    Json::object([
+                        ("type", Json::from("string")),
+                        (
+                            "description",
+                            Json::from("Signal the processes of the app are sent to stop it, when it has no `stop_command`"),
+                        ),
+                        ("enum", Json::strings(["SIGTERM", "SIGINT", "SIGHUP"])),
+                        ("default", Json::from("SIGTERM")),
+                    ]),
+                ),
+                (
+                    "kill_on_timeout",
+                    Json::object([
+                        ("type", Json::from("boolean")),
+                        (
+                            "description",
+                            Json::from("Kill the app with SIGKILL when it is still running after `stop_timeout`"),
+                        ),
+                        ("default", Json::from(false)),
+                    ]),
+                ),
+                (
+                    "instances",
+                    Json::object([
+                        ("type", Json::from("string")),
+                        (
+                            "description",
+                            Json::from("Whether the app is launched again while it runs: never (`single`) or always (`multiple`), whatever `--force` says"),
+                        ),
+                        ("enum", Json::strings(["single", "multiple"])),
+                    ]),
+                ),
+                ("thresholds", thresholds()),
+            ]),
+        ),
+        ("required", Json::strings(["name"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn slice() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "description",
+            Json::from("systemd user slice the apps of the environment are started in"),
+        ),
+        (
+            "properties",
+            Json::object([
+                ("name", string_type("Name of the slice, e.g. `gaming`")),
+                (
+                    "memory_max",
+                    string_type("`MemoryMax=` of the slice, e.g. `8G`"),
+                ),
+                (
+                    "cpu_quota",
+                    string_type("`CPUQuota=` of the slice, e.g. `200%`"),
+                ),
+            ]),
+        ),
+        ("required", Json::strings(["name"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn environment() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "description",
+            Json::from("A named set of applications launched together"),
+        ),
+        (
+            "properties",
+            Json::object([
+                (
+                    "description",
+                    string_type("What the environment is for, shown by launchers"),
+                ),
+                (
+                    "icon",
+                    string_type("Icon name or file shown by launchers"),
+                ),
+                (
+                    "color",
+                    Json::object([
+                        ("type", Json::from("string")),
+                        (
+                            "description",
+                            Json::from("Color of the environment's name in the terminal"),
+                        ),
+                        (
+                            "enum",
+                            Json::strings(["red", "green", "yellow", "blue", "magenta", "cyan"]),
+                        ),
+                    ]),
+                ),
+                (
+                    "apps",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Applications launched by this environment, in order"),
+                        ),
+                        ("items", app()),
+                    ]),
+                ),
+                ("slice", slice()),
+                (
+                    "conflicts_with",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Environments stopped before this one is launched, and the other way around"),
+                        ),
+                        ("items", Json::object([("type", Json::from("string"))])),
+                    ]),
+                ),
+                ("layout", layout()),
+                (
+                    "network",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Connections brought up before the apps are launched, in order"),
+                        ),
+                        ("items", network_action()),
+                    ]),
+                ),
+                (
+                    "mounts",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Shares and disks mounted before the apps are launched, in order, and unmounted once the environment is stopped"),
+                        ),
+                        ("items", mount()),
+                    ]),
+                ),
+                ("appearance", appearance()),
+                ("audio", audio()),
+                (
+                    "bluetooth",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Paired Bluetooth devices connected before the apps are launched, by address or alias"),
+                        ),
+                        ("items", Json::object([("type", Json::from("string"))])),
+                    ]),
+                ),
+                (
+                    "bluetooth_disconnect_on_stop",
+                    Json::object([
+                        ("type", Json::from("boolean")),
+                        (
+                            "description",
+                            Json::from("Disconnect the Bluetooth devices once the environment is stopped"),
+                        ),
+                        ("default", Json::from(false)),
+                    ]),
+                ),
+                (
+                    "blocks",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Programs `clovis daemon` terminates while the environment runs, by process name, e.g. `steam`"),
+                        ),
+                        ("items", Json::object([("type", Json::from("string"))])),
+                    ]),
+                ),
+                (
+                    "keyboard",
+                    Json::object([
+                        ("type", Json::from("object")),
+                        (
+                            "description",
+                            Json::from("XKB keyboard layout switched to while the environment runs"),
+                        ),
+                        (
+                            "properties",
+                            Json::object([
+                                ("layout", string_type("Layouts, comma-separated, e.g. `us,fr`")),
+                                (
+                                    "variant",
+                                    string_type("Variants of the layouts, comma-separated, e.g. `intl,`"),
+                                ),
+                                (
+                                    "options",
+                                    string_type("XKB options, comma-separated, e.g. `ctrl:nocaps`"),
+                                ),
+                            ]),
+                        ),
+                        ("required", Json::strings(["layout"])),
+                        ("additionalProperties", Json::from(false)),
+                    ]),
+                ),
+                (
+                    "do_not_disturb",
+                    Json::object([
+                        ("type", Json::from("boolean")),
+                        (
+                            "description",
+                            Json::from("Silence notifications (swaync, dunst, mako or GNOME) while the environment runs"),
+                        ),
+                        ("default", Json::from(false)),
+                    ]),
+                ),
+            ]),
+        ),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn audio() -> Json {
+    let percent = |description: &str| {
+        Json::object([
+            ("type", Json::from("integer")),
+            ("description", Json::from(description)),
+            ("minimum", Json::from(0i64)),
+            ("maximum", Json::from(150i64)),
+        ])
+    };
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "description",
+            Json::from("Audio devices set up when the environment is launched, named as by `pactl list short sinks` or `sources`"),
+        ),
+        (
+            "properties",
+            Json::object([
+                ("sink", string_type("Default output")),
+                ("source", string_type("Default input")),
+                ("volume", percent("Volume of the default output, in percent")),
+                (
+                    "source_volume",
+                    percent("Volume of the default input, in percent"),
+                ),
+                (
+                    "app_sinks",
+                    Json::object([
+                        ("type", Json::from("object")),
+                        (
+                            "description",
+                            Json::from("Outputs of the apps that do not play to the default one, by app name"),
+                        ),
+                        (
+                            "additionalProperties",
+                            Json::object([("type", Json::from("string"))]),
+                        ),
+                    ]),
+                ),
+            ]),
+        ),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn appearance() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "description",
+            Json::from(
+                "Look of the desktop while the environment runs, reverted once it is stopped",
+            ),
+        ),
+        (
+            "properties",
+            Json::object([
+                ("wallpaper", string_type("Image file set as the wallpaper")),
+                ("gtk_theme", string_type("GTK theme, e.g. `Adwaita-dark`")),
+                ("icon_theme", string_type("Icon theme, e.g. `Papirus`")),
+                (
+                    "color_scheme",
+                    Json::object([
+                        ("enum", Json::strings(["dark", "light"])),
+                        (
+                            "description",
+                            Json::from("Style apps following the desktop preference use"),
+                        ),
+                    ]),
+                ),
+                (
+                    "qt_style",
+                    string_type("Qt style of the apps of the environment, e.g. `kvantum`"),
+                ),
+            ]),
+        ),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn network_action() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "properties",
+            Json::object([
+                (
+                    "type",
+                    Json::object([
+                        ("enum", Json::strings(["nmcli", "wireguard"])),
+                        (
+                            "description",
+                            Json::from("A NetworkManager connection, or a WireGuard interface set up with `wg-quick`"),
+                        ),
+                    ]),
+                ),
+                (
+                    "name",
+                    string_type("Name of the NetworkManager connection, or of the WireGuard interface"),
+                ),
+                (
+                    "down_on_stop",
+                    Json::object([
+                        ("type", Json::from("boolean")),
+                        (
+                            "description",
+                            Json::from("Bring the connection down once the environment is stopped"),
+                        ),
+                        ("default", Json::from(false)),
+                    ]),
+                ),
+            ]),
+        ),
+        ("required", Json::strings(["type", "name"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn mount() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "properties",
+            Json::object([
+                (
+                    "type",
+                    Json::object([
+                        ("enum", Json::strings(["gio", "udisks", "command"])),
+                        (
+                            "description",
+                            Json::from("A GVfs share mounted with `gio mount`, a block device mounted with `udisksctl`, or shell commands"),
+                        ),
+                    ]),
+                ),
+                (
+                    "source",
+                    string_type("GVfs location of `gio` mounts, e.g. `smb://nas/share`, or block device of `udisks` ones"),
+                ),
+                (
+                    "path",
+                    string_type("Mount point of `command` mounts, checked before mounting"),
+                ),
+                ("mount", string_type("Shell command mounting `command` mounts")),
+                (
+                    "unmount",
+                    string_type("Shell command unmounting `command` mounts"),
+                ),
+                (
+                    "keep_mounted",
+                    Json::object([
+                        ("type", Json::from("boolean")),
+                        (
+                            "description",
+                            Json::from("Leave it mounted once the environment is stopped"),
+                        ),
+                        ("default", Json::from(false)),
+                    ]),
+                ),
+            ]),
+        ),
+        ("required", Json::strings(["type"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn layout() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "description",
+            Json::from("Monitor layout set up before the environment is launched"),
+        ),
+        (
+            "properties",
+            Json::object([
+                (
+                    "outputs",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Outputs that must be enabled, and no others, e.g. `eDP-1`"),
+                        ),
+                        ("items", Json::object([("type", Json::from("string"))])),
+                    ]),
+                ),
+                (
+                    "apply",
+                    string_type("Shell command setting up the layout when the enabled outputs differ, e.g. `kanshictl switch docked`"),
+                ),
+            ]),
+        ),
+        ("required", Json::strings(["outputs"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn sync_section() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "description",
+            Json::from("Git remote the config directory is synchronized with by `clovis sync`"),
+        ),
+        (
+            "properties",
+            Json::object([
+                ("remote", string_type("URL or name of the git remote")),
+                (
+                    "branch",
+                    Json::object([
+                        ("type", Json::from("string")),
+                        ("description", Json::from("Branch to pull from and push to")),
+                        ("default", Json::from("main")),
+                    ]),
+                ),
+            ]),
+        ),
+        ("required", Json::strings(["remote"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn mqtt_section() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "description",
+            Json::from("MQTT broker `clovis daemon` subscribes to, to launch or stop environments"),
+        ),
+        (
+            "properties",
+            Json::object([
+                (
+                    "broker",
+                    string_type("`host` or `host:port` of the broker, port 1883 by default"),
+                ),
+                ("username", string_type("User name to connect with")),
+                ("password", string_type("Password to connect with")),
+                (
+                    "client_id",
+                    Json::object([
+                        ("type", Json::from("string")),
+                        ("description", Json::from("Client identifier")),
+                        ("default", Json::from("clovis")),
+                    ]),
+                ),
+                (
+                    "triggers",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Actions run when a message arrives on a topic"),
+                        ),
+                        ("items", mqtt_trigger()),
+                    ]),
+                ),
+            ]),
+        ),
+        ("required", Json::strings(["broker"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn mqtt_trigger() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "properties",
+            Json::object([
+                (
+                    "topic",
+                    string_type("Topic to subscribe to, `+` and `#` wildcards allowed"),
+                ),
+                (
+                    "payload",
+                    string_type("Only trigger on this payload, on any payload when not set"),
+                ),
+                ("action", trigger_action()),
+                ("env", string_type("Environment (name or alias) to act on")),
+            ]),
+        ),
+        ("required", Json::strings(["topic", "action", "env"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn trigger_action() -> Json {
+    Json::object([
+        ("enum", Json::strings(["launch", "stop", "switch"])),
+        (
+            "description",
+            Json::from("`switch` stops the environments that have apps running, then launches"),
+        ),
+    ])
+}
+
+fn device_trigger() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "properties",
+            Json::object([
+                (
+                    "event",
+                    Json::object([
+                        ("enum", Json::strings(["add", "remove", "change"])),
+                        (
+                            "description",
+                            Json::from("Kind of event, monitors being plugged are `change` events of `drm`"),
+                        ),
+                        ("default", Json::from("add")),
+                    ]),
+                ),
+                (
+                    "subsystem",
+                    string_type("Kernel subsystem of the device, e.g. `usb` or `drm`"),
+                ),
+                (
+                    "usb_id",
+                    string_type("`vendor:product` id of a USB device, as shown by `lsusb`"),
+                ),
+                (
+                    "properties",
+                    Json::object([
+                        ("type", Json::from("object")),
+                        (
+                            "description",
+                            Json::from("Properties the event must have, as shown by `udevadm monitor -k -p`"),
+                        ),
+                        (
+                            "additionalProperties",
+                            Json::object([("type", Json::from("string"))]),
+                        ),
+                    ]),
+                ),
+                ("action", trigger_action()),
+                ("env", string_type("Environment (name or alias) to act on")),
+            ]),
+        ),
+        ("required", Json::strings(["action", "env"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn idle_section() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "description",
+            Json::from("Environments `clovis daemon` stops or pauses while the session is idle, according to logind"),
+        ),
+        (
+            "properties",
+            Json::object([
+                (
+                    "minutes",
+                    Json::object([
+                        ("type", Json::from("integer")),
+                        (
+                            "description",
+                            Json::from("How long the session must be idle"),
+                        ),
+                        ("minimum", Json::from(1i64)),
+                    ]),
+                ),
+                (
+                    "action",
+                    Json::object([
+                        ("enum", Json::strings(["stop", "pause"])),
+                        (
+                            "description",
+                            Json::from("Stop the apps and launch them again on resume, or suspend them and let them carry on"),
+                        ),
+                        ("default", Json::from("stop")),
+                    ]),
+                ),
+                (
+                    "environments",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        ("description", Json::from("Names of the environments")),
+                        ("items", Json::object([("type", Json::from("string"))])),
+                    ]),
+                ),
+            ]),
+        ),
+        ("required", Json::strings(["minutes", "environments"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn network_rule() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "properties",
+            Json::object([
+                ("ssid", string_type("Wi-Fi network that must be in use")),
+                (
+                    "vpn",
+                    string_type("Name of a VPN or WireGuard connection that must be active"),
+                ),
+                ("action", trigger_action()),
+                ("env", string_type("Environment (name or alias) to act on")),
+            ]),
+        ),
+        ("required", Json::strings(["action", "env"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn scheduled_action() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "properties",
+            Json::object([
+                (
+                    "cron",
+                    string_type("When to run, as `minute hour day month weekday` in local time, e.g. `25 9 * * mon-fri`"),
+                ),
+                ("action", trigger_action()),
+                ("env", string_type("Environment (name or alias) to act on")),
+            ]),
+        ),
+        ("required", Json::strings(["cron", "action", "env"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn calendar_section() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "description",
+            Json::from("Calendar `clovis daemon` reads to launch environments ahead of events"),
+        ),
+        (
+            "properties",
+            Json::object([
+                (
+                    "url",
+                    string_type("`http(s)://` URL of an ICS feed or CalDAV calendar, or path of an ICS file"),
+                ),
+                (
+                    "caldav",
+                    Json::object([
+                        ("type", Json::from("boolean")),
+                        (
+                            "description",
+                            Json::from("Query the URL as a CalDAV calendar collection rather than download an ICS feed"),
+                        ),
+                        ("default", Json::from(false)),
+                    ]),
+                ),
+                ("username", string_type("User name to fetch the calendar with")),
+                ("password", string_type("Password to fetch the calendar with")),
+                (
+                    "refresh_minutes",
+                    Json::object([
+                        ("type", Json::from("integer")),
+                        (
+                            "description",
+                            Json::from("How often the calendar is fetched again"),
+                        ),
+                        ("minimum", Json::from(1i64)),
+                        ("default", Json::from(15i64)),
+                    ]),
+                ),
+                (
+                    "rules",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Actions run ahead of the events matching a pattern"),
+                        ),
+                        ("items", calendar_rule()),
+                    ]),
+                ),
+            ]),
+        ),
+        ("required", Json::strings(["url"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+fn calendar_rule() -> Json {
+    Json::object([
+        ("type", Json::from("object")),
+        (
+            "properties",
+            Json::object([
+                (
+                    "pattern",
+                    string_type(
+                        "Text the summary of the event contains, ignoring case, e.g. `standup`",
+                    ),
+                ),
+                (
+                    "minutes_before",
+                    Json::object([
+                        ("type", Json::from("integer")),
+                        (
+                            "description",
+                            Json::from("How long before the start of the event to run the action"),
+                        ),
+                        ("minimum", Json::from(0i64)),
+                        ("default", Json::from(5i64)),
+                    ]),
+                ),
+                ("action", trigger_action()),
+                ("env", string_type("Environment (name or alias) to act on")),
+            ]),
+        ),
+        ("required", Json::strings(["pattern", "env"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+pub fn config_schema() -> Json {
+    Json::object([
+        (
+            "$schema",
+            Json::from("http://json-schema.org/draft-07/schema#"),
+        ),
+        ("title", Json::from("Clovis configuration")),
+        ("type", Json::from("object")),
+        (
+            "properties",
+            Json::object([
+                (
+                    "version",
+                    Json::object([
+                        ("const", Json::from(CONFIG_VERSION as u64)),
+                        (
+                            "description",
+                            Json::from("Format version of this file, see `clovis migrate`"),
+                        ),
+                    ]),
+                ),
+                (
+                    "environments",
+                    Json::object([
+                        ("type", Json::from("object")),
+                        ("additionalProperties", environment()),
+                    ]),
+                ),
+                (
+                    "default",
+                    string_type("Environment launched by a bare `clovis launch`"),
+                ),
+                (
+                    "aliases",
+                    Json::object([
+                        ("type", Json::from("object")),
+                        (
+                            "description",
+                            Json::from("Short names usable wherever an environment name is"),
+                        ),
+                        (
+                            "additionalProperties",
+                            Json::object([("type", Json::from("string"))]),
+                        ),
+                    ]),
+                ),
+                ("sync", sync_section()),
+                (
+                    "appimage_dirs",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from(
+                                "Directories searched for AppImages, `~/Applications` when empty",
+                            ),
+                        ),
+                        ("items", Json::object([("type", Json::from("string"))])),
+                    ]),
+                ),
+                (
+                    "shell",
+                    string_type("Shell running `script` apps, `$SHELL` when not set"),
+                ),
+                (
+                    "terminal",
+                    string_type(
+                        "Terminal emulator command terminal apps are run with, e.g. `foot -e`",
+                    ),
+                ),
+                (
+                    "editor",
+                    string_type(
+                        "Editor `clovis config` opens the config with, e.g. `code --wait`; `$VISUAL` then `$EDITOR` when not set",
+                    ),
+                ),
+                (
+                    "systemd_scope",
+                    Json::object([
+                        ("type", Json::from("boolean")),
+                        (
+                            "description",
+                            Json::from(
+                                "Start apps with `systemd-run --user --scope` so `clovis stop` stops their whole process tree",
+                            ),
+                        ),
+                        ("default", Json::from(false)),
+                    ]),
+                ),
+                (
+                    "clean_env",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from(
+                                "Launch apps with only the session variables (display, XDG, locale...) and these ones",
+                            ),
+                        ),
+                        ("items", Json::object([("type", Json::from("string"))])),
+                    ]),
+                ),
+                ("mqtt", mqtt_section()),
+                (
+                    "schedule",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Launches and stops run by `clovis daemon` at set times"),
+                        ),
+                        ("items", scheduled_action()),
+                    ]),
+                ),
+                (
+                    "device_triggers",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Launches and stops run by `clovis daemon` when a dock, monitor or USB device is plugged or unplugged"),
+                        ),
+                        ("items", device_trigger()),
+                    ]),
+                ),
+                ("idle", idle_section()),
+                (
+                    "network_rules",
+                    Json::object([
+                        ("type", Json::from("array")),
+                        (
+                            "description",
+                            Json::from("Launches and stops run by `clovis daemon` when NetworkManager joins a Wi-Fi network or VPN"),
+                        ),
+                        ("items", network_rule()),
+                    ]),
+                ),
+                ("calendar", calendar_section()),
+            ]),
+        ),
+        ("required", Json::strings(["version", "environments"])),
+        ("additionalProperties", Json::from(false)),
+    ])
+}
+
+/// Lists the keys of `value` that the schema does not allow, as dotted
+/// paths (`environments.work.apps[1].nmae`).
+pub fn unknown_fields(value: &serde_yaml::Value) -> Vec<String> {
+    let mut unknown = Vec::new();
+    collect_unknown_fields(&config_schema(), value, "", &mut unknown);
+    unknown
+}
+
+fn collect_unknown_fields(
+    schema: &Json,
+    value: &serde_yaml::Value,
+    path: &str,
+    unknown: &mut Vec<String>,
+) {
+    let join = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match value {
+        serde_yaml::Value::Mapping(mapping) => {
+            let properties = schema.get("properties");
+            let additional = schema.get("additionalProperties");
+            for (key, child) in mapping {
+                let key = key.as_str().unwrap_or("?");
+                match (properties.and_then(|p| p.get(key)), additional) {
+                    (Some(child_schema), _) => {
+                        collect_unknown_fields(child_schema, child, &join(key), unknown)
+                    }
+                    (None, Some(Json::Bool(false))) => unknown.push(join(key)),
+                    (None, Some(child_schema @ Json::Object(_))) => {
+                        collect_unknown_fields(child_schema, child, &join(key), unknown)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        serde_yaml::Value::Sequence(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    let item_path = format!("{}[{}]", path, i);
+                    collect_unknown_fields(item_schema, item, &item_path, unknown);
+                }
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,212 @@
+//! `clovis shell-init`: shell code defining a quick-switch alias per
+//! environment, a prompt segment showing the active environments and an
+//! Alt-E widget picking one to switch to.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+
+use crate::config::Config;
+use crate::paths;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// The environments launched and not stopped since, one per line, until
+/// logout. The prompt segment reads it rather than running clovis at every
+/// prompt.
+fn active_path() -> PathBuf {
+    paths::runtime_dir().join("active")
+}
+
+pub fn active_envs() -> Vec<String> {
+    fs::read_to_string(active_path())
+        .map(|contents| contents.lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Adds `env` to the active environments, or removes it.
+pub fn set_active(env: &str, active: bool) -> io::Result<()> {
+    let mut envs = active_envs();
+    envs.retain(|e| e != env);
+    if active {
+        envs.push(env.to_string());
+    }
+    write_active(&envs)
+}
+
+pub fn clear_active() -> io::Result<()> {
+    write_active(&[])
+}
+
+fn write_active(envs: &[String]) -> io::Result<()> {
+    let path = active_path();
+    if envs.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    paths::create_runtime_dir()?;
+    fs::write(
+        &path,
+        envs.iter()
+            .map(|env| format!("{}\n", env))
+            .collect::<String>(),
+    )
+}
+
+/// Whether `name` can be written in an alias without quoting.
+fn is_plain(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The alias of each environment: `c` followed by the shortest prefix of
+/// its name no other environment starts with, e.g. `cw` for `work`.
+fn shortcuts(config: &Config) -> Vec<(String, String)> {
+    let mut envs: Vec<&String> = config
+        .environments
+        .keys()
+        .filter(|env| is_plain(env))
+        .collect();
+    envs.sort();
+    envs.iter()
+        .map(|env| {
+            // The whole name when another one starts with it
+            let prefix = (1..env.len())
+                .map(|len| &env[..len])
+                .find(|prefix| !envs.iter().any(|o| o != env && o.starts_with(prefix)))
+                .unwrap_or(env.as_str());
+            (format!("c{}", prefix), env.to_string())
+        })
+        .collect()
+}
+
+/// The code to evaluate in `shell`'s startup file. The prompt is left
+/// untouched unless `prompt`; `__clovis_prompt` can be used in it then.
+pub fn script(config: &Config, shell: Shell, prompt: bool) -> String {
+    let mut script = String::new();
+    match shell {
+        Shell::Bash | Shell::Zsh => {
+            let name = if shell == Shell::Bash { "bash" } else { "zsh" };
+            script.push_str(&format!(
+                "# Add to ~/.{}rc: eval \"$(clovis shell-init {})\"\n",
+                name, name
+            ));
+            for (alias, env) in shortcuts(config) {
+                script.push_str(&format!(
+                    "command -v {alias} >/dev/null 2>&1 || alias {alias}='clovis switch {env}'\n"
+                ));
+            }
+        }
+        Shell::Fish => {
+            script
+                .push_str("# Add to ~/.config/fish/config.fish: clovis shell-init fish | source\n");
+            for (alias, env) in shortcuts(config) {
+                script.push_str(&format!(
+                    "if not type -q {alias}\n    \
+                         function {alias} --description 'clovis switch {env}'\n        \
+                             clovis switch {env} $argv\n    \
+                         end\n\
+                     end\n"
+                ));
+            }
+        }
+    }
+    script.push_str(match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+    });
+    if prompt {
+        script.push_str(match shell {
+            Shell::Bash => BASH_PROMPT,
+            Shell::Zsh => ZSH_PROMPT,
+            Shell::Fish => FISH_PROMPT,
+        });
+    }
+    script
+}
+
+const BASH: &str = r#"__clovis_prompt() {
+    local dir=${XDG_RUNTIME_DIR:+$XDG_RUNTIME_DIR/clovis} envs
+    local file="${dir:-/tmp/clovis-$UID}/active"
+    [ -s "$file" ] || return 0
+    mapfile -t envs < "$file"
+    local IFS=,
+    printf '(%s) ' "${envs[*]}"
+}
+__clovis_widget() {
+    clovis switch
+}
+if [[ $- == *i* ]]; then
+    bind -x '"\ee": __clovis_widget'
+fi
+"#;
+
+const BASH_PROMPT: &str = r#"case "$PS1" in
+    *__clovis_prompt*) ;;
+    *) PS1='$(__clovis_prompt)'"$PS1" ;;
+esac
+"#;
+
+const ZSH: &str = r#"__clovis_prompt() {
+    local dir=${XDG_RUNTIME_DIR:+$XDG_RUNTIME_DIR/clovis}
+    local file="${dir:-/tmp/clovis-$UID}/active"
+    [[ -s $file ]] || return 0
+    local -a envs
+    envs=("${(@f)$(<$file)}")
+    print -rn -- "(${(j:,:)envs}) "
+}
+__clovis_widget() {
+    zle -I
+    clovis switch </dev/tty
+    zle reset-prompt
+}
+zle -N __clovis_widget
+bindkey '\ee' __clovis_widget
+"#;
+
+const ZSH_PROMPT: &str = r#"setopt prompt_subst
+[[ $PROMPT == *__clovis_prompt* ]] || PROMPT='$(__clovis_prompt)'$PROMPT
+"#;
+
+const FISH: &str = r#"function __clovis_prompt
+    set -l file $XDG_RUNTIME_DIR/clovis/active
+    test -n "$XDG_RUNTIME_DIR"; or set file /tmp/clovis-(id -u)/active
+    test -s $file; or return 0
+    set -l envs
+    while read -l env
+        set -a envs $env
+    end <$file
+    printf '(%s) ' (string join , -- $envs)
+end
+function __clovis_widget
+    clovis switch </dev/tty
+    commandline -f repaint
+end
+bind \ee __clovis_widget
+"#;
+
+const FISH_PROMPT: &str = r#"function __clovis_return
+    return $argv[1]
+end
+if functions -q fish_prompt; and not functions -q __clovis_original_prompt
+    functions -c fish_prompt __clovis_original_prompt
+    function fish_prompt
+        set -l last_status $status
+        __clovis_prompt
+        __clovis_return $last_status
+        __clovis_original_prompt
+    end
+end
+"#;
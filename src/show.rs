@@ -0,0 +1,240 @@
+//! `clovis show`: the configured environments and their apps, as a list,
+//! a tree, a table or a line per environment.
+
+use clap::ValueEnum;
+
+use crate::config::{App, AppKind, Config, IdleAction};
+use crate::launch;
+use crate::network;
+use crate::style::{paint, Style};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ShowFormat {
+    /// Each environment and the names of its apps below it
+    List,
+    /// Each environment with its description and what launches it, and its
+    /// apps below it
+    Tree,
+    /// A row per app
+    Table,
+    /// A line per environment
+    Compact,
+}
+
+/// The type of `app` when it is not `auto`, shown after its name.
+fn kind(app: &App) -> Option<&'static str> {
+    (app.kind != AppKind::Auto).then(|| app.kind.as_str())
+}
+
+fn state(config: &Config, app: &App) -> String {
+    if launch::is_running(config, app) {
+        paint(Style::Running, "running")
+    } else {
+        paint(Style::Dim, "not running")
+    }
+}
+
+/// What `clovis daemon` does to `env` on its own, and when, e.g. "launch
+/// at '0 9 * * 1-5'".
+fn triggers(config: &Config, env: &str) -> Vec<String> {
+    let is_env = |other: &str| config.resolve_env(other) == env;
+    let mut triggers = Vec::new();
+    for entry in config.schedule.iter().filter(|e| is_env(&e.env)) {
+        triggers.push(format!("{} at '{}'", entry.action.as_str(), entry.cron));
+    }
+    for trigger in config.device_triggers.iter().filter(|t| is_env(&t.env)) {
+        let device = trigger
+            .usb_id
+            .as_deref()
+            .or(trigger.subsystem.as_deref())
+            .unwrap_or("a device");
+        triggers.push(format!(
+            "{} on {} of {}",
+            trigger.action.as_str(),
+            trigger.event.as_str(),
+            device
+        ));
+    }
+    for rule in config.network_rules.iter().filter(|r| is_env(&r.env)) {
+        triggers.push(format!(
+            "{} on network '{}'",
+            rule.action.as_str(),
+            network::describe(rule)
+        ));
+    }
+    for rule in config
+        .calendar
+        .iter()
+        .flat_map(|calendar| &calendar.rules)
+        .filter(|r| is_env(&r.env))
+    {
+        triggers.push(format!(
+            "{} {} minutes before events matching '{}'",
+            rule.action.as_str(),
+            rule.minutes_before,
+            rule.pattern
+        ));
+    }
+    for trigger in config
+        .mqtt
+        .iter()
+        .flat_map(|mqtt| &mqtt.triggers)
+        .filter(|t| is_env(&t.env))
+    {
+        triggers.push(format!(
+            "{} on MQTT topic '{}'",
+            trigger.action.as_str(),
+            trigger.topic
+        ));
+    }
+    if let Some(idle) = config
+        .idle
+        .as_ref()
+        .filter(|idle| idle.environments.iter().any(|e| is_env(e)))
+    {
+        let action = match idle.action {
+            IdleAction::Stop => "stop",
+            IdleAction::Pause => "pause",
+        };
+        triggers.push(format!("{} after {} idle minutes", action, idle.minutes));
+    }
+    triggers
+}
+
+fn list(config: &Config, envs: &[&String], status: bool) {
+    for env in envs {
+        println!(
+            "{}:",
+            paint(Style::env(config.environments[*env].color), env)
+        );
+        for app in &config.environments[*env].apps {
+            if status {
+                println!("  - {} ({})", app.name, state(config, app));
+            } else {
+                println!("  - {}", app.name);
+            }
+        }
+    }
+}
+
+fn tree(config: &Config, envs: &[&String], status: bool) {
+    for env in envs {
+        let environment = &config.environments[*env];
+        let name = paint(Style::env(environment.color), env);
+        match &environment.description {
+            Some(description) => println!("{}: {}", name, paint(Style::Dim, description)),
+            None => println!("{}:", name),
+        }
+        for trigger in triggers(config, env) {
+            println!("  {}", paint(Style::Dim, format!("trigger: {}", trigger)));
+        }
+        for (i, app) in environment.apps.iter().enumerate() {
+            let branch = if i + 1 == environment.apps.len() {
+                "└─"
+            } else {
+                "├─"
+            };
+            let mut line = format!("  {} {}", branch, app.name);
+            if let Some(kind) = kind(app) {
+                line.push_str(&format!(" {}", paint(Style::Dim, format!("({})", kind))));
+            }
+            if status {
+                line.push_str(&format!(" {}", state(config, app)));
+            }
+            println!("{}", line);
+        }
+    }
+}
+
+fn table(config: &Config, envs: &[&String], status: bool) {
+    let rows: Vec<(&str, &App)> = envs
+        .iter()
+        .flat_map(|env| {
+            config.environments[*env]
+                .apps
+                .iter()
+                .map(move |app| (env.as_str(), app))
+        })
+        .collect();
+    let env_width = envs.iter().map(|env| env.len()).max().unwrap_or(0).max(3);
+    let app_width = rows
+        .iter()
+        .map(|(_, app)| app.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+    let mut heading = format!(
+        "{:<env_width$}  {:<app_width$}  {:<14}",
+        "ENV", "APP", "TYPE"
+    );
+    if status {
+        heading.push_str("  STATUS");
+    }
+    println!("{}", paint(Style::Heading, heading.trim_end()));
+    for (env, app) in rows {
+        let color = config.environments[env].color;
+        let mut line = format!(
+            "{}  {:<app_width$}  {:<14}",
+            paint(Style::env(color), format!("{:<env_width$}", env)),
+            app.name,
+            app.kind.as_str()
+        );
+        if status {
+            line.push_str(&format!("  {}", state(config, app)));
+        }
+        println!("{}", line.trim_end());
+    }
+}
+
+fn compact(config: &Config, envs: &[&String], status: bool) {
+    for env in envs {
+        let environment = &config.environments[*env];
+        let apps: Vec<String> = environment
+            .apps
+            .iter()
+            .map(|app| {
+                if status && launch::is_running(config, app) {
+                    format!("{} {}", app.name, paint(Style::Running, "(running)"))
+                } else {
+                    app.name.clone()
+                }
+            })
+            .collect();
+        println!(
+            "{}: {}",
+            paint(Style::env(environment.color), env),
+            apps.join(", ")
+        );
+    }
+}
+
+/// Shows `env`, or every environment sorted by name, in `format`, with
+/// whether each app runs when `status`. Returns false when `env` is not
+/// configured.
+pub fn show(config: &Config, env: Option<&str>, format: ShowFormat, status: bool) -> bool {
+    let mut envs: Vec<&String> = match env {
+        Some(env) => {
+            let env = config.resolve_env(env);
+            match config.environments.get_key_value(env) {
+                Some((name, _)) => vec![name],
+                None => {
+                    println!("Environment '{}' not found.", env);
+                    return false;
+                }
+            }
+        }
+        None => config.environments.keys().collect(),
+    };
+    if envs.is_empty() {
+        println!("No environments configured. Run `clovis init` to create a starter config.");
+        return true;
+    }
+    envs.sort();
+    match format {
+        ShowFormat::List => list(config, &envs, status),
+        ShowFormat::Tree => tree(config, &envs, status),
+        ShowFormat::Table => table(config, &envs, status),
+        ShowFormat::Compact => compact(config, &envs, status),
+    }
+    true
+}
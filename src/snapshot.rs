@@ -0,0 +1,179 @@
+//! `clovis snapshot`: turns the apps running in the session into an
+//! environment.
+
+use std::fs;
+use std::path::Path;
+
+use log::info;
+
+use crate::config::{App, Config, Environment};
+use crate::desktop::{installed_desktop_entries, DesktopEntry};
+use crate::launch::is_command_available;
+use crate::windows::{list_windows, Window};
+
+/// Saves the running apps as environment `env`, along with the workspace
+/// and geometry of their windows. Returns whether the config
+/// changed.
+pub fn snapshot_environment(config: &mut Config, env: &str, force: bool) -> bool {
+    if config.environments.contains_key(env) && !force {
+        println!(
+            "Environment '{}' already exists, use --force to replace it.",
+            env
+        );
+        return false;
+    }
+
+    let entries = installed_desktop_entries();
+    let apps = match list_windows() {
+        Some(windows) => apps_from_windows(&entries, &windows),
+        None => {
+            println!("Windows can only be listed on Hyprland, Sway or with wmctrl, looking at processes instead.");
+            apps_from_processes(&entries)
+        }
+    };
+    if apps.is_empty() {
+        println!("No running applications found.");
+        return false;
+    }
+
+    for app in &apps {
+        println!("Captured: {}", app.name);
+    }
+    config.environments.insert(
+        env.to_string(),
+        Environment {
+            apps,
+            ..Environment::default()
+        },
+    );
+    println!("Saved environment '{}'", env);
+    info!("Saved running apps as environment '{}'", env);
+    true
+}
+
+/// Adds `name` unless it is already there. An app with several windows
+/// keeps the layout of the first one.
+fn push_unique(apps: &mut Vec<App>, name: &str, window: Option<&Window>) {
+    if apps.iter().any(|app| app.name == name) {
+        return;
+    }
+    let mut app = App::new(name);
+    if let Some(window) = window {
+        app.workspace = window.workspace.clone();
+        app.geometry = window.geometry;
+    }
+    apps.push(app);
+}
+
+/// Maps every window to the desktop entry of its app, or to the command
+/// that runs it when it has none.
+fn apps_from_windows(entries: &[DesktopEntry], windows: &[Window]) -> Vec<App> {
+    let mut apps = Vec::new();
+    for window in windows {
+        let names = window.pid.map(process_names).unwrap_or_default();
+        if let Some(entry) =
+            entry_for_class(entries, window).or_else(|| entry_for_process(entries, &names))
+        {
+            push_unique(&mut apps, &entry.id, Some(window));
+        } else if let Some(command) = names.iter().find(|name| is_command_available(name)) {
+            push_unique(&mut apps, command, Some(window));
+        } else {
+            println!(
+                "Skipping: window of '{}' (unknown application)",
+                window.class
+            );
+        }
+    }
+    apps
+}
+
+/// Looks for the apps of the session among the processes: the ones that
+/// can reach the display and run the program of a desktop entry.
+fn apps_from_processes(entries: &[DesktopEntry]) -> Vec<App> {
+    let Ok(read_dir) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut pids: Vec<u32> = read_dir
+        .filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    pids.sort_unstable();
+
+    let mut apps = Vec::new();
+    for pid in pids {
+        if pid == std::process::id() || !is_on_display(pid) {
+            continue;
+        }
+        if let Some(entry) = entry_for_process(entries, &process_names(pid)) {
+            push_unique(&mut apps, &entry.id, None);
+        }
+    }
+    apps
+}
+
+/// Whether the process was started with a display to draw on. The
+/// environment of other users' processes cannot be read.
+fn is_on_display(pid: u32) -> bool {
+    let Ok(environ) = fs::read(format!("/proc/{}/environ", pid)) else {
+        return false;
+    };
+    environ.split(|b| *b == 0).any(|variable| {
+        variable.starts_with(b"WAYLAND_DISPLAY=") || variable.starts_with(b"DISPLAY=")
+    })
+}
+
+fn file_name(path: &str) -> Option<String> {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(str::to_string)
+}
+
+/// Names the process goes by: the file name of its executable and of its
+/// first argument, which differ for interpreted apps.
+pub fn process_names(pid: u32) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok(exe) = fs::read_link(format!("/proc/{}/exe", pid)) {
+        names.extend(file_name(&exe.to_string_lossy()));
+    }
+    if let Ok(cmdline) = fs::read(format!("/proc/{}/cmdline", pid)) {
+        let argv0 = cmdline.split(|b| *b == 0).next().unwrap_or_default();
+        names.extend(file_name(&String::from_utf8_lossy(argv0)));
+    }
+    names.dedup();
+    names
+}
+
+/// The entry whose `StartupWMClass` or id is the window class, e.g.
+/// `org.gnome.Nautilus.desktop` for `org.gnome.Nautilus`.
+fn entry_for_class<'a>(entries: &'a [DesktopEntry], window: &Window) -> Option<&'a DesktopEntry> {
+    entries
+        .iter()
+        .find(|entry| {
+            entry
+                .get("StartupWMClass")
+                .is_some_and(|c| window.matches(c))
+        })
+        .or_else(|| {
+            entries.iter().find(|entry| {
+                let id = entry.id.strip_suffix(".desktop").unwrap_or(&entry.id);
+                window.matches(id)
+            })
+        })
+}
+
+/// The entry whose `Exec` runs a program with one of `names`.
+fn entry_for_process<'a>(
+    entries: &'a [DesktopEntry],
+    names: &[String],
+) -> Option<&'a DesktopEntry> {
+    entries.iter().find(|entry| {
+        // Skip `env VAR=value` prefixes to get to the program
+        let program = entry
+            .command_line()
+            .into_iter()
+            .find(|word| word != "env" && !word.contains('='));
+        program
+            .and_then(|program| file_name(&program))
+            .is_some_and(|program| names.contains(&program))
+    })
+}
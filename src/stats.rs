@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::config::Config;
+use crate::history::format_timestamp;
+use crate::launches::{AppLaunch, Outcome};
+use crate::paths;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Usage {
+    pub launches: u64,
+    pub last_used: Option<i64>,
+    /// Launches of an app that failed.
+    #[serde(default)]
+    pub failures: u64,
+    /// Total time spent starting an app, in milliseconds.
+    #[serde(default)]
+    pub launch_ms: u64,
+}
+
+/// Launch counters kept across runs; unlike the launch log it is never
+/// truncated.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Stats {
+    #[serde(default)]
+    pub environments: HashMap<String, Usage>,
+    #[serde(default)]
+    pub apps: HashMap<String, Usage>,
+    /// Times each app was restarted, by environment and app name.
+    #[serde(default)]
+    pub restarts: HashMap<String, HashMap<String, u64>>,
+}
+
+pub fn stats_path() -> PathBuf {
+    paths::state_dir().join("stats.yaml")
+}
+
+pub fn load_stats(path: &Path) -> io::Result<Stats> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Stats::default()),
+        Err(e) => return Err(e),
+    };
+    serde_yaml::from_str(&contents).map_err(|e| {
+        error!("Failed to parse stats file: {}", e);
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })
+}
+
+/// Counts a launch of `env`. Apps that were already running count as used
+/// but not as launched.
+pub fn record_usage(env: &str, apps: &[AppLaunch]) -> io::Result<()> {
+    let path = stats_path();
+    let mut stats = load_stats(&path)?;
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+
+    let usage = stats.environments.entry(env.to_string()).or_default();
+    usage.launches += 1;
+    usage.last_used = Some(now);
+    for launch in apps {
+        let usage = stats.apps.entry(launch.app.clone()).or_default();
+        match launch.outcome {
+            Outcome::Launched => {
+                usage.launches += 1;
+                usage.last_used = Some(now);
+                usage.launch_ms += launch.duration_ms.unwrap_or_default();
+            }
+            Outcome::AlreadyRunning | Outcome::Adopted => usage.last_used = Some(now),
+            Outcome::Failed(_) => usage.failures += 1,
+        }
+    }
+    save_stats(&path, &stats)
+}
+
+/// Counts a restart of each of `apps` of `env`.
+pub fn record_restarts(env: &str, apps: &[&str]) -> io::Result<()> {
+    if apps.is_empty() {
+        return Ok(());
+    }
+    let path = stats_path();
+    let mut stats = load_stats(&path)?;
+    let restarts = stats.restarts.entry(env.to_string()).or_default();
+    for app in apps {
+        *restarts.entry(app.to_string()).or_default() += 1;
+    }
+    save_stats(&path, &stats)
+}
+
+fn save_stats(path: &Path, stats: &Stats) -> io::Result<()> {
+    let contents = serde_yaml::to_string(stats).map_err(|e| {
+        error!("Failed to serialize stats: {}", e);
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })?;
+    fs::create_dir_all(paths::state_dir())?;
+    fs::write(path, contents)
+}
+
+fn print_usage_table(title: &str, rows: Vec<(&str, Usage)>) {
+    println!("{}:", title);
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, usage) in rows {
+        let last_used = usage
+            .last_used
+            .map(format_timestamp)
+            .unwrap_or_else(|| "never".to_string());
+        println!(
+            "  {:<width$}  {:>5} launches  last used {}",
+            name, usage.launches, last_used
+        );
+    }
+}
+
+/// Shows usage of everything currently configured, least used first, so
+/// candidates for pruning are at the top.
+pub fn show_stats(config: &Config) -> io::Result<()> {
+    let stats = load_stats(&stats_path())?;
+    let sort = |rows: &mut Vec<(&str, Usage)>| {
+        rows.sort_by(|a, b| {
+            (a.1.launches, a.1.last_used, a.0).cmp(&(b.1.launches, b.1.last_used, b.0))
+        })
+    };
+
+    let mut envs: Vec<(&str, Usage)> = config
+        .environments
+        .keys()
+        .map(|env| {
+            let usage = stats.environments.get(env).cloned().unwrap_or_default();
+            (env.as_str(), usage)
+        })
+        .collect();
+    sort(&mut envs);
+    print_usage_table("Environments", envs);
+
+    let mut apps: Vec<(&str, Usage)> = config
+        .environments
+        .values()
+        .flat_map(|env| env.apps.iter().map(|app| app.name.as_str()))
+        .map(|app| (app, stats.apps.get(app).cloned().unwrap_or_default()))
+        .collect();
+    sort(&mut apps);
+    apps.dedup_by(|a, b| a.0 == b.0);
+    println!();
+    print_usage_table("Apps", apps);
+    Ok(())
+}
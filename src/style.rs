@@ -0,0 +1,95 @@
+//! Colors of the terminal output. Styling is off when the output is not a
+//! terminal or `NO_COLOR` is set, unless `--color always` says otherwise.
+
+use std::fmt::Display;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ValueEnum;
+
+use crate::config::Color;
+
+static STDOUT: AtomicBool = AtomicBool::new(false);
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ColorChoice {
+    /// When writing to a terminal and `NO_COLOR` is not set
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    /// Environment names
+    Env,
+    /// Names of environments with a `color`
+    EnvColor(Color),
+    /// Running or successfully launched apps
+    Running,
+    /// Apps that failed, errors
+    Failed,
+    Warning,
+    /// Secondary information, such as skipped apps
+    Dim,
+    /// Table headers
+    Heading,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Env => "1;36",
+            Style::EnvColor(Color::Red) => "1;31",
+            Style::EnvColor(Color::Green) => "1;32",
+            Style::EnvColor(Color::Yellow) => "1;33",
+            Style::EnvColor(Color::Blue) => "1;34",
+            Style::EnvColor(Color::Magenta) => "1;35",
+            Style::EnvColor(Color::Cyan) => "1;36",
+            Style::Running => "32",
+            Style::Failed => "31",
+            Style::Warning => "33",
+            Style::Dim => "2",
+            Style::Heading => "1",
+        }
+    }
+
+    /// The style of the name of an environment with `color`.
+    pub fn env(color: Option<Color>) -> Style {
+        color.map_or(Style::Env, Style::EnvColor)
+    }
+}
+
+pub fn init(choice: ColorChoice) {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let (stdout, stderr) = match choice {
+        ColorChoice::Always => (true, true),
+        ColorChoice::Never => (false, false),
+        ColorChoice::Auto => (
+            !no_color && io::stdout().is_terminal(),
+            !no_color && io::stderr().is_terminal(),
+        ),
+    };
+    STDOUT.store(stdout, Ordering::Relaxed);
+    STDERR.store(stderr, Ordering::Relaxed);
+}
+
+fn apply(enabled: &AtomicBool, style: Style, text: impl Display) -> String {
+    if enabled.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", style.code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Styles text printed on stdout. Pad text before styling it, the escape
+/// codes would count towards the width.
+pub fn paint(style: Style, text: impl Display) -> String {
+    apply(&STDOUT, style, text)
+}
+
+/// Styles text printed on stderr.
+pub fn epaint(style: Style, text: impl Display) -> String {
+    apply(&STDERR, style, text)
+}
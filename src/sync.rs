@@ -0,0 +1,164 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{Command as ProcessCommand, Output};
+
+use log::{error, info};
+
+use crate::config::Config;
+use crate::history::{journal_path, load_journal};
+
+/// Files in the config directory that are machine-local and never synced.
+const GITIGNORE: &str = "history.yaml\nlaunches.yaml\nstats.yaml\n";
+
+fn git(dir: &Path, args: &[&str]) -> io::Result<Output> {
+    let output = ProcessCommand::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()?;
+    if !output.status.success() && !output.stderr.is_empty() {
+        error!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output)
+}
+
+/// Commits pending changes of the config directory and, unless `local` is
+/// set, pulls (rebasing local commits) from and pushes to the remote
+/// configured under `sync:`.
+pub fn sync_config(
+    config_dir: &Path,
+    config: &Config,
+    message: Option<&str>,
+    local: bool,
+) -> io::Result<()> {
+    if !config_dir.join(".git").exists() {
+        fs::create_dir_all(config_dir)?;
+        if !git(config_dir, &["init", "--quiet"])?.status.success() {
+            println!(
+                "Failed to initialize a git repository in {}",
+                config_dir.display()
+            );
+            return Ok(());
+        }
+        info!("Initialized git repository in {}", config_dir.display());
+    }
+    let gitignore = config_dir.join(".gitignore");
+    if !gitignore.exists() {
+        fs::write(&gitignore, GITIGNORE)?;
+    }
+
+    git(config_dir, &["add", "--all"])?;
+    let status = git(config_dir, &["status", "--porcelain"])?;
+    if status.stdout.is_empty() {
+        println!("No local changes to commit");
+    } else {
+        let message = match message {
+            Some(message) => message.to_string(),
+            None => generated_message(config_dir)?,
+        };
+        if git(config_dir, &["commit", "--quiet", "-m", &message])?
+            .status
+            .success()
+        {
+            println!("Committed: {}", message.lines().next().unwrap_or_default());
+        } else {
+            println!("Failed to commit config changes");
+            return Ok(());
+        }
+    }
+
+    if local {
+        return Ok(());
+    }
+    let Some(sync) = &config.sync else {
+        println!("No sync remote configured, add a `sync: {{ remote: ... }}` section to push");
+        return Ok(());
+    };
+
+    let has_remote_branch = git(
+        config_dir,
+        &[
+            "ls-remote",
+            "--exit-code",
+            "--heads",
+            &sync.remote,
+            &sync.branch,
+        ],
+    )?
+    .status
+    .success();
+    if has_remote_branch {
+        if !git(
+            config_dir,
+            &["pull", "--quiet", "--rebase", &sync.remote, &sync.branch],
+        )?
+        .status
+        .success()
+        {
+            println!(
+                "Pulling from {} failed, resolve the conflict in {}",
+                sync.remote,
+                config_dir.display()
+            );
+            return Ok(());
+        }
+        println!("Pulled changes from {}", sync.remote);
+    }
+
+    let refspec = format!("HEAD:{}", sync.branch);
+    if git(config_dir, &["push", "--quiet", &sync.remote, &refspec])?
+        .status
+        .success()
+    {
+        println!("Pushed config to {} ({})", sync.remote, sync.branch);
+        info!("Config synced with {}", sync.remote);
+    } else {
+        println!("Pushing to {} failed", sync.remote);
+    }
+    Ok(())
+}
+
+/// Builds a commit message from the journal entries recorded since the last
+/// commit, or a generic one when the changes were made by hand.
+fn generated_message(config_dir: &Path) -> io::Result<String> {
+    let has_commits = git(config_dir, &["rev-parse", "--quiet", "--verify", "HEAD"])?
+        .status
+        .success();
+    let since: i64 = if has_commits {
+        let last_commit = git(config_dir, &["log", "-1", "--format=%ct"])?;
+        String::from_utf8_lossy(&last_commit.stdout)
+            .trim()
+            .parse()
+            .unwrap_or(0)
+    } else {
+        0
+    };
+    let commands: Vec<String> = load_journal(&journal_path())?
+        .into_iter()
+        .filter(|change| change.timestamp >= since)
+        .map(|change| change.command)
+        .collect();
+
+    let host = fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().to_string())
+        .unwrap_or_else(|_| "unknown host".to_string());
+    Ok(match commands.as_slice() {
+        [] => format!("Update config from {}", host),
+        [command] => format!("{} (from {})", command, host),
+        _ => format!(
+            "{} config changes from {}\n\n{}",
+            commands.len(),
+            host,
+            commands
+                .iter()
+                .map(|c| format!("- {}", c))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    })
+}
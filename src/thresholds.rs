@@ -0,0 +1,165 @@
+//! Memory and CPU thresholds of apps: `clovis daemon` samples what the
+//! running apps that have some use, and notifies, restarts the app or runs
+//! its hook once a threshold has been exceeded for long enough.
+
+use std::collections::HashMap;
+use std::process::{Command as ProcessCommand, Stdio};
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use crate::config::{Config, ThresholdAction, Thresholds};
+use crate::launch;
+use crate::lock;
+use crate::notifications;
+use crate::process;
+use crate::stats;
+use crate::top::{Row, Sampler};
+
+/// How often the apps are sampled.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+const MEGABYTE: u64 = 1024 * 1024;
+
+/// An app above one of its thresholds.
+struct Exceeded {
+    since: Instant,
+    /// Whether the action was taken, which happens once until the app is
+    /// back under its thresholds.
+    acted: bool,
+}
+
+pub struct Monitor {
+    sampler: Sampler,
+    /// By environment and app name.
+    exceeded: HashMap<(String, String), Exceeded>,
+}
+
+/// What `row` uses above `thresholds`, e.g. "2100 MB of memory", `None`
+/// when it is under all of them.
+fn excess(thresholds: &Thresholds, row: &Row) -> Option<String> {
+    let mut over = Vec::new();
+    if let Some(memory_mb) = thresholds.memory_mb {
+        if row.memory > memory_mb * MEGABYTE {
+            over.push(format!("{} MB of memory", row.memory / MEGABYTE));
+        }
+    }
+    if let (Some(limit), Some(cpu)) = (thresholds.cpu_percent, row.cpu) {
+        if cpu > limit {
+            over.push(format!("{:.0}% CPU", cpu));
+        }
+    }
+    (!over.is_empty()).then(|| over.join(" and "))
+}
+
+impl Monitor {
+    pub fn new() -> Monitor {
+        Monitor {
+            sampler: Sampler::new(),
+            exceeded: HashMap::new(),
+        }
+    }
+
+    /// Samples the running apps of `config` that have thresholds, and acts
+    /// on the ones that have exceeded them for their `seconds`.
+    pub fn check(&mut self, config: &Config) {
+        let envs: Vec<String> = config
+            .environments
+            .iter()
+            .filter(|(_, environment)| environment.apps.iter().any(|app| app.thresholds.is_some()))
+            .map(|(env, _)| env.clone())
+            .collect();
+        let mut exceeded = HashMap::new();
+        for row in self.sampler.sample(config, &envs) {
+            let Some(thresholds) = &row.app.thresholds else {
+                continue;
+            };
+            let Some(usage) = excess(thresholds, &row) else {
+                continue;
+            };
+            let key = (row.env.clone(), row.app.name.clone());
+            let mut state = self.exceeded.remove(&key).unwrap_or(Exceeded {
+                since: Instant::now(),
+                acted: false,
+            });
+            if !state.acted && state.since.elapsed() >= Duration::from_secs(thresholds.seconds) {
+                state.acted = act(config, &row, thresholds, &usage);
+            }
+            exceeded.insert(key, state);
+        }
+        self.exceeded = exceeded;
+    }
+}
+
+/// Takes the action of `thresholds` for the app of `row`, which uses
+/// `usage`. Returns false when it is to be tried again at the next check.
+fn act(config: &Config, row: &Row, thresholds: &Thresholds, usage: &str) -> bool {
+    let name = &row.app.name;
+    info!(
+        "{} of {} has used {} for {}s",
+        name, row.env, usage, thresholds.seconds
+    );
+    match thresholds.action {
+        ThresholdAction::Notify => {
+            notifications::send(
+                &format!("{} is using {}", name, usage),
+                &format!(
+                    "It has been above its thresholds in '{}' for {} seconds.",
+                    row.env, thresholds.seconds
+                ),
+            );
+            true
+        }
+        ThresholdAction::Restart => {
+            let Ok(Some(_lock)) = lock::try_lock() else {
+                warn!(
+                    "Not restarting {} yet, another clovis launch or stop is in progress",
+                    name
+                );
+                return false;
+            };
+            let restarted = launch::stop(config, &row.app)
+                .and_then(|_| launch::spawn(config, &config.environments[&row.env], &row.app));
+            match restarted {
+                Ok(()) => {
+                    if let Err(e) = stats::record_restarts(&row.env, &[name]) {
+                        error!("Failed to count the restart of {}: {}", name, e);
+                    }
+                    notifications::send(
+                        &format!("Restarted {}", name),
+                        &format!("It was using {}.", usage),
+                    )
+                }
+                Err(e) => error!("Failed to restart {}: {}", name, e),
+            }
+            true
+        }
+        ThresholdAction::Hook => {
+            let Some(hook) = &thresholds.hook else {
+                error!("{} has the hook threshold action but no hook", name);
+                return true;
+            };
+            let status = process::status(
+                ProcessCommand::new("sh")
+                    .arg("-c")
+                    .arg(hook)
+                    .env("CLOVIS_ENV", &row.env)
+                    .env("CLOVIS_APP", name)
+                    .env("CLOVIS_MEMORY_MB", (row.memory / MEGABYTE).to_string())
+                    .env(
+                        "CLOVIS_CPU_PERCENT",
+                        row.cpu.map_or(String::new(), |cpu| format!("{:.0}", cpu)),
+                    )
+                    .stdin(Stdio::null()),
+            );
+            match status {
+                Ok(status) if !status.success() => {
+                    error!("The threshold hook of {} failed with {}", name, status)
+                }
+                Ok(_) => {}
+                Err(e) => error!("Failed to run the threshold hook of {}: {}", name, e),
+            }
+            true
+        }
+    }
+}
@@ -0,0 +1,348 @@
+//! `clovis top`: the CPU and memory used by the running apps of each
+//! environment, read from `/proc` and refreshed every second, with keys to
+//! stop or restart the selected app.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::error;
+
+use crate::config::{App, Config};
+use crate::launch;
+use crate::lock;
+use crate::stats;
+use crate::style::{paint, Style};
+
+/// How long the display waits for a key before it refreshes.
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// A running app, with what its processes and their children use.
+pub struct Row {
+    pub env: String,
+    pub app: App,
+    pub pids: Vec<u32>,
+    /// Percent of one CPU, unknown on the first sample.
+    pub cpu: Option<f64>,
+    /// Resident memory, in bytes.
+    pub memory: u64,
+}
+
+/// The parent and CPU time (in clock ticks) of a process, from
+/// `/proc/<pid>/stat`.
+fn proc_stat(pid: u32) -> Option<(u32, u64)> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may hold spaces and parentheses, the fields after it
+    // do not
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let parent = fields.get(1)?.parse().ok()?;
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some((parent, utime + stime))
+}
+
+/// Resident memory of a process in bytes, from `/proc/<pid>/statm`.
+fn resident_memory(pid: u32) -> Option<u64> {
+    let statm = fs::read_to_string(format!("/proc/{}/statm", pid)).ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * page_size.max(0) as u64)
+}
+
+/// The CPU time of every process, by pid, with its children.
+fn processes() -> (HashMap<u32, u64>, HashMap<u32, Vec<u32>>) {
+    let mut ticks = HashMap::new();
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    let pids = fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok());
+    for pid in pids {
+        if let Some((parent, time)) = proc_stat(pid) {
+            ticks.insert(pid, time);
+            children.entry(parent).or_default().push(pid);
+        }
+    }
+    (ticks, children)
+}
+
+/// `pids` and all their descendants.
+fn with_descendants(pids: &[u32], children: &HashMap<u32, Vec<u32>>) -> Vec<u32> {
+    let mut all = pids.to_vec();
+    let mut i = 0;
+    while i < all.len() {
+        for child in children.get(&all[i]).into_iter().flatten() {
+            if !all.contains(child) {
+                all.push(*child);
+            }
+        }
+        i += 1;
+    }
+    all
+}
+
+/// Samples the running apps of `envs`, the CPU use being measured since
+/// `previous`, the CPU times of the last sample.
+pub struct Sampler {
+    previous: HashMap<u32, u64>,
+    at: Instant,
+}
+
+impl Sampler {
+    pub fn new() -> Sampler {
+        Sampler {
+            previous: HashMap::new(),
+            at: Instant::now(),
+        }
+    }
+
+    pub fn sample(&mut self, config: &Config, envs: &[String]) -> Vec<Row> {
+        let (ticks, children) = processes();
+        // SAFETY: sysconf has no preconditions
+        let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64;
+        let elapsed = self.at.elapsed().as_secs_f64();
+        let mut rows = Vec::new();
+        for env in envs {
+            for app in &config.environments[env].apps {
+                if !launch::is_running(config, app) {
+                    continue;
+                }
+                let pids = with_descendants(&launch::pids(config, app), &children);
+                let used: Option<u64> = pids
+                    .iter()
+                    .map(|pid| Some(ticks.get(pid)? - self.previous.get(pid)?))
+                    .try_fold(0, |sum, used| used.map(|used| sum + used));
+                let cpu = (!self.previous.is_empty() && !pids.is_empty() && elapsed > 0.0)
+                    .then_some(used)
+                    .flatten()
+                    .map(|used| used as f64 / ticks_per_second / elapsed * 100.0);
+                let memory = pids.iter().filter_map(|pid| resident_memory(*pid)).sum();
+                rows.push(Row {
+                    env: env.clone(),
+                    app: app.clone(),
+                    pids,
+                    cpu,
+                    memory,
+                });
+            }
+        }
+        self.previous = ticks;
+        self.at = Instant::now();
+        rows
+    }
+}
+
+fn format_memory(bytes: u64) -> String {
+    format!("{:.1}M", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// The table of `rows`, grouped by environment, with `selected` marked
+/// when there is one.
+fn render(rows: &[Row], selected: Option<usize>) -> String {
+    let width = rows
+        .iter()
+        .map(|row| row.app.name.len() + 2)
+        .max()
+        .unwrap_or(0)
+        .max(3);
+    let mut out = format!(
+        "{}\n",
+        paint(
+            Style::Heading,
+            format!(
+                "{:<width$}  {:>5}  {:>6}  {:>9}",
+                "APP", "PROCS", "CPU", "MEMORY"
+            )
+        )
+    );
+    let mut env = None;
+    for (i, row) in rows.iter().enumerate() {
+        if env != Some(&row.env) {
+            out.push_str(&format!("{}\n", paint(Style::Env, &row.env)));
+            env = Some(&row.env);
+        }
+        let (procs, cpu, memory) = if row.pids.is_empty() {
+            ("-".to_string(), "-".to_string(), "-".to_string())
+        } else {
+            (
+                row.pids.len().to_string(),
+                row.cpu
+                    .map_or("-".to_string(), |cpu| format!("{:.1}%", cpu)),
+                format_memory(row.memory),
+            )
+        };
+        let line = format!(
+            "{:<width$}  {:>5}  {:>6}  {:>9}",
+            format!("  {}", row.app.name),
+            procs,
+            cpu,
+            memory
+        );
+        if selected == Some(i) {
+            out.push_str(&format!(
+                "{}\n",
+                paint(Style::Heading, format!("> {}", &line[2..]))
+            ));
+        } else {
+            out.push_str(&format!("{}\n", line));
+        }
+    }
+    if rows.is_empty() {
+        out.push_str("No app clovis manages is running.\n");
+    }
+    out
+}
+
+/// Puts the terminal in non-canonical mode without echo, reads returning
+/// after `REFRESH` without a key, until dropped.
+struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        let fd = io::stdin().as_raw_fd();
+        // SAFETY: termios is plain data, filled in by tcgetattr
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: fd is our stdin and original a valid termios
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = (REFRESH.as_millis() / 100) as libc::cc_t;
+        // SAFETY: as above
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in enable
+        unsafe { libc::tcsetattr(io::stdin().as_raw_fd(), libc::TCSANOW, &self.original) };
+    }
+}
+
+enum Key {
+    Up,
+    Down,
+    Stop,
+    Restart,
+    Quit,
+    Other,
+}
+
+/// Waits up to `REFRESH` for a key, `None` when none was pressed.
+fn read_key() -> io::Result<Option<Key>> {
+    let mut buf = [0u8; 3];
+    let n = io::stdin().read(&mut buf)?;
+    Ok(match &buf[..n] {
+        [] => None,
+        [b'q'] | [0x1b] => Some(Key::Quit),
+        [b'k'] | [0x1b, b'[', b'A'] => Some(Key::Up),
+        [b'j'] | [0x1b, b'[', b'B'] => Some(Key::Down),
+        [b's'] => Some(Key::Stop),
+        [b'r'] => Some(Key::Restart),
+        _ => Some(Key::Other),
+    })
+}
+
+/// Stops the app of `row`, and starts it again when `restart`. Returns the
+/// message shown under the table.
+fn act(config: &Config, row: &Row, restart: bool) -> String {
+    let Ok(Some(_lock)) = lock::try_lock() else {
+        return "Another clovis launch or stop is in progress, try again once it is done."
+            .to_string();
+    };
+    let name = &row.app.name;
+    if let Err(e) = launch::stop(config, &row.app) {
+        error!("Failed to stop {}: {}", name, e);
+        return format!("Failed to stop {}: {}", name, e);
+    }
+    if !restart {
+        return format!("Stopped {}.", name);
+    }
+    match launch::spawn(config, &config.environments[&row.env], &row.app) {
+        Ok(()) => {
+            if let Err(e) = stats::record_restarts(&row.env, &[name]) {
+                error!("Failed to count the restart of {}: {}", name, e);
+            }
+            format!("Restarted {}.", name)
+        }
+        Err(e) => {
+            error!("Failed to restart {}: {}", name, e);
+            format!("Failed to restart {}: {}", name, e)
+        }
+    }
+}
+
+/// Shows the running apps of `env`, or of every environment, until `q` is
+/// pressed. Without a terminal, the usage is printed once. Returns false
+/// when `env` is not configured.
+pub fn top(config: &Config, env: Option<&str>) -> io::Result<bool> {
+    let mut envs: Vec<String> = match env {
+        Some(env) => {
+            let env = config.resolve_env(env);
+            if !config.environments.contains_key(env) {
+                println!("Environment '{}' not found.", env);
+                return Ok(false);
+            }
+            vec![env.to_string()]
+        }
+        None => config.environments.keys().cloned().collect(),
+    };
+    envs.sort();
+
+    let mut sampler = Sampler::new();
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        // CPU use is measured between two samples
+        sampler.sample(config, &envs);
+        thread::sleep(REFRESH);
+        print!("{}", render(&sampler.sample(config, &envs), None));
+        return Ok(true);
+    }
+
+    let _raw = RawMode::enable()?;
+    let mut selected = 0;
+    let mut message = String::new();
+    let mut rows = sampler.sample(config, &envs);
+    loop {
+        selected = selected.min(rows.len().saturating_sub(1));
+        let mut stdout = io::stdout();
+        write!(
+            stdout,
+            "\x1b[H\x1b[2J{}\n{}\n\n{}",
+            paint(
+                Style::Dim,
+                "Up/Down or j/k select, s stops, r restarts, q quits"
+            ),
+            message,
+            render(&rows, (!rows.is_empty()).then_some(selected))
+        )?;
+        stdout.flush()?;
+        match read_key()? {
+            Some(Key::Quit) => break,
+            Some(Key::Up) => selected = selected.saturating_sub(1),
+            Some(Key::Down) => selected += 1,
+            Some(key @ (Key::Stop | Key::Restart)) => {
+                if let Some(row) = rows.get(selected) {
+                    message = act(config, row, matches!(key, Key::Restart));
+                    rows = sampler.sample(config, &envs);
+                }
+            }
+            Some(Key::Other) => {}
+            None => rows = sampler.sample(config, &envs),
+        }
+    }
+    println!();
+    Ok(true)
+}
@@ -0,0 +1,160 @@
+//! Hardware events (docks, monitors, USB devices) as the kernel reports
+//! them on its uevent netlink socket, for the `device_triggers:` of
+//! `clovis daemon`. Netlink is Linux only, elsewhere the monitor cannot be
+//! opened and device triggers are disabled.
+
+use std::collections::HashMap;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::fd::FromRawFd;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+
+use crate::config::DeviceTrigger;
+
+/// Multicast group of the events sent by the kernel itself, as opposed to
+/// the ones udev sends once it has processed them.
+#[cfg(target_os = "linux")]
+const KERNEL_GROUP: u32 = 1;
+
+#[derive(Debug)]
+pub struct Uevent {
+    /// `add`, `remove`, `change`, ...
+    pub action: String,
+    pub properties: HashMap<String, String>,
+}
+
+impl Uevent {
+    /// Parses `<action>@<devpath>` followed by `KEY=value` lines, all
+    /// separated by NUL bytes.
+    fn parse(datagram: &[u8]) -> Option<Uevent> {
+        let mut lines = datagram
+            .split(|b| *b == 0)
+            .map(|line| String::from_utf8_lossy(line).into_owned());
+        let header = lines.next()?;
+        let (action, _) = header.split_once('@')?;
+        let properties = lines
+            .filter_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                Some((key.to_string(), value.to_string()))
+            })
+            .collect();
+        Some(Uevent {
+            action: action.to_string(),
+            properties,
+        })
+    }
+
+    fn property(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+
+    /// The `vendor:product` id of a USB device, e.g. `17ef:30a9`.
+    fn usb_id(&self) -> Option<(u32, u32)> {
+        if self.property("DEVTYPE") != Some("usb_device") {
+            return None;
+        }
+        // PRODUCT=<vendor>/<product>/<release>, in hex without padding
+        let mut product = self.property("PRODUCT")?.split('/');
+        let vendor = u32::from_str_radix(product.next()?, 16).ok()?;
+        let product = u32::from_str_radix(product.next()?, 16).ok()?;
+        Some((vendor, product))
+    }
+}
+
+/// Whether `event` is one `trigger` acts on.
+pub fn trigger_matches(trigger: &DeviceTrigger, event: &Uevent) -> bool {
+    if event.action != trigger.event.as_str() {
+        return false;
+    }
+    if let Some(subsystem) = &trigger.subsystem {
+        if event.property("SUBSYSTEM") != Some(subsystem.as_str()) {
+            return false;
+        }
+    }
+    if let Some(usb_id) = &trigger.usb_id {
+        let wanted = usb_id.split_once(':').and_then(|(vendor, product)| {
+            Some((
+                u32::from_str_radix(vendor, 16).ok()?,
+                u32::from_str_radix(product, 16).ok()?,
+            ))
+        });
+        if wanted.is_none() || event.usb_id() != wanted {
+            return false;
+        }
+    }
+    trigger
+        .properties
+        .iter()
+        .all(|(key, value)| event.property(key) == Some(value.as_str()))
+}
+
+pub struct Monitor {
+    socket: OwnedFd,
+}
+
+impl Monitor {
+    /// Subscribes to the events of the kernel.
+    #[cfg(target_os = "linux")]
+    pub fn open() -> io::Result<Monitor> {
+        // SAFETY: plain socket creation, the result is checked
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd was just created and is owned by nothing else
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        // SAFETY: sockaddr_nl is plain data, all zeroes is valid
+        let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = KERNEL_GROUP;
+        // SAFETY: address is a valid sockaddr_nl of the given size
+        let bound = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if bound < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Monitor { socket })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn open() -> io::Result<Monitor> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "device events are only reported on Linux",
+        ))
+    }
+
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+
+    /// Reads the next event, blocking until one arrives. Returns `None` for
+    /// messages that are not events.
+    pub fn read(&self) -> io::Result<Option<Uevent>> {
+        let mut buf = vec![0u8; 8192];
+        // SAFETY: buf is valid for writes of buf.len() bytes
+        let len = unsafe {
+            libc::recv(
+                self.socket.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Uevent::parse(&buf[..len as usize]))
+    }
+}
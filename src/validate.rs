@@ -0,0 +1,393 @@
+//! `clovis validate`: whether the config is sound and every app can be
+//! launched on this machine, told by the exit code for scripts and CI.
+
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::config::{load_config, parse_config, AppKind, Config};
+use crate::desktop::installed_desktop_entries;
+use crate::fuzzy::fuzzy_score;
+use crate::history::save_config_with_history;
+use crate::json::Json;
+use crate::launch;
+use crate::lint;
+use crate::prompt;
+use crate::style::{paint, Style};
+
+/// Exit code when an app cannot be launched.
+const EXIT_MISSING_APPS: i32 = 1;
+/// Exit code when the config cannot be loaded or refers to what it lacks.
+const EXIT_CONFIG_ERRORS: i32 = 2;
+/// How many replacements are suggested for a missing app.
+const MAX_SUGGESTIONS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProblemKind {
+    MissingApp,
+    ConfigError,
+    /// Hygiene problems `clovis lint` also reports, only failing with
+    /// `--strict`.
+    Warning,
+}
+
+impl ProblemKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ProblemKind::MissingApp => "missing-app",
+            ProblemKind::ConfigError => "config-error",
+            ProblemKind::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    pub env: Option<String>,
+    pub app: Option<String>,
+    pub reason: String,
+    /// Installed apps a missing one may have been renamed to.
+    pub suggestions: Vec<String>,
+}
+
+impl Problem {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("kind", Json::from(self.kind.as_str())),
+            ("env", Json::from(self.env.clone())),
+            ("app", Json::from(self.app.clone())),
+            ("reason", Json::from(self.reason.as_str())),
+            ("suggestions", Json::strings(self.suggestions.clone())),
+        ])
+    }
+
+    fn describe(&self) -> String {
+        match (&self.env, &self.app) {
+            (Some(env), Some(app)) if self.kind == ProblemKind::MissingApp => format!(
+                "Application '{}' in environment '{}' {}.",
+                app,
+                paint(Style::Env, env),
+                self.reason
+            ),
+            _ => format!("{}.", self.reason),
+        }
+    }
+}
+
+/// What an installed app can be found by: its desktop file id or command,
+/// and the name and program of a desktop entry.
+struct Candidate {
+    value: String,
+    keys: Vec<String>,
+}
+
+/// The installed desktop entries and, unless only desktop entries are
+/// wanted, the commands in PATH.
+fn candidates(desktop_only: bool) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = installed_desktop_entries()
+        .into_iter()
+        .map(|entry| {
+            let mut keys = vec![
+                entry.id.trim_end_matches(".desktop").to_string(),
+                entry.name().to_string(),
+            ];
+            if let Some(program) = entry.command_line().first() {
+                keys.push(program.rsplit('/').next().unwrap_or(program).to_string());
+            }
+            Candidate {
+                value: entry.id,
+                keys,
+            }
+        })
+        .collect();
+    if desktop_only {
+        return candidates;
+    }
+    let mut commands: Vec<String> = std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|read_dir| read_dir.flatten())
+        .filter(|file| {
+            file.metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+        .map(|file| file.file_name().to_string_lossy().into_owned())
+        .collect();
+    commands.sort();
+    commands.dedup();
+    candidates.extend(commands.into_iter().map(|command| Candidate {
+        keys: vec![command.clone()],
+        value: command,
+    }));
+    candidates
+}
+
+/// How well `key` matches the name of a missing app, either way round so
+/// that `chromium-browser` finds `chromium` as `code` finds `code-oss`.
+/// Scattered matches, and keys covering little of the name, are left out.
+fn match_score(name: &str, key: &str) -> Option<i64> {
+    let key = key.to_lowercase();
+    let close = |query: &str, candidate: &str| {
+        fuzzy_score(query, candidate).filter(|score| *score >= 12 * query.chars().count() as i64)
+    };
+    let reverse = (key.chars().count() >= 4 && 2 * key.chars().count() >= name.chars().count())
+        .then(|| close(&key, name))
+        .flatten();
+    close(name, &key).max(reverse)
+}
+
+/// The installed apps `name` most likely stands for, best first.
+fn suggestions(name: &str, candidates: &[Candidate]) -> Vec<String> {
+    let stem = name.trim_end_matches(".desktop").to_lowercase();
+    let mut scored: Vec<(i64, &str)> = candidates
+        .iter()
+        .filter(|candidate| candidate.value != name)
+        .filter_map(|candidate| {
+            let score = candidate
+                .keys
+                .iter()
+                .filter_map(|key| match_score(&stem, key))
+                .max()?;
+            Some((score, candidate.value.as_str()))
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.len().cmp(&b.1.len())));
+    scored.dedup_by_key(|(_, value)| *value);
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, value)| value.to_string())
+        .collect()
+}
+
+/// Everything wrong with the config at `config_path`, the config errors
+/// first.
+fn find_problems(config_path: &Path) -> Vec<Problem> {
+    let config_error = |reason: String| Problem {
+        kind: ProblemKind::ConfigError,
+        env: None,
+        app: None,
+        reason,
+        suggestions: Vec::new(),
+    };
+    let contents = match fs::read_to_string(config_path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            return vec![config_error(format!(
+                "cannot read {}: {}",
+                config_path.display(),
+                e
+            ))]
+        }
+    };
+    let config = match parse_config(&contents) {
+        Ok((config, _)) => config,
+        Err(e) => return vec![config_error(format!("cannot parse the config: {}", e))],
+    };
+
+    let mut problems = lint::config_problems(&contents, &config);
+    let mut envs: Vec<&String> = config.environments.keys().collect();
+    envs.sort();
+    // Listed once, and only when an app is missing
+    let mut desktop_entries = None;
+    let mut all_apps = None;
+    for env in envs {
+        for app in &config.environments[env].apps {
+            if let Err(reason) = launch::check_available(&config, app) {
+                // Only desktop entries and commands are looked up by name
+                let suggestions = if app.kind != AppKind::Auto {
+                    Vec::new()
+                } else if app.name.ends_with(".desktop") {
+                    suggestions(
+                        &app.name,
+                        desktop_entries.get_or_insert_with(|| candidates(true)),
+                    )
+                } else {
+                    suggestions(&app.name, all_apps.get_or_insert_with(|| candidates(false)))
+                };
+                problems.push(Problem {
+                    kind: ProblemKind::MissingApp,
+                    env: Some(env.clone()),
+                    app: Some(app.name.clone()),
+                    reason,
+                    suggestions,
+                });
+            }
+        }
+    }
+    problems.sort_by_key(|problem| match problem.kind {
+        ProblemKind::ConfigError => 0,
+        ProblemKind::MissingApp => 1,
+        ProblemKind::Warning => 2,
+    });
+    problems
+}
+
+/// 0 when all is well, 1 when apps are missing and 2 on config errors, or
+/// on warnings too when `strict`.
+fn exit_code(problems: &[Problem], strict: bool) -> i32 {
+    let any = |kind| problems.iter().any(|p: &Problem| p.kind == kind);
+    if any(ProblemKind::ConfigError) || strict && any(ProblemKind::Warning) {
+        EXIT_CONFIG_ERRORS
+    } else if any(ProblemKind::MissingApp) {
+        EXIT_MISSING_APPS
+    } else {
+        0
+    }
+}
+
+/// Offers to replace each missing app with one of its suggestions, and
+/// saves the config when any was. Returns the problems left.
+fn fix_missing_apps(config_path: &Path, problems: Vec<Problem>) -> io::Result<Vec<Problem>> {
+    if !io::stdin().is_terminal() {
+        println!("--fix asks which app to use instead, it needs a terminal.");
+        return Ok(problems);
+    }
+    let mut config: Config = load_config(config_path)?;
+    let mut left = Vec::new();
+    let mut fixed = 0;
+    for problem in problems {
+        let (Some(env), Some(app)) = (&problem.env, &problem.app) else {
+            left.push(problem);
+            continue;
+        };
+        if problem.kind != ProblemKind::MissingApp || problem.suggestions.is_empty() {
+            left.push(problem);
+            continue;
+        }
+        println!(
+            "'{}' in environment '{}' {}. Replace it with:",
+            app,
+            paint(Style::Env, env),
+            problem.reason
+        );
+        for (i, suggestion) in problem.suggestions.iter().enumerate() {
+            println!("  {:>2}) {}", i + 1, suggestion);
+        }
+        let answer = prompt("Pick a number, or press enter to keep it: ")?;
+        let Some(replacement) = answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| problem.suggestions.get(n.wrapping_sub(1)))
+        else {
+            left.push(problem);
+            continue;
+        };
+        if let Some(environment) = config.environments.get_mut(env) {
+            for entry in environment.apps.iter_mut().filter(|a| a.name == *app) {
+                entry.name = replacement.clone();
+            }
+        }
+        println!(
+            "{} {} -> {}",
+            paint(Style::Running, "Replaced:"),
+            app,
+            replacement
+        );
+        fixed += 1;
+    }
+    if fixed > 0 {
+        save_config_with_history(config_path, &config)?;
+    }
+    Ok(left)
+}
+
+/// Prints what is wrong with the config, as text or as a JSON report, and
+/// returns the exit code (see [`exit_code`]). With `fix`, missing apps can
+/// then be replaced with the installed apps suggested for them.
+pub fn validate_config(config_path: &Path, strict: bool, json: bool, fix: bool) -> io::Result<i32> {
+    let mut problems = find_problems(config_path);
+    let count = |problems: &[Problem], kind| problems.iter().filter(|p| p.kind == kind).count();
+    let code = exit_code(&problems, strict);
+
+    if json {
+        let report = Json::object([
+            ("ok", Json::from(code == 0)),
+            ("exit_code", Json::from(code as i64)),
+            (
+                "problems",
+                Json::Array(problems.iter().map(Problem::to_json).collect()),
+            ),
+        ]);
+        println!("{}", report.pretty());
+        return Ok(code);
+    }
+    for problem in &problems {
+        let label = match problem.kind {
+            ProblemKind::ConfigError => paint(Style::Failed, "Error:"),
+            ProblemKind::MissingApp | ProblemKind::Warning => paint(Style::Warning, "Warning:"),
+        };
+        println!("{} {}", label, problem.describe());
+        if !problem.suggestions.is_empty() && !fix {
+            println!("  Did you mean {}?", problem.suggestions.join(", "));
+        }
+    }
+    let missing = count(&problems, ProblemKind::MissingApp);
+    if count(&problems, ProblemKind::ConfigError) == 0 && missing == 0 {
+        println!(
+            "{}",
+            paint(Style::Running, "All applications are properly installed.")
+        );
+    }
+    if fix && missing > 0 {
+        println!();
+        problems = fix_missing_apps(config_path, problems)?;
+    } else if missing > 0 && problems.iter().any(|p| !p.suggestions.is_empty()) {
+        println!("Run `clovis validate --fix` to replace them.");
+    }
+    Ok(exit_code(&problems, strict))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(value: &str, keys: &[&str]) -> Candidate {
+        Candidate {
+            value: value.to_string(),
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+        }
+    }
+
+    fn problem(kind: ProblemKind) -> Problem {
+        Problem {
+            kind,
+            env: None,
+            app: None,
+            reason: String::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn renamed_apps_are_suggested() {
+        let candidates = [
+            candidate("chromium.desktop", &["chromium", "Chromium", "chromium"]),
+            candidate("code-oss", &["code-oss"]),
+            candidate("cat", &["cat"]),
+        ];
+        assert_eq!(
+            suggestions("chromium-browser.desktop", &candidates),
+            ["chromium.desktop"]
+        );
+        assert_eq!(suggestions("code", &candidates), ["code-oss"]);
+        assert!(suggestions("firefox", &candidates).is_empty());
+    }
+
+    #[test]
+    fn config_errors_outrank_missing_apps() {
+        let missing = problem(ProblemKind::MissingApp);
+        let error = problem(ProblemKind::ConfigError);
+        let warning = problem(ProblemKind::Warning);
+        assert_eq!(exit_code(&[], false), 0);
+        assert_eq!(exit_code(&[missing], false), EXIT_MISSING_APPS);
+        let missing = problem(ProblemKind::MissingApp);
+        assert_eq!(exit_code(&[missing, error], false), EXIT_CONFIG_ERRORS);
+        assert_eq!(exit_code(std::slice::from_ref(&warning), false), 0);
+        assert_eq!(exit_code(&[warning], true), EXIT_CONFIG_ERRORS);
+    }
+}
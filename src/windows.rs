@@ -0,0 +1,473 @@
+//! Open windows, as reported by the compositor (Hyprland or Sway) or, on
+//! X11, by wmctrl, and how they are moved around.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::process::Command as ProcessCommand;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use serde_yaml::Value;
+
+use crate::config::{App, Geometry};
+use crate::launch::{is_command_available, window_class};
+
+/// How often the window list is polled while waiting for a window.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Hyprland,
+    Sway,
+    Wmctrl,
+}
+
+#[derive(Debug, Clone)]
+pub struct Window {
+    /// Hyprland address, Sway container id or X11 window id.
+    pub id: String,
+    /// Wayland app id or X11 `WM_CLASS`. wmctrl reports the latter as
+    /// `<instance>.<class>`.
+    pub class: String,
+    pub pid: Option<u32>,
+    /// Workspace name, or desktop number on X11.
+    pub workspace: Option<String>,
+    /// Position and size of the window, only known for floating windows on
+    /// tiling compositors.
+    pub geometry: Option<Geometry>,
+}
+
+impl Window {
+    /// Whether the window belongs to `class`, ignoring case.
+    pub fn matches(&self, class: &str) -> bool {
+        let own = self.class.to_lowercase();
+        let class = class.to_lowercase();
+        own == class || own.ends_with(&format!(".{}", class))
+    }
+}
+
+fn backend() -> Option<Backend> {
+    if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() && is_command_available("hyprctl")
+    {
+        Some(Backend::Hyprland)
+    } else if std::env::var_os("SWAYSOCK").is_some() && is_command_available("swaymsg") {
+        Some(Backend::Sway)
+    } else if is_command_available("wmctrl") {
+        Some(Backend::Wmctrl)
+    } else {
+        None
+    }
+}
+
+/// Lists the open windows, or `None` when no supported compositor or
+/// window manager tool is available.
+pub fn list_windows() -> Option<Vec<Window>> {
+    match backend()? {
+        Backend::Hyprland => hyprland_windows(),
+        Backend::Sway => sway_windows(),
+        Backend::Wmctrl => wmctrl_windows(),
+    }
+}
+
+/// How many open windows belong to `class`.
+pub fn count(class: &str) -> Option<usize> {
+    list_windows().map(|windows| windows.iter().filter(|w| w.matches(class)).count())
+}
+
+/// Waits until more than `open` windows of `class` are open. Returns false
+/// when none appeared before `timeout`.
+pub fn wait_for_window(class: &str, open: usize, timeout: Duration) -> bool {
+    let start = Instant::now();
+    loop {
+        if count(class).is_some_and(|n| n > open) {
+            return true;
+        }
+        if start.elapsed() >= timeout {
+            return false;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Waits until more than `open` windows of each class are open, up to
+/// `timeout`. Returns when each of them appeared, `None` for those that did
+/// not.
+pub fn wait_for_windows(watches: &[(String, usize)], timeout: Duration) -> Vec<Option<Instant>> {
+    let start = Instant::now();
+    let mut appeared = vec![None; watches.len()];
+    loop {
+        if let Some(windows) = list_windows() {
+            let now = Instant::now();
+            for ((class, open), appeared) in watches.iter().zip(&mut appeared) {
+                let count = windows.iter().filter(|w| w.matches(class)).count();
+                if appeared.is_none() && count > *open {
+                    *appeared = Some(now);
+                }
+            }
+        }
+        if appeared.iter().all(Option::is_some) || start.elapsed() >= timeout {
+            return appeared;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn has_layout(app: &App) -> bool {
+    app.workspace.is_some() || app.geometry.is_some()
+}
+
+/// The ids of the open windows when some of `apps` have a layout to
+/// restore, taken before launching them so that the windows already open
+/// are left where they are.
+pub fn windows_before_launch(apps: &[&App]) -> HashSet<String> {
+    if !apps.iter().any(|app| has_layout(app)) {
+        return HashSet::new();
+    }
+    list_windows()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|window| window.id)
+        .collect()
+}
+
+/// Moves the new windows of `apps`, those not in `existing`, to the
+/// workspace and geometry recorded for them. The windows that are not open
+/// yet are waited for up to `timeout` in the background, so that neither
+/// the launch nor the daemon running it waits for them. Each window is
+/// placed once, so two entries of the same app get one window each.
+pub fn restore_layout(apps: &[&App], existing: HashSet<String>, timeout: Duration) {
+    let mut pending: Vec<(&App, String)> = Vec::new();
+    for app in apps.iter().filter(|app| has_layout(app)) {
+        match window_class(app) {
+            Some(class) => pending.push((app, class)),
+            None => warn!(
+                "Not restoring the layout of '{}', set its `window_class`",
+                app.name
+            ),
+        }
+    }
+    if pending.is_empty() {
+        return;
+    }
+    let Some(backend) = backend() else {
+        warn!(
+            "Not restoring the layout, windows can only be moved on Hyprland, Sway or with wmctrl"
+        );
+        return;
+    };
+    if let Err(e) = in_background(|| place_new_windows(backend, pending, existing, timeout)) {
+        error!("Failed to restore the layout: {}", e);
+    }
+}
+
+fn place_new_windows(
+    backend: Backend,
+    mut pending: Vec<(&App, String)>,
+    mut placed: HashSet<String>,
+    timeout: Duration,
+) {
+    let start = Instant::now();
+    loop {
+        let windows = list_windows().unwrap_or_default();
+        pending.retain(|(app, class)| {
+            let Some(window) = windows
+                .iter()
+                .find(|w| w.matches(class) && !placed.contains(&w.id))
+            else {
+                return true;
+            };
+            placed.insert(window.id.clone());
+            match place(backend, window, app) {
+                Ok(()) => info!("Restored the layout of {}", app.name),
+                Err(e) => error!("Failed to restore the layout of {}: {}", app.name, e),
+            }
+            false
+        });
+        if pending.is_empty() || start.elapsed() >= timeout {
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    for (app, _) in pending {
+        warn!(
+            "No window of '{}' appeared, its layout was not restored",
+            app.name
+        );
+    }
+}
+
+/// Runs `work` in a process detached from clovis, as apps are, with its
+/// output going to the log only.
+fn in_background(work: impl FnOnce()) -> io::Result<()> {
+    io::stdout().flush()?;
+    // SAFETY: clovis is single-threaded, so the child can keep running
+    // Rust code after the fork
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if pid > 0 {
+        let mut status = 0;
+        // SAFETY: pid is a child of this process, which exits right away
+        unsafe { libc::waitpid(pid, &mut status, 0) };
+        return Ok(());
+    }
+    // The intermediate process exits right away, so that the worker is
+    // adopted by init instead of remaining our child
+    // SAFETY: no other thread runs in the child
+    unsafe {
+        libc::setsid();
+        if libc::fork() != 0 {
+            libc::_exit(0);
+        }
+    }
+    // The connection of a daemon client must not wait for the worker
+    if let Ok(null) = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+    {
+        for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            // SAFETY: the descriptor is open for the duration of the call
+            unsafe { libc::dup2(null.as_raw_fd(), fd) };
+        }
+    }
+    work();
+    std::process::exit(0);
+}
+
+fn run(command: &mut ProcessCommand) -> io::Result<()> {
+    let output = command.output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(io::Error::other(stderr.trim().to_string()))
+    }
+}
+
+/// Moves `window` to the workspace and geometry of `app`.
+fn place(backend: Backend, window: &Window, app: &App) -> io::Result<()> {
+    match backend {
+        Backend::Hyprland => {
+            let target = format!("address:{}", window.id);
+            if let Some(workspace) = &app.workspace {
+                // Named workspaces need a prefix, numbered and special ones
+                // are given as is
+                let workspace =
+                    if workspace.parse::<i64>().is_ok() || workspace.starts_with("special") {
+                        workspace.clone()
+                    } else {
+                        format!("name:{}", workspace)
+                    };
+                run(ProcessCommand::new("hyprctl").args([
+                    "dispatch",
+                    "movetoworkspacesilent",
+                    &format!("{},{}", workspace, target),
+                ]))?;
+            }
+            if let Some(g) = &app.geometry {
+                run(ProcessCommand::new("hyprctl").args([
+                    "dispatch",
+                    "movewindowpixel",
+                    &format!("exact {} {},{}", g.x, g.y, target),
+                ]))?;
+                run(ProcessCommand::new("hyprctl").args([
+                    "dispatch",
+                    "resizewindowpixel",
+                    &format!("exact {} {},{}", g.width, g.height, target),
+                ]))?;
+            }
+        }
+        Backend::Sway => {
+            let mut commands = Vec::new();
+            if let Some(workspace) = &app.workspace {
+                commands.push(format!("move container to workspace \"{}\"", workspace));
+            }
+            if let Some(g) = &app.geometry {
+                commands.push(format!("move position {} {}", g.x, g.y));
+                commands.push(format!("resize set {} {}", g.width, g.height));
+            }
+            run(ProcessCommand::new("swaymsg")
+                .arg(format!("[con_id={}]", window.id))
+                .arg(commands.join(", ")))?;
+        }
+        Backend::Wmctrl => {
+            if let Some(workspace) = &app.workspace {
+                run(wmctrl().args(["-i", "-r", &window.id, "-t", workspace]))?;
+            }
+            if let Some(g) = &app.geometry {
+                run(wmctrl().args([
+                    "-i",
+                    "-r",
+                    &window.id,
+                    "-e",
+                    &format!("0,{},{},{},{}", g.x, g.y, g.width, g.height),
+                ]))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn output_of(command: &mut ProcessCommand) -> Option<String> {
+    let output = command.output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn str_field(value: &Value, key: &str) -> Option<String> {
+    value.get(key)?.as_str().map(str::to_string)
+}
+
+fn int_field(value: &Value, key: &str) -> Option<i32> {
+    value.get(key)?.as_i64().and_then(|n| i32::try_from(n).ok())
+}
+
+fn pid_field(value: &Value) -> Option<u32> {
+    value
+        .get("pid")?
+        .as_u64()
+        .and_then(|pid| u32::try_from(pid).ok())
+        .filter(|pid| *pid > 0)
+}
+
+/// Reads a `[x, y]` pair of integers.
+fn pair_field(value: &Value, key: &str) -> Option<(i32, i32)> {
+    let pair = value.get(key)?.as_sequence()?;
+    let first = i32::try_from(pair.first()?.as_i64()?).ok()?;
+    let second = i32::try_from(pair.get(1)?.as_i64()?).ok()?;
+    Some((first, second))
+}
+
+fn hyprland_windows() -> Option<Vec<Window>> {
+    let output = output_of(ProcessCommand::new("hyprctl").args(["clients", "-j"]))?;
+    let clients: Value = serde_yaml::from_str(&output).ok()?;
+    let windows = clients
+        .as_sequence()?
+        .iter()
+        // Hyprland keeps entries around for unmapped windows
+        .filter(|client| client.get("mapped").and_then(Value::as_bool) != Some(false))
+        .map(|client| {
+            let floating = client.get("floating").and_then(Value::as_bool) == Some(true);
+            let geometry = match (pair_field(client, "at"), pair_field(client, "size")) {
+                (Some((x, y)), Some((width, height))) if floating => Some(Geometry {
+                    x,
+                    y,
+                    width,
+                    height,
+                }),
+                _ => None,
+            };
+            Window {
+                id: str_field(client, "address").unwrap_or_default(),
+                class: str_field(client, "class").unwrap_or_default(),
+                pid: pid_field(client),
+                workspace: client
+                    .get("workspace")
+                    .and_then(|workspace| str_field(workspace, "name")),
+                geometry,
+            }
+        })
+        .collect();
+    Some(windows)
+}
+
+fn sway_windows() -> Option<Vec<Window>> {
+    let output = output_of(ProcessCommand::new("swaymsg").args(["-t", "get_tree", "-r"]))?;
+    let tree: Value = serde_yaml::from_str(&output).ok()?;
+    let mut windows = Vec::new();
+    collect_sway_windows(&tree, None, &mut windows);
+    Some(windows)
+}
+
+fn collect_sway_windows(node: &Value, workspace: Option<&str>, windows: &mut Vec<Window>) {
+    let node_type = node.get("type").and_then(Value::as_str);
+    let name = str_field(node, "name");
+    let workspace = match node_type {
+        Some("workspace") => name.as_deref(),
+        _ => workspace,
+    };
+    // Xwayland windows have no app id, only X11 properties
+    let class = str_field(node, "app_id").or_else(|| {
+        node.get("window_properties")
+            .and_then(|properties| str_field(properties, "class"))
+    });
+    if let Some(class) = class {
+        let geometry = node
+            .get("rect")
+            .filter(|_| node_type == Some("floating_con"))
+            .and_then(|rect| {
+                Some(Geometry {
+                    x: int_field(rect, "x")?,
+                    y: int_field(rect, "y")?,
+                    width: int_field(rect, "width")?,
+                    height: int_field(rect, "height")?,
+                })
+            });
+        windows.push(Window {
+            id: node
+                .get("id")
+                .and_then(Value::as_u64)
+                .unwrap_or_default()
+                .to_string(),
+            class,
+            pid: pid_field(node),
+            workspace: workspace.map(str::to_string),
+            geometry,
+        });
+    }
+    for key in ["nodes", "floating_nodes"] {
+        if let Some(children) = node.get(key).and_then(Value::as_sequence) {
+            for child in children {
+                collect_sway_windows(child, workspace, windows);
+            }
+        }
+    }
+}
+
+fn wmctrl() -> ProcessCommand {
+    let mut command = ProcessCommand::new("wmctrl");
+    // Apps are started on the first display when none is set
+    if std::env::var_os("DISPLAY").is_none() {
+        command.env("DISPLAY", ":0");
+    }
+    command
+}
+
+/// Parses `wmctrl -lxpG`, whose lines read `<id> <desktop> <pid> <x> <y>
+/// <width> <height> <class> <host> <title>`.
+fn wmctrl_windows() -> Option<Vec<Window>> {
+    let output = output_of(wmctrl().arg("-lxpG"))?;
+    let windows = output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let id = columns.next()?.to_string();
+            let desktop = columns.next()?;
+            let pid = columns.next()?.parse().ok().filter(|pid| *pid > 0);
+            let mut number = || columns.next()?.parse::<i32>().ok();
+            let geometry = Geometry {
+                x: number()?,
+                y: number()?,
+                width: number()?,
+                height: number()?,
+            };
+            let class = columns.next()?.to_string();
+            Some(Window {
+                id,
+                class,
+                pid,
+                // Sticky windows are on desktop -1
+                workspace: (desktop != "-1").then(|| desktop.to_string()),
+                geometry: Some(geometry),
+            })
+        })
+        .collect();
+    Some(windows)
+}
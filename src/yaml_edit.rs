@@ -0,0 +1,532 @@
+//! Targeted, line-based edits of a YAML document.
+//!
+//! serde_yaml only round-trips data, so re-serializing the config after an
+//! `edit` throws away comments, blank lines and key ordering. The helpers in
+//! here instead locate a block by its key path and rewrite just the lines of
+//! that block, leaving the rest of the file byte-for-byte untouched. They only
+//! understand block-style mappings and sequences; anything else (flow
+//! collections, anchors, multi-document files) makes them bail out so the
+//! caller can fall back to a full rewrite.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_yaml::Value;
+
+pub struct YamlDocument {
+    lines: Vec<String>,
+    trailing_newline: bool,
+}
+
+/// One `- ...` entry of a block sequence, together with the comment lines
+/// directly above it and any more-indented continuation lines.
+struct SequenceItem {
+    lines: Vec<String>,
+}
+
+impl YamlDocument {
+    pub fn parse(text: &str) -> YamlDocument {
+        YamlDocument {
+            lines: text.lines().map(str::to_string).collect(),
+            trailing_newline: text.ends_with('\n') || text.is_empty(),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut text = self.lines.join("\n");
+        if self.trailing_newline {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Replaces the block sequence at `path` with `values`. Items already
+    /// present keep their exact lines (comments included), new or modified
+    /// ones are written at the sequence's indentation. When the last key of
+    /// `path` does not exist yet it is appended to its parent mapping.
+    pub fn set_sequence<T>(&mut self, path: &[&str], values: &[T]) -> bool
+    where
+        T: Serialize + DeserializeOwned + PartialEq,
+    {
+        let Some(key_line) = self.find_key(path) else {
+            return self.append_value(path, values);
+        };
+        let key_indent = indent_of(&self.lines[key_line]);
+        let (key_text, inline) = split_key_line(&self.lines[key_line]).unwrap();
+        let comment = comment_of(&self.lines[key_line]);
+        let end = self.block_end(key_line);
+
+        let (item_indent, mut items) = match inline.as_str() {
+            "" => match self.sequence_items(key_line + 1, end) {
+                Some(found) => found,
+                None => return false,
+            },
+            "[]" => (None, Vec::new()),
+            _ => return false,
+        };
+        let item_indent = item_indent.unwrap_or(key_indent + 2);
+        let mut parsed: Vec<Option<T>> = items
+            .iter()
+            .map(|item| parse_item(&item.lines, item_indent))
+            .collect();
+
+        let mut block = Vec::new();
+        for value in values {
+            match parsed.iter().position(|p| p.as_ref() == Some(value)) {
+                Some(pos) => {
+                    parsed.remove(pos);
+                    block.extend(items.remove(pos).lines);
+                }
+                None => match render_item(value, item_indent) {
+                    Some(lines) => block.extend(lines),
+                    None => return false,
+                },
+            }
+        }
+
+        let flow = if values.is_empty() { " []" } else { "" };
+        self.lines[key_line] =
+            format!("{}{}:{}{}", " ".repeat(key_indent), key_text, flow, comment);
+        self.lines.splice(key_line + 1..end, block);
+        true
+    }
+
+    /// Replaces the value at `path` with `value`, rendered by serde_yaml.
+    /// Comments inside the replaced block are lost, the ones around it and
+    /// on the key line itself are kept.
+    pub fn set_value<T: Serialize>(&mut self, path: &[&str], value: &T) -> bool {
+        let Some(key_line) = self.find_key(path) else {
+            return self.append_value(path, value);
+        };
+        let key_indent = indent_of(&self.lines[key_line]);
+        let (key_text, _) = split_key_line(&self.lines[key_line]).unwrap();
+        let comment = comment_of(&self.lines[key_line]);
+        let end = self.block_end(key_line);
+        let Some((inline, block)) = render_value(value, key_indent + 2) else {
+            return false;
+        };
+        self.lines[key_line] = format!(
+            "{}{}:{}{}",
+            " ".repeat(key_indent),
+            key_text,
+            inline,
+            comment
+        );
+        self.lines.splice(key_line + 1..end, block);
+        true
+    }
+
+    /// Inserts a top-level `key: value` line before the first key of the
+    /// document, after any leading comments.
+    pub fn insert_first<T: Serialize>(&mut self, key: &str, value: &T) -> bool {
+        let Some((inline, block)) = render_value(value, 2) else {
+            return false;
+        };
+        if !block.is_empty() {
+            return false;
+        }
+        let at = self
+            .lines
+            .iter()
+            .position(|l| !is_blank_or_comment(l) && l.trim() != "---")
+            .unwrap_or(self.lines.len());
+        self.lines
+            .insert(at, format!("{}:{}", render_scalar(key), inline));
+        true
+    }
+
+    pub fn contains_key(&self, path: &[&str]) -> bool {
+        self.find_key(path).is_some()
+    }
+
+    /// Removes the key at `path` together with its whole value block.
+    pub fn remove_key(&mut self, path: &[&str]) -> bool {
+        let Some(key_line) = self.find_key(path) else {
+            return false;
+        };
+        let end = self.block_end(key_line);
+        self.lines.drain(key_line..end);
+        true
+    }
+
+    fn append_value<T: Serialize + ?Sized>(&mut self, path: &[&str], value: &T) -> bool {
+        let (key, parent_path) = path.split_last().unwrap();
+        let (insert_at, child_indent) = if parent_path.is_empty() {
+            (self.lines.len(), 0)
+        } else {
+            let Some(parent_line) = self.find_key(parent_path) else {
+                return false;
+            };
+            let (_, inline) = split_key_line(&self.lines[parent_line]).unwrap();
+            if !inline.is_empty() {
+                return false;
+            }
+            let end = self.block_end(parent_line);
+            let child_indent = match self.lines[parent_line + 1..end]
+                .iter()
+                .find(|l| !is_blank_or_comment(l))
+            {
+                Some(line) if is_item_line(line) => return false,
+                Some(line) => indent_of(line),
+                None => indent_of(&self.lines[parent_line]) + 2,
+            };
+            (end, child_indent)
+        };
+
+        let Some((inline, rendered)) = render_value(value, child_indent + 2) else {
+            return false;
+        };
+        let mut block = vec![format!(
+            "{}{}:{}",
+            " ".repeat(child_indent),
+            render_scalar(key),
+            inline
+        )];
+        block.extend(rendered);
+        self.lines.splice(insert_at..insert_at, block);
+        true
+    }
+
+    /// Finds the line holding the last key of `path`, descending through
+    /// nested block mappings.
+    fn find_key(&self, path: &[&str]) -> Option<usize> {
+        let mut start = 0;
+        let mut end = self.lines.len();
+        let mut found = None;
+        for key in path {
+            let line = self.find_key_in(start, end, key)?;
+            found = Some(line);
+            start = line + 1;
+            end = self.block_end(line);
+        }
+        found
+    }
+
+    fn find_key_in(&self, start: usize, end: usize, key: &str) -> Option<usize> {
+        let mut mapping_indent = None;
+        for i in start..end {
+            let line = &self.lines[i];
+            if is_blank_or_comment(line) || line.trim() == "---" {
+                continue;
+            }
+            let indent = indent_of(line);
+            if indent != *mapping_indent.get_or_insert(indent) {
+                continue;
+            }
+            if let Some((found, _)) = split_key_line(line) {
+                if unquote(&found) == key {
+                    return Some(i);
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the index one past the last line belonging to the value of
+    /// the key on `key_line`. Trailing blank and comment lines are left out
+    /// since they usually introduce whatever comes next.
+    fn block_end(&self, key_line: usize) -> usize {
+        let key_indent = indent_of(&self.lines[key_line]);
+        let mut last = key_line;
+        for i in key_line + 1..self.lines.len() {
+            let line = &self.lines[i];
+            if is_blank_or_comment(line) {
+                continue;
+            }
+            let indent = indent_of(line);
+            if indent > key_indent || (indent == key_indent && is_item_line(line)) {
+                last = i;
+            } else {
+                break;
+            }
+        }
+        last + 1
+    }
+
+    fn sequence_items(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> Option<(Option<usize>, Vec<SequenceItem>)> {
+        let mut items: Vec<SequenceItem> = Vec::new();
+        let mut pending = Vec::new();
+        let mut item_indent = None;
+        for line in &self.lines[start..end] {
+            if is_blank_or_comment(line) {
+                pending.push(line.clone());
+                continue;
+            }
+            let indent = indent_of(line);
+            if is_item_line(line) && indent == *item_indent.get_or_insert(indent) {
+                let mut lines = std::mem::take(&mut pending);
+                lines.push(line.clone());
+                items.push(SequenceItem { lines });
+            } else if item_indent.is_some_and(|i| indent > i) {
+                let item = items.last_mut()?;
+                item.lines.append(&mut pending);
+                item.lines.push(line.clone());
+            } else {
+                return None;
+            }
+        }
+        Some((item_indent, items))
+    }
+}
+
+/// Parses the lines of one sequence item back into a value.
+fn parse_item<T: DeserializeOwned>(lines: &[String], indent: usize) -> Option<T> {
+    let text: Vec<&str> = lines
+        .iter()
+        .filter(|l| !is_blank_or_comment(l))
+        .map(|l| l.get(indent..).unwrap_or(""))
+        .collect();
+    let mut values: Vec<T> = serde_yaml::from_str(&text.join("\n")).ok()?;
+    (values.len() == 1).then(|| values.remove(0))
+}
+
+fn render_item<T: Serialize>(value: &T, indent: usize) -> Option<Vec<String>> {
+    let rendered = serde_yaml::to_string(&[value]).ok()?;
+    Some(
+        strip_document_marker(&rendered)
+            .lines()
+            .map(|l| format!("{}{}", " ".repeat(indent), l))
+            .collect(),
+    )
+}
+
+/// Renders `value` either inline (returned with its leading space, for
+/// scalars and empty collections) or as a block indented by `indent`.
+fn render_value<T: Serialize + ?Sized>(value: &T, indent: usize) -> Option<(String, Vec<String>)> {
+    let as_value = serde_yaml::to_value(value).ok()?;
+    let rendered = serde_yaml::to_string(value).ok()?;
+    let rendered = strip_document_marker(&rendered).trim_end();
+    let is_block = match &as_value {
+        Value::Mapping(m) => !m.is_empty(),
+        Value::Sequence(s) => !s.is_empty(),
+        _ => false,
+    };
+    if !is_block {
+        return (!rendered.contains('\n')).then(|| (format!(" {}", rendered), Vec::new()));
+    }
+    Some((
+        String::new(),
+        rendered
+            .lines()
+            .map(|l| format!("{}{}", " ".repeat(indent), l))
+            .collect(),
+    ))
+}
+
+fn strip_document_marker(rendered: &str) -> &str {
+    rendered
+        .strip_prefix("---\n")
+        .or_else(|| rendered.strip_prefix("--- "))
+        .unwrap_or(rendered)
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn is_blank_or_comment(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.is_empty() || trimmed.starts_with('#')
+}
+
+fn is_item_line(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed == "-" || trimmed.starts_with("- ")
+}
+
+/// Splits `key: value # comment` into the raw key text and the value with
+/// its comment removed.
+fn split_key_line(line: &str) -> Option<(String, String)> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with('-') || trimmed.starts_with('#') {
+        return None;
+    }
+    let key_end = match trimmed.chars().next()? {
+        quote @ ('"' | '\'') => trimmed[1..].find(quote)? + 2,
+        _ => trimmed.find(": ").or_else(|| {
+            let content = strip_comment(trimmed).trim_end();
+            content.ends_with(':').then(|| content.len() - 1)
+        })?,
+    };
+    let rest = trimmed[key_end..].strip_prefix(':')?;
+    Some((
+        trimmed[..key_end].to_string(),
+        strip_comment(rest).trim().to_string(),
+    ))
+}
+
+/// Returns the trailing ` # comment` of a line (with its leading spaces), or
+/// an empty string.
+fn comment_of(line: &str) -> String {
+    let content = strip_comment(line);
+    let trailing = &line[content.len()..];
+    if trailing.is_empty() {
+        String::new()
+    } else {
+        let spaces = content.len() - content.trim_end().len();
+        format!("{}{}", &content[content.len() - spaces..], trailing)
+    }
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') if previous.is_whitespace() => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, '#') if previous.is_whitespace() => return &text[..i],
+            _ => {}
+        }
+        previous = c;
+    }
+    text
+}
+
+fn unquote(text: &str) -> String {
+    let text = text.trim();
+    if text.len() >= 2 {
+        if let Some(inner) = text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+            return inner.replace("\\\"", "\"").replace("\\\\", "\\");
+        }
+        if let Some(inner) = text.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')) {
+            return inner.replace("''", "'");
+        }
+    }
+    text.to_string()
+}
+
+/// Renders a string the way serde_yaml would, quoting it when needed.
+pub fn render_scalar(value: &str) -> String {
+    let rendered = serde_yaml::to_string(value).unwrap_or_else(|_| format!("{:?}", value));
+    strip_document_marker(&rendered).trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+# Clovis configuration
+version: 2
+default: \"work\" # the usual one
+
+environments:
+  # Day job
+  work:
+    description: 'Day # job'
+    conflicts_with:
+      # Evenings only
+      - play
+      - \"games\"
+    apps:
+      - name: editor.desktop
+  play:
+    conflicts_with: []
+";
+
+    fn edited(edit: impl FnOnce(&mut YamlDocument) -> bool) -> String {
+        let mut document = YamlDocument::parse(CONFIG);
+        assert!(edit(&mut document));
+        document.render()
+    }
+
+    #[test]
+    fn documents_render_as_they_were_parsed() {
+        assert_eq!(YamlDocument::parse(CONFIG).render(), CONFIG);
+        let unterminated = "version: 2\nenvironments: {}";
+        assert_eq!(YamlDocument::parse(unterminated).render(), unterminated);
+    }
+
+    #[test]
+    fn sequences_keep_the_comments_and_quotes_of_their_items() {
+        let conflicts = ["games", "play", "chores"].map(String::from);
+        let text =
+            edited(|d| d.set_sequence(&["environments", "work", "conflicts_with"], &conflicts));
+        assert_eq!(
+            text,
+            CONFIG.replace(
+                "      # Evenings only\n      - play\n      - \"games\"\n",
+                "      - \"games\"\n      # Evenings only\n      - play\n      - chores\n"
+            )
+        );
+    }
+
+    #[test]
+    fn empty_inline_sequences_become_blocks() {
+        let conflicts = ["work".to_string()];
+        let text =
+            edited(|d| d.set_sequence(&["environments", "play", "conflicts_with"], &conflicts));
+        assert!(
+            text.ends_with("  play:\n    conflicts_with:\n      - work\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn inline_sequences_with_items_are_left_alone() {
+        let mut document =
+            YamlDocument::parse("environments:\n  work:\n    conflicts_with: [play]\n");
+        let conflicts = ["games".to_string()];
+        assert!(!document.set_sequence(&["environments", "work", "conflicts_with"], &conflicts));
+        assert_eq!(
+            document.render(),
+            "environments:\n  work:\n    conflicts_with: [play]\n"
+        );
+    }
+
+    #[test]
+    fn values_keep_the_comment_of_their_key() {
+        let text = edited(|d| d.set_value(&["default"], &"play"));
+        assert_eq!(
+            text,
+            CONFIG.replace(
+                "default: \"work\" # the usual one",
+                "default: play # the usual one"
+            )
+        );
+    }
+
+    #[test]
+    fn quoted_values_hide_comment_signs() {
+        let text = edited(|d| d.set_value(&["environments", "work", "description"], &"Office"));
+        assert!(text.contains("    description: Office\n"), "{}", text);
+        assert!(text.contains("  # Day job\n"), "{}", text);
+    }
+
+    #[test]
+    fn missing_nested_keys_are_appended_to_their_parent() {
+        let text = edited(|d| d.set_value(&["environments", "play", "do_not_disturb"], &true));
+        assert!(
+            text.ends_with("    conflicts_with: []\n    do_not_disturb: true\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn removed_keys_take_their_block_along() {
+        let text = edited(|d| d.remove_key(&["environments", "work", "conflicts_with"]));
+        assert_eq!(
+            text,
+            CONFIG.replace(
+                "    conflicts_with:\n      # Evenings only\n      - play\n      - \"games\"\n",
+                ""
+            )
+        );
+    }
+
+    #[test]
+    fn first_keys_go_after_the_leading_comments() {
+        let mut document = YamlDocument::parse("# Clovis\nenvironments: {}\n");
+        assert!(document.insert_first("version", &2));
+        assert_eq!(
+            document.render(),
+            "# Clovis\nversion: 2\nenvironments: {}\n"
+        );
+    }
+}
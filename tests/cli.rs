@@ -0,0 +1,190 @@
+//! Runs the clovis binary against a config and desktop files of its own,
+//! in a home directory made up for each test.
+
+mod common;
+
+use std::fs;
+
+use common::{stdout, Home};
+
+const CONFIG: &str = "version: 2
+environments:
+  work:
+    apps:
+      - name: editor.desktop
+      - name: notes
+        type: script
+        command: echo notes
+";
+
+#[test]
+fn validate_accepts_installed_apps() {
+    let home = Home::new("validate-ok", CONFIG);
+    home.desktop_file("editor.desktop", "Editor", "true");
+    let output = home.clovis(&["validate"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+    assert!(stdout(&output).contains("All applications are properly installed."));
+}
+
+#[test]
+fn validate_ignores_desktop_restrictions_outside_of_a_session() {
+    let home = Home::new("validate-no-desktop", CONFIG);
+    fs::write(
+        home.path(".local/share/applications/editor.desktop"),
+        "[Desktop Entry]\nType=Application\nName=Editor\nExec=true\nOnlyShowIn=GNOME;\n",
+    )
+    .unwrap();
+    let output = home.clovis(&["validate"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+}
+
+#[test]
+fn validate_reports_missing_apps() {
+    let home = Home::new("validate-missing", CONFIG);
+    home.desktop_file("editr.desktop", "Editor", "true");
+    let output = home.clovis(&["validate", "--json"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+    let report = stdout(&output);
+    assert!(report.contains(r#""kind": "missing-app""#), "{}", report);
+    assert!(report.contains(r#""app": "editor.desktop""#), "{}", report);
+    assert!(report.contains(r#""env": "work""#), "{}", report);
+    assert!(report.contains("editr.desktop"), "{}", report);
+}
+
+#[test]
+fn validate_rejects_unparseable_configs() {
+    let home = Home::new("validate-error", "environments: [");
+    let output = home.clovis(&["validate"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stdout(&output));
+}
+
+#[test]
+fn state_files_are_moved_out_of_the_config_dir() {
+    let home = Home::new("state-files", CONFIG);
+    home.desktop_file("editor.desktop", "Editor", "true");
+    fs::write(home.path(".config/clovis/history.yaml"), "[]\n").unwrap();
+    home.clovis(&["validate"]);
+    assert!(!home.path(".config/clovis/history.yaml").exists());
+    assert!(home.path(".local/state/clovis/history.yaml").exists());
+}
+
+#[test]
+fn environment_colors_style_their_names() {
+    let home = Home::new(
+        "env-color",
+        "version: 2
+environments:
+  work:
+    color: magenta
+    apps:
+      - name: notes
+        type: script
+        command: echo notes
+",
+    );
+    let output = home.clovis(&["show", "--color", "always", "--format", "compact"]);
+    assert!(
+        stdout(&output).starts_with("\x1b[1;35mwork\x1b[0m: notes"),
+        "{}",
+        stdout(&output)
+    );
+}
+
+#[test]
+fn interactive_launch_skips_the_apps_toggled_off() {
+    let home = Home::new(
+        "launch-interactive",
+        "version: 2
+environments:
+  work:
+    apps:
+      - name: first
+        type: script
+        command: true clovis-test-first
+      - name: second
+        type: script
+        command: true clovis-test-second
+",
+    );
+    let output =
+        home.clovis_with_input(&["launch", "work", "--interactive", "--no-daemon"], "1\n\n");
+    let printed = stdout(&output);
+    assert_eq!(output.status.code(), Some(0), "{}", printed);
+    assert!(printed.contains("[ ] first"), "{}", printed);
+    assert!(printed.contains("Launching: second"), "{}", printed);
+    assert!(!printed.contains("Launching: first"), "{}", printed);
+}
+
+#[test]
+fn restarts_are_counted_apart_from_launches() {
+    let home = Home::new("restart-count", CONFIG);
+    let output = home.clovis(&["restart", "work", "notes", "--no-daemon"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+    let stats = fs::read_to_string(home.path(".local/state/clovis/stats.yaml")).unwrap();
+    assert!(
+        stats.contains("restarts:\n  work:\n    notes: 1"),
+        "{}",
+        stats
+    );
+}
+
+#[test]
+fn logs_show_what_an_app_printed() {
+    let home = Home::new("logs", CONFIG);
+    let logs = home.path(".local/state/clovis/logs");
+    fs::create_dir_all(&logs).unwrap();
+    fs::write(logs.join("notes.log"), "notes\n").unwrap();
+    let output = home.clovis(&["logs", "notes"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+    assert_eq!(stdout(&output), "notes\n");
+}
+
+#[test]
+fn lint_reports_apps_shared_by_conflicting_environments() {
+    let home = Home::new(
+        "lint-conflict",
+        "version: 2
+environments:
+  work:
+    conflicts_with: [play]
+    apps:
+      - name: chat
+      - name: editor
+  play:
+    conflicts_with: [work]
+    apps:
+      - name: chat
+      - name: game
+",
+    );
+    let output = home.clovis(&["lint"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+    assert_eq!(
+        stdout(&output),
+        "warning: 'chat' is in both 'play' and 'work', which conflict, so launching one stops it \
+         for the other\n"
+    );
+}
+
+#[test]
+fn lint_rejects_privileged_apps_in_a_sandbox() {
+    let home = Home::new(
+        "lint-privileged-sandbox",
+        "version: 2
+environments:
+  admin:
+    apps:
+      - name: gparted
+        privileged: true
+        sandbox:
+          firejail: default
+",
+    );
+    let output = home.clovis(&["lint"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+    assert_eq!(
+        stdout(&output),
+        "warning: 'gparted' in environment 'admin' is both privileged and sandboxed, pkexec \
+         cannot run in a sandbox\n"
+    );
+}
@@ -0,0 +1,169 @@
+//! A home directory made up for each test, with a config and desktop files
+//! of its own, and snapshots of what clovis prints.
+
+// Each test file uses only some of these
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+pub struct Home {
+    dir: PathBuf,
+}
+
+impl Home {
+    /// A home directory holding `config` as the clovis config.
+    pub fn new(name: &str, config: &str) -> Home {
+        let dir = std::env::temp_dir().join(format!("clovis-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(".config/clovis")).unwrap();
+        fs::create_dir_all(dir.join(".local/share/applications")).unwrap();
+        fs::create_dir_all(dir.join("share")).unwrap();
+        fs::write(dir.join(".config/clovis/config.yaml"), config).unwrap();
+        Home { dir }
+    }
+
+    /// Installs a desktop file named `id` running `exec`.
+    pub fn desktop_file(&self, id: &str, name: &str, exec: &str) {
+        fs::write(
+            self.dir.join(".local/share/applications").join(id),
+            format!("[Desktop Entry]\nType=Application\nName={name}\nExec={exec}\n"),
+        )
+        .unwrap();
+    }
+
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.dir.join(path)
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let dir: &Path = &self.dir;
+        let mut command = Command::new(env!("CARGO_BIN_EXE_clovis"));
+        command
+            .args(args)
+            .env("HOME", dir)
+            .env("SHELL", "/bin/sh")
+            .env("XDG_CONFIG_HOME", dir.join(".config"))
+            .env("XDG_DATA_HOME", dir.join(".local/share"))
+            .env("XDG_DATA_DIRS", dir.join("share"))
+            .env("XDG_STATE_HOME", dir.join(".local/state"))
+            .env("XDG_CACHE_HOME", dir.join(".cache"))
+            .env("XDG_RUNTIME_DIR", dir.join("run"))
+            .env("NO_COLOR", "1")
+            .env_remove("XDG_CURRENT_DESKTOP");
+        command
+    }
+
+    pub fn clovis(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    /// Runs clovis with `input` as what the user types.
+    pub fn clovis_with_input(&self, args: &[&str], input: &str) -> Output {
+        let mut child = self
+            .command(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+}
+
+impl Drop for Home {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// Whether `word` is a duration such as `0.42s`.
+fn is_duration(word: &str) -> bool {
+    word.strip_suffix('s')
+        .and_then(|number| number.split_once('.'))
+        .is_some_and(|(whole, fraction)| {
+            [whole, fraction]
+                .iter()
+                .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+        })
+}
+
+/// Replaces what changes from one run to the next: the home directory,
+/// and the durations and timestamps printed.
+fn normalize(home: &Home, text: &str) -> String {
+    let text = text.replace(&home.dir.display().to_string(), "$HOME");
+    let mut normalized = String::new();
+    for line in text.lines() {
+        let words: Vec<String> = line
+            .split(' ')
+            .map(|word| {
+                if is_duration(word) {
+                    word.replace(|c: char| c.is_ascii_digit(), "0")
+                } else {
+                    word.to_string()
+                }
+            })
+            .collect();
+        let mut line = words.join(" ");
+        for key in ["\"timestamp\":", "\"duration_ms\":"] {
+            if let Some(start) = line.find(key) {
+                let value = start + key.len();
+                let end = line[value..]
+                    .find(|c: char| !c.is_ascii_digit() && c != '.')
+                    .map_or(line.len(), |end| value + end);
+                line.replace_range(value..end, "0");
+            }
+        }
+        normalized.push_str(&line);
+        normalized.push('\n');
+    }
+    normalized
+}
+
+/// Compares what `home`'s clovis printed, `output`, with the snapshot
+/// `tests/snapshots/<name>.txt`. Run the tests with `UPDATE_SNAPSHOTS=1` to
+/// write the snapshots again after a deliberate change, and review them.
+pub fn assert_snapshot(home: &Home, name: &str, output: &Output) {
+    let mut actual = format!("exit code: {:?}\n", output.status.code());
+    for (stream, text) in [("stdout", stdout(output)), ("stderr", stderr(output))] {
+        if !text.is_empty() {
+            actual.push_str(&format!("--- {}\n{}", stream, normalize(home, &text)));
+        }
+    }
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{}.txt", name));
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "no snapshot {}, run the tests with UPDATE_SNAPSHOTS=1 to write it",
+            path.display()
+        )
+    });
+    assert!(
+        expected == actual,
+        "{} differs from its snapshot {}:\n{}",
+        name,
+        path.display(),
+        actual
+    );
+}
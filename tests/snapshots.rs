@@ -0,0 +1,112 @@
+//! What clovis prints, compared with the snapshots in `tests/snapshots`, so
+//! that output scripts rely on does not change by accident.
+
+mod common;
+
+use common::{assert_snapshot, Home};
+
+const CONFIG: &str = "version: 2
+default: work
+schedule:
+  - cron: 0 9 * * 1-5
+    action: launch
+    env: work
+environments:
+  work:
+    description: Day job
+    apps:
+      - name: editor.desktop
+      - name: first
+        type: script
+        command: true clovis-snapshot-first
+      - name: second
+        type: script
+        command: true clovis-snapshot-second
+  play:
+    apps:
+      - name: clovis-snapshot-missing-game
+";
+
+fn home(name: &str) -> Home {
+    let home = Home::new(name, CONFIG);
+    home.desktop_file("editor.desktop", "Editor", "true");
+    home
+}
+
+#[test]
+fn show() {
+    let home = home("snapshot-show");
+    assert_snapshot(&home, "show", &home.clovis(&["show"]));
+}
+
+#[test]
+fn show_formats() {
+    let home = home("snapshot-show-formats");
+    let table = home.clovis(&["show", "--format", "table"]);
+    assert_snapshot(&home, "show-table", &table);
+    let compact = home.clovis(&["show", "work", "--format", "compact"]);
+    assert_snapshot(&home, "show-compact", &compact);
+    let tree = home.clovis(&["show", "--format", "tree"]);
+    assert_snapshot(&home, "show-tree", &tree);
+}
+
+#[test]
+fn validate() {
+    let home = home("snapshot-validate");
+    assert_snapshot(&home, "validate", &home.clovis(&["validate"]));
+}
+
+#[test]
+fn validate_json() {
+    let home = home("snapshot-validate-json");
+    assert_snapshot(
+        &home,
+        "validate-json",
+        &home.clovis(&["validate", "--json"]),
+    );
+}
+
+#[test]
+fn validate_config_error() {
+    let home = Home::new("snapshot-validate-error", "environments: [");
+    assert_snapshot(&home, "validate-config-error", &home.clovis(&["validate"]));
+}
+
+#[test]
+fn launch_summary() {
+    let home = Home::new(
+        "snapshot-launch",
+        &CONFIG.replace("      - name: editor.desktop\n", ""),
+    );
+    assert_snapshot(&home, "launch", &home.clovis(&["launch", "work"]));
+}
+
+#[test]
+fn launch_events() {
+    let home = Home::new(
+        "snapshot-launch-events",
+        &CONFIG.replace("      - name: editor.desktop\n", ""),
+    );
+    assert_snapshot(
+        &home,
+        "launch-events",
+        &home.clovis(&["launch", "work", "--events", "ndjson"]),
+    );
+}
+
+#[test]
+fn unknown_environment() {
+    let home = home("snapshot-unknown-env");
+    assert_snapshot(
+        &home,
+        "unknown-environment",
+        &home.clovis(&["launch", "nope"]),
+    );
+}
+
+#[test]
+fn help() {
+    let home = home("snapshot-help");
+    assert_snapshot(&home, "help", &home.clovis(&["--help"]));
+    assert_snapshot(&home, "launch-help", &home.clovis(&["launch", "--help"]));
+}